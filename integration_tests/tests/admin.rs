@@ -0,0 +1,346 @@
+use chrono::{Duration, Utc};
+use failure::Fallible;
+use swirl::admin;
+use swirl::storage::{self, JobFilter};
+use swirl::testing::jobs::*;
+use swirl::{JobsFailed, PerformError};
+
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
+#[test]
+fn failure_report_groups_failures_by_job_type_and_error() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    panic_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(3)), runner.check_for_failed_jobs());
+
+    let report = admin::failure_report(&conn, Utc::now() - Duration::hours(1))?;
+    assert_eq!(3, report.total_failures);
+    assert_eq!(2, report.groups.len());
+
+    let most_common = &report.groups[0];
+    assert_eq!("failure_job", most_common.job_type);
+    assert_eq!("failed", most_common.fingerprint);
+    assert_eq!(2, most_common.failures);
+    assert!(most_common.first_seen <= most_common.last_seen);
+    assert_eq!("panic_job", report.groups[1].job_type);
+    assert!(report.groups[1]
+        .fingerprint
+        .starts_with("job panicked at swirl/src/testing/jobs.rs:N:N"));
+
+    let report = admin::failure_report(&conn, Utc::now() + Duration::hours(1))?;
+    assert_eq!(0, report.total_failures);
+    assert!(report.groups.is_empty());
+    Ok(())
+}
+
+#[test]
+fn batch_progress_counts_the_jobs_in_a_batch() -> Fallible<()> {
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue_in_batch(&conn, "backfill")?;
+    succeeding_job().enqueue_in_batch(&conn, "backfill")?;
+    failure_job().enqueue_in_batch(&conn, "backfill")?;
+    succeeding_job().enqueue_in_batch(&conn, "import")?;
+    succeeding_job().enqueue(&conn)?;
+
+    let progress = admin::batch_progress(&conn, "backfill")?;
+    assert_eq!(3, progress.pending);
+    assert_eq!(0, progress.succeeded);
+    assert_eq!(0, progress.failed);
+    assert!(!progress.is_finished());
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let progress = admin::batch_progress(&conn, "backfill")?;
+    assert_eq!(0, progress.pending);
+    assert_eq!(2, progress.succeeded);
+    assert_eq!(1, progress.failed);
+    assert_eq!(3, progress.total());
+    assert!(progress.is_finished());
+
+    let progress = admin::batch_progress(&conn, "unknown")?;
+    assert_eq!(0, progress.total());
+    Ok(())
+}
+
+#[test]
+fn each_attempt_to_run_a_job_is_recorded() -> Fallible<()> {
+    use diesel::prelude::*;
+    use swirl::admin::AttemptOutcome;
+    use swirl::schema::background_jobs;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    panic_job().enqueue(&conn)?;
+    let ids = background_jobs::table
+        .select(background_jobs::id)
+        .order(background_jobs::id)
+        .load::<i64>(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+
+    let attempts = admin::job_attempts(&conn, ids[0])?;
+    assert_eq!(1, attempts.len());
+    assert_eq!("succeeding_job", attempts[0].job_type);
+    assert_eq!(1, attempts[0].attempt);
+    assert_eq!(AttemptOutcome::Succeeded, attempts[0].outcome);
+    assert_eq!(None, attempts[0].error);
+    assert!(attempts[0]
+        .worker
+        .starts_with(&format!("pid {} ", std::process::id())));
+    assert!(attempts[0].started_at <= Utc::now());
+
+    let attempts = admin::job_attempts(&conn, ids[1])?;
+    assert_eq!(AttemptOutcome::Failed, attempts[0].outcome);
+    assert_eq!(Some("failed"), attempts[0].error.as_deref());
+
+    let attempts = admin::recent_attempts(&conn, "panic_job", 10)?;
+    assert_eq!(1, attempts.len());
+    assert_eq!(ids[2], attempts[0].job_id);
+    assert_eq!(AttemptOutcome::Panicked, attempts[0].outcome);
+    assert!(admin::recent_attempts(&conn, "panic_job", 0)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn dead_jobs_can_be_listed_and_requeued() -> Fallible<()> {
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    panic_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(3)), runner.check_for_failed_jobs());
+
+    let dead = admin::dead_jobs(&conn, None, 10)?;
+    assert_eq!(3, dead.len());
+    assert!(dead.windows(2).all(|w| w[0].id < w[1].id));
+    assert_eq!(1, dead[0].retries);
+    assert_eq!(Some("failed"), dead[0].last_error.as_deref());
+
+    let failures = admin::dead_jobs(&conn, Some("failure_job"), 10)?;
+    assert_eq!(2, failures.len());
+    assert!(failures.iter().all(|job| job.job_type == "failure_job"));
+    assert_eq!(1, admin::dead_jobs(&conn, None, 1)?.len());
+
+    let panicked = admin::dead_jobs(&conn, Some("panic_job"), 10)?;
+    assert!(admin::requeue_dead_job(&conn, panicked[0].id)?);
+    assert!(!admin::requeue_dead_job(&conn, panicked[0].id)?);
+    assert_eq!(2, admin::requeue_dead_jobs(&conn, "failure_job")?);
+    assert!(admin::dead_jobs(&conn, None, 10)?.is_empty());
+
+    // Requeued jobs start over, so they are tried and fail again
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(3)), runner.check_for_failed_jobs());
+    assert_eq!(3, admin::dead_jobs(&conn, None, 10)?.len());
+    Ok(())
+}
+
+#[test]
+fn redacted_arguments_are_masked_when_jobs_are_listed() -> Fallible<()> {
+    #[swirl::background_job]
+    fn charge_card(customer: String, #[redact] card_number: String) -> Result<(), PerformError> {
+        let _ = (customer, card_number);
+        Err("declined".into())
+    }
+
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    charge_card("alice".into(), "4242424242424242".into()).enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let dead = admin::dead_jobs(&conn, None, 10)?;
+    assert_eq!("alice", dead[0].data["customer"]);
+    assert_eq!("[REDACTED]", dead[0].data["card_number"]);
+    let listed = storage::list_jobs(&conn, &JobFilter::new())?;
+    assert_eq!("alice", listed[0].data["customer"]);
+    assert_eq!("[REDACTED]", listed[0].data["card_number"]);
+    Ok(())
+}
+
+#[test]
+fn jobs_whose_worker_stopped_while_running_them_are_abandoned() -> Fallible<()> {
+    use diesel::prelude::*;
+    use swirl::schema::{background_job_heartbeats, background_jobs};
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let abandoned = succeeding_job().enqueue(&conn)?;
+    let retried = succeeding_job().enqueue(&conn)?;
+    let stalled = succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+    let an_hour_ago = Utc::now() - Duration::hours(1);
+    for job_id in &[abandoned, retried, stalled] {
+        diesel::insert_into(background_job_heartbeats::table)
+            .values((
+                background_job_heartbeats::job_id.eq(i64::from(*job_id)),
+                background_job_heartbeats::attempt.eq(0),
+                background_job_heartbeats::locked_by.eq("pid 1 ThreadId(2)"),
+                background_job_heartbeats::heartbeat_at.eq(an_hour_ago),
+            ))
+            .execute(&conn)?;
+    }
+    // The attempt the heartbeat was recorded for has already failed
+    diesel::update(background_jobs::table.find(retried))
+        .set(background_jobs::retries.eq(1))
+        .execute(&conn)?;
+
+    let worker = runner.connection_pool().get()?;
+    worker.transaction(|| {
+        background_jobs::table
+            .find(stalled)
+            .select(background_jobs::id)
+            .for_update()
+            .first::<i64>(&*worker)?;
+
+        let abandoned_jobs = admin::abandoned_jobs(&conn)?;
+        assert_eq!(1, abandoned_jobs.len());
+        assert_eq!(i64::from(abandoned), abandoned_jobs[0].job_id);
+        assert_eq!("succeeding_job", abandoned_jobs[0].job_type);
+        assert_eq!("pid 1 ThreadId(2)", abandoned_jobs[0].locked_by);
+        assert!(!abandoned_jobs[0].running);
+
+        let stalled_jobs = admin::stalled_jobs(&conn, Utc::now() - Duration::minutes(1))?;
+        assert_eq!(1, stalled_jobs.len());
+        assert_eq!(i64::from(stalled), stalled_jobs[0].job_id);
+        assert!(stalled_jobs[0].running);
+        assert!(admin::stalled_jobs(&conn, Utc::now() - Duration::hours(2))?.is_empty());
+        Ok::<_, failure::Error>(())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn jobs_which_are_not_running_can_be_deleted() -> Fallible<()> {
+    use diesel::prelude::*;
+    use swirl::schema::background_jobs;
+
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    let dead = admin::dead_jobs(&conn, None, 10)?;
+    let pending = succeeding_job().enqueue(&conn)?;
+    let running = succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+
+    assert!(admin::delete_job(&conn, dead[0].id)?);
+    assert!(!admin::delete_job(&conn, dead[0].id)?);
+    assert!(admin::delete_job(&conn, pending.into())?);
+
+    let worker = runner.connection_pool().get()?;
+    worker.transaction(|| {
+        background_jobs::table
+            .find(running)
+            .select(background_jobs::id)
+            .for_update()
+            .first::<i64>(&*worker)?;
+
+        assert!(!admin::delete_job(&conn, running.into())?);
+        assert_eq!(1, admin::delete_jobs(&conn, "succeeding_job")?);
+        Ok::<_, failure::Error>(())
+    })?;
+    let remaining = background_jobs::table
+        .select(background_jobs::id)
+        .load::<i64>(&conn)?;
+    assert_eq!(vec![i64::from(running)], remaining);
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_can_be_listed_and_retried_from_admin_endpoints() -> Fallible<()> {
+    use diesel::prelude::*;
+    use swirl::admin::{QueueStats, RetryOutcome};
+    use swirl::schema::background_jobs;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let failed = failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    let pending = succeeding_job().enqueue(&conn)?;
+    swirl::storage::pause_queue(&conn, "mailers")?;
+
+    let failed_jobs = admin::list_failed_jobs(&conn, 10)?;
+    assert_eq!(1, failed_jobs.len());
+    assert_eq!(i64::from(failed), failed_jobs[0].id);
+    assert_eq!(1, failed_jobs[0].retries);
+    assert!(!failed_jobs[0].dead);
+    let json = serde_json::to_value(&failed_jobs)?;
+    assert_eq!("failure_job", json[0]["job_type"]);
+    assert_eq!("failed", json[0]["last_error"]);
+
+    let stats = admin::queue_stats(&conn)?;
+    assert_eq!(
+        vec![
+            QueueStats {
+                queue: "default".into(),
+                pending: 1,
+                failed: 1,
+                dead: 0,
+                paused: false,
+            },
+            QueueStats {
+                queue: "mailers".into(),
+                pending: 0,
+                failed: 0,
+                dead: 0,
+                paused: true,
+            },
+        ],
+        stats
+    );
+
+    let worker = runner.connection_pool().get()?;
+    worker.transaction(|| {
+        background_jobs::table
+            .find(pending)
+            .select(background_jobs::id)
+            .for_update()
+            .first::<i64>(&*worker)?;
+
+        assert_eq!(
+            RetryOutcome::Running,
+            admin::retry_job(&conn, pending.into())?
+        );
+        Ok::<_, failure::Error>(())
+    })?;
+    assert_eq!(
+        RetryOutcome::NotFailed,
+        admin::retry_job(&conn, pending.into())?
+    );
+    assert_eq!(RetryOutcome::NotFound, admin::retry_job(&conn, -1)?);
+    let outcome = admin::retry_job(&conn, failed.into())?;
+    assert_eq!(RetryOutcome::Retried, outcome);
+    assert_eq!("retried", serde_json::to_value(outcome)?);
+
+    // The retried job doesn't wait out its backoff
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(2, admin::list_failed_jobs(&conn, 10)?[0].retries);
+
+    diesel::update(background_jobs::table.find(failed))
+        .set(background_jobs::dead_at.eq(diesel::dsl::now))
+        .execute(&conn)?;
+    assert!(admin::list_failed_jobs(&conn, 10)?[0].dead);
+    assert_eq!(1, admin::queue_stats(&conn)?[0].dead);
+    assert_eq!(
+        RetryOutcome::Retried,
+        admin::retry_job(&conn, failed.into())?
+    );
+    assert!(admin::list_failed_jobs(&conn, 10)?.is_empty());
+    Ok(())
+}
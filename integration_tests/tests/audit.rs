@@ -0,0 +1,95 @@
+use chrono::{Duration, Utc};
+use failure::Fallible;
+use swirl::audit::{self, AuditAction, Auditor};
+use swirl::testing::jobs::*;
+use swirl::{admin, JobsFailed};
+
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
+#[test]
+fn changes_made_through_an_auditor_are_recorded_with_their_actor() -> Fallible<()> {
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    let dead = admin::dead_jobs(&conn, None, 10)?;
+    succeeding_job().enqueue(&conn)?;
+
+    let auditor = Auditor::new(&conn, "alice").reason("INC-1234");
+    assert!(auditor.requeue_dead_job(dead[0].id)?);
+    assert_eq!(1, auditor.cancel(succeeding_job())?);
+    Auditor::new(&conn, "deploy-bot").set_schedule("send_digest", "0 0 9 * * *", "UTC")?;
+
+    let entries = audit::entries(&conn, Utc::now() - Duration::hours(1))?;
+    assert_eq!(3, entries.len());
+    assert_eq!(AuditAction::RequeueDeadJob, entries[0].action);
+    assert_eq!("alice", entries[0].actor);
+    assert_eq!(Some("INC-1234".to_string()), entries[0].reason);
+    assert_eq!(Some(dead[0].id), entries[0].job_id);
+    assert_eq!(1, entries[0].affected);
+    assert_eq!(AuditAction::Cancel, entries[1].action);
+    assert_eq!(Some("succeeding_job".to_string()), entries[1].job_type);
+    assert_eq!(AuditAction::SetSchedule, entries[2].action);
+    assert_eq!("deploy-bot", entries[2].actor);
+    assert_eq!(None, entries[2].reason);
+    assert_eq!("0 0 9 * * *", entries[2].details["cron"]);
+
+    let job_entries = audit::job_entries(&conn, dead[0].id)?;
+    assert_eq!(vec![entries[0].clone()], job_entries);
+    assert!(audit::entries(&conn, Utc::now() + Duration::hours(1))?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn changes_which_fail_are_not_recorded() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let auditor = Auditor::new(&conn, "alice");
+
+    assert!(auditor
+        .set_schedule("send_digest", "not cron", "UTC")
+        .is_err());
+    assert!(auditor.pause_schedule("send_digest", None).is_err());
+    assert!(audit::entries(&conn, Utc::now() - Duration::hours(1))?.is_empty());
+
+    // Changes which are allowed but have no effect are still recorded
+    assert!(!auditor.requeue_dead_job(1)?);
+    let entries = audit::entries(&conn, Utc::now() - Duration::hours(1))?;
+    assert_eq!(1, entries.len());
+    assert_eq!(0, entries[0].affected);
+    Ok(())
+}
+
+#[test]
+fn deleting_jobs_and_pausing_queues_are_recorded() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let job_id = succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+
+    let auditor = Auditor::new(&conn, "alice");
+    assert!(auditor.delete_job(job_id.into())?);
+    assert_eq!(1, auditor.delete_jobs("succeeding_job")?);
+    assert!(auditor.pause_queue("mailers")?);
+    assert!(auditor.resume_queue("mailers")?);
+    assert!(!auditor.resume_queue("mailers")?);
+
+    let entries = audit::entries(&conn, Utc::now() - Duration::hours(1))?;
+    let actions = entries.iter().map(|e| e.action).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            AuditAction::DeleteJob,
+            AuditAction::DeleteJobs,
+            AuditAction::PauseQueue,
+            AuditAction::ResumeQueue,
+            AuditAction::ResumeQueue,
+        ],
+        actions
+    );
+    assert_eq!(Some(i64::from(job_id)), entries[0].job_id);
+    assert_eq!(Some("succeeding_job".to_string()), entries[1].job_type);
+    assert_eq!("mailers", entries[2].details["queue"]);
+    assert_eq!(0, entries[4].affected);
+    Ok(())
+}
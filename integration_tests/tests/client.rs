@@ -0,0 +1,78 @@
+use diesel::prelude::*;
+use failure::Fallible;
+use swirl::schema::background_jobs;
+use swirl::testing::jobs::*;
+use swirl::{Client, PerformError};
+
+use crate::test_guard::TestGuard;
+
+#[swirl::background_job]
+fn greet(name: String) -> Result<(), PerformError> {
+    let _ = name;
+    Ok(())
+}
+
+#[test]
+fn clients_enqueue_jobs_which_runners_run() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let client = Client::new(runner.connection_pool().clone());
+    client.enqueue(succeeding_job())?;
+    assert!(client.enqueue_unless_pending(greet("Ferris".into()))?);
+    assert!(!client.enqueue_unless_pending(greet("Ferris".into()))?);
+
+    let summary = runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(2, summary.claimed);
+    Ok(())
+}
+
+#[test]
+fn clients_cancel_pending_jobs_with_the_same_arguments() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let client = Client::new(runner.connection_pool().clone());
+    client.enqueue(greet("Ferris".into()))?;
+    client.enqueue(greet("Ferris".into()))?;
+    client.enqueue(greet("Corro".into()))?;
+
+    assert_eq!(2, client.cancel(greet("Ferris".into()))?);
+    assert_eq!(0, client.cancel(greet("Ferris".into()))?);
+
+    let conn = runner.connection_pool().get()?;
+    let remaining = background_jobs::table
+        .select(background_jobs::data)
+        .load::<serde_json::Value>(&conn)?;
+    assert_eq!(vec![serde_json::json!({ "name": "Corro" })], remaining);
+    Ok(())
+}
+
+#[test]
+fn clients_schedule_recurring_jobs() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let client = Client::new(runner.connection_pool().clone());
+    client.schedule::<greet::Job>("0 0 9 * * *", "UTC")?;
+
+    let conn = runner.connection_pool().get()?;
+    let schedule = swirl::schedule::get(&conn, "greet")?.expect("schedule was not saved");
+    assert_eq!("0 0 9 * * *", schedule.cron);
+
+    assert!(client.unschedule::<greet::Job>()?);
+    assert!(!client.unschedule::<greet::Job>()?);
+    Ok(())
+}
+
+#[test]
+fn clients_enqueue_delayed_jobs() -> Fallible<()> {
+    use chrono::{Duration as ChronoDuration, Utc};
+    use std::time::Duration;
+
+    let runner = TestGuard::dummy_runner();
+    let client = Client::new(runner.connection_pool().clone());
+    client.enqueue_in(succeeding_job(), Duration::from_secs(60))?;
+    client.enqueue_at(succeeding_job(), Utc::now() + ChronoDuration::minutes(1))?;
+
+    assert_eq!(0, runner.run_all_pending_jobs()?.claimed);
+    let conn = runner.connection_pool().get()?;
+    let pending = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(2, pending);
+    Ok(())
+}
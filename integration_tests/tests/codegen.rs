@@ -1,10 +1,15 @@
-use crate::dummy_jobs::*;
-use crate::test_guard::TestGuard;
 use diesel::prelude::*;
 use failure::Fallible;
+use std::sync::{Arc, Mutex};
 use swirl::db::DieselPoolObj;
+use swirl::lifecycle::LifecycleEvent;
+use swirl::notifier::JobFailure;
+use swirl::schema::*;
+use swirl::testing::jobs::*;
 use swirl::{JobsFailed, PerformError};
 
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
 #[test]
 fn generated_jobs_serialize_all_arguments_except_first() -> Fallible<()> {
     #[swirl::background_job]
@@ -143,3 +148,339 @@ fn jobs_can_take_a_connection_as_an_argument() -> Fallible<()> {
     runner.check_for_failed_jobs()?;
     Ok(())
 }
+
+#[test]
+fn jobs_can_specify_a_queue() {
+    #[swirl::background_job]
+    fn no_queue() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    #[swirl::background_job(queue = "mailers")]
+    fn mailer_queue() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    assert_eq!("default", <no_queue::Job as Job>::QUEUE);
+    assert_eq!("mailers", <mailer_queue::Job as Job>::QUEUE);
+}
+
+#[test]
+fn jobs_can_specify_a_priority() {
+    #[swirl::background_job]
+    fn no_priority() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    #[swirl::background_job(queue = "mailers", priority = 10)]
+    fn urgent_email() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    assert_eq!(0, <no_priority::Job as Job>::PRIORITY);
+    assert_eq!(10, <urgent_email::Job as Job>::PRIORITY);
+    assert_eq!("mailers", <urgent_email::Job as Job>::QUEUE);
+}
+
+#[test]
+fn jobs_can_specify_a_statement_timeout() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::Text;
+    use std::time::Duration;
+
+    #[swirl::background_job(statement_timeout_ms = 1500)]
+    fn check_statement_timeout(conn: &PgConnection) -> Result<(), swirl::PerformError> {
+        let timeout = sql::<Text>("SELECT current_setting('statement_timeout')")
+            .get_result::<String>(conn)?;
+        assert_eq!("1500ms", timeout);
+        Ok(())
+    }
+
+    #[swirl::background_job(statement_timeout_ms = 10)]
+    fn runaway_query(conn: &PgConnection) -> Result<(), swirl::PerformError> {
+        diesel::sql_query("SELECT pg_sleep(1)").execute(conn)?;
+        Ok(())
+    }
+
+    assert_eq!(None, <no_timeout::Job as Job>::STATEMENT_TIMEOUT);
+    assert_eq!(
+        Some(Duration::from_millis(1500)),
+        <check_statement_timeout::Job as Job>::STATEMENT_TIMEOUT
+    );
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    check_statement_timeout().enqueue(&conn)?;
+    runaway_query().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[swirl::background_job]
+fn no_timeout() -> Result<(), swirl::PerformError> {
+    Ok(())
+}
+
+#[test]
+fn jobs_can_be_enqueued_with_spawn() -> Fallible<()> {
+    #[swirl::background_job]
+    fn assert_foo(arg: String) -> Result<(), PerformError> {
+        if arg == "foo" {
+            Ok(())
+        } else {
+            Err("arg wasn't foo!".into())
+        }
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    swirl::spawn!(conn, assert_foo("foo".into()))?;
+    swirl::spawn!(&*conn, assert_foo("bar".into()),)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn redacted_arguments_are_masked_outside_of_the_job() -> Fallible<()> {
+    #[swirl::background_job]
+    fn sign_in(user: String, #[redact] password: String) -> Result<(), PerformError> {
+        Err(format!("wrong password {} for {}", password, user).into())
+    }
+
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let failures2 = failures.clone();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors2 = errors.clone();
+    let runner = TestGuard::builder(())
+        .max_retries(0)
+        .failure_notifier(move |failure: &JobFailure| {
+            failures2.lock().unwrap().push(failure.clone());
+        })
+        .lifecycle_listener(move |event: &LifecycleEvent| {
+            if let LifecycleEvent::Failed { error, .. } = event {
+                errors2.lock().unwrap().push(error.clone());
+            }
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    sign_in("alice".into(), "hunter2".into()).enqueue(&conn)?;
+
+    let data = background_jobs::table
+        .select(background_jobs::data)
+        .first::<serde_json::Value>(&conn)?;
+    assert_eq!("hunter2", data["password"]);
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let expected = "wrong password [REDACTED] for alice";
+    let failures = failures.lock().unwrap();
+    assert_eq!(1, failures.len());
+    assert_eq!("alice", failures[0].data["user"]);
+    assert_eq!("[REDACTED]", failures[0].data["password"]);
+    assert_eq!(expected, failures[0].error);
+    assert_eq!(vec![expected.to_string()], *errors.lock().unwrap());
+    let stored = background_job_failures::table
+        .select(background_job_failures::error)
+        .first::<String>(&conn)?;
+    assert_eq!(expected, stored);
+    Ok(())
+}
+
+#[test]
+fn environments_do_not_need_to_be_unwind_safe() -> Fallible<()> {
+    // Trait objects are not `RefUnwindSafe` unless they say so
+    type Callback = Box<dyn Fn(&str) + Send + Sync>;
+
+    #[swirl::background_job]
+    fn call_back(env: &Callback, arg: String) -> Result<(), PerformError> {
+        env(&arg);
+        Ok(())
+    }
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let calls2 = Arc::clone(&calls);
+    let callback: Callback = Box::new(move |arg| {
+        if arg == "panic" {
+            panic!("callback panicked");
+        }
+        calls2.lock().unwrap().push(arg.to_string());
+    });
+    let runner = TestGuard::runner(callback);
+    let conn = runner.connection_pool().get()?;
+    call_back("first".into()).enqueue(&conn)?;
+    call_back("panic".into()).enqueue(&conn)?;
+    call_back("second".into()).enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    let mut calls = calls.lock().unwrap().clone();
+    calls.sort();
+    assert_eq!(vec!["first", "second"], calls);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_take_part_of_the_environment() -> Fallible<()> {
+    use swirl::FromEnv;
+
+    pub struct AppEnv {
+        greeting: Greeting,
+    }
+
+    pub struct Greeting(String);
+
+    impl FromEnv<AppEnv> for Greeting {
+        fn from_env(env: &AppEnv) -> &Self {
+            &env.greeting
+        }
+    }
+
+    #[swirl::background_job(environment(AppEnv))]
+    fn greet(greeting: &Greeting, name: String) -> Result<(), PerformError> {
+        if greeting.0 == "Hello" {
+            Ok(())
+        } else {
+            Err(format!("{}, {}!", greeting.0, name).into())
+        }
+    }
+
+    #[swirl::background_job(environment(AppEnv))]
+    fn no_environment() -> Result<(), PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::runner(AppEnv {
+        greeting: Greeting("Hello".into()),
+    });
+    let conn = runner.connection_pool().get()?;
+    greet("Ferris".into()).enqueue(&conn)?;
+    no_environment().enqueue(&conn)?;
+
+    let summary = runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(2, summary.claimed);
+
+    let error = greet("Ferris".into())
+        .perform_with(&Greeting("Goodbye".into()), runner.connection_pool())
+        .unwrap_err();
+    assert_eq!("Goodbye, Ferris!", error.to_string());
+    Ok(())
+}
+
+#[test]
+fn jobs_can_set_their_own_retry_limit() -> Fallible<()> {
+    #[swirl::background_job(max_retries = 0)]
+    fn fragile_job() -> Result<(), PerformError> {
+        Err("fragile".into())
+    }
+
+    let runner = TestGuard::builder(()).max_retries(5).build();
+    let conn = runner.connection_pool().get()?;
+    fragile_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+
+    let dead = background_jobs::table
+        .select(background_jobs::job_type)
+        .filter(background_jobs::dead_at.is_not_null())
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["fragile_job"], dead);
+    Ok(())
+}
+
+#[test]
+fn jobs_with_a_codec_store_their_arguments_as_bytes() -> Fallible<()> {
+    use swirl::serde::{de::DeserializeOwned, Serialize};
+    use swirl::{Codec, CodecError, Job};
+
+    /// JSON, backwards, so the arguments can only be read by decoding them
+    struct ReversedJson;
+
+    impl Codec for ReversedJson {
+        fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+            let mut bytes = serde_json::to_vec(value)?;
+            bytes.reverse();
+            Ok(bytes)
+        }
+
+        fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+            let bytes = bytes.iter().rev().copied().collect::<Vec<_>>();
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+    }
+
+    #[swirl::background_job(codec(ReversedJson))]
+    fn check_image(image: Vec<u8>) -> Result<(), PerformError> {
+        assert_eq!(vec![1, 2, 3], image);
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    check_image(vec![1, 2, 3]).enqueue(&conn)?;
+    check_image(vec![4, 5, 6]).enqueue(&conn)?;
+
+    let (data, binary_data) = background_jobs::table
+        .select((background_jobs::data, background_jobs::binary_data))
+        .order(background_jobs::id)
+        .first::<(serde_json::Value, Option<Vec<u8>>)>(&conn)?;
+    assert_eq!(serde_json::Value::Null, data);
+    assert_eq!(
+        check_image(vec![1, 2, 3]).encode_binary().unwrap(),
+        binary_data
+    );
+
+    let client = swirl::Client::new(runner.connection_pool().clone());
+    assert_eq!(1, client.cancel(check_image(vec![4, 5, 6]))?);
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Ok(()), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn jobs_can_take_their_context_as_an_argument() -> Fallible<()> {
+    use swirl::JobContext;
+
+    type Attempts = Arc<Mutex<Vec<(String, i32, bool)>>>;
+
+    #[swirl::background_job(queue = "reports")]
+    fn flaky_report(attempts: &Attempts, ctx: &JobContext) -> Result<(), PerformError> {
+        let age = chrono::Utc::now() - ctx.enqueued_at();
+        assert!(age >= chrono::Duration::zero() && age < chrono::Duration::minutes(1));
+        attempts.lock().unwrap().push((
+            ctx.queue().to_string(),
+            ctx.retries(),
+            ctx.is_final_attempt(),
+        ));
+        Err("flaky".into())
+    }
+
+    let attempts = Attempts::default();
+    let runner = TestGuard::builder(attempts.clone()).max_retries(1).build();
+    let conn = runner.connection_pool().get()?;
+    flaky_report().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    diesel::update(background_jobs::table)
+        .set(background_jobs::last_retry.eq(diesel::dsl::sql("'1970-01-01'")))
+        .execute(&conn)?;
+    runner.run_all_pending_jobs()?;
+
+    let expected = vec![("reports".into(), 0, false), ("reports".into(), 1, true)];
+    assert_eq!(expected, *attempts.lock().unwrap());
+
+    let error = flaky_report()
+        .perform(&attempts, runner.connection_pool())
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("can only be performed by a runner"));
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use diesel::prelude::*;
+use failure::Fallible;
+use http::{header, Method, Request, StatusCode};
+use swirl::audit::{self, AuditAction};
+use swirl::schema::background_jobs;
+use swirl::testing::jobs::*;
+use swirl::{JobsFailed, PerformError};
+use swirl_dashboard::{Actor, Dashboard};
+
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
+fn request(method: Method, uri: &str) -> Request<()> {
+    Request::builder().method(method).uri(uri).body(()).unwrap()
+}
+
+#[test]
+fn the_dashboard_shows_queues_and_jobs() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let dashboard = Dashboard::new().base_path("/admin/jobs/");
+    failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    swirl::storage::pause_queue(&conn, "mailers")?;
+
+    let response = dashboard.handle(&conn, &request(Method::GET, "/admin/jobs"));
+    assert_eq!(StatusCode::OK, response.status());
+    let body = response.body();
+    assert!(body.contains("1 waiting to be retried"), "{}", body);
+    assert!(body.contains("mailers</a> (paused)"), "{}", body);
+    assert!(body.contains("1 failed</title>"), "{}", body);
+
+    let response = dashboard.handle(
+        &conn,
+        &request(Method::GET, "/admin/jobs/jobs?state=failed"),
+    );
+    assert!(
+        response.body().contains("failure_job"),
+        "{}",
+        response.body()
+    );
+    let response = dashboard.handle(&conn, &request(Method::GET, "/admin/jobs/jobs?state=dead"));
+    assert!(response.body().contains("No jobs"), "{}", response.body());
+
+    let response = dashboard.handle(&conn, &request(Method::GET, "/jobs"));
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    let response = dashboard.handle(&conn, &request(Method::GET, "/admin/jobsjobs"));
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    Ok(())
+}
+
+#[test]
+fn redacted_arguments_are_masked_on_the_dashboard() -> Fallible<()> {
+    #[swirl::background_job]
+    fn reset_password(user: String, #[redact] token: String) -> Result<(), PerformError> {
+        let _ = (user, token);
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let dashboard = Dashboard::new();
+    let job_id = reset_password("alice".into(), "s3cr3t-token".into()).enqueue(&conn)?;
+
+    let response = dashboard.handle(&conn, &request(Method::GET, &format!("/jobs/{}", job_id)));
+    assert_eq!(StatusCode::OK, response.status());
+    let body = response.body();
+    assert!(body.contains("alice"), "{}", body);
+    assert!(body.contains("[REDACTED]"), "{}", body);
+    assert!(!body.contains("s3cr3t-token"), "{}", body);
+
+    // Which arguments of a job type which isn't registered are secret isn't
+    // known, so none of them are shown
+    diesel::update(background_jobs::table.find(i64::from(job_id)))
+        .set(background_jobs::job_type.eq("unregistered_job"))
+        .execute(&conn)?;
+    let response = dashboard.handle(&conn, &request(Method::GET, &format!("/jobs/{}", job_id)));
+    assert!(!response.body().contains("alice"), "{}", response.body());
+    Ok(())
+}
+
+#[test]
+fn dead_jobs_can_be_retried_and_deleted_from_the_dashboard() -> Fallible<()> {
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    let dashboard = Dashboard::new();
+    let job_id = failure_job().enqueue(&conn)?.0;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let response = dashboard.handle(&conn, &request(Method::GET, &format!("/jobs/{}", job_id)));
+    assert!(
+        response.body().contains("<p>Dead</p>"),
+        "{}",
+        response.body()
+    );
+
+    let retry = format!("/jobs/{}/retry", job_id);
+    let mut retry_as_alice = request(Method::POST, &retry);
+    retry_as_alice
+        .extensions_mut()
+        .insert(Actor("alice".into()));
+    let response = dashboard.handle(&conn, &retry_as_alice);
+    assert_eq!(StatusCode::SEE_OTHER, response.status());
+    assert_eq!(
+        format!("/jobs/{}", job_id),
+        response.headers()[header::LOCATION].to_str()?
+    );
+    let dead = background_jobs::table
+        .select(background_jobs::dead_at.is_not_null())
+        .first::<bool>(&conn)?;
+    assert!(!dead);
+    let response = dashboard.handle(&conn, &request(Method::POST, &retry));
+    assert_eq!(StatusCode::CONFLICT, response.status());
+
+    let delete = format!("/jobs/{}/delete", job_id);
+    let response = dashboard.handle(&conn, &request(Method::POST, &delete));
+    assert_eq!(StatusCode::SEE_OTHER, response.status());
+    let remaining = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(0, remaining);
+
+    let entries = audit::job_entries(&conn, job_id)?;
+    let recorded = entries
+        .iter()
+        .map(|e| (e.action, e.actor.as_str(), e.affected))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (AuditAction::RequeueDeadJob, "alice", 1),
+            (AuditAction::RequeueDeadJob, "swirl-dashboard", 0),
+            (AuditAction::DeleteJob, "swirl-dashboard", 1),
+        ],
+        recorded
+    );
+    Ok(())
+}
+
+#[test]
+fn actions_sent_from_other_sites_are_forbidden() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let job_id = succeeding_job().enqueue(&conn)?.0;
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/jobs/{}/delete", job_id))
+        .header(header::ORIGIN, "https://example.com")
+        .header(header::HOST, "localhost:8080")
+        .body(())?;
+    let response = Dashboard::new().handle(&conn, &request);
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+    let remaining = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(1, remaining);
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use assert_matches::assert_matches;
+use diesel::prelude::*;
+use diesel::select;
+use failure::Fallible;
+use std::sync::Mutex;
+use swirl::db::DieselPoolObj;
+use swirl::schema::background_jobs;
+use swirl::{EnqueueError, Job, JobContext, JobId, PerformError};
+
+use crate::test_guard::TestGuard;
+
+/// Only used by these tests, so other tests' jobs are never performed inline
+#[derive(Default)]
+pub struct InlineEnv {
+    performed: Mutex<Vec<i32>>,
+}
+
+#[swirl::background_job]
+fn record(env: &InlineEnv, ctx: &JobContext, value: i32) -> Result<(), PerformError> {
+    assert_eq!(0, ctx.job_id());
+    if value < 0 {
+        return Err("negative".into());
+    }
+    env.performed.lock().unwrap().push(value);
+    Ok(())
+}
+
+#[swirl::background_job]
+fn query(env: &InlineEnv, pool: &dyn DieselPoolObj) -> Result<(), PerformError> {
+    let conn = pool.get()?;
+    let value = select(1.into_sql::<diesel::sql_types::Integer>()).get_result(&**conn)?;
+    env.performed.lock().unwrap().push(value);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_be_performed_without_being_enqueued() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let env = InlineEnv::default();
+
+    swirl::perform_now(&conn, &env, record(1)).unwrap();
+    swirl::perform_now(&conn, &env, query()).unwrap();
+    let error = swirl::perform_now(&conn, &env, record(-1)).unwrap_err();
+
+    assert_eq!("negative", error.to_string());
+    assert_eq!(vec![1, 1], *env.performed.lock().unwrap());
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn jobs_are_performed_as_they_are_enqueued_while_inline() -> Fallible<()> {
+    #[derive(Default)]
+    pub struct OtherEnv;
+
+    #[swirl::background_job]
+    fn other(_env: &OtherEnv) -> Result<(), PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+
+    swirl::perform_jobs_inline(InlineEnv::default());
+    let result = (|| -> Result<(), EnqueueError> {
+        assert_eq!(JobId(0), record(1).enqueue(&conn)?);
+        assert!(record(2).enqueue_unless_pending(&conn)?);
+        swirl::enqueue_batch(&conn, vec![record(3), record(4)])?;
+        assert_matches!(record(-1).enqueue(&conn), Err(EnqueueError::JobFailed(_)));
+        other().enqueue(&conn)?;
+        Ok(())
+    })();
+    assert!(swirl::stop_performing_jobs_inline::<InlineEnv>());
+    result?;
+
+    assert!(!swirl::stop_performing_jobs_inline::<InlineEnv>());
+    record(5).enqueue(&conn)?;
+    let job_types = background_jobs::table
+        .select(background_jobs::job_type)
+        .order(background_jobs::id)
+        .load::<String>(&conn)?;
+    assert_eq!(vec![other::Job::JOB_TYPE, record::Job::JOB_TYPE], job_types);
+    Ok(())
+}
+
+#[test]
+fn chains_are_performed_in_order_while_inline() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+
+    swirl::perform_jobs_inline(InlineEnv::default());
+    let result = record(1).then(record(2)).then(query()).enqueue(&conn);
+    let failed = record(3).then(record(-1)).then(record(4)).enqueue(&conn);
+    assert!(swirl::stop_performing_jobs_inline::<InlineEnv>());
+
+    assert_eq!(vec![JobId(0); 3], result?);
+    assert_matches!(failed, Err(EnqueueError::JobFailed(_)));
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn batch_callbacks_are_performed_after_the_batch_while_inline() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+
+    swirl::perform_jobs_inline(InlineEnv::default());
+    let result = (|| -> Result<(), EnqueueError> {
+        swirl::Batch::new("inline")
+            .on_success(record(10))
+            .on_failure(record(20))
+            .enqueue(&conn, vec![record(1), record(2)])?;
+        let failed = swirl::Batch::new("inline")
+            .on_success(record(30))
+            .on_failure(record(40))
+            .enqueue(&conn, vec![record(3), record(-1), record(4)]);
+        assert_matches!(failed, Err(EnqueueError::JobFailed(_)));
+        Ok(())
+    })();
+    assert!(swirl::stop_performing_jobs_inline::<InlineEnv>());
+    result?;
+
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
@@ -1,10 +1,18 @@
 #![deny(warnings)]
 
-mod db;
-mod dummy_jobs;
-mod sync;
 mod test_guard;
-mod util;
 
+mod admin;
+mod audit;
+mod client;
 mod codegen;
+mod dashboard;
+mod inline;
+mod outbox;
+mod payload_store;
 mod runner;
+mod schedule;
+mod storage;
+mod testing;
+mod trigger;
+mod workflow;
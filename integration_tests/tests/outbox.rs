@@ -0,0 +1,108 @@
+use diesel::prelude::*;
+use diesel::result::Error::RollbackTransaction;
+use failure::Fallible;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use swirl::outbox::{self, DeliverMessage, Publisher};
+use swirl::schema::*;
+use swirl::PerformError;
+
+use crate::test_guard::TestGuard;
+
+#[derive(Clone, Default)]
+struct RecordingPublisher {
+    messages: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl Publisher for RecordingPublisher {
+    fn publish(&self, topic: &str, payload: &Value) -> Result<(), PerformError> {
+        self.messages
+            .lock()
+            .unwrap()
+            .push((topic.to_string(), payload.clone()));
+        Ok(())
+    }
+}
+
+impl RecordingPublisher {
+    fn messages(&self) -> Vec<(String, Value)> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn messages_written_to_the_outbox_are_delivered() -> Fallible<()> {
+    let publisher = RecordingPublisher::default();
+    let runner = TestGuard::runner(publisher.clone());
+    runner
+        .registry()
+        .register::<DeliverMessage<RecordingPublisher>>();
+    let conn = runner.connection_pool().get()?;
+    outbox::publish(&conn, "users", &json!({ "id": 1 }))?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    assert_eq!(
+        vec![("users".to_string(), json!({ "id": 1 }))],
+        publisher.messages()
+    );
+    let undelivered = background_job_outbox::table
+        .filter(background_job_outbox::delivered_at.is_null())
+        .count()
+        .get_result(&conn);
+    assert_eq!(Ok(0), undelivered);
+    Ok(())
+}
+
+#[test]
+fn messages_are_not_delivered_again_once_delivered() -> Fallible<()> {
+    let publisher = RecordingPublisher::default();
+    let runner = TestGuard::runner(publisher.clone());
+    runner
+        .registry()
+        .register::<DeliverMessage<RecordingPublisher>>();
+    let conn = runner.connection_pool().get()?;
+    let message_id = outbox::publish(&conn, "users", &json!({ "id": 1 }))?;
+    let delivery = background_jobs::table
+        .select((background_jobs::job_type, background_jobs::data))
+        .first::<(String, Value)>(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    diesel::insert_into(background_jobs::table)
+        .values((
+            background_jobs::job_type.eq(delivery.0),
+            background_jobs::data.eq(delivery.1),
+        ))
+        .execute(&conn)?;
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    assert_eq!(1, publisher.messages().len());
+    let remaining_messages = background_job_outbox::table
+        .select(background_job_outbox::id)
+        .load(&conn);
+    assert_eq!(Ok(vec![message_id]), remaining_messages);
+    Ok(())
+}
+
+#[test]
+fn messages_are_discarded_if_the_transaction_is_rolled_back() -> Fallible<()> {
+    let runner = TestGuard::runner(RecordingPublisher::default());
+    let conn = runner.connection_pool().get()?;
+
+    let result = conn.transaction::<(), _, _>(|| {
+        outbox::publish(&conn, "users", &json!({ "id": 1 })).unwrap();
+        Err(RollbackTransaction)
+    });
+
+    assert_eq!(Err(RollbackTransaction), result);
+    assert_eq!(
+        Ok(0),
+        background_job_outbox::table.count().get_result(&conn)
+    );
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use diesel::prelude::*;
+use failure::Fallible;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use swirl::payload_store::{ClaimCheck, DirectoryPayloadStore, PayloadStore};
+use swirl::schema::*;
+use swirl::PerformError;
+
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
+#[swirl::background_job]
+fn assert_length(text: String, expected: usize) -> Result<(), PerformError> {
+    if text.len() == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {} bytes, got {}", expected, text.len()).into())
+    }
+}
+
+fn payload_directory() -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("swirl-payloads-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+#[test]
+fn large_payloads_are_stored_outside_of_the_queue_until_the_job_succeeds() -> Fallible<()> {
+    let directory = payload_directory();
+    let store: Arc<dyn PayloadStore> = Arc::new(DirectoryPayloadStore::new(&directory));
+    let claim_check = ClaimCheck::new(store.clone(), 100);
+    let runner = TestGuard::builder(()).payload_store(store).build();
+    let conn = runner.connection_pool().get()?;
+
+    claim_check.enqueue(&conn, assert_length("a".repeat(1000), 1000))?;
+    claim_check.enqueue(&conn, assert_length("a".repeat(10), 10))?;
+
+    let data = background_jobs::table
+        .select(background_jobs::data)
+        .order(background_jobs::id)
+        .load::<serde_json::Value>(&conn)?;
+    let key = data[0]["$swirl_payload"]
+        .as_str()
+        .expect("payload was not offloaded");
+    assert!(directory.join(format!("{}.json", key)).exists());
+    assert_eq!("a".repeat(10), data[1]["text"]);
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert!(!directory.join(format!("{}.json", key)).exists());
+    Ok(())
+}
+
+#[test]
+fn jobs_whose_payload_is_missing_fail() -> Fallible<()> {
+    let directory = payload_directory();
+    let store: Arc<dyn PayloadStore> = Arc::new(DirectoryPayloadStore::new(&directory));
+    let claim_check = ClaimCheck::new(store.clone(), 0);
+    let runner = TestGuard::builder(()).payload_store(store.clone()).build();
+    let conn = runner.connection_pool().get()?;
+
+    claim_check.enqueue(&conn, assert_length("a".into(), 1))?;
+    let data = background_jobs::table
+        .select(background_jobs::data)
+        .first::<serde_json::Value>(&conn)?;
+    store
+        .delete(data["$swirl_payload"].as_str().unwrap())
+        .unwrap();
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(swirl::JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
@@ -2,14 +2,20 @@ use assert_matches::assert_matches;
 use diesel::prelude::*;
 use failure::Fallible;
 use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
+use swirl::lifecycle::LifecycleEvent;
+use swirl::notifier::JobFailure;
 use swirl::schema::*;
-use swirl::JobsFailed;
+use swirl::testing::jobs::*;
+use swirl::testing::Barrier;
+use swirl::{
+    Builder, ConfigError, ExpiredJobPolicy, Job, JobsFailed, MaintenancePolicy, PanicPolicy,
+    RunSummary, RunnerConfig,
+};
 
-use crate::dummy_jobs::*;
-use crate::sync::Barrier;
-use crate::test_guard::TestGuard;
+use crate::test_guard::{GuardBuilderExt, TestGuard};
 
 #[test]
 fn run_all_pending_jobs_returns_when_all_jobs_enqueued() -> Fallible<()> {
@@ -113,6 +119,56 @@ fn run_all_pending_jobs_errs_if_jobs_dont_start_in_timeout() -> Fallible<()> {
     Ok(())
 }
 
+#[swirl::background_job]
+fn countdown(pool: &dyn swirl::db::DieselPoolObj, n: i32) -> Result<(), swirl::PerformError> {
+    if n > 0 {
+        countdown(n - 1).enqueue(&*pool.get()?)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn jobs_enqueued_by_jobs_are_run_until_the_queue_is_empty() -> Fallible<()> {
+    let runner = TestGuard::builder(()).thread_count(2).build();
+    let conn = runner.connection_pool().get()?;
+    countdown(3).enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    let summary = runner.run_pending_jobs_until_empty()?;
+    assert_eq!(
+        RunSummary {
+            claimed: 5,
+            succeeded: 4,
+            failed: 1,
+            skipped: 0,
+            snoozed: 0,
+        },
+        summary
+    );
+    let pending = background_jobs::table
+        .filter(background_jobs::retries.eq(0))
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, pending);
+    Ok(())
+}
+
+#[test]
+fn running_until_empty_gives_up_on_queues_which_never_empty() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .backoff(swirl::Backoff::fixed(Duration::from_secs(0)))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+
+    let run_result = runner.run_pending_jobs_until_empty();
+    assert_matches!(
+        run_result,
+        Err(swirl::FetchError::QueueNotEmptied { rounds: 100 })
+    );
+    Ok(())
+}
+
 #[test]
 fn jobs_failing_to_load_doesnt_panic_threads() -> Fallible<()> {
     let runner = TestGuard::builder(())
@@ -139,3 +195,1898 @@ fn jobs_failing_to_load_doesnt_panic_threads() -> Fallible<()> {
     runner.check_for_failed_jobs()?;
     Ok(())
 }
+
+#[test]
+fn errors_updating_jobs_are_returned_instead_of_panicking_threads() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+
+    let run_result = conn.transaction::<_, diesel::result::Error, _>(|| {
+        // Recording the attempt times out until this transaction ends
+        diesel::sql_query("LOCK TABLE background_job_attempts IN EXCLUSIVE MODE").execute(&conn)?;
+        Ok(runner.run_pending_jobs_until_empty())
+    })?;
+    assert_matches!(run_result, Err(swirl::FetchError::FailedUpdatingJob(_)));
+
+    // The job's transaction was rolled back, so it is run again
+    assert_eq!(Ok(1), background_jobs::table.count().get_result(&conn));
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn jobs_can_be_registered_after_the_runner_is_built() -> Fallible<()> {
+    #[derive(swirl::Serialize, swirl::Deserialize)]
+    #[serde(crate = "swirl::serde")]
+    struct UnregisteredJob;
+
+    impl swirl::Job for UnregisteredJob {
+        type Environment = ();
+        const JOB_TYPE: &'static str = "unregistered_job";
+
+        fn perform(
+            self,
+            _: &Self::Environment,
+            _: &dyn swirl::db::DieselPoolObj,
+        ) -> Result<(), swirl::PerformError> {
+            Ok(())
+        }
+    }
+
+    let runner = TestGuard::dummy_runner();
+    runner.registry().register::<UnregisteredJob>();
+    let conn = runner.connection_pool().get()?;
+    UnregisteredJob.enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn jobs_are_given_the_environment_for_their_queue() -> Fallible<()> {
+    #[swirl::background_job]
+    fn default_queue_job(env: &String) -> Result<(), swirl::PerformError> {
+        assert_eq!(env, "default");
+        Ok(())
+    }
+
+    #[swirl::background_job(queue = "other")]
+    fn other_queue_job(env: &String) -> Result<(), swirl::PerformError> {
+        assert_eq!(env, "other");
+        Ok(())
+    }
+
+    let runner = TestGuard::builder(String::from("default"))
+        .queue_environment("other", String::from("other"))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    default_queue_job().enqueue(&conn)?;
+    other_queue_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn jobs_which_exceed_max_retries_are_marked_dead_and_reported() -> Fallible<()> {
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let failures2 = failures.clone();
+    let runner = TestGuard::builder(())
+        .max_retries(0)
+        .failure_notifier(move |failure: &JobFailure| {
+            failures2.lock().unwrap().push(failure.clone());
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    {
+        let failures = failures.lock().unwrap();
+        assert_eq!(1, failures.len());
+        assert_eq!("failure_job", failures[0].job_type);
+        assert_eq!("failed", failures[0].error);
+        assert_eq!(1, failures[0].retries);
+    }
+
+    // Make the job eligible for a retry. It should still not be run again.
+    diesel::update(background_jobs::table)
+        .set(background_jobs::last_retry.eq(diesel::dsl::sql("'1970-01-01'")))
+        .execute(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(1, failures.lock().unwrap().len());
+
+    let retries = background_jobs::table
+        .select(background_jobs::retries)
+        .first::<i32>(&conn)?;
+    assert_eq!(1, retries);
+    Ok(())
+}
+
+#[test]
+fn error_handlers_are_given_each_failed_attempt() -> Fallible<()> {
+    use swirl::notifier::JobError;
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors2 = errors.clone();
+    let runner = TestGuard::builder(())
+        .thread_count(1)
+        .max_retries(0)
+        .error_handler(move |error: &JobError<'_>| {
+            errors2.lock().unwrap().push((
+                error.job_type.to_string(),
+                error.message.to_string(),
+                error.panicked,
+                error.dead,
+            ));
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    panic_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(2, errors.len());
+    assert_eq!(
+        ("failure_job".into(), "failed".into(), false, true),
+        errors[0]
+    );
+    assert_eq!("panic_job", errors[1].0);
+    assert!(errors[1].2);
+    Ok(())
+}
+
+#[test]
+fn lifecycle_events_are_emitted_for_each_job() -> Fallible<()> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events2 = events.clone();
+    let runner = TestGuard::builder(())
+        .thread_count(1)
+        .max_retries(0)
+        .lifecycle_listener(move |event: &LifecycleEvent| {
+            let name = match event {
+                LifecycleEvent::Started { .. } => "started",
+                LifecycleEvent::Succeeded { .. } => "succeeded",
+                LifecycleEvent::Failed { .. } => "failed",
+                LifecycleEvent::Dead { .. } => "dead",
+                LifecycleEvent::Slow { .. } => "slow",
+                LifecycleEvent::Expired { .. } => "expired",
+                LifecycleEvent::Cancelled { .. } => "cancelled",
+                LifecycleEvent::Snoozed { .. } => "snoozed",
+            };
+            events2
+                .lock()
+                .unwrap()
+                .push((event.job().job_type.clone(), name));
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let expected = vec![
+        ("succeeding_job".to_string(), "started"),
+        ("succeeding_job".to_string(), "succeeded"),
+        ("failure_job".to_string(), "started"),
+        ("failure_job".to_string(), "failed"),
+        ("failure_job".to_string(), "dead"),
+    ];
+    assert_eq!(expected, *events.lock().unwrap());
+    Ok(())
+}
+
+#[test]
+fn middleware_is_called_around_each_job() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::Text;
+    use swirl::lifecycle::JobMetadata;
+    use swirl::middleware::{Middleware, SessionSettings};
+    use swirl::PerformError;
+
+    #[swirl::background_job]
+    fn check_tenant(conn: &PgConnection) -> Result<(), PerformError> {
+        let tenant = sql::<Text>("SELECT current_setting('swirl_test.tenant')")
+            .get_result::<String>(conn)?;
+        assert_eq!("acme", tenant);
+        Ok(())
+    }
+
+    struct Recorder(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Recorder {
+        fn before_perform(
+            &self,
+            job: &JobMetadata,
+            session: &mut SessionSettings,
+        ) -> Result<(), PerformError> {
+            session.set("swirl_test.tenant", "acme");
+            self.record(format!("before {}", job.job_type));
+            Ok(())
+        }
+
+        fn after_perform(&self, job: &JobMetadata, error: Option<&str>) {
+            self.record(format!("after {} {:?}", job.job_type, error));
+        }
+
+        fn on_failure(&self, job: &JobMetadata, error: &str, dead: bool) {
+            self.record(format!("failure {} {} {}", job.job_type, error, dead));
+        }
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) {
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", self.0, event));
+        }
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let runner = TestGuard::builder(())
+        .thread_count(1)
+        .connection_count(3)
+        .max_retries(0)
+        .middleware(Recorder("outer", events.clone()))
+        .middleware(Recorder("inner", events.clone()))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    check_tenant().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let expected = vec![
+        "outer: before check_tenant",
+        "inner: before check_tenant",
+        "inner: after check_tenant None",
+        "outer: after check_tenant None",
+        "outer: before failure_job",
+        "inner: before failure_job",
+        "inner: after failure_job Some(\"failed\")",
+        "outer: after failure_job Some(\"failed\")",
+        "inner: failure failure_job failed true",
+        "outer: failure failure_job failed true",
+    ];
+    assert_eq!(expected, *events.lock().unwrap());
+    Ok(())
+}
+
+#[test]
+fn middleware_can_stop_a_job_from_running() -> Fallible<()> {
+    use swirl::lifecycle::JobMetadata;
+    use swirl::middleware::{Middleware, SessionSettings};
+    use swirl::PerformError;
+
+    struct Refuse;
+
+    impl Middleware for Refuse {
+        fn before_perform(
+            &self,
+            _: &JobMetadata,
+            _: &mut SessionSettings,
+        ) -> Result<(), PerformError> {
+            Err("refused".into())
+        }
+    }
+
+    let runner = TestGuard::builder(()).middleware(Refuse).build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn drain_reports_jobs_which_are_still_running() -> Fallible<()> {
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let conn = runner.connection_pool().get()?;
+    barrier_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    let report = runner.drain(Duration::from_millis(100));
+
+    assert_eq!(1, report.interrupted.len());
+    assert_eq!("barrier_job", report.interrupted[0].job.job_type);
+    assert!(report.interrupted[0].elapsed >= Duration::from_millis(100));
+
+    barrier.wait();
+    assert!(runner.drain(Duration::from_secs(10)).is_clean());
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn runners_configured_with_queues_only_run_jobs_on_those_queues() -> Fallible<()> {
+    #[swirl::background_job(queue = "other")]
+    fn other_queue_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let config = RunnerConfig {
+        queues: Some(vec!["other".into()]),
+        ..RunnerConfig::default()
+    };
+    let runner = TestGuard::builder(()).config(&config).build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    other_queue_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let remaining_queues = background_jobs::table
+        .select(background_jobs::queue)
+        .load::<String>(&conn);
+    assert_eq!(Ok(vec!["default".to_string()]), remaining_queues);
+    Ok(())
+}
+
+#[test]
+fn queues_only_use_the_threads_they_are_given() -> Fallible<()> {
+    #[swirl::background_job(queue = "bulk")]
+    fn bulk_barrier_job(env: &Barrier) -> Result<(), swirl::PerformError> {
+        env.wait();
+        Ok(())
+    }
+
+    #[swirl::background_job(queue = "bulk", environment(Barrier))]
+    fn bulk_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let barrier = Barrier::new(3);
+    let runner = TestGuard::builder(barrier.clone())
+        .queue("bulk", 1)
+        .queue("default", 2)
+        .build();
+    let conn = runner.connection_pool().get()?;
+    bulk_barrier_job().enqueue(&conn)?;
+    bulk_job().enqueue(&conn)?;
+    barrier_job().enqueue(&conn)?;
+
+    // The second bulk job waits for the first, but the default queue still
+    // has a thread
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(2, summary.claimed);
+    barrier.wait();
+    runner.check_for_failed_jobs()?;
+
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(1, summary.claimed);
+    runner.check_for_failed_jobs()?;
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn job_types_only_run_as_many_jobs_at_once_as_their_max_concurrency() -> Fallible<()> {
+    #[swirl::background_job(max_concurrency = 1)]
+    fn rebuild_index(env: &Barrier) -> Result<(), swirl::PerformError> {
+        env.wait();
+        Ok(())
+    }
+
+    #[swirl::background_job(environment(Barrier))]
+    fn other_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    assert_eq!(Some(1), rebuild_index::Job::MAX_CONCURRENCY);
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let conn = runner.connection_pool().get()?;
+    rebuild_index().enqueue(&conn)?;
+    rebuild_index().enqueue(&conn)?;
+    other_job().enqueue(&conn)?;
+
+    // The second rebuild waits for the first, but other jobs still run
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(2, summary.claimed);
+    barrier.wait();
+    runner.check_for_failed_jobs()?;
+
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(1, summary.claimed);
+    barrier.wait();
+    runner.check_for_failed_jobs()?;
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn max_concurrency_is_shared_by_every_runner() -> Fallible<()> {
+    use swirl::Runner;
+
+    #[swirl::background_job(max_concurrency = 1)]
+    fn singleton_job(env: &Barrier) -> Result<(), swirl::PerformError> {
+        env.wait();
+        Ok(())
+    }
+
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let other_runner = Runner::builder(barrier.clone())
+        .connection_pool(runner.connection_pool().clone())
+        .build();
+    let conn = runner.connection_pool().get()?;
+    singleton_job().enqueue(&conn)?;
+    singleton_job().enqueue(&conn)?;
+
+    assert_eq!(1, runner.run_all_pending_jobs()?.claimed);
+    assert_eq!(0, other_runner.run_all_pending_jobs()?.claimed);
+    barrier.wait();
+    runner.check_for_failed_jobs()?;
+
+    assert_eq!(1, other_runner.run_all_pending_jobs()?.claimed);
+    barrier.wait();
+    other_runner.check_for_failed_jobs()?;
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn queues_can_be_referred_to_by_type() -> Fallible<()> {
+    swirl::queue!(Critical, Bulk = "bulk");
+
+    #[swirl::background_job(queue(Critical))]
+    fn critical_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    #[swirl::background_job(queue(Bulk))]
+    fn bulk_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    assert_eq!("Critical", Critical::NAME);
+    assert_eq!("bulk", String::from(Bulk));
+
+    let runner = TestGuard::builder(()).queues(vec![Critical]).build();
+    let conn = runner.connection_pool().get()?;
+    critical_job().enqueue(&conn)?;
+    bulk_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let remaining_queues = background_jobs::table
+        .select(background_jobs::queue)
+        .load::<String>(&conn);
+    assert_eq!(Ok(vec![Bulk::NAME.to_string()]), remaining_queues);
+    Ok(())
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let config = RunnerConfig {
+        thread_count: Some(0),
+        ..RunnerConfig::default()
+    };
+    let error = Builder::from_config((), &config).err().unwrap();
+
+    assert_matches!(
+        error,
+        ConfigError::Invalid {
+            field: "thread_count",
+            ..
+        }
+    );
+    assert_eq!(
+        "Invalid runner config: `thread_count` must be at least 1",
+        error.to_string()
+    );
+}
+
+#[test]
+fn configs_can_be_overridden_by_environment_variables() {
+    use std::env;
+
+    let file_config = RunnerConfig {
+        thread_count: Some(2),
+        max_retries: Some(3),
+        ..RunnerConfig::default()
+    };
+    env::set_var("SWIRL_THREAD_COUNT", "8");
+    env::set_var("SWIRL_POLL_INTERVAL", "2s");
+    env::set_var("SWIRL_QUEUES", "default, mailers");
+    let config = file_config.clone().with_env_overrides();
+    env::set_var("SWIRL_THREAD_COUNT", "eight");
+    let invalid = RunnerConfig::from_env();
+    for var in &["SWIRL_THREAD_COUNT", "SWIRL_POLL_INTERVAL", "SWIRL_QUEUES"] {
+        env::remove_var(var);
+    }
+
+    let expected = RunnerConfig {
+        thread_count: Some(8),
+        poll_interval_ms: Some(2000),
+        queues: Some(vec!["default".into(), "mailers".into()]),
+        max_retries: Some(3),
+        job_start_timeout_ms: None,
+    };
+    assert_eq!(expected, config.unwrap());
+    assert_eq!(
+        "Invalid runner config: `SWIRL_THREAD_COUNT` must be a whole number, got `eight`",
+        invalid.unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn connections_checked_out_by_the_runner_are_customized() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+
+    #[swirl::background_job]
+    fn check_application_name(conn: &PgConnection) -> Result<(), swirl::PerformError> {
+        let workers = sql::<BigInt>(
+            "SELECT COUNT(*) FROM pg_stat_activity WHERE application_name = 'swirl-test-worker'",
+        )
+        .get_result::<i64>(conn)?;
+        assert!(workers >= 1, "no connections were customized");
+        Ok(())
+    }
+
+    let runner = TestGuard::builder(())
+        .connection_customizer(|conn: &PgConnection| {
+            diesel::sql_query("SET application_name = 'swirl-test-worker'")
+                .execute(conn)
+                .map(|_| ())
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    check_application_name().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn errors_customizing_connections_are_reported() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .connection_customizer(|_: &PgConnection| Err(diesel::result::Error::NotFound))
+        .build();
+
+    let run_result = runner.run_all_pending_jobs();
+    assert_matches!(run_result, Err(swirl::FetchError::FailedLoadingJob(_)));
+    Ok(())
+}
+
+#[test]
+fn connections_are_named_after_the_job_using_them() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{BigInt, Text};
+
+    #[swirl::background_job]
+    fn check_job_application_name(conn: &PgConnection) -> Result<(), swirl::PerformError> {
+        let name =
+            sql::<Text>("SELECT current_setting('application_name')").get_result::<String>(conn)?;
+        assert!(
+            name.starts_with("swirl:check_job_application_name:"),
+            "{}",
+            name
+        );
+        let tagged = sql::<BigInt>(&format!(
+            "SELECT COUNT(*) FROM pg_stat_activity WHERE application_name = '{}'",
+            name
+        ))
+        .get_result::<i64>(conn)?;
+        // The connection holding the job's lock, and the one given to the job
+        assert_eq!(2, tagged);
+        Ok(())
+    }
+
+    let runner = TestGuard::builder(()).job_application_names(true).build();
+    let conn = runner.connection_pool().get()?;
+    check_job_application_name().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let still_tagged = sql::<BigInt>(
+        "SELECT COUNT(*) FROM pg_stat_activity WHERE application_name LIKE 'swirl:%'",
+    )
+    .get_result::<i64>(&conn)?;
+    assert_eq!(0, still_tagged);
+    Ok(())
+}
+
+#[test]
+fn queries_made_by_the_runner_are_reported_to_the_query_hook() -> Fallible<()> {
+    use swirl::query_hook::StorageQuery;
+
+    let queries = Arc::new(Mutex::new(Vec::new()));
+    let queries2 = queries.clone();
+    let runner = TestGuard::builder(())
+        .thread_count(1)
+        .query_hook(move |query, _| queries2.lock().unwrap().push(query))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let queries = queries.lock().unwrap();
+    let count = |query| queries.iter().filter(|&&q| q == query).count();
+    assert!(count(StorageQuery::Claim) >= 2);
+    assert_eq!(1, count(StorageQuery::Delete));
+    assert_eq!(1, count(StorageQuery::UpdateFailed));
+    Ok(())
+}
+
+#[test]
+fn queries_which_fail_to_serialize_are_retried() -> Fallible<()> {
+    use diesel::connection::SimpleConnection;
+    use diesel::result::Error as DieselError;
+    use swirl::query_hook::{QueryHook, StorageQuery};
+
+    struct RecordRetries(Arc<Mutex<Vec<(StorageQuery, u32)>>>);
+
+    impl QueryHook for RecordRetries {
+        fn on_query(&self, _: StorageQuery, _: Duration) {}
+
+        fn on_retry(&self, query: StorageQuery, attempt: u32, _: &DieselError) {
+            self.0.lock().unwrap().push((query, attempt));
+        }
+    }
+
+    /// Makes the first `n` deletes from the queue fail with a serialization
+    /// failure, until it is dropped
+    struct FailDeletes<'a>(&'a PgConnection);
+
+    impl<'a> FailDeletes<'a> {
+        fn install(conn: &'a PgConnection, n: i64) -> QueryResult<Self> {
+            conn.batch_execute(&format!(
+                "CREATE SEQUENCE swirl_test_failed_deletes;
+                 CREATE FUNCTION swirl_test_fail_delete() RETURNS trigger AS $$
+                 BEGIN
+                     IF nextval('swirl_test_failed_deletes') <= {} THEN
+                         RAISE EXCEPTION 'injected' USING ERRCODE = 'serialization_failure';
+                     END IF;
+                     RETURN OLD;
+                 END
+                 $$ LANGUAGE plpgsql;
+                 CREATE TRIGGER swirl_test_fail_delete BEFORE DELETE ON background_jobs
+                     FOR EACH ROW EXECUTE PROCEDURE swirl_test_fail_delete();",
+                n
+            ))?;
+            Ok(FailDeletes(conn))
+        }
+    }
+
+    impl Drop for FailDeletes<'_> {
+        fn drop(&mut self) {
+            self.0
+                .batch_execute(
+                    "DROP TRIGGER swirl_test_fail_delete ON background_jobs;
+                     DROP FUNCTION swirl_test_fail_delete();
+                     DROP SEQUENCE swirl_test_failed_deletes;",
+                )
+                .unwrap();
+        }
+    }
+
+    let retries = Arc::new(Mutex::new(Vec::new()));
+    let runner = TestGuard::builder(())
+        .query_hook(RecordRetries(retries.clone()))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let _fail_deletes = FailDeletes::install(&conn, 2)?;
+    succeeding_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    assert_eq!(
+        vec![(StorageQuery::Delete, 1), (StorageQuery::Delete, 2)],
+        *retries.lock().unwrap()
+    );
+    let remaining = background_jobs::table.count().get_result(&conn);
+    assert_eq!(Ok(0), remaining);
+    Ok(())
+}
+
+#[test]
+fn slow_jobs_are_reported_while_they_are_still_running() -> Fallible<()> {
+    let barrier = Barrier::new(2);
+    let (tx, rx) = sync_channel(1);
+    let runner = TestGuard::builder(barrier.clone())
+        .slow_job_threshold("barrier_job", Duration::from_millis(50))
+        .on_slow_job(move |job, elapsed| {
+            tx.send((job.job_type.clone(), elapsed)).unwrap();
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    barrier_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    let (job_type, elapsed) = rx.recv_timeout(Duration::from_secs(5))?;
+    assert_eq!("barrier_job", job_type);
+    assert!(elapsed >= Duration::from_millis(50));
+
+    barrier.wait();
+    assert!(runner.drain(Duration::from_secs(10)).is_clean());
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn jobs_claimed_after_their_deadline_are_dropped_by_default() -> Fallible<()> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events2 = events.clone();
+    let runner = TestGuard::builder(())
+        .lifecycle_listener(move |event: &LifecycleEvent| {
+            events2.lock().unwrap().push(event.clone());
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    failure_job().enqueue_with_deadline(&conn, yesterday)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let remaining = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(0, remaining);
+    let events = events.lock().unwrap();
+    assert_eq!(1, events.len());
+    assert_matches!(events[0], LifecycleEvent::Expired { .. });
+    Ok(())
+}
+
+#[test]
+fn expired_jobs_can_be_dead_lettered() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .on_expired(ExpiredJobPolicy::DeadLetter)
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    succeeding_job().enqueue_with_deadline(&conn, yesterday)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let dead = background_jobs::table
+        .filter(background_jobs::dead_at.is_not_null())
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(1, dead);
+    let error = background_job_failures::table
+        .select(background_job_failures::error)
+        .first::<String>(&conn)?;
+    assert_eq!("Job was not run before its deadline", error);
+    Ok(())
+}
+
+#[test]
+fn panicking_jobs_can_be_dead_lettered() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .on_panic(PanicPolicy::DeadLetter)
+        .build();
+    let conn = runner.connection_pool().get()?;
+    panic_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+
+    let dead = background_jobs::table
+        .filter(background_jobs::dead_at.is_not_null())
+        .select(background_jobs::job_type)
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["panic_job"], dead);
+    let error = background_job_failures::table
+        .filter(background_job_failures::job_type.eq("panic_job"))
+        .select(background_job_failures::error)
+        .first::<String>(&conn)?;
+    assert!(error.starts_with("job panicked at "), "{}", error);
+    Ok(())
+}
+
+#[test]
+fn expired_jobs_can_be_run_anyway() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .on_expired(ExpiredJobPolicy::Run)
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    failure_job().enqueue_with_deadline(&conn, yesterday)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn jobs_claimed_before_their_deadline_are_run() -> Fallible<()> {
+    let runner = TestGuard::runner(());
+    let conn = runner.connection_pool().get()?;
+    let tomorrow = chrono::Utc::now() + chrono::Duration::days(1);
+    failure_job().enqueue_with_deadline(&conn, tomorrow)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn jobs_older_than_their_ttl_are_swept() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .job_ttl("succeeding_job", Duration::from_secs(60 * 60))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    diesel::sql_query(
+        "UPDATE background_jobs SET created_at = NOW() - INTERVAL '2 hours' \
+         WHERE id = (SELECT MIN(id) FROM background_jobs)",
+    )
+    .execute(&conn)?;
+    diesel::sql_query(
+        "UPDATE background_jobs SET created_at = NOW() - INTERVAL '2 hours' \
+         WHERE job_type = 'failure_job'",
+    )
+    .execute(&conn)?;
+
+    assert_eq!(1, runner.sweep_stale_jobs()?);
+    assert_eq!(0, runner.sweep_stale_jobs()?);
+
+    let dead = background_jobs::table
+        .filter(background_jobs::dead_at.is_not_null())
+        .select(background_jobs::job_type)
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["succeeding_job"], dead);
+    Ok(())
+}
+
+#[test]
+fn jobs_are_marked_as_dead_once_the_retry_budget_is_spent() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .retry_budget("failure_job", 1)
+        .build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(3)), runner.check_for_failed_jobs());
+
+    let dead = background_jobs::table
+        .filter(background_jobs::dead_at.is_not_null())
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(2, dead);
+    Ok(())
+}
+
+#[test]
+fn jobs_on_paused_queues_are_not_run_until_they_are_resumed() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let handle = runner.handle();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+
+    handle.pause_queue("default");
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    handle.resume_queue("default");
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    Ok(())
+}
+
+#[test]
+fn run_polls_when_asked_to_and_stops_when_shut_down() -> Fallible<()> {
+    let (tx, rx) = sync_channel(1);
+    let runner = TestGuard::builder(())
+        .config(&RunnerConfig {
+            poll_interval_ms: Some(60 * 60 * 1000),
+            ..RunnerConfig::default()
+        })
+        .lifecycle_listener(move |event: &LifecycleEvent| {
+            if let LifecycleEvent::Succeeded { .. } = event {
+                tx.send(()).unwrap();
+            }
+        })
+        .build();
+    let handle = runner.handle();
+    let conn = runner.connection_pool().get()?;
+
+    let report = thread::scope(|s| {
+        let run = s.spawn(|| runner.run());
+        // Give the runner time to find the queue empty and start waiting
+        thread::sleep(Duration::from_millis(100));
+        succeeding_job().enqueue(&conn).unwrap();
+        handle.poll_now();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        handle.shutdown(Duration::from_secs(5));
+        run.join().unwrap()
+    })?;
+    assert!(report.is_clean());
+    Ok(())
+}
+
+#[test]
+fn runners_shut_down_on_sigterm_after_running_jobs_finish() -> Fallible<()> {
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::builder(barrier.clone())
+        .shutdown_on_signals(Duration::from_secs(5))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    barrier_job().enqueue(&conn)?;
+
+    let report = thread::scope(|s| {
+        let run = s.spawn(|| runner.run());
+        // Give the runner time to start the job
+        thread::sleep(Duration::from_millis(100));
+        unsafe { libc::raise(libc::SIGTERM) };
+        // The runner waits for the job instead of returning
+        thread::sleep(Duration::from_millis(300));
+        assert!(!run.is_finished());
+        barrier.wait();
+        run.join().unwrap()
+    })?;
+    assert!(report.is_clean());
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn runners_listening_for_jobs_start_them_without_polling() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+
+    let (tx, rx) = sync_channel(1);
+    let runner = TestGuard::builder(())
+        .config(&RunnerConfig {
+            poll_interval_ms: Some(60 * 60 * 1000),
+            ..RunnerConfig::default()
+        })
+        .listen_for_jobs(swirl::testing::database_url())
+        .lifecycle_listener(move |event: &LifecycleEvent| {
+            if let LifecycleEvent::Succeeded { .. } = event {
+                tx.send(()).unwrap();
+            }
+        })
+        .build();
+    let handle = runner.handle();
+    let conn = runner.connection_pool().get()?;
+
+    let (started, report) = thread::scope(|s| {
+        let run = s.spawn(|| runner.run());
+        let listening = || {
+            sql::<BigInt>(
+                "SELECT COUNT(*) FROM pg_stat_activity \
+                 WHERE application_name = 'swirl-listener' AND query = 'LISTEN swirl_jobs'",
+            )
+            .get_result::<i64>(&conn)
+            .unwrap()
+        };
+        for _ in 0..100 {
+            if listening() > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        // Let the poll after connecting find the queue empty
+        thread::sleep(Duration::from_millis(100));
+
+        succeeding_job().enqueue(&conn).unwrap();
+        let started = rx.recv_timeout(Duration::from_secs(5));
+
+        handle.shutdown(Duration::from_secs(5));
+        (started, run.join().unwrap())
+    });
+    assert!(started.is_ok(), "the job was not run until the next poll");
+    assert!(report?.is_clean());
+    Ok(())
+}
+
+#[test]
+fn commands_are_acknowledged_once_the_runner_applies_them() -> Fallible<()> {
+    use std::sync::mpsc::RecvTimeoutError;
+    use swirl::Command;
+
+    let runner = TestGuard::dummy_runner();
+    let handle = runner.handle();
+
+    let ack = handle.send(Command::SetThreadCount(2));
+    assert_eq!(
+        Err(RecvTimeoutError::Timeout),
+        ack.wait(Duration::from_millis(10))
+    );
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Ok(()), ack.wait(Duration::from_secs(0)));
+
+    let ack = handle.pause_queue("default");
+    drop(runner);
+    assert_eq!(
+        Err(RecvTimeoutError::Disconnected),
+        ack.wait(Duration::from_secs(1))
+    );
+    Ok(())
+}
+
+#[test]
+fn run_all_pending_jobs_reports_the_jobs_it_handled() -> Fallible<()> {
+    let runner = TestGuard::builder(()).thread_count(1).build();
+    let conn = runner.connection_pool().get()?;
+    let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    succeeding_job().enqueue_with_deadline(&conn, yesterday)?;
+
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(4, summary.claimed);
+    // With a single thread, the queue is only found to be empty once every
+    // other job has finished
+    assert_eq!(0, summary.running());
+    assert_eq!(2, summary.succeeded);
+    assert_eq!(1, summary.failed);
+    assert_eq!(1, summary.skipped);
+
+    assert_eq!(RunSummary::default(), runner.run_all_pending_jobs()?);
+    Ok(())
+}
+
+#[test]
+fn runners_count_the_jobs_they_handle_by_job_type() -> Fallible<()> {
+    let runner = TestGuard::builder(()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    assert!(runner.counters().is_empty());
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let counters = runner.counters();
+    assert_eq!(2, counters.len());
+    let succeeding = counters["succeeding_job"];
+    assert_eq!(2, succeeding.started);
+    assert_eq!(2, succeeding.succeeded);
+    assert_eq!(0, succeeding.failed);
+    let failing = counters["failure_job"];
+    assert_eq!(1, failing.started);
+    assert_eq!(0, failing.succeeded);
+    assert_eq!(1, failing.failed);
+    assert_eq!(1, failing.dead);
+    Ok(())
+}
+
+#[test]
+fn runners_time_how_long_they_spend_looking_for_jobs() -> Fallible<()> {
+    let runner = TestGuard::builder(()).thread_count(1).build();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+    assert_eq!(None, runner.loop_timings().polls.mean());
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+
+    let timings = runner.loop_timings();
+    assert_eq!(1, timings.polls.count);
+    assert!(timings.polls.mean() <= Some(timings.polls.max));
+    // Two jobs are claimed, and one more claim finds the queue empty
+    assert_eq!(3, timings.claims.count);
+    assert_eq!(3, timings.connection_waits.count);
+    assert!(timings.claims.max <= timings.polls.total);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_use_the_configured_stack_size() -> Fallible<()> {
+    #[swirl::background_job]
+    fn stack_hungry_job() -> Result<(), swirl::PerformError> {
+        // Larger than the default stack of 2MiB
+        let buffer = std::hint::black_box([1u8; 8 << 20]);
+        assert_eq!(8 << 20, buffer.iter().map(|&b| b as usize).sum::<usize>());
+        Ok(())
+    }
+
+    let runner = TestGuard::builder(()).worker_stack_size(32 << 20).build();
+    let conn = runner.connection_pool().get()?;
+    stack_hungry_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn runner_groups_run_and_shut_down_their_runners_together() -> Fallible<()> {
+    use swirl::{Runner, RunnerGroup};
+
+    #[swirl::background_job(queue = "other")]
+    fn other_queue_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let (tx, rx) = sync_channel(2);
+    let tx2 = tx.clone();
+    let guard = TestGuard::dummy_runner();
+    let group = RunnerGroup::new(guard.connection_pool().clone())
+        .runner(
+            Runner::builder(())
+                .queues(vec!["default"])
+                .thread_count(1)
+                .lifecycle_listener(move |event: &LifecycleEvent| {
+                    if let LifecycleEvent::Succeeded { .. } = event {
+                        tx.send(()).unwrap();
+                    }
+                }),
+        )
+        .runner(
+            Runner::builder(())
+                .queues(vec!["other"])
+                .thread_count(2)
+                .lifecycle_listener(move |event: &LifecycleEvent| {
+                    if let LifecycleEvent::Succeeded { .. } = event {
+                        tx2.send(()).unwrap();
+                    }
+                }),
+        );
+    let handle = group.handle();
+    let conn = guard.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+    other_queue_job().enqueue(&conn)?;
+
+    let report = thread::scope(|s| {
+        let run = s.spawn(|| group.run());
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let ack = handle.shutdown(Duration::from_secs(5));
+        let report = run.join().unwrap();
+        assert_eq!(Ok(()), ack.wait(Duration::from_secs(0)));
+        report
+    });
+    assert!(report.is_clean());
+
+    let runners = group.runners();
+    assert_eq!(1, runners[0].counters()["succeeding_job"].succeeded);
+    assert!(!runners[0].counters().contains_key("other_queue_job"));
+    assert_eq!(1, runners[1].counters()["other_queue_job"].succeeded);
+    Ok(())
+}
+
+#[test]
+fn operations_which_completed_are_skipped_when_a_job_is_retried() -> Fallible<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use swirl::{JobContext, PerformError};
+
+    #[swirl::background_job]
+    fn charge_order(
+        charges: &Arc<AtomicUsize>,
+        conn: &PgConnection,
+        order_id: i64,
+    ) -> Result<(), PerformError> {
+        let ctx = JobContext::current().ok_or("not run by a runner")?;
+        assert_eq!("charge_order", ctx.job_type());
+        ctx.exactly_once(conn, &format!("charge_order:{}", order_id), || {
+            charges.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, PerformError>(())
+        })?;
+        Err("failed to send the receipt".into())
+    }
+
+    let charges = Arc::new(AtomicUsize::new(0));
+    let runner = TestGuard::runner(charges.clone());
+    let conn = runner.connection_pool().get()?;
+    charge_order(42).enqueue(&conn)?;
+    assert_eq!(None, JobContext::current());
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(1, charges.load(Ordering::SeqCst));
+
+    diesel::update(background_jobs::table)
+        .set(background_jobs::last_retry.eq(diesel::dsl::sql("'1970-01-01'")))
+        .execute(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(1, charges.load(Ordering::SeqCst));
+
+    let (key, job_id) = background_job_ledger::table
+        .select((background_job_ledger::key, background_job_ledger::job_id))
+        .first::<(String, i64)>(&conn)?;
+    let id = background_jobs::table
+        .select(background_jobs::id)
+        .first::<i64>(&conn)?;
+    assert_eq!("charge_order:42", key);
+    assert_eq!(id, job_id);
+    Ok(())
+}
+
+#[test]
+fn operations_which_fail_are_not_recorded() -> Fallible<()> {
+    use swirl::JobContext;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let ctx = JobContext::new(1, "manual".into());
+
+    let failed = ctx.exactly_once(&conn, "key", || {
+        Err::<(), _>(diesel::result::Error::NotFound)
+    });
+    assert!(failed.is_err());
+    assert_eq!(
+        Ok(Some(1)),
+        ctx.exactly_once::<_, diesel::result::Error, _>(&conn, "key", || Ok(1))
+    );
+    assert_eq!(
+        Ok(None),
+        ctx.exactly_once::<i32, diesel::result::Error, _>(&conn, "key", || Ok(2))
+    );
+
+    let current = ctx.enter(|| JobContext::current().map(|ctx| ctx.job_id()));
+    assert_eq!(Some(1), current);
+    assert_eq!(None, JobContext::current());
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_are_retried_after_their_backoff() -> Fallible<()> {
+    use chrono::{DateTime, Duration as ChronoDuration, Utc};
+    use swirl::{Backoff, PerformError};
+
+    const IMMEDIATELY: Backoff = Backoff::fixed(Duration::from_secs(0));
+
+    #[swirl::background_job(backoff(IMMEDIATELY))]
+    fn flaky_job() -> Result<(), PerformError> {
+        Err("flaky".into())
+    }
+
+    let runner = TestGuard::builder(())
+        .max_retries(2)
+        .backoff(Backoff::fixed(Duration::from_secs(3600)).jitter(0.5))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    flaky_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    let started = Utc::now();
+
+    // The flaky job can be retried right away, so it is run until it is dead
+    for _ in 0..3 {
+        runner.run_all_pending_jobs()?;
+        assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+    }
+
+    let (retries, dead_at) = background_jobs::table
+        .select((background_jobs::retries, background_jobs::dead_at))
+        .filter(background_jobs::job_type.eq("flaky_job"))
+        .first::<(i32, Option<DateTime<Utc>>)>(&conn)?;
+    assert_eq!(3, retries);
+    assert!(dead_at.is_some());
+
+    let (retries, retry_at) = background_jobs::table
+        .select((background_jobs::retries, background_jobs::retry_at))
+        .filter(background_jobs::job_type.eq("failure_job"))
+        .first::<(i32, Option<DateTime<Utc>>)>(&conn)?;
+    let retry_at = retry_at.expect("failure_job should have a retry scheduled");
+    assert_eq!(1, retries);
+    assert!(retry_at >= started + ChronoDuration::minutes(29));
+    assert!(retry_at <= Utc::now() + ChronoDuration::minutes(60));
+
+    // Resetting the last retry no longer makes the job eligible
+    diesel::update(background_jobs::table)
+        .set(background_jobs::last_retry.eq(diesel::dsl::sql("'1970-01-01'")))
+        .execute(&conn)?;
+    assert_eq!(0, runner.run_all_pending_jobs()?.claimed);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_choose_how_their_errors_are_handled() -> Fallible<()> {
+    use chrono::{DateTime, Duration as ChronoDuration, Utc};
+    use swirl::PerformError;
+
+    #[swirl::background_job]
+    fn rate_limited_job() -> Result<(), PerformError> {
+        Err(PerformError::retry_after(
+            "rate limited",
+            Duration::from_secs(600),
+        ))
+    }
+
+    #[swirl::background_job]
+    fn fatal_job() -> Result<(), PerformError> {
+        Err(PerformError::fatal("the account was deleted"))
+    }
+
+    #[swirl::background_job]
+    fn snoozing_job() -> Result<(), PerformError> {
+        Err(PerformError::snooze(Duration::from_secs(60)))
+    }
+
+    let runner = TestGuard::builder(()).max_retries(5).build();
+    let conn = runner.connection_pool().get()?;
+    rate_limited_job().enqueue(&conn)?;
+    fatal_job().enqueue(&conn)?;
+    snoozing_job().enqueue(&conn)?;
+    let started = Utc::now();
+
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+    assert_eq!(2, summary.failed);
+    assert_eq!(1, summary.snoozed);
+
+    let jobs = background_jobs::table
+        .select((
+            background_jobs::job_type,
+            background_jobs::retries,
+            background_jobs::retry_at,
+            background_jobs::dead_at.is_not_null(),
+        ))
+        .order(background_jobs::id)
+        .load::<(String, i32, Option<DateTime<Utc>>, bool)>(&conn)?;
+    let (_, retries, retry_at, dead) = &jobs[0];
+    assert_eq!((1, false), (*retries, *dead));
+    assert!(retry_at.unwrap() >= started + ChronoDuration::minutes(10));
+    assert_eq!(("fatal_job", 1, true), (&*jobs[1].0, jobs[1].1, jobs[1].3));
+    let (_, retries, retry_at, dead) = &jobs[2];
+    assert_eq!((0, false), (*retries, *dead));
+    assert!(retry_at.unwrap() >= started + ChronoDuration::minutes(1));
+    assert!(retry_at.unwrap() < started + ChronoDuration::minutes(2));
+    Ok(())
+}
+
+#[test]
+fn rate_limited_jobs_are_skipped_once_their_limit_is_reached() -> Fallible<()> {
+    use swirl::{PerformError, RateLimit};
+
+    const TWICE_AN_HOUR: RateLimit = RateLimit::per_hour(2);
+
+    #[swirl::background_job(rate_limit(TWICE_AN_HOUR))]
+    fn call_api() -> Result<(), PerformError> {
+        Ok(())
+    }
+
+    assert_eq!(Some(TWICE_AN_HOUR), call_api::Job::RATE_LIMIT);
+    let runner = TestGuard::runner(());
+    let conn = runner.connection_pool().get()?;
+    for _ in 0..4 {
+        call_api().enqueue(&conn)?;
+    }
+    succeeding_job().enqueue(&conn)?;
+
+    let summary = runner.run_pending_jobs_until_empty()?;
+    assert_eq!(3, summary.succeeded);
+    let remaining = background_jobs::table
+        .select(background_jobs::job_type)
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["call_api"; 2], remaining);
+    Ok(())
+}
+
+#[test]
+fn backoff_doubles_the_delay_up_to_the_maximum() {
+    use swirl::Backoff;
+
+    let backoff = Backoff::exponential(Duration::from_secs(1), Duration::from_secs(60));
+    assert_eq!(Duration::from_secs(1), backoff.delay(0));
+    assert_eq!(Duration::from_secs(1), backoff.delay(1));
+    assert_eq!(Duration::from_secs(2), backoff.delay(2));
+    assert_eq!(Duration::from_secs(32), backoff.delay(6));
+    assert_eq!(Duration::from_secs(60), backoff.delay(7));
+    assert_eq!(Duration::from_secs(60), backoff.delay(1000));
+
+    let backoff = Backoff::fixed(Duration::from_secs(5));
+    assert_eq!(Duration::from_secs(5), backoff.delay(10));
+}
+
+#[test]
+fn interrupted_jobs_resume_from_their_last_checkpoint() -> Fallible<()> {
+    use swirl::{JobContext, PerformError};
+
+    #[swirl::background_job]
+    fn process_items(
+        processed: &Arc<Mutex<Vec<i64>>>,
+        conn: &PgConnection,
+        fail_at: i64,
+    ) -> Result<(), PerformError> {
+        let ctx = JobContext::current().ok_or("not run by a runner")?;
+        let start = ctx.checkpoint::<i64>(conn)?.unwrap_or(0);
+        for item in start..5 {
+            if item == fail_at && start == 0 {
+                return Err("interrupted".into());
+            }
+            processed.lock().unwrap().push(item);
+            ctx.save_checkpoint(conn, &(item + 1))?;
+        }
+        Ok(())
+    }
+
+    let processed = Arc::new(Mutex::new(Vec::<i64>::new()));
+    let runner = TestGuard::runner(processed.clone());
+    let conn = runner.connection_pool().get()?;
+    process_items(3).enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(vec![0, 1, 2], *processed.lock().unwrap());
+    let checkpoint = background_job_checkpoints::table
+        .select(background_job_checkpoints::state)
+        .first::<serde_json::Value>(&conn)?;
+    assert_eq!(serde_json::json!(3), checkpoint);
+
+    diesel::update(background_jobs::table)
+        .set(background_jobs::last_retry.eq(diesel::dsl::sql("'1970-01-01'")))
+        .execute(&conn)?;
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(vec![0, 1, 2, 3, 4], *processed.lock().unwrap());
+
+    // Checkpoints are removed once the job succeeds
+    let checkpoints = background_job_checkpoints::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, checkpoints);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_see_that_the_runner_is_shutting_down() -> Fallible<()> {
+    use swirl::{JobContext, PerformError};
+
+    #[swirl::background_job]
+    fn wait_for_shutdown(barrier: &Barrier, conn: &PgConnection) -> Result<(), PerformError> {
+        let ctx = JobContext::current().ok_or("not run by a runner")?;
+        assert!(!ctx.is_shutting_down());
+        barrier.wait();
+        for _ in 0..500 {
+            if ctx.is_shutting_down() {
+                ctx.save_checkpoint(conn, &"stopped early")?;
+                return Err("interrupted by shutdown".into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Err("the runner was never shut down".into())
+    }
+
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let conn = runner.connection_pool().get()?;
+    wait_for_shutdown().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    barrier.wait();
+    runner.handle().shutdown(Duration::from_secs(5));
+    // Commands are applied when the runner next looks for jobs
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let checkpoint = background_job_checkpoints::table
+        .select(background_job_checkpoints::state)
+        .first::<serde_json::Value>(&conn)?;
+    assert_eq!("stopped early", checkpoint);
+    Ok(())
+}
+
+#[test]
+fn running_jobs_can_be_cancelled() -> Fallible<()> {
+    use swirl::{Cancellation, JobContext, PerformError};
+
+    #[swirl::background_job]
+    fn wait_for_cancellation(barrier: &Barrier, conn: &PgConnection) -> Result<(), PerformError> {
+        let ctx = JobContext::current().ok_or("not run by a runner")?;
+        assert!(!ctx.is_cancelled(conn)?);
+        barrier.wait();
+        for _ in 0..500 {
+            if ctx.is_cancelled(conn)? {
+                return Err("cancelled".into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Err("the job was never cancelled".into())
+    }
+
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let conn = runner.connection_pool().get()?;
+    let job_id = wait_for_cancellation().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    barrier.wait();
+    assert_eq!(Cancellation::Requested, swirl::cancel(&conn, job_id)?);
+    runner.check_for_failed_jobs()?;
+
+    let remaining = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(0, remaining);
+    let cancellations = background_job_cancellations::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, cancellations);
+    assert_eq!(Cancellation::NotFound, swirl::cancel(&conn, job_id)?);
+    Ok(())
+}
+
+#[test]
+fn delayed_jobs_are_not_run_until_their_time_has_come() -> Fallible<()> {
+    use chrono::{Duration as ChronoDuration, Utc};
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue_in(&conn, Duration::from_secs(24 * 60 * 60))?;
+    failure_job().enqueue_at(&conn, Utc::now() + ChronoDuration::hours(1))?;
+    succeeding_job().enqueue_at(&conn, Utc::now() - ChronoDuration::seconds(1))?;
+
+    let summary = runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(1, summary.claimed);
+    let pending = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(2, pending);
+
+    diesel::update(background_jobs::table)
+        .set(background_jobs::retry_at.eq(Utc::now() - ChronoDuration::seconds(1)))
+        .execute(&conn)?;
+    let summary = runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert_eq!(2, summary.claimed);
+    Ok(())
+}
+
+#[swirl::background_job]
+fn double(ctx: &swirl::JobContext, value: i32) -> Result<(), swirl::PerformError> {
+    ctx.set_result(&(value * 2))?;
+    if value < 0 {
+        return Err("negative".into());
+    }
+    Ok(())
+}
+
+#[test]
+fn results_of_successful_jobs_can_be_looked_up() -> Fallible<()> {
+    let runner = TestGuard::runner(());
+    let conn = runner.connection_pool().get()?;
+    let succeeded = double(21).enqueue(&conn)?;
+    let failed = double(-1).enqueue(&conn)?;
+    assert_eq!(None, swirl::job_result::<i32>(&conn, succeeded)?);
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    assert_eq!(Some(42), swirl::job_result::<i32>(&conn, succeeded)?);
+    assert_eq!(None, swirl::job_result::<i32>(&conn, failed)?);
+    assert_matches!(
+        swirl::job_result::<String>(&conn, succeeded),
+        Err(diesel::result::Error::DeserializationError(_))
+    );
+    Ok(())
+}
+
+#[test]
+fn job_results_expire_after_the_result_ttl() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .result_ttl(Duration::from_secs(0))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let first = double(1).enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(None, swirl::job_result::<i32>(&conn, first)?);
+
+    // Expired results are deleted as other jobs complete
+    double(2).enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    let job_ids = background_job_results::table
+        .select(background_job_results::job_id)
+        .load::<i64>(&conn)?;
+    assert!(!job_ids.contains(&first.0));
+    Ok(())
+}
+
+#[test]
+fn completed_jobs_can_be_archived_until_they_are_pruned() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .archive_completed_jobs(Duration::from_secs(60 * 60))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let succeeded = succeeding_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+
+    let archived = background_jobs_archive::table
+        .select((
+            background_jobs_archive::id,
+            background_jobs_archive::job_type,
+        ))
+        .load::<(i64, String)>(&conn)?;
+    assert_eq!(vec![(succeeded.0, "succeeding_job".to_string())], archived);
+    let remaining = background_jobs::table
+        .select(background_jobs::job_type)
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["failure_job"], remaining);
+
+    assert_eq!(0, runner.prune_archive()?);
+    let two_hours_ago = chrono::Utc::now() - chrono::Duration::hours(2);
+    diesel::update(background_jobs_archive::table)
+        .set(background_jobs_archive::finished_at.eq(two_hours_ago))
+        .execute(&conn)?;
+    assert_eq!(1, runner.prune_archive()?);
+    Ok(())
+}
+
+#[test]
+fn maintenance_prunes_old_dead_jobs_a_batch_at_a_time() -> Fallible<()> {
+    let runner = TestGuard::builder(())
+        .max_retries(0)
+        .maintenance(MaintenancePolicy {
+            dead_job_retention: Some(Duration::from_secs(60 * 60)),
+            batch_size: 1,
+            ..MaintenancePolicy::default()
+        })
+        .build();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    let two_hours_ago = chrono::Utc::now() - chrono::Duration::hours(2);
+    diesel::update(background_jobs::table)
+        .set(background_jobs::dead_at.eq(two_hours_ago))
+        .execute(&conn)?;
+    let recent = failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+
+    let report = runner.run_maintenance()?;
+    assert_eq!(2, report.dead_jobs_pruned);
+    assert_eq!(0, report.archived_jobs_pruned);
+    assert!(report.tables.iter().any(|t| t.name == "background_jobs"));
+    let remaining = background_jobs::table
+        .select(background_jobs::id)
+        .load::<i64>(&conn)?;
+    assert_eq!(vec![recent.0], remaining);
+    Ok(())
+}
+
+#[swirl::background_job]
+fn record_step(log: &Arc<Mutex<Vec<i32>>>, step: i32) -> Result<(), swirl::PerformError> {
+    if step < 0 {
+        return Err("negative".into());
+    }
+    log.lock().unwrap().push(step);
+    Ok(())
+}
+
+#[test]
+fn chained_jobs_run_once_the_job_before_them_succeeds() -> Fallible<()> {
+    let log = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let runner = TestGuard::runner(log.clone());
+    let conn = runner.connection_pool().get()?;
+    // Enqueued last, but claimed first since the others wait their turn
+    let ids = record_step(3)
+        .then(record_step(2))
+        .then(record_step(1))
+        .enqueue(&conn)?;
+    assert_eq!(3, ids.len());
+
+    let summary = runner.run_pending_jobs_until_empty()?;
+    assert_eq!(3, summary.succeeded);
+    assert_eq!(vec![3, 2, 1], *log.lock().unwrap());
+    let dependencies = background_job_dependencies::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, dependencies);
+    Ok(())
+}
+
+#[test]
+fn chained_jobs_wait_while_the_job_before_them_fails() -> Fallible<()> {
+    let log = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let runner = TestGuard::runner(log.clone());
+    let conn = runner.connection_pool().get()?;
+    let ids = record_step(-1)
+        .then(record_step(1))
+        .then(record_step(2))
+        .enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert!(log.lock().unwrap().is_empty());
+    assert_eq!(3, background_jobs::table.count().get_result::<i64>(&conn)?);
+
+    // Cancelling the failed job deletes the jobs waiting for it
+    assert_eq!(swirl::Cancellation::Removed, swirl::cancel(&conn, ids[0])?);
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn batch_callbacks_run_once_every_job_in_the_batch_succeeds() -> Fallible<()> {
+    use swirl::Batch;
+
+    let log = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let runner = TestGuard::runner(log.clone());
+    let conn = runner.connection_pool().get()?;
+    let ids = Batch::new("import")
+        .on_success(record_step(100))
+        .on_failure(record_step(200))
+        .enqueue(&conn, (1..=3).map(record_step))?;
+    assert_eq!(3, ids.len());
+
+    let summary = runner.run_pending_jobs_until_empty()?;
+    assert_eq!(4, summary.succeeded);
+    assert_eq!(Some(&100), log.lock().unwrap().last());
+    assert!(!log.lock().unwrap().contains(&200));
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+
+    // A batch with nothing left to run has already succeeded
+    Batch::new("empty")
+        .on_success(record_step(300))
+        .enqueue(&conn, Vec::<record_step::Job>::new())?;
+    runner.run_pending_jobs_until_empty()?;
+    assert_eq!(Some(&300), log.lock().unwrap().last());
+    Ok(())
+}
+
+#[test]
+fn batch_failure_callbacks_run_once_a_job_in_the_batch_is_dead() -> Fallible<()> {
+    use swirl::Batch;
+
+    let log = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let runner = TestGuard::builder(log.clone()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    Batch::new("import")
+        .on_success(record_step(100))
+        .on_failure(record_step(200))
+        .enqueue(
+            &conn,
+            vec![record_step(1), record_step(-1), record_step(-2)],
+        )?;
+
+    runner.run_pending_jobs_until_empty()?;
+    assert_eq!(Err(JobsFailed(2)), runner.check_for_failed_jobs());
+    let log = log.lock().unwrap();
+    assert_eq!(1, log.iter().filter(|&&step| step == 200).count());
+    assert!(!log.contains(&100));
+    // Only the dead jobs are left
+    let dead = background_jobs::table
+        .filter(background_jobs::dead_at.is_not_null())
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(2, dead);
+    assert_eq!(2, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn runners_record_a_heartbeat_for_each_running_job() -> Fallible<()> {
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::builder(barrier.clone())
+        .heartbeat_interval(Duration::from_millis(20))
+        .build();
+    let conn = runner.connection_pool().get()?;
+    let job_id = barrier_job().enqueue(&conn)?;
+
+    runner.run_all_pending_jobs()?;
+    let mut heartbeats = Vec::new();
+    for _ in 0..250 {
+        heartbeats = background_job_heartbeats::table
+            .select((
+                background_job_heartbeats::job_id,
+                background_job_heartbeats::attempt,
+                background_job_heartbeats::locked_by,
+            ))
+            .load::<(i64, i32, String)>(&conn)?;
+        if !heartbeats.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(1, heartbeats.len());
+    let (heartbeat_job_id, attempt, locked_by) = &heartbeats[0];
+    assert_eq!(i64::from(job_id), *heartbeat_job_id);
+    assert_eq!(0, *attempt);
+    assert!(locked_by.starts_with(&format!("pid {}", std::process::id())));
+    // A job which is still running hasn't been abandoned
+    assert!(swirl::admin::abandoned_jobs(&conn)?.is_empty());
+
+    barrier.wait();
+    runner.check_for_failed_jobs()?;
+    let remaining = background_job_heartbeats::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, remaining);
+    Ok(())
+}
+
+#[test]
+fn runners_report_the_state_of_the_queue() -> Fallible<()> {
+    let barrier = Barrier::new(2);
+    let runner = TestGuard::runner(barrier.clone());
+    let conn = runner.connection_pool().get()?;
+    assert_eq!(None, runner.stats()?.oldest_pending_age);
+
+    failure_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    barrier_job().enqueue(&conn)?;
+    runner.run_all_pending_jobs()?;
+    let waiting = succeeding_job().enqueue(&conn)?;
+    diesel::sql_query(
+        "UPDATE background_jobs SET created_at = created_at - INTERVAL '1 hour' WHERE id = $1",
+    )
+    .bind::<diesel::sql_types::BigInt, _>(i64::from(waiting))
+    .execute(&conn)?;
+
+    let stats = runner.stats()?;
+    assert_eq!(Some(&3), stats.pending.get("default"));
+    assert_eq!(1, stats.failed);
+    // The failed job is backing off, and the barrier job is running
+    let oldest_pending_age = stats.oldest_pending_age.unwrap();
+    assert!(oldest_pending_age >= Duration::from_secs(59 * 60));
+    assert!(oldest_pending_age < Duration::from_secs(61 * 60));
+    assert_eq!(1, stats.active_threads);
+    assert_eq!(1, stats.processed);
+
+    barrier.wait();
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    let stats = runner.stats()?;
+    assert_eq!(Some(&2), stats.pending.get("default"));
+    assert_eq!(0, stats.active_threads);
+    assert_eq!(2, stats.processed);
+    Ok(())
+}
+
+#[test]
+fn runners_are_unhealthy_while_jobs_wait_too_long() -> Fallible<()> {
+    use swirl::HealthError;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    runner.check_health(Duration::from_secs(60))?;
+
+    let waiting = succeeding_job().enqueue(&conn)?;
+    runner.check_health(Duration::from_secs(60))?;
+    diesel::sql_query(
+        "UPDATE background_jobs SET created_at = created_at - INTERVAL '1 hour' WHERE id = $1",
+    )
+    .bind::<diesel::sql_types::BigInt, _>(i64::from(waiting))
+    .execute(&conn)?;
+
+    let error = runner.check_health(Duration::from_secs(60)).unwrap_err();
+    assert_matches!(
+        error,
+        HealthError::JobsBacklogged { oldest_pending_age, max_pending_age }
+            if oldest_pending_age >= Duration::from_secs(59 * 60)
+                && max_pending_age == Duration::from_secs(60)
+    );
+    runner.check_health(Duration::from_secs(2 * 60 * 60))?;
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    runner.check_health(Duration::from_secs(60))?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use failure::Fallible;
+use swirl::schedule::{self, ScheduleError};
+use swirl::schema::background_jobs;
+
+use crate::test_guard::TestGuard;
+
+fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+#[test]
+fn upcoming_runs_are_planned_in_the_schedules_time_zone() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    schedule::set(&conn, "send_digest", "0 0 9 * * *", "Europe/Berlin")?;
+
+    let schedule = schedule::get(&conn, "send_digest")?.expect("schedule was not saved");
+    // Daylight saving time starts in Berlin on 2020-03-29
+    let runs = schedule.upcoming_after(utc("2020-03-27T12:00:00Z"), 3)?;
+    assert_eq!(
+        vec![
+            utc("2020-03-28T08:00:00Z"),
+            utc("2020-03-29T07:00:00Z"),
+            utc("2020-03-30T07:00:00Z"),
+        ],
+        runs
+    );
+
+    let runs = schedule::upcoming(&conn, "send_digest", 2)?;
+    assert_eq!(2, runs.len());
+    assert!(Utc::now() < runs[0] && runs[0] < runs[1]);
+    Ok(())
+}
+
+#[test]
+fn paused_schedules_skip_runs_until_they_are_resumed() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    schedule::set(&conn, "hourly", "0 0 * * * *", "UTC")?;
+    let in_a_day = Utc::now() + Duration::days(1);
+
+    schedule::pause(&conn, "hourly", Some(in_a_day))?;
+    let runs = schedule::upcoming(&conn, "hourly", 1)?;
+    assert!(runs[0] > in_a_day && runs[0] <= in_a_day + Duration::hours(1));
+
+    schedule::pause(&conn, "hourly", None)?;
+    assert!(schedule::upcoming(&conn, "hourly", 1)?.is_empty());
+
+    // Changing the schedule keeps it paused
+    schedule::set(&conn, "hourly", "0 30 * * * *", "UTC")?;
+    assert!(schedule::upcoming(&conn, "hourly", 1)?.is_empty());
+
+    schedule::resume(&conn, "hourly")?;
+    let runs = schedule::upcoming(&conn, "hourly", 1)?;
+    assert!(runs[0] <= Utc::now() + Duration::hours(1));
+    Ok(())
+}
+
+#[test]
+fn invalid_schedules_are_rejected() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+
+    let err = schedule::set(&conn, "job", "every tuesday", "UTC").unwrap_err();
+    assert!(matches!(err, ScheduleError::InvalidCron { .. }));
+    let err = schedule::set(&conn, "job", "0 0 9 * * *", "Mars/Olympus_Mons").unwrap_err();
+    assert!(matches!(err, ScheduleError::InvalidTimezone(_)));
+    assert_eq!(None, schedule::get(&conn, "job")?);
+
+    let err = schedule::upcoming(&conn, "job", 1).unwrap_err();
+    assert!(matches!(err, ScheduleError::NotFound(_)));
+    assert!(matches!(
+        schedule::resume(&conn, "job"),
+        Err(ScheduleError::NotFound(_))
+    ));
+    assert!(!schedule::remove(&conn, "job")?);
+
+    schedule::set(&conn, "job", "0 0 9 * * *", "UTC")?;
+    assert!(schedule::remove(&conn, "job")?);
+    Ok(())
+}
+
+#[swirl::background_job]
+fn refresh_stats() -> Result<(), swirl::PerformError> {
+    Ok(())
+}
+
+#[swirl::background_job]
+fn refresh_user_stats(user_id: i64) -> Result<(), swirl::PerformError> {
+    let _ = user_id;
+    Ok(())
+}
+
+fn move_back_schedule(conn: &PgConnection, job_type: &str, by: Duration) -> QueryResult<()> {
+    use swirl::schema::background_job_schedules::dsl::{background_job_schedules, updated_at};
+
+    let changed_at = background_job_schedules
+        .find(job_type)
+        .select(updated_at)
+        .first::<DateTime<Utc>>(conn)?;
+    diesel::update(background_job_schedules.find(job_type))
+        .set(updated_at.eq(changed_at - by))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[test]
+fn recurring_jobs_are_enqueued_once_per_planned_run() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    runner
+        .registry()
+        .register_recurring::<refresh_stats::Job>("0 0 * * * *")?;
+
+    // Nothing is due right after the schedule is saved
+    assert_eq!(0, runner.enqueue_scheduled_jobs()?);
+    let saved = schedule::get(&conn, "refresh_stats")?.expect("schedule was not saved");
+    assert_eq!("0 0 * * * *", saved.cron);
+    assert_eq!("UTC", saved.timezone);
+
+    // Only the most recent missed run is enqueued
+    move_back_schedule(&conn, "refresh_stats", Duration::hours(3))?;
+    assert_eq!(1, runner.enqueue_scheduled_jobs()?);
+    assert_eq!(0, runner.enqueue_scheduled_jobs()?);
+    let job_types = background_jobs::table
+        .select(background_jobs::job_type)
+        .load::<String>(&conn)?;
+    assert_eq!(vec!["refresh_stats"], job_types);
+
+    // Registering the same schedule again leaves it alone
+    runner
+        .registry()
+        .register_recurring::<refresh_stats::Job>("0 0 * * * *")?;
+    assert_eq!(0, runner.enqueue_scheduled_jobs()?);
+    let schedule = schedule::get(&conn, "refresh_stats")?.expect("schedule was removed");
+    assert_eq!(saved.updated_at - Duration::hours(3), schedule.updated_at);
+    assert!(schedule.last_enqueued_at.is_some());
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    Ok(())
+}
+
+#[test]
+fn paused_schedules_are_not_enqueued() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    schedule::set(&conn, "refresh_stats", "0 0 * * * *", "UTC")?;
+    schedule::pause(&conn, "refresh_stats", None)?;
+    move_back_schedule(&conn, "refresh_stats", Duration::hours(3))?;
+
+    assert_eq!(0, runner.enqueue_scheduled_jobs()?);
+
+    // Runs missed while the schedule was paused are skipped
+    schedule::resume(&conn, "refresh_stats")?;
+    assert_eq!(0, runner.enqueue_scheduled_jobs()?);
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn jobs_with_arguments_cannot_be_recurring() {
+    let runner = TestGuard::dummy_runner();
+
+    let err = runner
+        .registry()
+        .register_recurring::<refresh_user_stats::Job>("0 0 * * * *")
+        .unwrap_err();
+    assert!(matches!(err, ScheduleError::TakesArguments(_)));
+    let err = runner
+        .registry()
+        .register_recurring::<refresh_stats::Job>("every hour")
+        .unwrap_err();
+    assert!(matches!(err, ScheduleError::InvalidCron { .. }));
+}
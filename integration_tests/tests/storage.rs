@@ -0,0 +1,506 @@
+use assert_matches::assert_matches;
+use diesel::prelude::*;
+use failure::Fallible;
+use swirl::schema::*;
+use swirl::storage;
+use swirl::testing::jobs::*;
+
+use crate::test_guard::TestGuard;
+
+#[test]
+fn claimed_jobs_are_locked_until_the_transaction_ends() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let other_conn = runner.connection_pool().get()?;
+    succeeding_job().enqueue(&conn)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!("succeeding_job", job.job_type);
+        assert!(storage::claim_one(&other_conn, None)?.is_none());
+        storage::complete(&conn, job.id)
+    })?;
+
+    let remaining = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(0, remaining);
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_are_not_claimed_again_until_they_can_be_retried() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+    failure_job().enqueue(&conn)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert!(!storage::fail(&conn, &job, "failed", None));
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert!(storage::fail(&conn, &job, "failed", Some(0)));
+        Ok(())
+    })?;
+
+    assert!(storage::claim_one(&conn, Some(&["default".into()]))?.is_none());
+    let failures = background_job_failures::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(2, failures);
+    Ok(())
+}
+
+#[test]
+fn failed_jobs_keep_the_error_they_last_failed_with() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!(None, job.last_error);
+        assert_eq!(None, job.failed_at);
+        assert!(!storage::fail(&conn, &job, "connection refused", None));
+        Ok(())
+    })?;
+    diesel::update(background_jobs::table)
+        .set(background_jobs::retry_at.eq(diesel::dsl::now))
+        .execute(&conn)?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!(Some("connection refused"), job.last_error.as_deref());
+        let failed_at = job.failed_at.expect("the failure time was not recorded");
+        assert!(chrono::Utc::now() - failed_at < chrono::Duration::minutes(1));
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn jobs_record_when_they_were_started_and_finished() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    failure_job().enqueue(&conn)?;
+
+    let started_at = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!(None, job.finished_at);
+        let started_at = job.started_at.expect("the start time was not recorded");
+        assert!(job.enqueued_at <= started_at);
+        assert!(!storage::fail(&conn, &job, "failed", None));
+        Ok(started_at)
+    })?;
+
+    let jobs = storage::list_jobs(&conn, &storage::JobFilter::new())?;
+    assert_eq!(Some(started_at), jobs[0].started_at);
+    let finished_at = jobs[0]
+        .finished_at
+        .expect("the finish time was not recorded");
+    assert!(started_at <= finished_at);
+    Ok(())
+}
+
+#[test]
+fn jobs_are_not_enqueued_while_an_identical_job_is_pending() -> Fallible<()> {
+    #[swirl::background_job]
+    fn job_with_arg(arg: String) -> Result<(), swirl::PerformError> {
+        Err(arg.into())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    assert!(job_with_arg("a".into()).enqueue_unless_pending(&conn)?);
+    assert!(!job_with_arg("a".into()).enqueue_unless_pending(&conn)?);
+    assert!(job_with_arg("b".into()).enqueue_unless_pending(&conn)?);
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert!(storage::fail(&conn, &job, "failed", Some(0)));
+        Ok(())
+    })?;
+    assert!(job_with_arg("a".into()).enqueue_unless_pending(&conn)?);
+    assert!(!job_with_arg("b".into()).enqueue_unless_pending(&conn)?);
+
+    let jobs = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(3, jobs);
+    Ok(())
+}
+
+#[test]
+fn jobs_are_not_enqueued_while_a_job_with_the_same_unique_key_is_pending() -> Fallible<()> {
+    #[swirl::background_job(unique(cache_key))]
+    fn refresh_cache(cache_key: String, attempt: i32) -> Result<(), swirl::PerformError> {
+        Err(format!("{} {}", cache_key, attempt).into())
+    }
+
+    #[swirl::background_job(unique)]
+    fn refresh_everything() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    assert!(refresh_cache("a".into(), 1).enqueue_unique(&conn)?);
+    assert!(!refresh_cache("a".into(), 2).enqueue_unique(&conn)?);
+    refresh_cache("a".into(), 3).enqueue(&conn)?;
+    assert!(refresh_cache("b".into(), 1).enqueue_unique(&conn)?);
+    assert!(refresh_everything().enqueue_unique(&conn)?);
+    assert!(!refresh_everything().enqueue_unique(&conn)?);
+    assert!(succeeding_job().enqueue_unique(&conn)?);
+    assert!(succeeding_job().enqueue_unique(&conn)?);
+
+    // Jobs which have died no longer hold on to their key
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!("refresh_cache", job.job_type);
+        assert!(storage::fail(&conn, &job, "failed", Some(0)));
+        Ok(())
+    })?;
+    assert!(refresh_cache("a".into(), 4).enqueue_unique(&conn)?);
+
+    let keys = background_jobs::table
+        .select(background_jobs::unique_key)
+        .filter(background_jobs::job_type.eq("refresh_cache"))
+        .order(background_jobs::id)
+        .load::<Option<String>>(&conn)?;
+    let expected = vec![r#"["a"]"#, r#"["b"]"#, r#"["a"]"#];
+    assert_eq!(expected, keys.iter().flatten().collect::<Vec<_>>());
+    Ok(())
+}
+
+#[test]
+fn large_arguments_are_compressed_until_the_job_is_run() -> Fallible<()> {
+    #[swirl::background_job(compress_over = 100)]
+    fn import_rows(ctx: &swirl::JobContext, rows: Vec<String>) -> Result<(), swirl::PerformError> {
+        ctx.set_result(&rows.len())?;
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let small = import_rows(vec!["a".into()]).enqueue(&conn)?;
+    let large = import_rows(vec!["row".repeat(10); 50]).enqueue(&conn)?;
+
+    let compressed = background_jobs::table
+        .select((background_jobs::compressed, background_jobs::data))
+        .order(background_jobs::id)
+        .load::<(bool, serde_json::Value)>(&conn)?;
+    assert!(!compressed[0].0);
+    assert_eq!((true, serde_json::Value::Null), compressed[1]);
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    assert_eq!(Some(1), swirl::job_result::<usize>(&conn, small)?);
+    assert_eq!(Some(50), swirl::job_result::<usize>(&conn, large)?);
+    Ok(())
+}
+
+#[test]
+fn jobs_with_arguments_over_their_size_limit_are_not_enqueued() -> Fallible<()> {
+    use swirl::EnqueueError;
+
+    #[swirl::background_job(max_payload_size = 64)]
+    fn send_message(body: String) -> Result<(), swirl::PerformError> {
+        Err(body.into())
+    }
+
+    #[swirl::background_job(max_payload_size = 64, compress_over = 64)]
+    fn send_compressed_message(body: String) -> Result<(), swirl::PerformError> {
+        Err(body.into())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    send_message("hi".into()).enqueue(&conn)?;
+    assert_matches!(
+        send_message("hi".repeat(40)).enqueue(&conn),
+        Err(EnqueueError::PayloadTooLarge {
+            size: 91,
+            limit: 64
+        })
+    );
+    // The limit applies to the compressed arguments
+    send_compressed_message("hi".repeat(40)).enqueue(&conn)?;
+
+    let jobs = background_jobs::table.count().get_result::<i64>(&conn)?;
+    assert_eq!(2, jobs);
+    Ok(())
+}
+
+#[test]
+fn enqueueing_a_job_returns_its_id() -> Fallible<()> {
+    #[swirl::background_job(unique(report_id))]
+    fn build_report(report_id: i32, requested_by: String) -> Result<(), swirl::PerformError> {
+        Err(format!("{} {}", report_id, requested_by).into())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let first = build_report(1, "a".into()).enqueue(&conn)?;
+    let second = build_report(2, "a".into()).enqueue(&conn)?;
+    let duplicate = build_report(1, "b".into()).enqueue_in_batch(&conn, "reports")?;
+    assert_ne!(first, second);
+    assert_eq!(first, duplicate);
+
+    let data = background_jobs::table
+        .find(second)
+        .select(background_jobs::data)
+        .get_result::<serde_json::Value>(&conn)?;
+    assert_eq!(
+        serde_json::json!({ "report_id": 2, "requested_by": "a" }),
+        data
+    );
+    Ok(())
+}
+
+#[test]
+fn pending_jobs_can_be_cancelled_by_id() -> Fallible<()> {
+    use swirl::{Cancellation, JobId};
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let cancelled = failure_job().enqueue(&conn)?;
+    let dead = failure_job().enqueue(&conn)?;
+    let kept = succeeding_job().enqueue(&conn)?;
+    diesel::update(background_jobs::table.find(dead))
+        .set(background_jobs::dead_at.eq(diesel::dsl::now))
+        .execute(&conn)?;
+
+    assert_eq!(Cancellation::Removed, swirl::cancel(&conn, cancelled)?);
+    assert_eq!(Cancellation::NotFound, swirl::cancel(&conn, cancelled)?);
+    assert_eq!(Cancellation::NotFound, swirl::cancel(&conn, dead)?);
+
+    let remaining = background_jobs::table
+        .select(background_jobs::id)
+        .order(background_jobs::id)
+        .load::<JobId>(&conn)?;
+    assert_eq!(vec![dead, kept], remaining);
+    Ok(())
+}
+
+#[test]
+fn the_status_of_a_job_can_be_looked_up() -> Fallible<()> {
+    use storage::JobStatus;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let other_conn = runner.connection_pool().get()?;
+    let failing = failure_job().enqueue(&conn)?;
+    let succeeding = succeeding_job().enqueue(&conn)?;
+    assert_eq!(JobStatus::Pending, storage::job_status(&conn, failing)?);
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        assert_eq!(
+            JobStatus::Running,
+            storage::job_status(&other_conn, failing)?
+        );
+        assert!(!storage::fail(&conn, &job, "failed once", None));
+        Ok(())
+    })?;
+    let expected = JobStatus::Failed {
+        retries: 1,
+        last_error: Some("failed once".into()),
+        dead: false,
+    };
+    assert_eq!(expected, storage::job_status(&conn, failing)?);
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let job = storage::claim_one(&conn, None)?.expect("no job was claimed");
+        storage::complete(&conn, job.id)
+    })?;
+    assert_eq!(JobStatus::Done, storage::job_status(&conn, succeeding)?);
+    Ok(())
+}
+
+#[test]
+fn jobs_can_be_listed_a_page_at_a_time() -> Fallible<()> {
+    use storage::JobFilter;
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        ids.push(failure_job().enqueue(&conn)?);
+        succeeding_job().enqueue(&conn)?;
+    }
+    diesel::update(background_jobs::table.find(ids[2]))
+        .set(background_jobs::dead_at.eq(diesel::dsl::now))
+        .execute(&conn)?;
+
+    let filter = JobFilter::new().job_type("failure_job").limit(2);
+    let page = storage::list_jobs(&conn, &filter)?;
+    assert_eq!(ids[..2], *page.iter().map(|job| job.id).collect::<Vec<_>>());
+    let next_page = storage::list_jobs(&conn, &filter.clone().after(page[1].id))?;
+    assert_eq!(
+        ids[2..],
+        *next_page.iter().map(|job| job.id).collect::<Vec<_>>()
+    );
+    assert!(next_page[0].dead_at.is_some());
+
+    let pending = storage::list_jobs(&conn, &filter.dead(false).limit(10))?;
+    assert_eq!(
+        ids[..2],
+        *pending.iter().map(|job| job.id).collect::<Vec<_>>()
+    );
+    let everything = storage::list_jobs(&conn, &JobFilter::new())?;
+    assert_eq!(6, everything.len());
+
+    diesel::update(background_jobs::table.find(ids[1]))
+        .set(background_jobs::retries.eq(1))
+        .execute(&conn)?;
+    let failed = storage::list_jobs(&conn, &JobFilter::new().failed(true).dead(false))?;
+    assert_eq!(
+        vec![ids[1]],
+        failed.iter().map(|job| job.id).collect::<Vec<_>>()
+    );
+    let never_failed = storage::list_jobs(&conn, &JobFilter::new().failed(false))?;
+    assert_eq!(5, never_failed.len());
+
+    let one = storage::list_jobs(&conn, &JobFilter::new().id(ids[1]))?;
+    assert_eq!(
+        vec![ids[1]],
+        one.iter().map(|job| job.id).collect::<Vec<_>>()
+    );
+    Ok(())
+}
+
+#[test]
+fn pending_jobs_are_counted_by_queue() -> Fallible<()> {
+    #[swirl::background_job(queue = "bulk")]
+    fn bulk_job() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    assert!(storage::queue_depths(&conn)?.is_empty());
+
+    succeeding_job().enqueue(&conn)?;
+    let dead = failure_job().enqueue(&conn)?;
+    bulk_job().enqueue(&conn)?;
+    bulk_job().enqueue(&conn)?;
+    diesel::update(background_jobs::table.find(dead))
+        .set(background_jobs::dead_at.eq(diesel::dsl::now))
+        .execute(&conn)?;
+
+    let depths = storage::queue_depths(&conn)?;
+    assert_eq!(2, depths.len());
+    assert_eq!(Some(&1), depths.get("default"));
+    assert_eq!(Some(&2), depths.get("bulk"));
+    Ok(())
+}
+
+#[test]
+fn jobs_with_a_higher_priority_are_claimed_first() -> Fallible<()> {
+    #[swirl::background_job]
+    fn import_row(row: i32) -> Result<(), swirl::PerformError> {
+        let _ = row;
+        Ok(())
+    }
+
+    #[swirl::background_job(priority = 10)]
+    fn send_notification() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    import_row(1).enqueue(&conn)?;
+    import_row(2).enqueue_with_priority(&conn, -1)?;
+    import_row(3).enqueue(&conn)?;
+    send_notification().enqueue(&conn)?;
+
+    let claimed = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let mut claimed = Vec::new();
+        while let Some(job) = storage::claim_one(&conn, None)? {
+            storage::complete(&conn, job.id)?;
+            claimed.push(job.data);
+        }
+        Ok(claimed)
+    })?;
+
+    let expected = vec![
+        serde_json::json!({}),
+        serde_json::json!({ "row": 1 }),
+        serde_json::json!({ "row": 3 }),
+        serde_json::json!({ "row": 2 }),
+    ];
+    assert_eq!(expected, claimed);
+    Ok(())
+}
+
+#[test]
+fn jobs_on_paused_queues_are_not_claimed_until_they_are_resumed() -> Fallible<()> {
+    #[swirl::background_job(queue = "mailers")]
+    fn send_email() -> Result<(), swirl::PerformError> {
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    send_email().enqueue(&conn)?;
+    succeeding_job().enqueue(&conn)?;
+
+    assert!(storage::pause_queue(&conn, "mailers")?);
+    assert!(!storage::pause_queue(&conn, "mailers")?);
+    assert_eq!(vec!["mailers"], storage::paused_queues(&conn)?);
+    assert_eq!(1, runner.run_all_pending_jobs()?.claimed);
+    runner.check_for_failed_jobs()?;
+
+    assert!(storage::resume_queue(&conn, "mailers")?);
+    assert!(!storage::resume_queue(&conn, "mailers")?);
+    assert_eq!(1, runner.run_all_pending_jobs()?.claimed);
+    runner.check_for_failed_jobs()?;
+    assert_eq!(Ok(0), background_jobs::table.count().get_result(&conn));
+    Ok(())
+}
+
+#[test]
+fn jobs_can_be_enqueued_in_bulk() -> Fallible<()> {
+    #[swirl::background_job(unique)]
+    fn number_job(n: i64) -> Result<(), swirl::PerformError> {
+        assert!(n >= 0);
+        Ok(())
+    }
+
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    number_job(0).enqueue(&conn)?;
+    let ids = swirl::enqueue_batch(&conn, (0..12_000).map(number_job))?;
+    assert_eq!(11_999, ids.len());
+
+    let enqueued = background_jobs::table
+        .select((background_jobs::id, background_jobs::data))
+        .filter(background_jobs::id.eq_any(&ids))
+        .order(background_jobs::id)
+        .load::<(swirl::JobId, serde_json::Value)>(&conn)?;
+    assert_eq!(ids, enqueued.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+    assert_eq!(serde_json::json!({ "n": 1 }), enqueued[0].1);
+    assert_eq!(serde_json::json!({ "n": 11_999 }), enqueued[11_998].1);
+    Ok(())
+}
+
+#[test]
+fn migrations_create_every_table_and_are_only_run_once() -> Fallible<()> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+
+    // The test database already has the tables, so they are created again in
+    // a schema of their own, which is rolled back with the test transaction
+    let conn = PgConnection::establish(&swirl::testing::database_url())?;
+    conn.begin_test_transaction()?;
+    diesel::sql_query("CREATE SCHEMA swirl_migrations").execute(&conn)?;
+    diesel::sql_query("SET LOCAL search_path TO swirl_migrations").execute(&conn)?;
+
+    let run = swirl::run_migrations(&conn)?;
+    assert_eq!(Some(&"2018-05-03-150523_create_jobs"), run.first());
+    let tables = diesel::select(sql::<BigInt>(
+        "(SELECT COUNT(*) FROM information_schema.tables \
+         WHERE table_schema = 'swirl_migrations' AND table_name LIKE 'background_job%')",
+    ))
+    .get_result::<i64>(&conn)?;
+    assert_eq!(swirl::testing::TABLES.len() as i64, tables);
+    assert!(swirl::run_migrations(&conn)?.is_empty());
+    Ok(())
+}
@@ -1,94 +1,201 @@
-use antidote::{Mutex, MutexGuard};
-use diesel::prelude::*;
-use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 use std::time::Duration;
-use swirl::{Builder, Runner};
-
-use crate::db::*;
-use crate::util::*;
-
-lazy_static::lazy_static! {
-    // Since these tests deal with behavior concerning multiple connections
-    // running concurrently, they have to run outside of a transaction.
-    // Therefore we can't run more than one at a time.
-    //
-    // Rather than forcing the whole suite to be run with `--test-threads 1`,
-    // we just lock these tests instead.
-    static ref TEST_MUTEX: Mutex<()> = Mutex::new(());
-}
+use swirl::db::ConnectionCustomizer;
+use swirl::lifecycle::{JobMetadata, LifecycleListener};
+use swirl::middleware::Middleware;
+use swirl::notifier::{ErrorHandler, FailureNotifier};
+use swirl::payload_store::PayloadStore;
+use swirl::query_hook::QueryHook;
+use swirl::testing::GuardBuilder;
+use swirl::{Backoff, ExpiredJobPolicy, MaintenancePolicy, PanicPolicy, RunnerConfig};
+
+pub use swirl::testing::TestGuard;
+
+/// Shorthand for the builder options used by these tests
+pub trait GuardBuilderExt<Env>: Sized {
+    fn thread_count(self, count: usize) -> Self;
+
+    fn connection_count(self, count: u32) -> Self;
+
+    fn job_start_timeout(self, timeout: Duration) -> Self;
+
+    fn worker_stack_size(self, bytes: usize) -> Self;
+
+    fn max_retries(self, max_retries: u32) -> Self;
+
+    fn backoff(self, backoff: Backoff) -> Self;
+
+    fn failure_notifier<N: FailureNotifier>(self, notifier: N) -> Self;
+
+    fn error_handler<H: ErrorHandler>(self, handler: H) -> Self;
+
+    fn lifecycle_listener<L: LifecycleListener>(self, listener: L) -> Self;
+
+    fn middleware<M: Middleware>(self, middleware: M) -> Self;
+
+    fn config(self, config: &RunnerConfig) -> Self;
+
+    fn connection_customizer<C: ConnectionCustomizer>(self, customizer: C) -> Self;
+
+    fn job_application_names(self, enabled: bool) -> Self;
+
+    fn query_hook<H: QueryHook>(self, hook: H) -> Self;
+
+    fn slow_job_threshold(self, job_type: &str, threshold: Duration) -> Self;
+
+    fn on_slow_job<F>(self, callback: F) -> Self
+    where
+        F: Fn(&JobMetadata, Duration) + Send + Sync + 'static;
+
+    fn on_expired(self, policy: ExpiredJobPolicy) -> Self;
 
-pub struct TestGuard<'a, Env: 'static> {
-    runner: Runner<Env, DieselPool>,
-    _lock: MutexGuard<'a, ()>,
+    fn on_panic(self, policy: PanicPolicy) -> Self;
+
+    fn job_ttl(self, job_type: &str, ttl: Duration) -> Self;
+
+    fn result_ttl(self, ttl: Duration) -> Self;
+
+    fn archive_completed_jobs(self, retention: Duration) -> Self;
+
+    fn maintenance(self, policy: MaintenancePolicy) -> Self;
+
+    fn retry_budget(self, job_type: &str, retries_per_minute: u32) -> Self;
+
+    fn payload_store(self, store: Arc<dyn PayloadStore>) -> Self;
+
+    fn queues<S: Into<String>>(self, queues: Vec<S>) -> Self;
+
+    fn queue_environment(self, queue: &str, env: Env) -> Self;
+
+    fn queue(self, queue: &str, threads: usize) -> Self;
+
+    fn listen_for_jobs(self, database_url: String) -> Self;
+    fn shutdown_on_signals(self, drain_timeout: Duration) -> Self;
+
+    fn heartbeat_interval(self, interval: Duration) -> Self;
 }
 
-impl<'a, Env> TestGuard<'a, Env> {
-    pub fn builder(env: Env) -> GuardBuilder<Env> {
-        let database_url =
-            dotenv::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run tests");
-        let builder = Runner::builder(env).connection_pool_builder(database_url, pool_builder());
+impl<Env> GuardBuilderExt<Env> for GuardBuilder<Env> {
+    fn thread_count(self, count: usize) -> Self {
+        self.configure(|b| b.thread_count(count))
+    }
 
-        GuardBuilder { builder }
+    fn connection_count(self, count: u32) -> Self {
+        self.configure(|b| b.connection_count(count))
     }
 
-    pub fn runner(env: Env) -> Self {
-        Self::builder(env).build()
+    fn job_start_timeout(self, timeout: Duration) -> Self {
+        self.configure(|b| b.job_start_timeout(timeout))
     }
-}
 
-impl<'a> TestGuard<'a, ()> {
-    pub fn dummy_runner() -> Self {
-        Self::builder(()).build()
+    fn worker_stack_size(self, bytes: usize) -> Self {
+        self.configure(|b| b.worker_stack_size(bytes))
     }
-}
 
-pub struct GuardBuilder<Env: 'static> {
-    builder: Builder<Env, PoolBuilder>,
-}
+    fn max_retries(self, max_retries: u32) -> Self {
+        self.configure(|b| b.max_retries(max_retries))
+    }
 
-impl<Env> GuardBuilder<Env> {
-    pub fn thread_count(mut self, count: usize) -> Self {
-        self.builder = self.builder.thread_count(count);
-        self
+    fn backoff(self, backoff: Backoff) -> Self {
+        self.configure(|b| b.backoff(backoff))
     }
 
-    pub fn connection_count(mut self, count: u32) -> Self {
-        self.builder = self.builder.connection_count(count);
-        self
+    fn failure_notifier<N: FailureNotifier>(self, notifier: N) -> Self {
+        self.configure(|b| b.failure_notifier(notifier))
     }
 
-    pub fn job_start_timeout(mut self, timeout: Duration) -> Self {
-        self.builder = self.builder.job_start_timeout(timeout);
-        self
+    fn error_handler<H: ErrorHandler>(self, handler: H) -> Self {
+        self.configure(|b| b.error_handler(handler))
     }
 
-    pub fn build<'a>(self) -> TestGuard<'a, Env> {
-        TestGuard {
-            _lock: TEST_MUTEX.lock(),
-            runner: self.builder.build(),
-        }
+    fn lifecycle_listener<L: LifecycleListener>(self, listener: L) -> Self {
+        self.configure(|b| b.lifecycle_listener(listener))
     }
-}
 
-impl<'a, Env> Deref for TestGuard<'a, Env> {
-    type Target = Runner<Env, DieselPool>;
+    fn middleware<M: Middleware>(self, middleware: M) -> Self {
+        self.configure(|b| b.middleware(middleware))
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.runner
+    fn config(self, config: &RunnerConfig) -> Self {
+        self.configure(|b| b.config(config).unwrap())
     }
-}
 
-impl<'a, Env> DerefMut for TestGuard<'a, Env> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.runner
+    fn connection_customizer<C: ConnectionCustomizer>(self, customizer: C) -> Self {
+        self.configure(|b| b.connection_customizer(customizer))
+    }
+
+    fn job_application_names(self, enabled: bool) -> Self {
+        self.configure(|b| b.job_application_names(enabled))
+    }
+
+    fn query_hook<H: QueryHook>(self, hook: H) -> Self {
+        self.configure(|b| b.query_hook(hook))
+    }
+
+    fn slow_job_threshold(self, job_type: &str, threshold: Duration) -> Self {
+        self.configure(|b| b.slow_job_threshold(job_type, threshold))
+    }
+
+    fn on_slow_job<F>(self, callback: F) -> Self
+    where
+        F: Fn(&JobMetadata, Duration) + Send + Sync + 'static,
+    {
+        self.configure(|b| b.on_slow_job(callback))
+    }
+
+    fn on_expired(self, policy: ExpiredJobPolicy) -> Self {
+        self.configure(|b| b.on_expired(policy))
+    }
+
+    fn on_panic(self, policy: PanicPolicy) -> Self {
+        self.configure(|b| b.on_panic(policy))
+    }
+
+    fn job_ttl(self, job_type: &str, ttl: Duration) -> Self {
+        self.configure(|b| b.job_ttl(job_type, ttl))
+    }
+
+    fn result_ttl(self, ttl: Duration) -> Self {
+        self.configure(|b| b.result_ttl(ttl))
+    }
+
+    fn archive_completed_jobs(self, retention: Duration) -> Self {
+        self.configure(|b| b.archive_completed_jobs(retention))
+    }
+
+    fn maintenance(self, policy: MaintenancePolicy) -> Self {
+        self.configure(|b| b.maintenance(policy))
+    }
+
+    fn retry_budget(self, job_type: &str, retries_per_minute: u32) -> Self {
+        self.configure(|b| b.retry_budget(job_type, retries_per_minute))
+    }
+
+    fn payload_store(self, store: Arc<dyn PayloadStore>) -> Self {
+        self.configure(|b| b.payload_store(store))
+    }
+
+    fn queues<S: Into<String>>(self, queues: Vec<S>) -> Self {
+        self.configure(|b| b.queues(queues))
+    }
+
+    fn queue_environment(self, queue: &str, env: Env) -> Self {
+        self.configure(|b| b.queue_environment(queue, env))
+    }
+
+    fn queue(self, queue: &str, threads: usize) -> Self {
+        self.configure(|b| b.queue(queue, threads))
+    }
+
+    fn listen_for_jobs(self, database_url: String) -> Self {
+        self.configure(|b| b.listen_for_jobs(database_url))
+    }
+
+    fn shutdown_on_signals(self, drain_timeout: Duration) -> Self {
+        self.configure(|b| b.shutdown_on_signals(drain_timeout))
     }
-}
 
-impl<'a, Env> Drop for TestGuard<'a, Env> {
-    fn drop(&mut self) {
-        let conn = self.runner.connection_pool().get().unwrap();
-        ::diesel::sql_query("TRUNCATE TABLE background_jobs")
-            .execute(&conn)
-            .unwrap_from_drop();
+    fn heartbeat_interval(self, interval: Duration) -> Self {
+        self.configure(|b| b.heartbeat_interval(interval))
     }
 }
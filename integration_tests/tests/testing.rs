@@ -0,0 +1,76 @@
+use failure::Fallible;
+use std::sync::Mutex;
+use swirl::testing::TestRunner;
+use swirl::{JobContext, PerformError};
+
+#[swirl::background_job]
+fn send_email(sent: &Mutex<Vec<String>>, to: String) -> Result<(), PerformError> {
+    if to.is_empty() {
+        return Err("no recipient".into());
+    }
+    sent.lock().unwrap().push(to);
+    Ok(())
+}
+
+#[test]
+fn test_runners_record_the_jobs_enqueued_with_them() -> Fallible<()> {
+    let runner = TestRunner::new(Mutex::new(Vec::new()));
+    let first = runner.enqueue(send_email("a@example.com".into()))?;
+    let second = runner.enqueue(send_email("b@example.com".into()))?;
+
+    assert_ne!(first, second);
+    assert_eq!(2, runner.pending_job_count());
+    runner.assert_enqueued(&send_email("b@example.com".into()));
+    let recipients = runner
+        .enqueued::<send_email::Job>()
+        .into_iter()
+        .map(|job| job.to)
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["a@example.com", "b@example.com"], recipients);
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "Expected a send_email job with the arguments")]
+fn asserting_a_job_was_enqueued_panics_if_it_was_not() {
+    let runner = TestRunner::new(Mutex::new(Vec::new()));
+    runner.enqueue(send_email("a@example.com".into())).unwrap();
+    runner.assert_enqueued(&send_email("b@example.com".into()));
+}
+
+#[test]
+fn test_runners_run_jobs_without_a_database() -> Fallible<()> {
+    #[swirl::background_job]
+    fn use_database(pool: &dyn swirl::db::DieselPoolObj) -> Result<(), PerformError> {
+        pool.get()?;
+        Ok(())
+    }
+
+    #[swirl::background_job]
+    fn check_context(ctx: &JobContext) -> Result<(), PerformError> {
+        assert_eq!("check_context", ctx.job_type());
+        Ok(())
+    }
+
+    let runner = TestRunner::new(Mutex::new(Vec::new()));
+    runner.enqueue(send_email("a@example.com".into()))?;
+    runner.enqueue(send_email(String::new()))?;
+    runner.enqueue(send_email("b@example.com".into()))?;
+
+    let error = runner.run_all_pending_jobs().unwrap_err();
+    assert_eq!("no recipient", error.to_string());
+    assert_eq!(1, runner.pending_job_count());
+    runner.run_all_pending_jobs().unwrap();
+    assert_eq!(0, runner.pending_job_count());
+    assert_eq!(
+        vec!["a@example.com", "b@example.com"],
+        *runner.environment().lock().unwrap()
+    );
+
+    let runner = TestRunner::new(());
+    runner.enqueue(check_context())?;
+    runner.run_all_pending_jobs().unwrap();
+    runner.enqueue(use_database())?;
+    assert!(runner.run_all_pending_jobs().is_err());
+    Ok(())
+}
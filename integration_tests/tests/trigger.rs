@@ -0,0 +1,102 @@
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use failure::Fallible;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use swirl::schema::*;
+use swirl::trigger::{EnqueueTrigger, TriggerEvent};
+use swirl::PerformError;
+
+use crate::test_guard::TestGuard;
+
+#[swirl::background_job]
+fn reindex_post(reindexed: &Arc<Mutex<Vec<i32>>>, id: i32) -> Result<(), PerformError> {
+    reindexed.lock().unwrap().push(id);
+    Ok(())
+}
+
+#[test]
+fn triggers_enqueue_jobs_when_rows_change() -> Fallible<()> {
+    let reindexed = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let runner = TestGuard::runner(reindexed.clone());
+    let conn = runner.connection_pool().get()?;
+    conn.batch_execute(
+        "CREATE TEMPORARY TABLE posts (id SERIAL PRIMARY KEY, title TEXT NOT NULL)",
+    )?;
+    let trigger = EnqueueTrigger::new::<reindex_post::Job>("posts");
+    trigger.create(&conn)?;
+
+    conn.batch_execute(
+        "INSERT INTO posts (title) VALUES ('first'), ('second');
+         UPDATE posts SET title = 'updated' WHERE id = 2;
+         DELETE FROM posts WHERE id = 1;",
+    )?;
+    trigger.drop(&conn)?;
+    conn.batch_execute("INSERT INTO posts (title) VALUES ('after drop')")?;
+
+    let jobs = background_jobs::table
+        .select((background_jobs::job_type, background_jobs::data))
+        .order(background_jobs::id)
+        .load::<(String, serde_json::Value)>(&conn)?;
+    let expected = [1, 2, 2, 1]
+        .iter()
+        .map(|id| ("reindex_post".to_string(), json!({ "id": id })))
+        .collect::<Vec<_>>();
+    assert_eq!(expected, jobs);
+
+    runner.run_all_pending_jobs()?;
+    runner.check_for_failed_jobs()?;
+    let mut ids = reindexed.lock().unwrap().clone();
+    ids.sort();
+    assert_eq!(vec![1, 1, 2, 2], ids);
+    Ok(())
+}
+
+#[swirl::background_job(queue = "search", priority = 5)]
+fn reindex_post_urgently(reindexed: &Arc<Mutex<Vec<i32>>>, id: i32) -> Result<(), PerformError> {
+    reindexed.lock().unwrap().push(id);
+    Ok(())
+}
+
+#[test]
+fn triggers_enqueue_jobs_on_their_queue_with_their_priority() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    conn.batch_execute("CREATE TEMPORARY TABLE posts (id SERIAL PRIMARY KEY)")?;
+    let trigger = EnqueueTrigger::new::<reindex_post_urgently::Job>("posts");
+    trigger.create(&conn)?;
+    conn.batch_execute("INSERT INTO posts DEFAULT VALUES")?;
+    trigger.drop(&conn)?;
+
+    let jobs = background_jobs::table
+        .select((background_jobs::queue, background_jobs::priority))
+        .load::<(String, i16)>(&conn)?;
+    assert_eq!(vec![("search".to_string(), 5)], jobs);
+    Ok(())
+}
+
+#[test]
+fn triggers_can_be_limited_to_some_events_and_columns() -> Fallible<()> {
+    let runner = TestGuard::dummy_runner();
+    let conn = runner.connection_pool().get()?;
+    conn.batch_execute(
+        "CREATE TEMPORARY TABLE posts (id SERIAL PRIMARY KEY, title TEXT NOT NULL)",
+    )?;
+    let trigger = EnqueueTrigger::new::<reindex_post::Job>("posts")
+        .name("reindex_post_titles")
+        .events(&[TriggerEvent::Update])
+        .columns(&["id", "title"]);
+    trigger.create(&conn)?;
+
+    conn.batch_execute(
+        "INSERT INTO posts (title) VALUES ('first');
+         UPDATE posts SET title = 'updated';",
+    )?;
+    trigger.drop(&conn)?;
+
+    let data = background_jobs::table
+        .select(background_jobs::data)
+        .load::<serde_json::Value>(&conn)?;
+    assert_eq!(vec![json!({ "id": 1, "title": "updated" })], data);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use diesel::prelude::*;
+use failure::Fallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swirl::schema::*;
+use swirl::workflow::Workflow;
+use swirl::{JobsFailed, PerformError};
+
+use crate::test_guard::{GuardBuilderExt, TestGuard};
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+#[swirl::background_job]
+fn step(log: &Log, name: String, fail: bool) -> Result<(), PerformError> {
+    if fail {
+        return Err(format!("{} failed", name).into());
+    }
+    log.lock().unwrap().push(name);
+    Ok(())
+}
+
+fn video_workflow(failing_thumbnail: Option<usize>) -> Workflow {
+    let mut workflow = Workflow::new();
+    let upload = workflow.add(step("upload".into(), false));
+    let thumbnails = (0..3)
+        .map(|i| {
+            let fail = failing_thumbnail == Some(i);
+            workflow.add_after(step(format!("thumbnail {}", i), fail), &[upload])
+        })
+        .collect::<Vec<_>>();
+    workflow.add_after(step("publish".into(), false), &thumbnails);
+    workflow
+}
+
+#[test]
+fn jobs_run_once_everything_they_depend_on_has_succeeded() -> Fallible<()> {
+    let log = Log::default();
+    let runner = TestGuard::runner(log.clone());
+    let conn = runner.connection_pool().get()?;
+    let ids = video_workflow(None).enqueue(&conn)?;
+    assert_eq!(5, ids.len());
+
+    let summary = runner.run_pending_jobs_until_empty()?;
+    assert_eq!(5, summary.succeeded);
+    let log = log.lock().unwrap();
+    assert_eq!("upload", log[0]);
+    assert_eq!("publish", log[4]);
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    Ok(())
+}
+
+#[test]
+fn jobs_wait_while_anything_they_depend_on_is_failing() -> Fallible<()> {
+    let log = Log::default();
+    let runner = TestGuard::runner(log.clone());
+    let conn = runner.connection_pool().get()?;
+    let ids = video_workflow(Some(1)).enqueue(&conn)?;
+
+    runner.run_pending_jobs_until_empty()?;
+    assert_eq!(Err(JobsFailed(1)), runner.check_for_failed_jobs());
+    assert!(!log.lock().unwrap().contains(&"publish".to_string()));
+    let remaining = background_jobs::table
+        .select(background_jobs::id)
+        .order(background_jobs::id)
+        .load::<i64>(&conn)?;
+    assert_eq!(vec![ids[2].0, ids[4].0], remaining);
+    let waiting_on = background_job_dependencies::table
+        .select(background_job_dependencies::depends_on)
+        .filter(background_job_dependencies::job_id.eq(ids[4].0))
+        .load::<i64>(&conn)?;
+    assert_eq!(vec![ids[2].0], waiting_on);
+    Ok(())
+}
+
+#[test]
+fn pruning_a_dead_job_deletes_the_jobs_waiting_for_it() -> Fallible<()> {
+    let log = Log::default();
+    let runner = TestGuard::builder(log.clone()).max_retries(0).build();
+    let conn = runner.connection_pool().get()?;
+    let ids = video_workflow(Some(1)).enqueue(&conn)?;
+    runner.run_pending_jobs_until_empty()?;
+    let two_hours_ago = chrono::Utc::now() - chrono::Duration::hours(2);
+    diesel::update(background_jobs::table.find(ids[2].0))
+        .set(background_jobs::dead_at.eq(two_hours_ago))
+        .execute(&conn)?;
+
+    let pruned = swirl::storage::prune_dead_jobs(&conn, Duration::from_secs(60 * 60), 10)?;
+    assert_eq!(1, pruned);
+    assert_eq!(0, background_jobs::table.count().get_result::<i64>(&conn)?);
+    let dependencies = background_job_dependencies::table
+        .count()
+        .get_result::<i64>(&conn)?;
+    assert_eq!(0, dependencies);
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "added to the same workflow before it")]
+fn jobs_can_only_depend_on_jobs_added_before_them() {
+    let mut other = Workflow::new();
+    other.add(step("upload".into(), false));
+    let later = other.add(step("publish".into(), false));
+
+    let mut workflow = Workflow::new();
+    workflow.add(step("upload".into(), false));
+    workflow.add_after(step("publish".into(), false), &[later]);
+}
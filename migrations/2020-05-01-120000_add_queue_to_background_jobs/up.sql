@@ -0,0 +1 @@
+ALTER TABLE background_jobs ADD COLUMN queue TEXT NOT NULL DEFAULT 'default';
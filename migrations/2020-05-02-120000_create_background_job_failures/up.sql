@@ -0,0 +1,10 @@
+CREATE TABLE background_job_failures (
+  id BIGSERIAL PRIMARY KEY,
+  job_id BIGINT NOT NULL,
+  job_type TEXT NOT NULL,
+  error TEXT NOT NULL,
+  fingerprint TEXT NOT NULL,
+  failed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX background_job_failures_failed_at ON background_job_failures (failed_at);
@@ -0,0 +1 @@
+ALTER TABLE background_jobs ADD COLUMN deadline TIMESTAMPTZ;
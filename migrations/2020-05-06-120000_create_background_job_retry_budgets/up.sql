@@ -0,0 +1,6 @@
+CREATE TABLE background_job_retry_budgets (
+  job_type TEXT NOT NULL,
+  window_start TIMESTAMPTZ NOT NULL,
+  retries INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (job_type, window_start)
+);
@@ -0,0 +1,2 @@
+DROP TABLE background_job_batches;
+ALTER TABLE background_jobs DROP COLUMN batch_id;
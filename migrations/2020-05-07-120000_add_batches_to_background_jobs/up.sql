@@ -0,0 +1,7 @@
+ALTER TABLE background_jobs ADD COLUMN batch_id TEXT;
+CREATE INDEX background_jobs_batch_id ON background_jobs (batch_id) WHERE batch_id IS NOT NULL;
+
+CREATE TABLE background_job_batches (
+  batch_id TEXT NOT NULL PRIMARY KEY,
+  succeeded BIGINT NOT NULL DEFAULT 0
+);
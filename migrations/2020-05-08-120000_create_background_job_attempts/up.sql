@@ -0,0 +1,14 @@
+CREATE TABLE background_job_attempts (
+  id BIGSERIAL PRIMARY KEY,
+  job_id BIGINT NOT NULL,
+  job_type TEXT NOT NULL,
+  attempt INTEGER NOT NULL,
+  worker TEXT NOT NULL,
+  outcome TEXT NOT NULL,
+  error TEXT,
+  started_at TIMESTAMPTZ NOT NULL,
+  duration_ms BIGINT NOT NULL
+);
+
+CREATE INDEX background_job_attempts_job_id ON background_job_attempts (job_id);
+CREATE INDEX background_job_attempts_job_type_started_at ON background_job_attempts (job_type, started_at);
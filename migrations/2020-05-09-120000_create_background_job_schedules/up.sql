@@ -0,0 +1,8 @@
+CREATE TABLE background_job_schedules (
+  job_type TEXT NOT NULL PRIMARY KEY,
+  cron TEXT NOT NULL,
+  timezone TEXT NOT NULL DEFAULT 'UTC',
+  paused BOOLEAN NOT NULL DEFAULT false,
+  paused_until TIMESTAMPTZ,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
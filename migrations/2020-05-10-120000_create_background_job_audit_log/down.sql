@@ -0,0 +1 @@
+DROP TABLE background_job_audit_log;
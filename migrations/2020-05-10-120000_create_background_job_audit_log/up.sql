@@ -0,0 +1,14 @@
+CREATE TABLE background_job_audit_log (
+  id BIGSERIAL PRIMARY KEY,
+  action TEXT NOT NULL,
+  actor TEXT NOT NULL,
+  reason TEXT,
+  job_id BIGINT,
+  job_type TEXT,
+  details JSONB NOT NULL DEFAULT '{}',
+  affected BIGINT NOT NULL,
+  performed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX background_job_audit_log_performed_at ON background_job_audit_log (performed_at);
+CREATE INDEX background_job_audit_log_job_id ON background_job_audit_log (job_id);
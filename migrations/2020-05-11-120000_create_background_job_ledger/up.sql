@@ -0,0 +1,8 @@
+CREATE TABLE background_job_ledger (
+  key TEXT NOT NULL PRIMARY KEY,
+  job_id BIGINT NOT NULL,
+  job_type TEXT NOT NULL,
+  completed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX background_job_ledger_completed_at ON background_job_ledger (completed_at);
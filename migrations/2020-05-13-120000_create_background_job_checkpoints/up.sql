@@ -0,0 +1,5 @@
+CREATE TABLE background_job_checkpoints (
+  job_id BIGINT NOT NULL PRIMARY KEY,
+  state JSONB NOT NULL,
+  saved_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1 @@
+ALTER TABLE background_job_schedules DROP COLUMN last_enqueued_at;
@@ -0,0 +1 @@
+ALTER TABLE background_job_schedules ADD COLUMN last_enqueued_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+ALTER TABLE background_jobs ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+CREATE INDEX background_jobs_priority_id ON background_jobs (priority DESC, id) WHERE dead_at IS NULL;
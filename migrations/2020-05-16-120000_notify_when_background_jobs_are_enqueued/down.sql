@@ -0,0 +1,2 @@
+DROP TRIGGER background_jobs_notify_enqueued ON background_jobs;
+DROP FUNCTION swirl_notify_job_enqueued();
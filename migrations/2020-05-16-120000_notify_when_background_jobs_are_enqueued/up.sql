@@ -0,0 +1,12 @@
+CREATE FUNCTION swirl_notify_job_enqueued() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('swirl_jobs', NEW.queue);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER background_jobs_notify_enqueued
+AFTER INSERT ON background_jobs
+FOR EACH ROW
+WHEN (NEW.retry_at IS NULL OR NEW.retry_at <= NOW())
+EXECUTE PROCEDURE swirl_notify_job_enqueued();
@@ -0,0 +1,2 @@
+DROP INDEX background_jobs_unique_key;
+ALTER TABLE background_jobs DROP COLUMN unique_key;
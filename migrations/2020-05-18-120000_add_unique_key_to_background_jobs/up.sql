@@ -0,0 +1,2 @@
+ALTER TABLE background_jobs ADD COLUMN unique_key TEXT;
+CREATE UNIQUE INDEX background_jobs_unique_key ON background_jobs (job_type, md5(unique_key)) WHERE dead_at IS NULL;
@@ -0,0 +1,4 @@
+CREATE TABLE background_job_cancellations (
+  job_id BIGINT NOT NULL PRIMARY KEY,
+  requested_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+ALTER TABLE background_jobs DROP COLUMN failed_at;
+ALTER TABLE background_jobs DROP COLUMN last_error;
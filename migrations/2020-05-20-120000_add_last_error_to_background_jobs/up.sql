@@ -0,0 +1,11 @@
+ALTER TABLE background_jobs ADD COLUMN last_error TEXT;
+ALTER TABLE background_jobs ADD COLUMN failed_at TIMESTAMPTZ;
+
+UPDATE background_jobs
+SET last_error = failures.error, failed_at = failures.failed_at
+FROM (
+  SELECT DISTINCT ON (job_id) job_id, error, failed_at
+  FROM background_job_failures
+  ORDER BY job_id, id DESC
+) failures
+WHERE background_jobs.id = failures.job_id;
@@ -0,0 +1,6 @@
+CREATE TABLE background_job_heartbeats (
+  job_id BIGINT NOT NULL PRIMARY KEY,
+  attempt INTEGER NOT NULL,
+  locked_by TEXT NOT NULL,
+  heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
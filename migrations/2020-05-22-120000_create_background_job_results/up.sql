@@ -0,0 +1,8 @@
+CREATE TABLE background_job_results (
+  job_id BIGINT NOT NULL PRIMARY KEY,
+  job_type TEXT NOT NULL,
+  result JSONB NOT NULL,
+  completed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX background_job_results_expires_at ON background_job_results (expires_at);
@@ -0,0 +1,6 @@
+CREATE TABLE background_job_dependencies (
+  job_id BIGINT NOT NULL,
+  depends_on BIGINT NOT NULL,
+  PRIMARY KEY (job_id, depends_on)
+);
+CREATE INDEX background_job_dependencies_depends_on ON background_job_dependencies (depends_on);
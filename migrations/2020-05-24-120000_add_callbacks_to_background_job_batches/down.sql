@@ -0,0 +1,2 @@
+ALTER TABLE background_job_batches DROP COLUMN on_failure;
+ALTER TABLE background_job_batches DROP COLUMN on_success;
@@ -0,0 +1,2 @@
+ALTER TABLE background_job_batches ADD COLUMN on_success BIGINT;
+ALTER TABLE background_job_batches ADD COLUMN on_failure BIGINT;
@@ -0,0 +1,4 @@
+CREATE TABLE background_job_paused_queues (
+  queue TEXT NOT NULL PRIMARY KEY,
+  paused_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
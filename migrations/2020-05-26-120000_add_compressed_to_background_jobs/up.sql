@@ -0,0 +1 @@
+ALTER TABLE background_jobs ADD COLUMN compressed BOOLEAN NOT NULL DEFAULT FALSE;
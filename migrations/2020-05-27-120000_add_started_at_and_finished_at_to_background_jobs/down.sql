@@ -0,0 +1 @@
+ALTER TABLE background_jobs DROP COLUMN started_at, DROP COLUMN finished_at;
@@ -0,0 +1,2 @@
+ALTER TABLE background_jobs ADD COLUMN started_at TIMESTAMPTZ,
+    ADD COLUMN finished_at TIMESTAMPTZ;
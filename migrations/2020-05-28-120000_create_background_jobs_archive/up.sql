@@ -0,0 +1,14 @@
+CREATE TABLE background_jobs_archive (
+  id BIGINT NOT NULL PRIMARY KEY,
+  job_type TEXT NOT NULL,
+  queue TEXT NOT NULL,
+  data JSONB NOT NULL,
+  binary_data BYTEA,
+  compressed BOOLEAN NOT NULL,
+  retries INTEGER NOT NULL,
+  batch_id TEXT,
+  enqueued_at TIMESTAMPTZ NOT NULL,
+  started_at TIMESTAMPTZ,
+  finished_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX background_jobs_archive_finished_at ON background_jobs_archive (finished_at);
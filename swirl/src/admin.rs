@@ -0,0 +1,734 @@
+//! Functions for inspecting the state of the job queue.
+//!
+//! These are intended to be used by operators and admin tooling, rather than
+//! by the runner itself. Everything they return can be serialized with serde,
+//! so an application can expose them as JSON from its own admin endpoints:
+//!
+//! ```ignore
+//! let failed = admin::list_failed_jobs(&conn, 100)?;
+//! let body = serde_json::to_string(&failed)?;
+//! ```
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Bool, Integer, Text, Timestamptz};
+use serde_derive::Serialize;
+
+use crate::schema::{
+    background_job_attempts, background_job_batches, background_job_heartbeats, background_jobs,
+};
+use crate::storage::paused_queues;
+use crate::{redact, registry};
+
+/// A summary of the jobs which have failed over some period of time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailureReport {
+    /// The total number of failures in this report
+    pub total_failures: i64,
+    /// The failures, grouped by job type and error. The most common failures
+    /// come first.
+    pub groups: Vec<FailureGroup>,
+}
+
+/// Failures of a single job type which failed with the same error.
+#[derive(Debug, Clone, PartialEq, QueryableByName, Serialize)]
+pub struct FailureGroup {
+    /// The type of job which failed
+    #[sql_type = "Text"]
+    pub job_type: String,
+    /// The error the jobs failed with, with any numbers in it replaced by `N`
+    #[sql_type = "Text"]
+    pub fingerprint: String,
+    /// The number of times this failure occurred
+    #[sql_type = "BigInt"]
+    pub failures: i64,
+    /// When this failure first occurred
+    #[sql_type = "Timestamptz"]
+    pub first_seen: DateTime<Utc>,
+    /// When this failure most recently occurred
+    #[sql_type = "Timestamptz"]
+    pub last_seen: DateTime<Utc>,
+}
+
+impl FailureGroup {
+    /// The percentage of all failures in the report which are in this group
+    pub fn percentage_of(&self, report: &FailureReport) -> f64 {
+        if report.total_failures == 0 {
+            0.0
+        } else {
+            self.failures as f64 * 100.0 / report.total_failures as f64
+        }
+    }
+}
+
+/// Summarizes every job failure which has occurred since the given time,
+/// grouped by job type and error.
+///
+/// Every failed attempt to run a job is counted, so a single job which has
+/// been retried 5 times will count as 5 failures.
+pub fn failure_report(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<FailureReport> {
+    let groups = sql_query(
+        "SELECT job_type, fingerprint, COUNT(*) AS failures, \
+         MIN(failed_at) AS first_seen, MAX(failed_at) AS last_seen \
+         FROM background_job_failures \
+         WHERE failed_at >= $1 \
+         GROUP BY job_type, fingerprint \
+         ORDER BY failures DESC, job_type, fingerprint",
+    )
+    .bind::<Timestamptz, _>(since)
+    .load::<FailureGroup>(conn)?;
+
+    Ok(FailureReport {
+        total_failures: groups.iter().map(|g| g.failures).sum(),
+        groups,
+    })
+}
+
+/// The progress of the jobs enqueued with
+/// [`Job::enqueue_in_batch`](crate::Job::enqueue_in_batch)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BatchProgress {
+    /// Jobs which have not run yet, or which failed and will be retried
+    pub pending: i64,
+    /// Jobs which ran successfully
+    pub succeeded: i64,
+    /// Jobs which failed permanently, and have been marked as dead
+    pub failed: i64,
+}
+
+impl BatchProgress {
+    /// The total number of jobs in the batch
+    pub fn total(&self) -> i64 {
+        self.pending + self.succeeded + self.failed
+    }
+
+    /// Returns `true` if no jobs in the batch are left to run
+    pub fn is_finished(&self) -> bool {
+        self.pending == 0
+    }
+}
+
+/// Counts the jobs in the given batch which are pending, have succeeded, and
+/// have failed permanently.
+///
+/// A batch which no jobs have been enqueued in has every count set to 0.
+pub fn batch_progress(conn: &PgConnection, batch_id: &str) -> QueryResult<BatchProgress> {
+    let in_batch = background_jobs::table.filter(background_jobs::batch_id.eq(batch_id));
+    let pending = in_batch
+        .filter(background_jobs::dead_at.is_null())
+        .count()
+        .get_result(conn)?;
+    let failed = in_batch
+        .filter(background_jobs::dead_at.is_not_null())
+        .count()
+        .get_result(conn)?;
+    let succeeded = background_job_batches::table
+        .find(batch_id)
+        .select(background_job_batches::succeeded)
+        .get_result(conn)
+        .optional()?
+        .unwrap_or(0);
+
+    Ok(BatchProgress {
+        pending,
+        succeeded,
+        failed,
+    })
+}
+
+/// How an attempt to run a job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptOutcome {
+    /// The job ran successfully
+    Succeeded,
+    /// The job returned an error
+    Failed,
+    /// The job panicked
+    Panicked,
+    /// The job [snoozed](crate::PerformError::Snooze)
+    Snoozed,
+}
+
+impl AttemptOutcome {
+    /// The name this outcome is stored as
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttemptOutcome::Succeeded => "succeeded",
+            AttemptOutcome::Failed => "failed",
+            AttemptOutcome::Panicked => "panicked",
+            AttemptOutcome::Snoozed => "snoozed",
+        }
+    }
+
+    fn from_str(outcome: &str) -> Option<Self> {
+        match outcome {
+            "succeeded" => Some(AttemptOutcome::Succeeded),
+            "failed" => Some(AttemptOutcome::Failed),
+            "panicked" => Some(AttemptOutcome::Panicked),
+            "snoozed" => Some(AttemptOutcome::Snoozed),
+            _ => None,
+        }
+    }
+}
+
+/// A single attempt to run a job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attempt {
+    /// The id of this attempt
+    pub id: i64,
+    /// The id of the job
+    pub job_id: i64,
+    /// The type of the job
+    pub job_type: String,
+    /// Which attempt this was, starting from 1
+    pub attempt: i32,
+    /// The process and thread which ran the job
+    pub worker: String,
+    /// How the attempt ended
+    pub outcome: AttemptOutcome,
+    /// The error the attempt failed with, if it failed
+    pub error: Option<String>,
+    /// When the attempt started
+    pub started_at: DateTime<Utc>,
+    /// How long the attempt took, in milliseconds
+    pub duration_ms: i64,
+}
+
+type AttemptRow = (
+    i64,
+    i64,
+    String,
+    i32,
+    String,
+    String,
+    Option<String>,
+    DateTime<Utc>,
+    i64,
+);
+
+impl Attempt {
+    fn from_row(row: AttemptRow) -> QueryResult<Self> {
+        let (id, job_id, job_type, attempt, worker, outcome, error, started_at, duration_ms) = row;
+        let outcome = AttemptOutcome::from_str(&outcome).ok_or_else(|| {
+            diesel::result::Error::DeserializationError(
+                format!("Unknown attempt outcome {:?}", outcome).into(),
+            )
+        })?;
+        Ok(Self {
+            id,
+            job_id,
+            job_type,
+            attempt,
+            worker,
+            outcome,
+            error,
+            started_at,
+            duration_ms,
+        })
+    }
+}
+
+fn load_attempts<Q>(conn: &PgConnection, query: Q) -> QueryResult<Vec<Attempt>>
+where
+    Q: diesel::query_dsl::LoadQuery<PgConnection, AttemptRow>,
+{
+    query
+        .load::<AttemptRow>(conn)?
+        .into_iter()
+        .map(Attempt::from_row)
+        .collect()
+}
+
+/// Every recorded attempt to run the given job, oldest first
+pub fn job_attempts(conn: &PgConnection, job_id: i64) -> QueryResult<Vec<Attempt>> {
+    load_attempts(
+        conn,
+        background_job_attempts::table
+            .filter(background_job_attempts::job_id.eq(job_id))
+            .order(background_job_attempts::id),
+    )
+}
+
+/// The most recent attempts to run jobs of the given type, newest first.
+///
+/// This is useful for finding jobs which fail intermittently.
+pub fn recent_attempts(
+    conn: &PgConnection,
+    job_type: &str,
+    limit: i64,
+) -> QueryResult<Vec<Attempt>> {
+    load_attempts(
+        conn,
+        background_job_attempts::table
+            .filter(background_job_attempts::job_type.eq(job_type))
+            .order(background_job_attempts::id.desc())
+            .limit(limit),
+    )
+}
+
+/// The attempts to run jobs which started in one hour
+#[derive(Debug, Clone, PartialEq, Eq, QueryableByName, Serialize)]
+pub struct Throughput {
+    /// The start of the hour
+    #[sql_type = "Timestamptz"]
+    pub hour: DateTime<Utc>,
+    /// How many attempts succeeded
+    #[sql_type = "BigInt"]
+    pub succeeded: i64,
+    /// How many attempts failed or panicked
+    #[sql_type = "BigInt"]
+    pub failed: i64,
+}
+
+/// How many attempts to run jobs succeeded and failed in each hour since
+/// `since`, oldest first. Hours without any attempts are left out.
+pub fn throughput(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<Vec<Throughput>> {
+    sql_query(
+        "SELECT date_trunc('hour', started_at) AS hour, \
+             COUNT(*) FILTER (WHERE outcome = 'succeeded') AS succeeded, \
+             COUNT(*) FILTER (WHERE outcome IN ('failed', 'panicked')) AS failed \
+         FROM background_job_attempts \
+         WHERE started_at >= $1 \
+         GROUP BY hour \
+         ORDER BY hour",
+    )
+    .bind::<Timestamptz, _>(since)
+    .load(conn)
+}
+
+/// The jobs on one queue
+#[derive(Debug, Clone, PartialEq, Eq, QueryableByName, Serialize)]
+pub struct QueueStats {
+    /// The name of the queue
+    #[sql_type = "Text"]
+    pub queue: String,
+    /// Jobs which haven't failed yet, including jobs which are running
+    #[sql_type = "BigInt"]
+    pub pending: i64,
+    /// Jobs which have failed, and will be retried
+    #[sql_type = "BigInt"]
+    pub failed: i64,
+    /// Jobs which have been marked as dead
+    #[sql_type = "BigInt"]
+    pub dead: i64,
+    /// Whether the queue has been [paused](crate::storage::pause_queue)
+    #[sql_type = "Bool"]
+    pub paused: bool,
+}
+
+/// The jobs on each queue which has any, or which is paused, by name
+pub fn queue_stats(conn: &PgConnection) -> QueryResult<Vec<QueueStats>> {
+    let mut stats = sql_query(
+        "SELECT queue, \
+             COUNT(*) FILTER (WHERE dead_at IS NULL AND retries = 0) AS pending, \
+             COUNT(*) FILTER (WHERE dead_at IS NULL AND retries > 0) AS failed, \
+             COUNT(*) FILTER (WHERE dead_at IS NOT NULL) AS dead, \
+             FALSE AS paused \
+         FROM background_jobs \
+         GROUP BY queue",
+    )
+    .load::<QueueStats>(conn)?;
+    for queue in paused_queues(conn)? {
+        match stats.iter_mut().find(|s| s.queue == queue) {
+            Some(queue_stats) => queue_stats.paused = true,
+            None => stats.push(QueueStats {
+                queue,
+                pending: 0,
+                failed: 0,
+                dead: 0,
+                paused: true,
+            }),
+        }
+    }
+    stats.sort_by(|a, b| a.queue.cmp(&b.queue));
+    Ok(stats)
+}
+
+/// A job which failed permanently, and will not be run again unless it is
+/// requeued
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeadJob {
+    /// The id of the job
+    pub id: i64,
+    /// The type of the job
+    pub job_type: String,
+    /// The queue the job is on
+    pub queue: String,
+    /// The serialized arguments of the job, with the values of
+    /// [redacted fields](crate::Job::REDACTED_FIELDS) masked
+    pub data: serde_json::Value,
+    /// The number of times the job was retried before it was marked as dead
+    pub retries: i32,
+    /// When the job was marked as dead
+    pub dead_at: DateTime<Utc>,
+    /// The error the job last failed with, if it was recorded
+    pub last_error: Option<String>,
+}
+
+/// The jobs which have been marked as dead, oldest first. If `job_type` is
+/// given, only jobs of that type are listed.
+pub fn dead_jobs(
+    conn: &PgConnection,
+    job_type: Option<&str>,
+    limit: i64,
+) -> QueryResult<Vec<DeadJob>> {
+    let mut query = background_jobs::table
+        .select((
+            background_jobs::id,
+            background_jobs::job_type,
+            background_jobs::queue,
+            background_jobs::data,
+            background_jobs::retries,
+            background_jobs::dead_at,
+            background_jobs::last_error,
+        ))
+        .filter(background_jobs::dead_at.is_not_null())
+        .order(background_jobs::id)
+        .limit(limit)
+        .into_boxed();
+    if let Some(job_type) = job_type {
+        query = query.filter(background_jobs::job_type.eq(job_type));
+    }
+    let rows = query.load::<(
+        i64,
+        String,
+        String,
+        serde_json::Value,
+        i32,
+        Option<DateTime<Utc>>,
+        Option<String>,
+    )>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(
+            |(id, job_type, queue, mut data, retries, dead_at, last_error)| {
+                redact::data(
+                    &mut data,
+                    registry::redacted_fields(&job_type).unwrap_or_default(),
+                );
+                Some(DeadJob {
+                    id,
+                    job_type,
+                    queue,
+                    data,
+                    retries,
+                    dead_at: dead_at?,
+                    last_error,
+                })
+            },
+        )
+        .collect())
+}
+
+/// The [redacted fields](crate::Job::REDACTED_FIELDS) of a job type, which
+/// are masked when jobs are listed. This is `None` if the job type isn't
+/// registered with [`register_job!`](crate::register_job) in this binary, so
+/// it isn't known which of its arguments are secret.
+pub fn redacted_fields(job_type: &str) -> Option<&'static [&'static str]> {
+    registry::redacted_fields(job_type)
+}
+
+/// A job which has failed at least once
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedJob {
+    /// The id of the job
+    pub id: i64,
+    /// The type of the job
+    pub job_type: String,
+    /// The queue the job is on
+    pub queue: String,
+    /// The number of times the job has failed
+    pub retries: i32,
+    /// The error the job last failed with, if it was recorded
+    pub last_error: Option<String>,
+    /// When the job last failed, if it was recorded
+    pub failed_at: Option<DateTime<Utc>>,
+    /// Whether the job has been marked as dead, and will not be retried
+    /// unless it is requeued
+    pub dead: bool,
+}
+
+/// The jobs which have failed at least once, including dead jobs, most
+/// recently failed first
+pub fn list_failed_jobs(conn: &PgConnection, limit: i64) -> QueryResult<Vec<FailedJob>> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let rows = background_jobs
+        .select((
+            id,
+            job_type,
+            queue,
+            retries,
+            last_error,
+            failed_at,
+            dead_at.is_not_null(),
+        ))
+        .filter(retries.gt(0).or(dead_at.is_not_null()))
+        .order((failed_at.desc().nulls_last(), id.desc()))
+        .limit(limit)
+        .load::<(
+            i64,
+            String,
+            String,
+            i32,
+            Option<String>,
+            Option<DateTime<Utc>>,
+            bool,
+        )>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(id_, job_type_, queue_, retries_, last_error_, failed_at_, dead)| FailedJob {
+                id: id_,
+                job_type: job_type_,
+                queue: queue_,
+                retries: retries_,
+                last_error: last_error_,
+                failed_at: failed_at_,
+                dead,
+            },
+        )
+        .collect())
+}
+
+/// Make a dead job pending again, with its retry count reset. Returns whether
+/// the job was dead.
+///
+/// The job no longer has a [unique key](crate::Job::unique_key), so it is run
+/// even if a job with the same key has been enqueued since it died.
+pub fn requeue_dead_job(conn: &PgConnection, job_id: i64) -> QueryResult<bool> {
+    use crate::schema::background_jobs::dsl::*;
+
+    diesel::delete(background_job_heartbeats::table.find(job_id)).execute(conn)?;
+    let requeued = diesel::update(background_jobs.find(job_id).filter(dead_at.is_not_null()))
+        .set((
+            dead_at.eq(None::<DateTime<Utc>>),
+            retries.eq(0),
+            last_retry.eq(never_retried()),
+            retry_at.eq(None::<DateTime<Utc>>),
+            unique_key.eq(None::<String>),
+        ))
+        .execute(conn)?;
+    Ok(requeued > 0)
+}
+
+/// Make every dead job of the given type pending again, with their retry
+/// counts reset and their unique keys removed. Returns how many jobs were
+/// requeued.
+pub fn requeue_dead_jobs(conn: &PgConnection, dead_job_type: &str) -> QueryResult<usize> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let dead_jobs = background_jobs
+        .select(id)
+        .filter(job_type.eq(dead_job_type))
+        .filter(dead_at.is_not_null())
+        .load::<i64>(conn)?;
+    diesel::delete(
+        background_job_heartbeats::table
+            .filter(background_job_heartbeats::job_id.eq_any(dead_jobs)),
+    )
+    .execute(conn)?;
+    diesel::update(
+        background_jobs
+            .filter(job_type.eq(dead_job_type))
+            .filter(dead_at.is_not_null()),
+    )
+    .set((
+        dead_at.eq(None::<DateTime<Utc>>),
+        retries.eq(0),
+        last_retry.eq(never_retried()),
+        retry_at.eq(None::<DateTime<Utc>>),
+        unique_key.eq(None::<String>),
+    ))
+    .execute(conn)
+}
+
+/// What [`retry_job`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOutcome {
+    /// The job will be run again as soon as a runner is free
+    Retried,
+    /// The job hasn't failed, so it is already waiting to be run
+    NotFailed,
+    /// The job is being run
+    Running,
+    /// The job is no longer in the queue
+    NotFound,
+}
+
+/// Run a job which has failed again right away.
+///
+/// Dead jobs are requeued like with [`requeue_dead_job`], and jobs waiting to
+/// be retried skip the rest of their backoff. Jobs which are running are left
+/// alone, since their rows are locked.
+pub fn retry_job(conn: &PgConnection, job_id: i64) -> QueryResult<RetryOutcome> {
+    use crate::schema::background_jobs::dsl::*;
+
+    conn.transaction(|| {
+        let unlocked = background_jobs
+            .find(job_id)
+            .select((retries, dead_at.is_not_null()))
+            .for_update()
+            .skip_locked()
+            .first::<(i32, bool)>(conn)
+            .optional()?;
+        match unlocked {
+            Some((_, true)) => {
+                requeue_dead_job(conn, job_id)?;
+                Ok(RetryOutcome::Retried)
+            }
+            Some((0, false)) => Ok(RetryOutcome::NotFailed),
+            Some((_, false)) => {
+                diesel::update(background_jobs.find(job_id))
+                    .set(retry_at.eq(diesel::dsl::now))
+                    .execute(conn)?;
+                Ok(RetryOutcome::Retried)
+            }
+            None => {
+                let exists = diesel::select(diesel::dsl::exists(background_jobs.find(job_id)))
+                    .get_result(conn)?;
+                if exists {
+                    Ok(RetryOutcome::Running)
+                } else {
+                    Ok(RetryOutcome::NotFound)
+                }
+            }
+        }
+    })
+}
+
+/// Remove a job from the queue, whether it is pending, waiting to be retried,
+/// or dead. Returns whether the job was removed.
+///
+/// Jobs which are running are left alone, since their rows are locked. Use
+/// [`cancel`](crate::cancel) to stop them instead.
+pub fn delete_job(conn: &PgConnection, job_id: i64) -> QueryResult<bool> {
+    use crate::schema::background_jobs::dsl::*;
+
+    conn.transaction(|| {
+        let unlocked = background_jobs
+            .find(job_id)
+            .select(id)
+            .for_update()
+            .skip_locked()
+            .first::<i64>(conn)
+            .optional()?;
+        if unlocked.is_none() {
+            return Ok(false);
+        }
+        crate::storage::discard(conn, job_id)?;
+        Ok(true)
+    })
+}
+
+/// Remove every job of the given type which isn't running, whether it is
+/// pending, waiting to be retried, or dead. Returns how many jobs were
+/// removed.
+pub fn delete_jobs(conn: &PgConnection, delete_job_type: &str) -> QueryResult<usize> {
+    use crate::schema::background_jobs::dsl::*;
+
+    conn.transaction(|| {
+        let unlocked = background_jobs
+            .select(id)
+            .filter(job_type.eq(delete_job_type))
+            .for_update()
+            .skip_locked()
+            .load::<i64>(conn)?;
+        for &job_id in &unlocked {
+            crate::storage::discard(conn, job_id)?;
+        }
+        Ok(unlocked.len())
+    })
+}
+
+/// The last heartbeat recorded for an attempt to run a job, when the runner
+/// has a [`heartbeat_interval`](crate::Builder::heartbeat_interval)
+#[derive(Debug, Clone, PartialEq, QueryableByName, Serialize)]
+pub struct JobHeartbeat {
+    /// The id of the job
+    #[sql_type = "BigInt"]
+    pub job_id: i64,
+    /// The type of the job
+    #[sql_type = "Text"]
+    pub job_type: String,
+    /// The queue the job is on
+    #[sql_type = "Text"]
+    pub queue: String,
+    /// The number of times the job had been retried before this attempt
+    #[sql_type = "Integer"]
+    pub retries: i32,
+    /// The process and thread which was running the job
+    #[sql_type = "Text"]
+    pub locked_by: String,
+    /// The last time the worker recorded that it was still running the job
+    #[sql_type = "Timestamptz"]
+    pub heartbeat_at: DateTime<Utc>,
+    /// Whether the job's row is still locked
+    #[sql_type = "Bool"]
+    pub running: bool,
+}
+
+/// The heartbeats of attempts which haven't finished
+fn unfinished_attempts(conn: &PgConnection) -> QueryResult<Vec<JobHeartbeat>> {
+    // Rows which are locked by a runner are left out of `unlocked`
+    sql_query(
+        "WITH unlocked AS ( \
+             SELECT id FROM background_jobs \
+             WHERE id IN (SELECT job_id FROM background_job_heartbeats) \
+             FOR KEY SHARE SKIP LOCKED \
+         ) \
+         SELECT j.id AS job_id, j.job_type, j.queue, j.retries, h.locked_by, h.heartbeat_at, \
+             j.id NOT IN (SELECT id FROM unlocked) AS running \
+         FROM background_job_heartbeats h \
+         INNER JOIN background_jobs j ON j.id = h.job_id \
+         WHERE h.attempt = j.retries AND j.dead_at IS NULL \
+         ORDER BY j.id",
+    )
+    .load(conn)
+}
+
+/// Jobs whose worker stopped while running them, before recording whether
+/// they succeeded or failed, such as when its process was killed.
+///
+/// The job's row was unlocked when its worker's connection was closed, so it
+/// will be run again. Only attempts which recorded a heartbeat are found.
+pub fn abandoned_jobs(conn: &PgConnection) -> QueryResult<Vec<JobHeartbeat>> {
+    let mut heartbeats = unfinished_attempts(conn)?;
+    heartbeats.retain(|heartbeat| !heartbeat.running);
+    Ok(heartbeats)
+}
+
+/// Jobs which are still locked by a worker, but haven't had a heartbeat
+/// recorded since `since`.
+///
+/// Their worker is still connected to the database, but has stopped
+/// responding, such as when its process is frozen, or its heartbeat thread
+/// can't get a connection.
+pub fn stalled_jobs(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<Vec<JobHeartbeat>> {
+    let mut heartbeats = unfinished_attempts(conn)?;
+    heartbeats.retain(|heartbeat| heartbeat.running && heartbeat.heartbeat_at < since);
+    Ok(heartbeats)
+}
+
+/// Forget the operations recorded by
+/// [`JobContext::exactly_once`](crate::JobContext::exactly_once) before the
+/// given time. Returns how many were removed.
+///
+/// An operation which is forgotten will be run again if a job with its key is
+/// run, so this should only remove operations old enough that their jobs can
+/// no longer be retried.
+pub fn purge_ledger(conn: &PgConnection, before: DateTime<Utc>) -> QueryResult<usize> {
+    use crate::schema::background_job_ledger::dsl::*;
+
+    diesel::delete(background_job_ledger.filter(completed_at.lt(before))).execute(conn)
+}
+
+/// The `last_retry` of a newly enqueued job, so requeued jobs are run right
+/// away instead of waiting out a retry delay
+fn never_retried() -> NaiveDateTime {
+    NaiveDateTime::default()
+}
@@ -0,0 +1,434 @@
+//! A record of the changes operators make to the job queue.
+//!
+//! Changes made through an [`Auditor`] are recorded in the same transaction
+//! as the change itself, along with who made it and why, so every change to
+//! the queue can be traced back to a person or tool:
+//!
+//! ```ignore
+//! let auditor = audit::Auditor::new(&conn, "alice@example.com")
+//!     .reason("INC-1234: payment provider is back up");
+//! auditor.requeue_dead_jobs("charge_card")?;
+//!
+//! for entry in audit::entries(&conn, Utc::now() - Duration::days(7))? {
+//!     println!("{} {} {:?}", entry.actor, entry.action.as_str(), entry.reason);
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use diesel::insert_into;
+use diesel::prelude::*;
+use serde_json::json;
+
+use crate::errors::EnqueueError;
+#[cfg(feature = "schedule")]
+use crate::schedule::{self, ScheduleError};
+use crate::schema::background_job_audit_log;
+use crate::{admin, outbox, storage, Job};
+
+/// A change which was made to the job queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditAction {
+    /// A dead job was made pending again
+    RequeueDeadJob,
+    /// Every dead job of a type was made pending again
+    RequeueDeadJobs,
+    /// Pending jobs were removed from the queue
+    Cancel,
+    /// A job which wasn't running was removed from the queue
+    DeleteJob,
+    /// Every job of a type which wasn't running was removed from the queue
+    DeleteJobs,
+    /// Runners stopped starting jobs on a queue
+    PauseQueue,
+    /// Runners started running jobs on a paused queue again
+    ResumeQueue,
+    /// Delivered messages were deleted from the outbox
+    PurgeDelivered,
+    /// A job type's schedule was created or replaced
+    SetSchedule,
+    /// A job type's schedule was paused
+    PauseSchedule,
+    /// A job type's schedule was resumed
+    ResumeSchedule,
+    /// A job type's schedule was removed
+    RemoveSchedule,
+}
+
+impl AuditAction {
+    /// The name of the action, as it is stored in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::RequeueDeadJob => "requeue_dead_job",
+            AuditAction::RequeueDeadJobs => "requeue_dead_jobs",
+            AuditAction::Cancel => "cancel",
+            AuditAction::DeleteJob => "delete_job",
+            AuditAction::DeleteJobs => "delete_jobs",
+            AuditAction::PauseQueue => "pause_queue",
+            AuditAction::ResumeQueue => "resume_queue",
+            AuditAction::PurgeDelivered => "purge_delivered",
+            AuditAction::SetSchedule => "set_schedule",
+            AuditAction::PauseSchedule => "pause_schedule",
+            AuditAction::ResumeSchedule => "resume_schedule",
+            AuditAction::RemoveSchedule => "remove_schedule",
+        }
+    }
+
+    fn from_str(action: &str) -> Option<Self> {
+        match action {
+            "requeue_dead_job" => Some(AuditAction::RequeueDeadJob),
+            "requeue_dead_jobs" => Some(AuditAction::RequeueDeadJobs),
+            "cancel" => Some(AuditAction::Cancel),
+            "delete_job" => Some(AuditAction::DeleteJob),
+            "delete_jobs" => Some(AuditAction::DeleteJobs),
+            "pause_queue" => Some(AuditAction::PauseQueue),
+            "resume_queue" => Some(AuditAction::ResumeQueue),
+            "purge_delivered" => Some(AuditAction::PurgeDelivered),
+            "set_schedule" => Some(AuditAction::SetSchedule),
+            "pause_schedule" => Some(AuditAction::PauseSchedule),
+            "resume_schedule" => Some(AuditAction::ResumeSchedule),
+            "remove_schedule" => Some(AuditAction::RemoveSchedule),
+            _ => None,
+        }
+    }
+}
+
+/// A recorded change to the job queue
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// The id of this entry
+    pub id: i64,
+    /// What was changed
+    pub action: AuditAction,
+    /// Who made the change
+    pub actor: String,
+    /// Why the change was made, if a reason was given
+    pub reason: Option<String>,
+    /// The job which was changed, if the action was on a single job
+    pub job_id: Option<i64>,
+    /// The type of the jobs which were changed, if the action was on a job
+    /// type
+    pub job_type: Option<String>,
+    /// The arguments of the action, such as the cron expression of a schedule
+    pub details: serde_json::Value,
+    /// How many rows the action changed. An action which changed nothing is
+    /// still recorded.
+    pub affected: i64,
+    /// When the change was made
+    pub performed_at: DateTime<Utc>,
+}
+
+type AuditRow = (
+    i64,
+    String,
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    serde_json::Value,
+    i64,
+    DateTime<Utc>,
+);
+
+impl AuditEntry {
+    fn from_row(row: AuditRow) -> QueryResult<Self> {
+        let (id, action, actor, reason, job_id, job_type, details, affected, performed_at) = row;
+        let action = AuditAction::from_str(&action).ok_or_else(|| {
+            diesel::result::Error::DeserializationError(
+                format!("Unknown audit action {:?}", action).into(),
+            )
+        })?;
+        Ok(Self {
+            id,
+            action,
+            actor,
+            reason,
+            job_id,
+            job_type,
+            details,
+            affected,
+            performed_at,
+        })
+    }
+}
+
+/// Makes changes to the job queue on behalf of an actor, recording each one
+/// in the audit log.
+///
+/// Each change and its entry are committed together, so a change which fails
+/// is not recorded.
+#[derive(Clone, Copy)]
+#[allow(missing_debug_implementations)]
+pub struct Auditor<'a> {
+    conn: &'a PgConnection,
+    actor: &'a str,
+    reason: Option<&'a str>,
+}
+
+impl<'a> Auditor<'a> {
+    /// Make changes as `actor`, such as the email address of an operator or
+    /// the name of a tool
+    pub fn new(conn: &'a PgConnection, actor: &'a str) -> Self {
+        Self {
+            conn,
+            actor,
+            reason: None,
+        }
+    }
+
+    /// Record why the changes are being made, such as a ticket number
+    pub fn reason(mut self, reason: &'a str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    /// See [`admin::requeue_dead_job`]
+    pub fn requeue_dead_job(&self, job_id: i64) -> QueryResult<bool> {
+        self.conn.transaction(|| {
+            let requeued = admin::requeue_dead_job(self.conn, job_id)?;
+            self.record(
+                AuditAction::RequeueDeadJob,
+                Some(job_id),
+                None,
+                json!({}),
+                requeued as usize,
+            )?;
+            Ok(requeued)
+        })
+    }
+
+    /// See [`admin::requeue_dead_jobs`]
+    pub fn requeue_dead_jobs(&self, job_type: &str) -> QueryResult<usize> {
+        self.conn.transaction(|| {
+            let requeued = admin::requeue_dead_jobs(self.conn, job_type)?;
+            self.record(
+                AuditAction::RequeueDeadJobs,
+                None,
+                Some(job_type),
+                json!({}),
+                requeued,
+            )?;
+            Ok(requeued)
+        })
+    }
+
+    /// See [`Client::cancel`](crate::Client::cancel)
+    pub fn cancel<T: Job>(&self, job: T) -> Result<usize, EnqueueError> {
+        let data = serde_json::to_value(&job)?;
+        self.conn.transaction(|| {
+            let cancelled = storage::cancel_pending_jobs(self.conn, job)?;
+            self.record(
+                AuditAction::Cancel,
+                None,
+                Some(T::JOB_TYPE),
+                json!({ "data": data }),
+                cancelled,
+            )?;
+            Ok(cancelled)
+        })
+    }
+
+    /// See [`admin::delete_job`]
+    pub fn delete_job(&self, job_id: i64) -> QueryResult<bool> {
+        self.conn.transaction(|| {
+            let deleted = admin::delete_job(self.conn, job_id)?;
+            self.record(
+                AuditAction::DeleteJob,
+                Some(job_id),
+                None,
+                json!({}),
+                deleted as usize,
+            )?;
+            Ok(deleted)
+        })
+    }
+
+    /// See [`admin::delete_jobs`]
+    pub fn delete_jobs(&self, job_type: &str) -> QueryResult<usize> {
+        self.conn.transaction(|| {
+            let deleted = admin::delete_jobs(self.conn, job_type)?;
+            self.record(
+                AuditAction::DeleteJobs,
+                None,
+                Some(job_type),
+                json!({}),
+                deleted,
+            )?;
+            Ok(deleted)
+        })
+    }
+
+    /// See [`storage::pause_queue`]
+    pub fn pause_queue(&self, queue: &str) -> QueryResult<bool> {
+        self.conn.transaction(|| {
+            let paused = storage::pause_queue(self.conn, queue)?;
+            self.record(
+                AuditAction::PauseQueue,
+                None,
+                None,
+                json!({ "queue": queue }),
+                paused as usize,
+            )?;
+            Ok(paused)
+        })
+    }
+
+    /// See [`storage::resume_queue`]
+    pub fn resume_queue(&self, queue: &str) -> QueryResult<bool> {
+        self.conn.transaction(|| {
+            let resumed = storage::resume_queue(self.conn, queue)?;
+            self.record(
+                AuditAction::ResumeQueue,
+                None,
+                None,
+                json!({ "queue": queue }),
+                resumed as usize,
+            )?;
+            Ok(resumed)
+        })
+    }
+
+    /// See [`outbox::purge_delivered`]
+    pub fn purge_delivered(&self, before: DateTime<Utc>) -> QueryResult<usize> {
+        self.conn.transaction(|| {
+            let purged = outbox::purge_delivered(self.conn, before)?;
+            self.record(
+                AuditAction::PurgeDelivered,
+                None,
+                None,
+                json!({ "before": before.to_rfc3339() }),
+                purged,
+            )?;
+            Ok(purged)
+        })
+    }
+
+    /// See [`schedule::set`]
+    #[cfg(feature = "schedule")]
+    pub fn set_schedule(
+        &self,
+        job_type: &str,
+        cron: &str,
+        timezone: &str,
+    ) -> Result<(), ScheduleError> {
+        self.conn.transaction(|| {
+            schedule::set(self.conn, job_type, cron, timezone)?;
+            self.record(
+                AuditAction::SetSchedule,
+                None,
+                Some(job_type),
+                json!({ "cron": cron, "timezone": timezone }),
+                1,
+            )?;
+            Ok(())
+        })
+    }
+
+    /// See [`schedule::pause`]
+    #[cfg(feature = "schedule")]
+    pub fn pause_schedule(
+        &self,
+        job_type: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), ScheduleError> {
+        self.conn.transaction(|| {
+            schedule::pause(self.conn, job_type, until)?;
+            self.record(
+                AuditAction::PauseSchedule,
+                None,
+                Some(job_type),
+                json!({ "until": until.map(|until| until.to_rfc3339()) }),
+                1,
+            )?;
+            Ok(())
+        })
+    }
+
+    /// See [`schedule::resume`]
+    #[cfg(feature = "schedule")]
+    pub fn resume_schedule(&self, job_type: &str) -> Result<(), ScheduleError> {
+        self.conn.transaction(|| {
+            schedule::resume(self.conn, job_type)?;
+            self.record(
+                AuditAction::ResumeSchedule,
+                None,
+                Some(job_type),
+                json!({}),
+                1,
+            )?;
+            Ok(())
+        })
+    }
+
+    /// See [`schedule::remove`]
+    #[cfg(feature = "schedule")]
+    pub fn remove_schedule(&self, job_type: &str) -> QueryResult<bool> {
+        self.conn.transaction(|| {
+            let removed = schedule::remove(self.conn, job_type)?;
+            self.record(
+                AuditAction::RemoveSchedule,
+                None,
+                Some(job_type),
+                json!({}),
+                removed as usize,
+            )?;
+            Ok(removed)
+        })
+    }
+
+    fn record(
+        &self,
+        action: AuditAction,
+        job_id: Option<i64>,
+        job_type: Option<&str>,
+        details: serde_json::Value,
+        affected: usize,
+    ) -> QueryResult<()> {
+        use crate::schema::background_job_audit_log::dsl;
+
+        insert_into(background_job_audit_log::table)
+            .values((
+                dsl::action.eq(action.as_str()),
+                dsl::actor.eq(self.actor),
+                dsl::reason.eq(self.reason),
+                dsl::job_id.eq(job_id),
+                dsl::job_type.eq(job_type),
+                dsl::details.eq(details),
+                dsl::affected.eq(affected as i64),
+            ))
+            .execute(self.conn)?;
+        Ok(())
+    }
+}
+
+fn load_entries<Q>(conn: &PgConnection, query: Q) -> QueryResult<Vec<AuditEntry>>
+where
+    Q: diesel::query_dsl::LoadQuery<PgConnection, AuditRow>,
+{
+    query
+        .load::<AuditRow>(conn)?
+        .into_iter()
+        .map(AuditEntry::from_row)
+        .collect()
+}
+
+/// Every change recorded since the given time, oldest first
+pub fn entries(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<Vec<AuditEntry>> {
+    load_entries(
+        conn,
+        background_job_audit_log::table
+            .filter(background_job_audit_log::performed_at.ge(since))
+            .order(background_job_audit_log::id),
+    )
+}
+
+/// Every change recorded for the given job, oldest first. Changes to every
+/// job of its type, such as
+/// [`requeue_dead_jobs`](Auditor::requeue_dead_jobs), are not included.
+pub fn job_entries(conn: &PgConnection, job_id: i64) -> QueryResult<Vec<AuditEntry>> {
+    load_entries(
+        conn,
+        background_job_audit_log::table
+            .filter(background_job_audit_log::job_id.eq(job_id))
+            .order(background_job_audit_log::id),
+    )
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// How long to wait before retrying a job which failed.
+///
+/// The delay doubles after each failure, starting from `base`, until it
+/// reaches `max`. With jitter, each delay is shortened by a random amount, so
+/// jobs which failed together, such as when a service they depend on went
+/// down, are not all retried at the same moment.
+///
+/// ```ignore
+/// const SLOW: Backoff = Backoff::exponential(Duration::from_secs(30), Duration::from_secs(3600))
+///     .jitter(0.5);
+///
+/// let runner = Runner::builder(env).backoff(SLOW).build();
+/// ```
+///
+/// Without a backoff, jobs are retried after `2 ^ {retry_count}` minutes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: f64,
+}
+
+impl Backoff {
+    /// Wait `base` before the first retry, doubling the delay after each
+    /// failure up to `max`
+    pub const fn exponential(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            jitter: 0.0,
+        }
+    }
+
+    /// Wait the same amount of time before every retry
+    pub const fn fixed(delay: Duration) -> Self {
+        Self::exponential(delay, delay)
+    }
+
+    /// Shorten each delay by a random amount, up to `fraction` of it.
+    ///
+    /// A fraction of 0 disables jitter, and a fraction of 1 makes each delay
+    /// anywhere between zero and its full length. Fractions outside of that
+    /// range are clamped to it.
+    pub const fn jitter(self, fraction: f64) -> Self {
+        Self {
+            jitter: fraction,
+            ..self
+        }
+    }
+
+    /// The delay before the retry after the job has failed `retries` times,
+    /// before jitter is applied
+    pub fn delay(&self, retries: u32) -> Duration {
+        let factor = 2u32.checked_pow(retries.saturating_sub(1));
+        factor
+            .and_then(|factor| self.base.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    pub(crate) fn jitter_fraction(&self) -> f64 {
+        if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        }
+    }
+}
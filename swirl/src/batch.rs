@@ -0,0 +1,140 @@
+//! Batches of jobs with a job which is run once they have all finished.
+
+use diesel::PgConnection;
+use std::fmt;
+
+use crate::errors::EnqueueError;
+use crate::inline::{self, INLINE_JOB_ID};
+use crate::storage::{self, NewJob};
+use crate::workflow::ErasedJob;
+use crate::{Job, JobId};
+
+/// Many jobs enqueued together, with callbacks which are run once when the
+/// batch finishes.
+///
+/// The `on_success` callback is enqueued once every job in the batch has
+/// succeeded, and the `on_failure` callback once any of them is marked as
+/// dead. Only one of them is ever run. Both are stored when the batch is
+/// enqueued, and released in the transaction which finishes the batch, so
+/// they are never lost or run twice. Jobs enqueued with
+/// [`Job::enqueue_in_batch`] and the same batch id are part of the batch too,
+/// and its progress can be checked with
+/// [`admin::batch_progress`](crate::admin::batch_progress).
+///
+/// ```ignore
+/// Batch::new(format!("import:{}", import_id))
+///     .on_success(send_summary(import_id))
+///     .on_failure(report_failed_import(import_id))
+///     .enqueue(&conn, files.into_iter().map(process_file))?;
+/// ```
+pub struct Batch {
+    batch_id: String,
+    on_success: Option<Box<dyn ErasedJob>>,
+    on_failure: Option<Box<dyn ErasedJob>>,
+}
+
+impl Batch {
+    /// A batch with the id `batch_id`, and no callbacks
+    pub fn new<S: Into<String>>(batch_id: S) -> Self {
+        Self {
+            batch_id: batch_id.into(),
+            on_success: None,
+            on_failure: None,
+        }
+    }
+
+    /// Run `job` once every job in the batch has succeeded.
+    ///
+    /// It is enqueued even if a pending job has the same
+    /// [unique key](Job::unique_key).
+    pub fn on_success<T: Job + 'static>(mut self, job: T) -> Self {
+        self.on_success = Some(Box::new(job));
+        self
+    }
+
+    /// Run `job` once any job in the batch has been marked as dead.
+    ///
+    /// It is enqueued even if a pending job has the same
+    /// [unique key](Job::unique_key).
+    pub fn on_failure<T: Job + 'static>(mut self, job: T) -> Self {
+        self.on_failure = Some(Box::new(job));
+        self
+    }
+
+    /// Enqueue `jobs` in the batch, returning their ids.
+    ///
+    /// The jobs and callbacks are enqueued in a single transaction, like
+    /// [`enqueue_batch`](crate::enqueue_batch). Enqueueing more jobs with the
+    /// same batch id replaces any callbacks it had. A batch with no jobs left
+    /// to run has already succeeded, so its `on_success` callback is
+    /// released right away.
+    ///
+    /// If the jobs are being [performed inline](crate::perform_jobs_inline),
+    /// the `on_success` callback is enqueued once they have all been
+    /// performed. If one fails, the `on_failure` callback is enqueued instead,
+    /// and the error is returned.
+    pub fn enqueue<T, I>(self, conn: &PgConnection, jobs: I) -> Result<Vec<JobId>, EnqueueError>
+    where
+        T: Job,
+        I: IntoIterator<Item = T>,
+    {
+        if let Some(inline) = inline::environment::<T>() {
+            let mut ids = Vec::new();
+            for job in jobs {
+                if let Err(e) = inline.perform(conn, job) {
+                    if let Some(on_failure) = self.on_failure {
+                        on_failure.enqueue_after(conn, &[])?;
+                    }
+                    return Err(e);
+                }
+                ids.push(INLINE_JOB_ID);
+            }
+            if let Some(on_success) = self.on_success {
+                on_success.enqueue_after(conn, &[])?;
+            }
+            return Ok(ids);
+        }
+        let jobs = jobs
+            .into_iter()
+            .map(|job| {
+                Ok(NewJob {
+                    batch_id: Some(&self.batch_id),
+                    ..NewJob::of(&job)?
+                })
+            })
+            .collect::<Result<Vec<_>, EnqueueError>>()?;
+        let on_success = self
+            .on_success
+            .as_ref()
+            .map(|job| job.new_job())
+            .transpose()?;
+        let on_failure = self
+            .on_failure
+            .as_ref()
+            .map(|job| job.new_job())
+            .transpose()?;
+        Ok(storage::enqueue_batch_with_callbacks(
+            conn,
+            &self.batch_id,
+            &jobs,
+            on_success,
+            on_failure,
+        )?)
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("batch_id", &self.batch_id)
+            .field(
+                "on_success",
+                &self.on_success.as_ref().map(|job| job.job_type()),
+            )
+            .field(
+                "on_failure",
+                &self.on_failure.as_ref().map(|job| job.job_type()),
+            )
+            .finish()
+    }
+}
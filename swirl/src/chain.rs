@@ -0,0 +1,55 @@
+//! Jobs which are enqueued together, and run one after another.
+
+use diesel::PgConnection;
+
+use crate::errors::EnqueueError;
+use crate::workflow::{StepId, Workflow};
+use crate::{Job, JobId};
+
+/// Jobs which are run one after another, each once the job before it has
+/// succeeded.
+///
+/// Created with [`Job::then`]. This is a [`Workflow`] where each job depends
+/// on the one before it. Every job is enqueued in a single transaction by
+/// [`enqueue`](Self::enqueue), and the runner releases each job in the
+/// transaction which removes the job before it from the queue, so a job is
+/// never run unless the one before it succeeded. A job which fails is retried
+/// as normal, and the jobs after it wait while it does, including while it is
+/// dead. If a job is cancelled, or dropped after its deadline, the jobs after
+/// it are deleted.
+///
+/// ```ignore
+/// upload_file(file_id)
+///     .then(generate_thumbnails(file_id))
+///     .then(notify_owner(file_id))
+///     .enqueue(&conn)?;
+/// ```
+#[derive(Debug)]
+pub struct Chain {
+    workflow: Workflow,
+    last: StepId,
+}
+
+impl Chain {
+    pub(crate) fn new<T: Job + 'static>(first: T) -> Self {
+        let mut workflow = Workflow::new();
+        let last = workflow.add(first);
+        Self { workflow, last }
+    }
+
+    /// Run `next` once every job in the chain so far has succeeded
+    pub fn then<T: Job + 'static>(mut self, next: T) -> Self {
+        self.last = self.workflow.add_after(next, &[self.last]);
+        self
+    }
+
+    /// Enqueue every job in the chain, returning their ids in order.
+    ///
+    /// Jobs at the start of the chain whose environment is being
+    /// [performed inline](crate::perform_jobs_inline) are performed right
+    /// away, one after another. Once a job is enqueued, the jobs after it are
+    /// enqueued too.
+    pub fn enqueue(self, conn: &PgConnection) -> Result<Vec<JobId>, EnqueueError> {
+        self.workflow.enqueue(conn)
+    }
+}
@@ -0,0 +1,165 @@
+//! Enqueueing jobs from services which never run them.
+
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use crate::db::{DieselPool, DieselPooledConn};
+use crate::errors::EnqueueError;
+use crate::job::run_at_after;
+#[cfg(feature = "schedule")]
+use crate::schedule::{self, ScheduleError};
+use crate::{storage, Cancellation, Job, JobId};
+
+/// Enqueues and cancels jobs using a connection pool.
+///
+/// Unlike a [`Runner`](crate::Runner), a client has no registry, environment,
+/// or threads, so it is cheap to create and clone, and is available without
+/// the `runner` feature. This makes it suitable for services which only
+/// produce jobs.
+///
+/// ```ignore
+/// let client = swirl::Client::new(connection_pool);
+/// client.enqueue(send_welcome_email(user_id))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client<ConnectionPool> {
+    connection_pool: ConnectionPool,
+}
+
+impl<ConnectionPool: DieselPool> Client<ConnectionPool> {
+    /// Create a client which enqueues jobs using connections from
+    /// `connection_pool`
+    pub fn new(connection_pool: ConnectionPool) -> Self {
+        Self { connection_pool }
+    }
+
+    /// The connection pool used by this client
+    pub fn connection_pool(&self) -> &ConnectionPool {
+        &self.connection_pool
+    }
+
+    /// Enqueue a job to be run at some point in the future, returning its id.
+    ///
+    /// See [`Job::enqueue`].
+    pub fn enqueue<T: Job>(&self, job: T) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job(&*self.connection()?, job)
+    }
+
+    /// Enqueue a job, unless a pending job has the same unique key. Returns
+    /// whether the job was enqueued.
+    ///
+    /// See [`Job::enqueue_unique`].
+    pub fn enqueue_unique<T: Job>(&self, job: T) -> Result<bool, EnqueueError> {
+        storage::enqueue_job_unique(&*self.connection()?, job)
+    }
+
+    /// Enqueue many jobs of the same type at once, returning their ids.
+    ///
+    /// See [`enqueue_batch`](crate::enqueue_batch).
+    pub fn enqueue_batch<T, I>(&self, jobs: I) -> Result<Vec<JobId>, EnqueueError>
+    where
+        T: Job,
+        I: IntoIterator<Item = T>,
+    {
+        storage::enqueue_jobs(&*self.connection()?, jobs)
+    }
+
+    /// Enqueue a job, to be run before `deadline`.
+    ///
+    /// See [`Job::enqueue_with_deadline`].
+    pub fn enqueue_with_deadline<T: Job>(
+        &self,
+        job: T,
+        deadline: DateTime<Utc>,
+    ) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_with_deadline(&*self.connection()?, job, Some(deadline))
+    }
+
+    /// Enqueue a job, to be run no earlier than `run_at`.
+    ///
+    /// See [`Job::enqueue_at`].
+    pub fn enqueue_at<T: Job>(&self, job: T, run_at: DateTime<Utc>) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_at(&*self.connection()?, job, run_at)
+    }
+
+    /// Enqueue a job, to be run once `delay` has passed.
+    ///
+    /// See [`Job::enqueue_in`].
+    pub fn enqueue_in<T: Job>(&self, job: T, delay: Duration) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_at(&*self.connection()?, job, run_at_after(delay))
+    }
+
+    /// Enqueue a job with a priority other than its own.
+    ///
+    /// See [`Job::enqueue_with_priority`].
+    pub fn enqueue_with_priority<T: Job>(
+        &self,
+        job: T,
+        priority: i16,
+    ) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_with_priority(&*self.connection()?, job, priority)
+    }
+
+    /// Enqueue a job, unless an identical job is already pending. Returns
+    /// whether the job was enqueued.
+    ///
+    /// See [`Job::enqueue_unless_pending`].
+    pub fn enqueue_unless_pending<T: Job>(&self, job: T) -> Result<bool, EnqueueError> {
+        storage::enqueue_job_unless_pending(&*self.connection()?, job)
+    }
+
+    /// Enqueue a job as part of a batch.
+    ///
+    /// See [`Job::enqueue_in_batch`].
+    pub fn enqueue_in_batch<T: Job>(&self, job: T, batch_id: &str) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_in_batch(&*self.connection()?, job, batch_id)
+    }
+
+    /// Remove every pending job with the same type and arguments as `job`
+    /// from the queue. Returns how many jobs were removed.
+    ///
+    /// Jobs which are already running are not interrupted, and jobs which
+    /// have been marked as dead are kept so they can still be inspected.
+    pub fn cancel<T: Job>(&self, job: T) -> Result<usize, EnqueueError> {
+        storage::cancel_pending_jobs(&*self.connection()?, job)
+    }
+
+    /// Cancel the job with the id `job_id`.
+    ///
+    /// See [`cancel`](crate::cancel).
+    pub fn cancel_job(&self, job_id: JobId) -> Result<Cancellation, EnqueueError> {
+        Ok(crate::cancel(&*self.connection()?, job_id)?)
+    }
+
+    /// Run jobs of type `T` on a cron schedule, replacing any existing
+    /// schedule for it.
+    ///
+    /// See [`schedule::set`].
+    #[cfg(feature = "schedule")]
+    pub fn schedule<T: Job>(&self, cron: &str, timezone: &str) -> Result<(), ScheduleError> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(|e| ScheduleError::NoDatabaseConnection(Box::new(e)))?;
+        schedule::set(&conn, T::JOB_TYPE, cron, timezone)
+    }
+
+    /// Stop running jobs of type `T` on a schedule. Returns whether it had
+    /// one.
+    ///
+    /// See [`schedule::remove`].
+    #[cfg(feature = "schedule")]
+    pub fn unschedule<T: Job>(&self) -> Result<bool, ScheduleError> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(|e| ScheduleError::NoDatabaseConnection(Box::new(e)))?;
+        Ok(schedule::remove(&conn, T::JOB_TYPE)?)
+    }
+
+    fn connection(&self) -> Result<DieselPooledConn<'_, ConnectionPool>, EnqueueError> {
+        self.connection_pool
+            .get()
+            .map_err(|e| EnqueueError::NoDatabaseConnection(Box::new(e)))
+    }
+}
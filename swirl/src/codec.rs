@@ -0,0 +1,43 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+
+/// An error encoding or decoding a job's arguments
+pub type CodecError = Box<dyn Error + Send + Sync>;
+
+/// A binary format job arguments can be stored in, instead of JSON.
+///
+/// By default, a job's arguments are stored as JSON in the `data` column,
+/// where they can be read and queried from SQL. Jobs with large or binary
+/// arguments can be stored more compactly by giving them a codec, which
+/// stores them in the `binary_data` column instead, and leaves `data` as
+/// `null`. With `#[background_job]`, this is set with `codec(Type)`:
+///
+/// ```ignore
+/// struct Cbor;
+///
+/// impl Codec for Cbor {
+///     fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+///         Ok(serde_cbor::to_vec(value)?)
+///     }
+///
+///     fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+///         Ok(serde_cbor::from_slice(bytes)?)
+///     }
+/// }
+///
+/// #[swirl::background_job(codec(Cbor))]
+/// fn resize_image(env: &Env, image: Vec<u8>) -> Result<(), PerformError> {
+///     // ...
+/// }
+/// ```
+///
+/// Every process which enqueues or runs the job must use the same codec.
+/// Changing a job type's codec while jobs of that type are pending will cause
+/// them to fail.
+pub trait Codec {
+    /// Encode a job's arguments
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Decode a job's arguments which were encoded by [`encode`](Self::encode)
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
@@ -0,0 +1,42 @@
+//! Compressing the arguments of large jobs, set with
+//! [`Job::COMPRESS_OVER`](crate::Job::COMPRESS_OVER).
+//!
+//! Compressed arguments are stored with gzip in the `binary_data` column, and
+//! the job's `compressed` column is set, so the runner knows to decompress
+//! them before the job is deserialized.
+
+use crate::codec::CodecError;
+
+#[cfg(feature = "compression")]
+pub(crate) fn compress(bytes: &[u8]) -> Result<Vec<u8>, CodecError> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(feature = "compression")]
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, CodecError> {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress(_: &[u8]) -> Result<Vec<u8>, CodecError> {
+    Err(NOT_ENABLED.into())
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress(_: &[u8]) -> Result<Vec<u8>, CodecError> {
+    Err(NOT_ENABLED.into())
+}
+
+#[cfg(not(feature = "compression"))]
+const NOT_ENABLED: &str = "Compressing job arguments needs swirl's `compression` feature";
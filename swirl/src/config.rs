@@ -0,0 +1,220 @@
+//! Configuration for a runner which can be loaded at runtime.
+//!
+//! This allows worker tuning to be changed without recompiling. A
+//! [`RunnerConfig`] can be deserialized from any format supported by serde,
+//! and is applied with [`Builder::from_config`](crate::Builder::from_config).
+//! With the `toml` feature enabled, it can be loaded directly from a TOML file:
+//!
+//! ```toml
+//! thread_count = 8
+//! poll_interval_ms = 500
+//! queues = ["default", "mailers"]
+//! max_retries = 10
+//! job_start_timeout_ms = 30000
+//! ```
+//!
+//! Any setting can also be overridden with an environment variable, which is
+//! useful for tuning workers per deployment. See
+//! [`RunnerConfig::with_env_overrides`].
+
+use serde_derive::Deserialize;
+use std::error::Error;
+use std::fmt;
+
+/// Settings for a [`Runner`](crate::Runner).
+///
+/// Every setting is optional. Settings which are not present are left at
+/// their defaults, or at whatever was set on the builder before the config
+/// was applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunnerConfig {
+    /// See [`Builder::thread_count`](crate::Builder::thread_count)
+    pub thread_count: Option<usize>,
+    /// See [`Builder::poll_interval`](crate::Builder::poll_interval)
+    pub poll_interval_ms: Option<u64>,
+    /// See [`Builder::queues`](crate::Builder::queues)
+    pub queues: Option<Vec<String>>,
+    /// See [`Builder::max_retries`](crate::Builder::max_retries)
+    pub max_retries: Option<u32>,
+    /// See [`Builder::job_start_timeout`](crate::Builder::job_start_timeout)
+    pub job_start_timeout_ms: Option<u64>,
+}
+
+impl RunnerConfig {
+    /// Parse a config from a TOML document
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let config = toml::from_str::<Self>(toml).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read and parse a TOML config file
+    #[cfg(feature = "toml")]
+    pub fn from_toml_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ConfigError> {
+        let toml = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&toml)
+    }
+
+    /// Load a config from environment variables only.
+    ///
+    /// See [`with_env_overrides`](Self::with_env_overrides) for the variables
+    /// which are read.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::default().with_env_overrides()
+    }
+
+    /// Override settings with any of these environment variables which are
+    /// set:
+    ///
+    /// - `SWIRL_THREAD_COUNT`
+    /// - `SWIRL_POLL_INTERVAL`: a number of milliseconds, or a number followed
+    ///   by `ms` or `s`
+    /// - `SWIRL_QUEUES`: a comma separated list of queues
+    /// - `SWIRL_MAX_RETRIES`
+    /// - `SWIRL_JOB_START_TIMEOUT`: in the same format as
+    ///   `SWIRL_POLL_INTERVAL`
+    ///
+    /// Variables which are set to an empty string are ignored.
+    pub fn with_env_overrides(mut self) -> Result<Self, ConfigError> {
+        if let Some(value) = env_var("SWIRL_THREAD_COUNT")? {
+            self.thread_count = Some(parse_number("SWIRL_THREAD_COUNT", &value)?);
+        }
+        if let Some(value) = env_var("SWIRL_POLL_INTERVAL")? {
+            self.poll_interval_ms = Some(parse_millis("SWIRL_POLL_INTERVAL", &value)?);
+        }
+        if let Some(value) = env_var("SWIRL_QUEUES")? {
+            self.queues = Some(value.split(',').map(|q| q.trim().to_string()).collect());
+        }
+        if let Some(value) = env_var("SWIRL_MAX_RETRIES")? {
+            self.max_retries = Some(parse_number("SWIRL_MAX_RETRIES", &value)?);
+        }
+        if let Some(value) = env_var("SWIRL_JOB_START_TIMEOUT")? {
+            self.job_start_timeout_ms = Some(parse_millis("SWIRL_JOB_START_TIMEOUT", &value)?);
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check that every setting which is present has a usable value
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.thread_count == Some(0) {
+            return Err(ConfigError::invalid("thread_count", "must be at least 1"));
+        }
+        if self.poll_interval_ms == Some(0) {
+            return Err(ConfigError::invalid(
+                "poll_interval_ms",
+                "must be at least 1",
+            ));
+        }
+        if self.job_start_timeout_ms == Some(0) {
+            return Err(ConfigError::invalid(
+                "job_start_timeout_ms",
+                "must be at least 1",
+            ));
+        }
+        if let Some(queues) = &self.queues {
+            if queues.is_empty() {
+                return Err(ConfigError::invalid(
+                    "queues",
+                    "must contain at least one queue",
+                ));
+            }
+            if queues.iter().any(|q| q.trim().is_empty()) {
+                return Err(ConfigError::invalid(
+                    "queues",
+                    "must not contain empty names",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn env_var(name: &'static str) -> Result<Option<String>, ConfigError> {
+    match std::env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => {
+            Err(ConfigError::invalid(name, "must be valid unicode"))
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &'static str, value: &str) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::invalid(name, format!("must be a whole number, got `{}`", value)))
+}
+
+fn parse_millis(name: &'static str, value: &str) -> Result<u64, ConfigError> {
+    let invalid = || {
+        ConfigError::invalid(
+            name,
+            format!(
+                "must be a duration such as `500ms` or `2s`, got `{}`",
+                value
+            ),
+        )
+    };
+
+    if let Some(ms) = value.strip_suffix("ms") {
+        ms.trim().parse().map_err(|_| invalid())
+    } else if let Some(secs) = value.strip_suffix('s') {
+        let secs = secs.trim().parse::<u64>().map_err(|_| invalid())?;
+        secs.checked_mul(1000).ok_or_else(invalid)
+    } else {
+        value.parse().map_err(|_| invalid())
+    }
+}
+
+/// An error loading or validating a [`RunnerConfig`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io(std::io::Error),
+
+    /// The config could not be parsed
+    Parse(String),
+
+    /// A setting had a value which cannot be used
+    Invalid {
+        /// The name of the setting
+        field: &'static str,
+        /// Why the value cannot be used
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub(crate) fn invalid<S: Into<String>>(field: &'static str, message: S) -> Self {
+        ConfigError::Invalid {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Could not read the runner config: {}", e),
+            ConfigError::Parse(e) => write!(f, "Could not parse the runner config: {}", e),
+            ConfigError::Invalid { field, message } => {
+                write!(f, "Invalid runner config: `{}` {}", field, message)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
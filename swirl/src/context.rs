@@ -0,0 +1,329 @@
+//! Information about the job which is currently running.
+
+use chrono::{DateTime, Utc};
+use diesel::dsl::now;
+use diesel::insert_into;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::errors::PerformError;
+use crate::schema::{background_job_checkpoints, background_job_ledger};
+use crate::storage::{self, BackgroundJob};
+
+thread_local! {
+    static CURRENT: RefCell<Option<JobContext>> = const { RefCell::new(None) };
+}
+
+/// The job which is running on the current thread.
+///
+/// The runner makes this available while a job is performed, so code called
+/// by the job can find out which job it is running for without it being
+/// passed down. Jobs defined with `#[background_job]` can also take it as an
+/// argument of type `&JobContext`.
+///
+/// ```ignore
+/// #[swirl::background_job]
+/// fn charge_order(env: &Env, ctx: &JobContext, order_id: i64) -> Result<(), PerformError> {
+///     let conn = env.pool.get()?;
+///     ctx.exactly_once(&conn, &format!("charge_order:{}", order_id), || {
+///         env.payments.charge(order_id)
+///     })?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    job_id: i64,
+    job_type: String,
+    queue: String,
+    retries: i32,
+    enqueued_at: DateTime<Utc>,
+    max_retries: Option<u32>,
+    shutting_down: Option<Arc<AtomicBool>>,
+    /// Shared between clones, so a result set through
+    /// [`current`](Self::current) is seen by the runner
+    result: Arc<Mutex<Option<serde_json::Value>>>,
+}
+
+impl PartialEq for JobContext {
+    fn eq(&self, other: &Self) -> bool {
+        self.job_id == other.job_id && self.job_type == other.job_type
+    }
+}
+
+impl Eq for JobContext {}
+
+impl JobContext {
+    /// Create the context of a job on the default queue, which was enqueued
+    /// just now and has not been retried.
+    ///
+    /// Runners built on [`storage`](crate::storage) should use
+    /// [`for_job`](Self::for_job) instead.
+    pub fn new(job_id: i64, job_type: String) -> Self {
+        Self {
+            job_id,
+            job_type,
+            queue: "default".into(),
+            retries: 0,
+            enqueued_at: Utc::now(),
+            max_retries: None,
+            shutting_down: None,
+            result: Arc::default(),
+        }
+    }
+
+    /// Create the context of a job which was claimed from the queue, for
+    /// runners built on [`storage`](crate::storage) to pass to
+    /// [`enter`](Self::enter)
+    pub fn for_job(job: &BackgroundJob) -> Self {
+        Self {
+            queue: job.queue.clone(),
+            retries: job.retries,
+            enqueued_at: job.enqueued_at,
+            ..Self::new(job.id, job.job_type.clone())
+        }
+    }
+
+    /// Report whether this attempt is the job's last through
+    /// [`is_final_attempt`](Self::is_final_attempt)
+    #[cfg(feature = "runner")]
+    pub(crate) fn max_retries(mut self, max_retries: Option<u32>) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Report `queue` as the job's queue, for jobs run without being claimed
+    pub(crate) fn on_queue(mut self, queue: &str) -> Self {
+        self.queue = queue.into();
+        self
+    }
+
+    /// Report the runner's shutdown through
+    /// [`is_shutting_down`](Self::is_shutting_down)
+    #[cfg(feature = "runner")]
+    pub(crate) fn shutdown_flag(mut self, shutting_down: Arc<AtomicBool>) -> Self {
+        self.shutting_down = Some(shutting_down);
+        self
+    }
+
+    /// The context of the job running on this thread, or `None` if this
+    /// thread is not running a job
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Make this the current context while `f` runs
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Reset(Option<JobContext>);
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = previous);
+            }
+        }
+
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let _reset = Reset(previous);
+        f()
+    }
+
+    /// The id of the job
+    pub fn job_id(&self) -> i64 {
+        self.job_id
+    }
+
+    /// The type of the job
+    pub fn job_type(&self) -> &str {
+        &self.job_type
+    }
+
+    /// The queue the job is on
+    pub fn queue(&self) -> &str {
+        &self.queue
+    }
+
+    /// The number of times the job has previously been retried
+    pub fn retries(&self) -> i32 {
+        self.retries
+    }
+
+    /// When the job was enqueued
+    pub fn enqueued_at(&self) -> DateTime<Utc> {
+        self.enqueued_at
+    }
+
+    /// Whether the job will be marked as dead if this attempt fails.
+    ///
+    /// This is based on the job's [`MAX_RETRIES`](crate::Job::MAX_RETRIES),
+    /// or the runner's [`max_retries`](crate::Builder::max_retries). Jobs can
+    /// also be marked as dead early when their type runs out of
+    /// [retry budget](crate::Builder::retry_budget), which this does not
+    /// predict. Always `false` for jobs which aren't run by a
+    /// [`Runner`](crate::Runner), or which are retried forever.
+    pub fn is_final_attempt(&self) -> bool {
+        self.max_retries
+            .is_some_and(|max_retries| i64::from(self.retries) >= i64::from(max_retries))
+    }
+
+    /// Whether the runner running this job has been shut down.
+    ///
+    /// Long running jobs can check this between steps, and save a
+    /// [checkpoint](Self::save_checkpoint) and return an error instead of
+    /// being cut off when the shutdown deadline passes. Always `false` for
+    /// jobs which aren't run by a [`Runner`](crate::Runner).
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+            .as_ref()
+            .is_some_and(|shutting_down| shutting_down.load(Ordering::SeqCst))
+    }
+
+    /// Whether the job has been [cancelled](crate::cancel) while it was
+    /// running.
+    ///
+    /// Long running jobs can check this between steps, and return an error
+    /// to stop early. The job is removed from the queue instead of being
+    /// retried once it fails. `conn` should not be in a transaction which
+    /// started before the job was cancelled.
+    pub fn is_cancelled(&self, conn: &PgConnection) -> Result<bool, PerformError> {
+        Ok(storage::cancel_requested(conn, self.job_id)?)
+    }
+
+    /// Store `result` as the result of this job, to be looked up with
+    /// [`job_result`](crate::job_result) once the job succeeds.
+    ///
+    /// The result replaces any set earlier in the same attempt, and is
+    /// dropped if the attempt fails. It is saved in the
+    /// `background_job_results` table in the transaction which removes the
+    /// job from the queue, and kept for the runner's
+    /// [`result_ttl`](crate::Builder::result_ttl).
+    ///
+    /// ```ignore
+    /// #[swirl::background_job]
+    /// fn render_invoice(env: &Env, ctx: &JobContext, order_id: i64) -> Result<(), PerformError> {
+    ///     let url = env.renderer.render(order_id)?;
+    ///     ctx.set_result(&url)
+    /// }
+    /// ```
+    pub fn set_result<R: Serialize>(&self, result: &R) -> Result<(), PerformError> {
+        let result = serde_json::to_value(result)?;
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        Ok(())
+    }
+
+    /// The result set with [`set_result`](Self::set_result), if any
+    #[cfg(feature = "runner")]
+    pub(crate) fn take_result(&self) -> Option<serde_json::Value> {
+        self.result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Save how far this job has gotten, so it can resume from there if it
+    /// is interrupted and run again.
+    ///
+    /// The checkpoint replaces any checkpoint saved before it, and is deleted
+    /// once the job succeeds. It is committed with any transaction `conn` is
+    /// in, so `conn` should not be in one if the checkpoint needs to survive
+    /// the job failing or the process dying.
+    ///
+    /// ```ignore
+    /// let mut next = ctx.checkpoint::<i64>(&conn)?.unwrap_or(0);
+    /// while let Some(batch) = load_batch(&conn, next)? {
+    ///     process(&batch)?;
+    ///     next = batch.last_id + 1;
+    ///     ctx.save_checkpoint(&conn, &next)?;
+    ///     if ctx.is_shutting_down() {
+    ///         return Err("interrupted by shutdown".into());
+    ///     }
+    /// }
+    /// ```
+    pub fn save_checkpoint<S: Serialize>(
+        &self,
+        conn: &PgConnection,
+        state: &S,
+    ) -> Result<(), PerformError> {
+        use crate::schema::background_job_checkpoints::dsl;
+
+        let state = serde_json::to_value(state)?;
+        // The job's own row is locked by the runner, so checkpoints are kept
+        // in a table of their own
+        insert_into(background_job_checkpoints::table)
+            .values((dsl::job_id.eq(self.job_id), dsl::state.eq(&state)))
+            .on_conflict(dsl::job_id)
+            .do_update()
+            .set((dsl::state.eq(&state), dsl::saved_at.eq(now)))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// The last checkpoint saved by this job, or `None` if it hasn't saved
+    /// one
+    pub fn checkpoint<S: DeserializeOwned>(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<Option<S>, PerformError> {
+        let state = background_job_checkpoints::table
+            .find(self.job_id)
+            .select(background_job_checkpoints::state)
+            .first::<serde_json::Value>(conn)
+            .optional()?;
+        match state {
+            Some(state) => Ok(Some(serde_json::from_value(state)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Run `f` unless an operation with the same key has already completed.
+    ///
+    /// Jobs are delivered at least once, so a job which did some of its work
+    /// before failing, or before its runner was killed, will do that work
+    /// again when it is retried. Wrapping each piece of work which must not
+    /// be repeated in `exactly_once` with a key identifying it, such as
+    /// `charge_order:42`, makes the retry skip it.
+    ///
+    /// The key is recorded in the `background_job_ledger` table in a
+    /// transaction on `conn`, which `f` runs inside of. If `f` returns an
+    /// error, nothing is recorded and the operation will be run again. Any
+    /// changes `f` makes using `conn` are committed together with the key, so
+    /// they happen exactly once. Changes made elsewhere, such as calls to
+    /// other services, are repeated if the process dies after `f` returns
+    /// but before the transaction commits.
+    ///
+    /// If another job is running an operation with the same key, this waits
+    /// for it to finish. Returns the result of `f`, or `None` if the
+    /// operation had already completed.
+    pub fn exactly_once<T, E, F>(
+        &self,
+        conn: &PgConnection,
+        key: &str,
+        f: F,
+    ) -> Result<Option<T>, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+        E: From<DieselError>,
+    {
+        use crate::schema::background_job_ledger::dsl;
+
+        conn.transaction(|| {
+            let recorded = insert_into(background_job_ledger::table)
+                .values((
+                    dsl::key.eq(key),
+                    dsl::job_id.eq(self.job_id),
+                    dsl::job_type.eq(&self.job_type),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            if recorded == 0 {
+                Ok(None)
+            } else {
+                f().map(Some)
+            }
+        })
+    }
+}
@@ -1,7 +1,9 @@
-use diesel::PgConnection;
+use diesel::{PgConnection, QueryResult};
 use std::error::Error;
 use std::ops::Deref;
 
+use crate::errors::PerformError;
+
 pub type DieselPooledConn<'a, T> = <T as BorrowedConnection<'a>>::Connection;
 
 /// A trait to work around associated type constructors
@@ -17,7 +19,8 @@ pub trait BorrowedConnection<'a> {
 /// If you don't care about the details of connection pooling, or want to use
 /// the r2d2 crate, you can enable the r2d2 feature on this crate and never
 /// be concerned with this trait. If you want to use your own connection pool,
-/// you can implement this trait manually.
+/// you can implement this trait manually, and disable the r2d2 feature so that
+/// r2d2 is not compiled at all.
 pub trait DieselPool: Clone + Send + for<'a> BorrowedConnection<'a> {
     /// The error type returned when a connection could not be retreived from
     /// the pool.
@@ -44,8 +47,8 @@ pub trait DieselPoolObj {
 
     fn with_connection(
         &self,
-        f: &dyn Fn(&PgConnection) -> Result<(), Box<dyn Error>>,
-    ) -> Result<(), Box<dyn Error>>;
+        f: &dyn Fn(&PgConnection) -> Result<(), PerformError>,
+    ) -> Result<(), PerformError>;
 }
 
 impl<T: DieselPool> DieselPoolObj for T {
@@ -57,13 +60,40 @@ impl<T: DieselPool> DieselPoolObj for T {
 
     fn with_connection(
         &self,
-        f: &dyn Fn(&PgConnection) -> Result<(), Box<dyn Error>>,
-    ) -> Result<(), Box<dyn Error>> {
+        f: &dyn Fn(&PgConnection) -> Result<(), PerformError>,
+    ) -> Result<(), PerformError> {
         let conn = DieselPool::get(self)?;
         f(&conn)
     }
 }
 
+/// Prepares connections checked out by the runner.
+///
+/// This is called every time a worker checks out a connection to look for a
+/// job, before the job is locked and outside of any transaction. It can be
+/// used to set session settings such as `application_name`,
+/// `statement_timeout`, or `search_path`. Since pooled connections are reused,
+/// these settings will also be seen by anything else which later checks out
+/// the same connection.
+///
+/// This trait is implemented for any closure which takes a `&PgConnection`
+/// and returns a `QueryResult<()>`.
+pub trait ConnectionCustomizer: Send + Sync + 'static {
+    /// Called with every connection checked out by the runner. If an error is
+    /// returned, no job will be run with the connection, and the error is
+    /// reported by [`Runner::run_all_pending_jobs`](crate::Runner::run_all_pending_jobs).
+    fn on_checkout(&self, conn: &PgConnection) -> QueryResult<()>;
+}
+
+impl<F> ConnectionCustomizer for F
+where
+    F: Fn(&PgConnection) -> QueryResult<()> + Send + Sync + 'static,
+{
+    fn on_checkout(&self, conn: &PgConnection) -> QueryResult<()> {
+        self(conn)
+    }
+}
+
 /// A builder for connection pools
 pub trait DieselPoolBuilder {
     /// The concrete connection pool built by this type
@@ -77,52 +107,8 @@ pub trait DieselPoolBuilder {
 }
 
 #[cfg(feature = "r2d2")]
-mod r2d2_impl {
-    use super::*;
-    use diesel::r2d2;
-
-    type ConnectionManager = r2d2::ConnectionManager<PgConnection>;
-
-    impl<'a> BorrowedConnection<'a> for r2d2::Pool<ConnectionManager> {
-        type Connection = r2d2::PooledConnection<ConnectionManager>;
-    }
-
-    impl DieselPool for r2d2::Pool<ConnectionManager> {
-        type Error = r2d2::PoolError;
-
-        fn get<'a>(&'a self) -> Result<DieselPooledConn<'a, Self>, Self::Error> {
-            self.get()
-        }
-    }
-
-    pub struct R2d2Builder {
-        url: String,
-        builder: r2d2::Builder<ConnectionManager>,
-        connection_count: Option<u32>,
-    }
+mod r2d2;
 
-    impl R2d2Builder {
-        pub(crate) fn new(url: String, builder: r2d2::Builder<ConnectionManager>) -> Self {
-            Self {
-                url,
-                builder,
-                connection_count: None,
-            }
-        }
-
-        pub(crate) fn connection_count(&mut self, connection_count: u32) {
-            self.connection_count = Some(connection_count);
-        }
-
-        pub(crate) fn build(self, default_connection_count: u32) -> r2d2::Pool<ConnectionManager> {
-            let max_size = self.connection_count.unwrap_or(default_connection_count);
-            self.builder
-                .max_size(max_size)
-                .build_unchecked(ConnectionManager::new(self.url))
-        }
-    }
-}
-
-#[cfg(feature = "r2d2")]
+#[cfg(all(feature = "r2d2", feature = "runner"))]
 #[doc(hidden)]
-pub use self::r2d2_impl::R2d2Builder;
+pub use self::r2d2::R2d2Builder;
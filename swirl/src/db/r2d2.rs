@@ -0,0 +1,49 @@
+//! Support for r2d2 connection pools, enabled by the `r2d2` feature.
+
+use diesel::r2d2;
+use diesel::PgConnection;
+
+use super::{BorrowedConnection, DieselPool, DieselPooledConn};
+
+type ConnectionManager = r2d2::ConnectionManager<PgConnection>;
+
+impl<'a> BorrowedConnection<'a> for r2d2::Pool<ConnectionManager> {
+    type Connection = r2d2::PooledConnection<ConnectionManager>;
+}
+
+impl DieselPool for r2d2::Pool<ConnectionManager> {
+    type Error = r2d2::PoolError;
+
+    fn get<'a>(&'a self) -> Result<DieselPooledConn<'a, Self>, Self::Error> {
+        self.get()
+    }
+}
+
+#[cfg(feature = "runner")]
+pub struct R2d2Builder {
+    url: String,
+    builder: r2d2::Builder<ConnectionManager>,
+    connection_count: Option<u32>,
+}
+
+#[cfg(feature = "runner")]
+impl R2d2Builder {
+    pub(crate) fn new(url: String, builder: r2d2::Builder<ConnectionManager>) -> Self {
+        Self {
+            url,
+            builder,
+            connection_count: None,
+        }
+    }
+
+    pub(crate) fn connection_count(&mut self, connection_count: u32) {
+        self.connection_count = Some(connection_count);
+    }
+
+    pub(crate) fn build(self, default_connection_count: u32) -> r2d2::Pool<ConnectionManager> {
+        let max_size = self.connection_count.unwrap_or(default_connection_count);
+        self.builder
+            .max_size(max_size)
+            .build_unchecked(ConnectionManager::new(self.url))
+    }
+}
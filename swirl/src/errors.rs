@@ -1,6 +1,7 @@
 use diesel::result::Error as DieselError;
 use std::error::Error;
 use std::fmt;
+use std::time::Duration;
 
 use crate::db::DieselPool;
 
@@ -10,9 +11,34 @@ pub enum EnqueueError {
     /// An error occurred serializing the job
     SerializationError(serde_json::error::Error),
 
+    /// An error occurred encoding the job with its [`Codec`](crate::Codec)
+    EncodingError(Box<dyn Error + Send + Sync>),
+
     /// An error occurred inserting the job into the database
     DatabaseError(DieselError),
 
+    /// An error occurred writing the job's arguments to a
+    /// [`PayloadStore`](crate::payload_store::PayloadStore)
+    PayloadStoreError(Box<dyn Error + Send + Sync>),
+
+    /// A connection could not be retrieved from the
+    /// [`Client`](crate::Client)'s connection pool
+    NoDatabaseConnection(Box<dyn Error + Send + Sync>),
+
+    /// The job was performed instead of being enqueued, since jobs with its
+    /// environment are [performed inline](crate::perform_jobs_inline), and
+    /// it failed
+    JobFailed(Box<dyn Error + Send + Sync>),
+
+    /// The job's serialized arguments were larger than its
+    /// [`MAX_PAYLOAD_SIZE`](crate::Job::MAX_PAYLOAD_SIZE)
+    PayloadTooLarge {
+        /// The size of the arguments, in bytes
+        size: usize,
+        /// The largest the arguments may be, in bytes
+        limit: usize,
+    },
+
     #[doc(hidden)]
     /// Match on `_` instead, more variants may be added in the future
     __NonExhaustive,
@@ -34,7 +60,16 @@ impl fmt::Display for EnqueueError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             EnqueueError::SerializationError(e) => e.fmt(f),
+            EnqueueError::EncodingError(e) => e.fmt(f),
             EnqueueError::DatabaseError(e) => e.fmt(f),
+            EnqueueError::PayloadStoreError(e) => e.fmt(f),
+            EnqueueError::NoDatabaseConnection(e) => e.fmt(f),
+            EnqueueError::JobFailed(e) => e.fmt(f),
+            EnqueueError::PayloadTooLarge { size, limit } => write!(
+                f,
+                "The job's arguments are {} bytes, over its limit of {} bytes",
+                size, limit
+            ),
             EnqueueError::__NonExhaustive => unreachable!(),
         }
     }
@@ -44,14 +79,98 @@ impl Error for EnqueueError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             EnqueueError::SerializationError(e) => Some(e),
+            EnqueueError::EncodingError(e) => Some(&**e),
             EnqueueError::DatabaseError(e) => Some(e),
+            EnqueueError::PayloadStoreError(e) => Some(&**e),
+            EnqueueError::NoDatabaseConnection(e) => Some(&**e),
+            EnqueueError::JobFailed(e) => Some(&**e),
+            EnqueueError::PayloadTooLarge { .. } => None,
             EnqueueError::__NonExhaustive => unreachable!(),
         }
     }
 }
 
-/// An error occurred performing the job
-pub type PerformError = Box<dyn Error>;
+/// An error occurred performing the job, and how the runner should handle it.
+///
+/// Any error can be converted into a `PerformError` with `?` or `.into()`,
+/// which retries the job after its backoff. Jobs which know better can
+/// return one of the other variants:
+///
+/// ```ignore
+/// match response.status() {
+///     429 => Err(PerformError::retry_after("rate limited", retry_after)),
+///     404 => Err(PerformError::fatal("the account was deleted")),
+///     409 => Err(PerformError::snooze(Duration::from_secs(30))),
+///     _ => Ok(()),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum PerformError {
+    /// The job failed, and is retried after `after`, or after its backoff if
+    /// `after` is `None`. It is marked as dead once it is out of retries.
+    Retryable {
+        /// Why the job failed
+        error: Box<dyn Error>,
+        /// How long to wait before the retry, instead of the backoff
+        after: Option<Duration>,
+    },
+
+    /// The job failed, and would fail again if it was retried, so it is
+    /// marked as dead right away
+    Fatal(Box<dyn Error>),
+
+    /// The job isn't ready to run yet, so it is run again after the given
+    /// time. This is not a failure, and doesn't count as a retry.
+    Snooze(Duration),
+}
+
+impl PerformError {
+    /// Retry the job after `after`, instead of after its backoff
+    pub fn retry_after<E: Into<Box<dyn Error>>>(error: E, after: Duration) -> Self {
+        PerformError::Retryable {
+            error: error.into(),
+            after: Some(after),
+        }
+    }
+
+    /// Mark the job as dead without retrying it
+    pub fn fatal<E: Into<Box<dyn Error>>>(error: E) -> Self {
+        PerformError::Fatal(error.into())
+    }
+
+    /// Run the job again after `delay`, without counting it as a failure
+    pub fn snooze(delay: Duration) -> Self {
+        PerformError::Snooze(delay)
+    }
+
+    /// The underlying error, unless the job was snoozed
+    pub fn error(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PerformError::Retryable { error, .. } | PerformError::Fatal(error) => Some(&**error),
+            PerformError::Snooze(_) => None,
+        }
+    }
+}
+
+// `PerformError` doesn't implement `Error`, so that any error can be
+// converted into it
+impl<E: Into<Box<dyn Error>>> From<E> for PerformError {
+    fn from(error: E) -> Self {
+        PerformError::Retryable {
+            error: error.into(),
+            after: None,
+        }
+    }
+}
+
+impl fmt::Display for PerformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PerformError::Retryable { error, .. } | PerformError::Fatal(error) => error.fmt(f),
+            PerformError::Snooze(delay) => write!(f, "The job was snoozed for {:?}", delay),
+        }
+    }
+}
 
 /// An error occurred while attempting to fetch jobs from the queue
 pub enum FetchError<Pool: DieselPool> {
@@ -64,10 +183,24 @@ pub enum FetchError<Pool: DieselPool> {
     /// Could not execute the query to load a job from the database.
     FailedLoadingJob(DieselError),
 
+    /// A job ran, but could not be marked as finished or failed. The job's
+    /// transaction was rolled back, so it will be run again.
+    ///
+    /// This usually happens after the call which started the job has
+    /// returned, so it is returned by the next call instead.
+    FailedUpdatingJob(DieselError),
+
     /// No message was received from the worker thread.
     ///
     /// Either the thread pool is too small, or jobs have hung indefinitely
     NoMessageReceived,
+
+    /// [`Runner::run_pending_jobs_until_empty`](crate::Runner::run_pending_jobs_until_empty)
+    /// ran this many rounds of jobs, and there were still jobs to run
+    QueueNotEmptied {
+        /// The number of rounds of jobs which were run
+        rounds: usize,
+    },
 }
 
 impl<Pool: DieselPool> fmt::Debug for FetchError<Pool> {
@@ -77,7 +210,14 @@ impl<Pool: DieselPool> fmt::Debug for FetchError<Pool> {
                 f.debug_tuple("NoDatabaseConnection").field(e).finish()
             }
             FetchError::FailedLoadingJob(e) => f.debug_tuple("FailedLoadingJob").field(e).finish(),
+            FetchError::FailedUpdatingJob(e) => {
+                f.debug_tuple("FailedUpdatingJob").field(e).finish()
+            }
             FetchError::NoMessageReceived => f.debug_struct("NoMessageReceived").finish(),
+            FetchError::QueueNotEmptied { rounds } => f
+                .debug_struct("QueueNotEmptied")
+                .field("rounds", rounds)
+                .finish(),
         }
     }
 }
@@ -94,10 +234,21 @@ impl<Pool: DieselPool> fmt::Display for FetchError<Pool> {
                 write!(f, "An error occurred loading a job from the database: ")?;
                 write!(f, "{}", e)?;
             }
+            FetchError::FailedUpdatingJob(e) => {
+                write!(f, "An error occurred updating a job which ran: ")?;
+                write!(f, "{}", e)?;
+            }
             FetchError::NoMessageReceived => {
                 write!(f, "No message was received from the worker thread. ")?;
                 write!(f, "Try increasing the thread pool size or timeout period.")?;
             }
+            FetchError::QueueNotEmptied { rounds } => {
+                write!(f, "There were still jobs to run after {} rounds. ", rounds)?;
+                write!(
+                    f,
+                    "Jobs may be enqueueing each other, or retrying without a backoff."
+                )?;
+            }
         }
         Ok(())
     }
@@ -108,7 +259,78 @@ impl<Pool: DieselPool> Error for FetchError<Pool> {
         match self {
             FetchError::NoDatabaseConnection(e) => Some(e),
             FetchError::FailedLoadingJob(e) => Some(e),
+            FetchError::FailedUpdatingJob(e) => Some(e),
             FetchError::NoMessageReceived => None,
+            FetchError::QueueNotEmptied { .. } => None,
+        }
+    }
+}
+
+/// Why a runner is unhealthy, returned by
+/// [`Runner::check_health`](crate::Runner::check_health)
+pub enum HealthError<Pool: DieselPool> {
+    /// A connection could not be retrieved from the pool
+    NoDatabaseConnection(Pool::Error),
+
+    /// The query looking for the oldest pending job failed
+    DatabaseError(DieselError),
+
+    /// A job has been waiting to run for longer than the allowed age
+    JobsBacklogged {
+        /// How long the oldest pending job has been waiting
+        oldest_pending_age: Duration,
+        /// The longest a job was allowed to wait
+        max_pending_age: Duration,
+    },
+}
+
+impl<Pool: DieselPool> fmt::Debug for HealthError<Pool> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthError::NoDatabaseConnection(e) => {
+                f.debug_tuple("NoDatabaseConnection").field(e).finish()
+            }
+            HealthError::DatabaseError(e) => f.debug_tuple("DatabaseError").field(e).finish(),
+            HealthError::JobsBacklogged {
+                oldest_pending_age,
+                max_pending_age,
+            } => f
+                .debug_struct("JobsBacklogged")
+                .field("oldest_pending_age", oldest_pending_age)
+                .field("max_pending_age", max_pending_age)
+                .finish(),
+        }
+    }
+}
+
+impl<Pool: DieselPool> fmt::Display for HealthError<Pool> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthError::NoDatabaseConnection(e) => {
+                write!(f, "Failed to acquire a database connection: {}", e)
+            }
+            HealthError::DatabaseError(e) => {
+                write!(f, "An error occurred looking for pending jobs: {}", e)
+            }
+            HealthError::JobsBacklogged {
+                oldest_pending_age,
+                max_pending_age,
+            } => write!(
+                f,
+                "The oldest pending job has been waiting for {}s, longer than the allowed {}s",
+                oldest_pending_age.as_secs(),
+                max_pending_age.as_secs(),
+            ),
+        }
+    }
+}
+
+impl<Pool: DieselPool> Error for HealthError<Pool> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HealthError::NoDatabaseConnection(e) => Some(e),
+            HealthError::DatabaseError(e) => Some(e),
+            HealthError::JobsBacklogged { .. } => None,
         }
     }
 }
@@ -0,0 +1,124 @@
+//! Performing jobs on the thread which enqueues them, instead of storing them
+//! for a runner.
+
+use diesel::PgConnection;
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::ops::Deref;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::db::DieselPoolObj;
+use crate::errors::{EnqueueError, PerformError};
+use crate::{Job, JobContext, JobId};
+
+/// The id returned for jobs which were performed instead of being enqueued.
+/// Ids given out by the queue start at 1, so this never refers to a job.
+pub(crate) const INLINE_JOB_ID: JobId = JobId(0);
+
+/// The environments of the jobs which are performed inline, by type
+static ENVIRONMENTS: RwLock<Vec<(TypeId, Arc<dyn Any + Send + Sync>)>> = RwLock::new(Vec::new());
+
+/// Perform jobs which take the environment `Env` as soon as they are
+/// enqueued, instead of storing them for a runner.
+///
+/// This is meant for development and test environments where running a
+/// runner is inconvenient. Once it is called, every job with the environment
+/// `Env` which is enqueued by this process, with any of the `enqueue`
+/// methods, a [`Client`](crate::Client), or [`enqueue_batch`](crate::enqueue_batch),
+/// is performed right away with `env` by the thread enqueueing it, before
+/// the enqueue returns. Jobs are not stored, so they can't be listed,
+/// cancelled, or retried, and are given the id `JobId(0)`.
+///
+/// Jobs run on the connection they were enqueued with, which may be in the
+/// middle of a transaction, and any delay, deadline, or priority they were
+/// enqueued with is ignored. If the job fails, the enqueue returns
+/// [`EnqueueError::JobFailed`], and if it panics, so does the enqueue.
+///
+/// ```ignore
+/// if config.inline_jobs {
+///     swirl::perform_jobs_inline(environment.clone());
+/// }
+/// ```
+pub fn perform_jobs_inline<Env: Send + Sync + 'static>(env: Env) {
+    let mut environments = ENVIRONMENTS.write().unwrap_or_else(PoisonError::into_inner);
+    environments.retain(|(env_type, _)| *env_type != TypeId::of::<Env>());
+    environments.push((TypeId::of::<Env>(), Arc::new(env)));
+}
+
+/// Go back to enqueueing jobs which take the environment `Env`, after
+/// [`perform_jobs_inline`] was called for it. Returns whether they were
+/// being performed inline.
+pub fn stop_performing_jobs_inline<Env: 'static>() -> bool {
+    let mut environments = ENVIRONMENTS.write().unwrap_or_else(PoisonError::into_inner);
+    let len = environments.len();
+    environments.retain(|(env_type, _)| *env_type != TypeId::of::<Env>());
+    environments.len() != len
+}
+
+/// Perform a job right away on the calling thread, without enqueueing it.
+///
+/// The job is serialized and deserialized as it would be if it were
+/// enqueued, and is given a connection pool which always hands out `conn`.
+/// Unlike [`perform_jobs_inline`], this works the same way whether or not
+/// jobs are being performed inline.
+pub fn perform_now<T: Job>(
+    conn: &PgConnection,
+    env: &T::Environment,
+    job: T,
+) -> Result<(), PerformError> {
+    let job = match job
+        .encode_binary()
+        .map_err(|e| PerformError::from(e as Box<dyn Error>))?
+    {
+        Some(bytes) => {
+            T::decode_binary(&bytes).map_err(|e| PerformError::from(e as Box<dyn Error>))?
+        }
+        None => serde_json::from_value(serde_json::to_value(job)?)?,
+    };
+    let context = JobContext::new(INLINE_JOB_ID.0, T::JOB_TYPE.into()).on_queue(T::QUEUE);
+    context.enter(|| job.perform(env, &SingleConnection(conn)))
+}
+
+/// The environment jobs of type `T` are performed inline with, if they are
+pub(crate) fn environment<T: Job>() -> Option<InlineEnvironment> {
+    let environments = ENVIRONMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    environments
+        .iter()
+        .find(|(env_type, _)| *env_type == TypeId::of::<T::Environment>())
+        .map(|(_, env)| InlineEnvironment(env.clone()))
+}
+
+/// An environment given to [`perform_jobs_inline`]
+pub(crate) struct InlineEnvironment(Arc<dyn Any + Send + Sync>);
+
+impl InlineEnvironment {
+    /// Perform `job` in place of enqueueing it
+    pub(crate) fn perform<T: Job>(
+        &self,
+        conn: &PgConnection,
+        job: T,
+    ) -> Result<JobId, EnqueueError> {
+        let env = self
+            .0
+            .downcast_ref::<T::Environment>()
+            .expect("inline environments are looked up by type");
+        perform_now(conn, env, job).map_err(|e| EnqueueError::JobFailed(e.to_string().into()))?;
+        Ok(INLINE_JOB_ID)
+    }
+}
+
+/// A connection pool which always hands out the same connection
+struct SingleConnection<'a>(&'a PgConnection);
+
+impl DieselPoolObj for SingleConnection<'_> {
+    fn get(&self) -> Result<Box<dyn Deref<Target = PgConnection> + '_>, Box<dyn Error>> {
+        Ok(Box::new(self.0))
+    }
+
+    fn with_connection(
+        &self,
+        f: &dyn Fn(&PgConnection) -> Result<(), PerformError>,
+    ) -> Result<(), PerformError> {
+        f(self.0)
+    }
+}
@@ -1,6 +1,17 @@
-use diesel::PgConnection;
+use chrono::{DateTime, Utc};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::BigInt;
+use diesel::{PgConnection, QueryResult};
 use serde::{de::DeserializeOwned, Serialize};
+use serde_derive::Deserialize;
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
 
+use crate::chain::Chain;
+use crate::codec::CodecError;
 use crate::db::DieselPoolObj;
 use crate::errors::{EnqueueError, PerformError};
 use crate::storage;
@@ -17,12 +28,462 @@ pub trait Job: Serialize + DeserializeOwned {
     /// Typically this is the name of your struct in `snake_case`
     const JOB_TYPE: &'static str;
 
-    /// Enqueue this job to be run at some point in the future.
-    fn enqueue(self, conn: &PgConnection) -> Result<(), EnqueueError> {
+    /// The queue this job is placed on when it is enqueued.
+    ///
+    /// The runner uses this to pick the environment the job is run with. See
+    /// [`Builder::queue_environment`](crate::Builder::queue_environment).
+    /// Queues defined with [`queue!`] can be referred to as `Critical::NAME`,
+    /// so a misspelled queue fails to compile.
+    const QUEUE: &'static str = "default";
+
+    /// How urgently this job should be run.
+    ///
+    /// Runners claim pending jobs with a higher priority before jobs with a
+    /// lower one, no matter how long those have been waiting, so a backlog
+    /// of bulk jobs doesn't hold up jobs which need to run quickly. Jobs
+    /// with the same priority are run in the order they were enqueued. A
+    /// single job can be given another priority with
+    /// [`enqueue_with_priority`](Self::enqueue_with_priority).
+    const PRIORITY: i16 = 0;
+
+    /// The `statement_timeout` set on connections this job checks out of the
+    /// pool it is given.
+    ///
+    /// This prevents a runaway query from holding locks indefinitely. When
+    /// `None`, the connection's existing timeout is used.
+    const STATEMENT_TIMEOUT: Option<Duration> = None;
+
+    /// The number of times this job is retried before it is marked as dead.
+    ///
+    /// When `None`, the runner's
+    /// [`max_retries`](crate::Builder::max_retries) is used. Dead jobs can be
+    /// listed and requeued with [`admin::dead_jobs`](crate::admin::dead_jobs)
+    /// and [`admin::requeue_dead_job`](crate::admin::requeue_dead_job).
+    const MAX_RETRIES: Option<u32> = None;
+
+    /// How long to wait before retrying this job when it fails.
+    ///
+    /// When `None`, the runner's [`backoff`](crate::Builder::backoff) is
+    /// used. With `#[background_job]`, set this with `backoff(CONST)`, where
+    /// `CONST` is a `Backoff` constant.
+    const BACKOFF: Option<crate::Backoff> = None;
+
+    /// How many jobs of this type each runner may start in a period of time.
+    ///
+    /// Jobs which call a service with a strict quota can be limited, so a
+    /// backlog of them, or of their retries, doesn't exceed it. Runners skip
+    /// jobs of this type while they are at the limit, and run other jobs
+    /// instead. Retries count towards the limit. With `#[background_job]`,
+    /// set this with `rate_limit(CONST)`, where `CONST` is a `RateLimit`
+    /// constant.
+    const RATE_LIMIT: Option<crate::RateLimit> = None;
+
+    /// How many jobs of this type may run at once, across every runner using
+    /// the database.
+    ///
+    /// This is separate from the runners' thread counts, so `Some(1)` keeps a
+    /// job such as rebuilding a search index from running twice at once, even
+    /// with many runner processes, while other jobs use the rest of the
+    /// threads. Runners skip jobs of this type while they are at the limit.
+    /// Each running job holds one of its type's Postgres advisory locks until
+    /// its transaction ends. With `#[background_job]`, set this with
+    /// `max_concurrency = 1`.
+    const MAX_CONCURRENCY: Option<usize> = None;
+
+    /// The largest this job's arguments may be once serialized, in bytes.
+    ///
+    /// Enqueueing a job whose arguments are larger fails with
+    /// [`EnqueueError::PayloadTooLarge`], so a bug can't fill the table with
+    /// huge rows. Compressed arguments are measured once they have been
+    /// compressed. With `#[background_job]`, set this with
+    /// `max_payload_size = 65536`.
+    const MAX_PAYLOAD_SIZE: Option<usize> = None;
+
+    /// Compress this job's arguments with gzip when they are larger than this
+    /// many bytes once serialized as JSON.
+    ///
+    /// Compressed arguments are stored as bytes, and decompressed by the
+    /// runner before the job is deserialized, so they can't be queried from
+    /// SQL. Every process which enqueues or runs the job needs swirl's
+    /// `compression` feature. Jobs with a [`Codec`](crate::Codec) are never
+    /// compressed. With `#[background_job]`, set this with
+    /// `compress_over = 4096`.
+    const COMPRESS_OVER: Option<usize> = None;
+
+    /// Arguments which should not appear anywhere outside of the job itself.
+    ///
+    /// The runner masks these in the data given to failure notifiers, and
+    /// masks any of their string values which appear in errors before the
+    /// errors are stored, logged, or sent to listeners. They are still
+    /// serialized into the queue as normal. With `#[background_job]`, mark
+    /// arguments with `#[redact]` instead of setting this directly.
+    const REDACTED_FIELDS: &'static [&'static str] = &[];
+
+    /// A key identifying jobs of this type which do the same work, such as
+    /// the id of the cache entry they refresh.
+    ///
+    /// While a job with a key is pending, enqueueing another job of the same
+    /// type with the same key does nothing, even from another process. Use
+    /// [`enqueue_unique`](Self::enqueue_unique) to find out whether the job
+    /// was enqueued. Returns `None` by default, so every job is enqueued. With
+    /// `#[background_job]`, set `unique` to use all of the job's arguments as
+    /// its key, or `unique(arg, ...)` to use some of them.
+    fn unique_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Enqueue this job to be run at some point in the future, returning its
+    /// id.
+    ///
+    /// If a pending job of the same type has the same
+    /// [`unique_key`](Self::unique_key), this job is not enqueued, and the id
+    /// of the pending job is returned instead. The other `enqueue_` methods
+    /// behave the same way.
+    fn enqueue(self, conn: &PgConnection) -> Result<JobId, EnqueueError> {
         storage::enqueue_job(conn, self)
     }
 
+    /// Enqueue this job, unless a pending job of the same type has the same
+    /// [`unique_key`](Self::unique_key). Returns whether the job was
+    /// enqueued.
+    ///
+    /// Jobs without a unique key are always enqueued.
+    fn enqueue_unique(self, conn: &PgConnection) -> Result<bool, EnqueueError> {
+        storage::enqueue_job_unique(conn, self)
+    }
+
+    /// Enqueue this job, to be run before `deadline`.
+    ///
+    /// If the job is not picked up by a runner until after the deadline,
+    /// it is handled according to the runner's
+    /// [`ExpiredJobPolicy`](crate::ExpiredJobPolicy) instead of being run.
+    fn enqueue_with_deadline(
+        self,
+        conn: &PgConnection,
+        deadline: DateTime<Utc>,
+    ) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_with_deadline(conn, self, Some(deadline))
+    }
+
+    /// Enqueue this job, to be run no earlier than `run_at`.
+    ///
+    /// The job is pending until then, but runners will not pick it up. If it
+    /// fails, it is retried as normal.
+    fn enqueue_at(self, conn: &PgConnection, run_at: DateTime<Utc>) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_at(conn, self, run_at)
+    }
+
+    /// Enqueue this job, to be run once `delay` has passed.
+    ///
+    /// The delay is measured from this process's clock. See
+    /// [`enqueue_at`](Self::enqueue_at).
+    fn enqueue_in(self, conn: &PgConnection, delay: Duration) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_at(conn, self, run_at_after(delay))
+    }
+
+    /// Enqueue this job with `priority` instead of [`PRIORITY`](Self::PRIORITY).
+    fn enqueue_with_priority(
+        self,
+        conn: &PgConnection,
+        priority: i16,
+    ) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_with_priority(conn, self, priority)
+    }
+
+    /// Enqueue this job, unless an identical job is already pending.
+    ///
+    /// A job is identical if it has the same type and arguments. Jobs which
+    /// are running or waiting to be retried are pending, but jobs which have
+    /// been marked as dead are not. Returns whether the job was enqueued.
+    fn enqueue_unless_pending(self, conn: &PgConnection) -> Result<bool, EnqueueError> {
+        storage::enqueue_job_unless_pending(conn, self)
+    }
+
+    /// Enqueue this job as part of a batch, such as a backfill or an import.
+    ///
+    /// The progress of every job enqueued with the same `batch_id` can be
+    /// checked with [`admin::batch_progress`](crate::admin::batch_progress).
+    fn enqueue_in_batch(self, conn: &PgConnection, batch_id: &str) -> Result<JobId, EnqueueError> {
+        storage::enqueue_job_in_batch(conn, self, batch_id)
+    }
+
+    /// Run `next` once this job has succeeded.
+    ///
+    /// Returns a [`Chain`], which more jobs can be added to, and which
+    /// enqueues all of them at once.
+    fn then<U: Job + 'static>(self, next: U) -> Chain
+    where
+        Self: 'static,
+    {
+        Chain::new(self).then(next)
+    }
+
+    /// Encode this job's arguments with a [`Codec`](crate::Codec), to store
+    /// them as bytes instead of as JSON.
+    ///
+    /// Returns `None` by default, which stores the arguments as JSON. With
+    /// `#[background_job]`, set `codec(Type)` instead of implementing this.
+    fn encode_binary(&self) -> Result<Option<Vec<u8>>, CodecError> {
+        Ok(None)
+    }
+
+    /// Decode this job's arguments, which were stored as bytes by
+    /// [`encode_binary`](Self::encode_binary)
+    fn decode_binary(bytes: &[u8]) -> Result<Self, CodecError> {
+        let _ = bytes;
+        Err(format!("{} does not have a codec", Self::JOB_TYPE).into())
+    }
+
     /// The logic involved in actually performing this job.
     fn perform(self, env: &Self::Environment, pool: &dyn DieselPoolObj)
         -> Result<(), PerformError>;
 }
+
+/// Enqueue many jobs of the same type at once, returning their ids.
+///
+/// The jobs are inserted with as few statements as possible, inside of a
+/// transaction, which is much faster than enqueueing them one at a time.
+/// Either every job is enqueued, or none of them are. Runners which are
+/// listening for jobs are woken once, since Postgres delivers identical
+/// notifications sent by a transaction only once. Jobs which are skipped
+/// because a pending job has the same [unique key](Job::unique_key) have no
+/// id.
+///
+/// ```ignore
+/// let ids = swirl::enqueue_batch(&conn, user_ids.into_iter().map(send_digest))?;
+/// ```
+pub fn enqueue_batch<T, I>(conn: &PgConnection, jobs: I) -> Result<Vec<JobId>, EnqueueError>
+where
+    T: Job,
+    I: IntoIterator<Item = T>,
+{
+    storage::enqueue_jobs(conn, jobs)
+}
+
+/// Cancel a job which was enqueued earlier.
+///
+/// A pending job is removed from the queue right away, including one which
+/// is waiting to be retried. A job which is running can't be interrupted, so
+/// it is asked to stop instead. Long running jobs can check
+/// [`JobContext::is_cancelled`](crate::JobContext::is_cancelled) between
+/// steps, and return an error to stop early. Once a cancelled job fails, it
+/// is removed from the queue instead of being retried. Jobs which have been
+/// marked as dead are kept so they can still be inspected.
+///
+/// ```ignore
+/// let job_id = export_report(report_id).enqueue(&conn)?;
+/// // ...
+/// swirl::cancel(&conn, job_id)?;
+/// ```
+pub fn cancel(conn: &PgConnection, job_id: JobId) -> QueryResult<Cancellation> {
+    storage::cancel_job(conn, job_id)
+}
+
+/// The result the job with the id `job_id` set with
+/// [`JobContext::set_result`](crate::JobContext::set_result), once it has
+/// succeeded.
+///
+/// Returns `None` while the job is pending or running, if it failed or set no
+/// result, and once the result has been kept for longer than the runner's
+/// [`result_ttl`](crate::Builder::result_ttl). A result which can't be
+/// deserialized as `T` is returned as a
+/// [`DeserializationError`](diesel::result::Error::DeserializationError).
+///
+/// ```ignore
+/// let job_id = render_invoice(order_id).enqueue(&conn)?;
+/// // ...
+/// if let Some(url) = swirl::job_result::<String>(&conn, job_id)? {
+///     redirect_to(&url);
+/// }
+/// ```
+pub fn job_result<T: DeserializeOwned>(
+    conn: &PgConnection,
+    job_id: JobId,
+) -> QueryResult<Option<T>> {
+    match storage::load_result(conn, job_id)? {
+        Some(result) => serde_json::from_value(result)
+            .map(Some)
+            .map_err(|e| diesel::result::Error::DeserializationError(e.into())),
+        None => Ok(None),
+    }
+}
+
+/// What [`cancel`] did with a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cancellation {
+    /// The job was pending, and has been removed from the queue
+    Removed,
+    /// The job is running, and has been asked to stop
+    Requested,
+    /// There is no pending or running job with the id
+    NotFound,
+}
+
+/// The id of a job in the `background_jobs` table.
+///
+/// This is returned when a job is enqueued, and can be stored to look the job
+/// up later. It can be used in queries, and stored in a `BIGINT` column.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde_derive::Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[serde(transparent)]
+#[sql_type = "BigInt"]
+pub struct JobId(pub i64);
+
+impl ToSql<BigInt, Pg> for JobId {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<BigInt, Pg>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<BigInt, Pg> for JobId {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        <i64 as FromSql<BigInt, Pg>>::from_sql(bytes).map(JobId)
+    }
+}
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<JobId> for i64 {
+    fn from(id: JobId) -> Self {
+        id.0
+    }
+}
+
+/// The unique key of a job defined with `#[background_job(unique)]`
+#[doc(hidden)]
+pub fn unique_key_of<T: Serialize>(args: &T) -> Option<String> {
+    serde_json::to_string(args).ok()
+}
+
+/// The time `delay` from now. Delays too long to represent are capped, so the
+/// job is never run instead of being run right away.
+pub(crate) fn run_at_after(delay: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(delay)
+        .ok()
+        .and_then(|delay| Utc::now().checked_add_signed(delay))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+/// A part of a runner's environment which jobs can take instead of the whole
+/// environment.
+///
+/// With `#[background_job(environment(AppEnv))]`, a job's environment
+/// argument can be any type which implements `FromEnv<AppEnv>`, so the job
+/// only sees what it uses. The job is still run by a runner with an
+/// `AppEnv`, but its tests can call the generated `perform_with` method with
+/// just the part it takes:
+///
+/// ```ignore
+/// impl swirl::FromEnv<AppEnv> for Mailer {
+///     fn from_env(env: &AppEnv) -> &Self {
+///         &env.mailer
+///     }
+/// }
+///
+/// #[swirl::background_job(environment(AppEnv))]
+/// fn send_welcome_email(mailer: &Mailer, user_id: i64) -> Result<(), swirl::PerformError> {
+///     // ...
+/// }
+///
+/// send_welcome_email(1).perform_with(&Mailer::fake(), &pool)?;
+/// ```
+pub trait FromEnv<Env> {
+    /// Borrow this part of the environment
+    fn from_env(env: &Env) -> &Self;
+}
+
+/// Jobs which don't take an environment can be run with any environment
+impl<Env> FromEnv<Env> for () {
+    fn from_env(_: &Env) -> &Self {
+        &()
+    }
+}
+
+/// Enqueues a job. `spawn!(conn, job)` is shorthand for
+/// [`Job::enqueue`], and returns the same result.
+///
+/// The connection can be a `&PgConnection`, or anything which dereferences
+/// to one, such as a pooled connection.
+///
+/// ```ignore
+/// swirl::spawn!(conn, send_email(user_id, template))?;
+/// ```
+#[macro_export]
+macro_rules! spawn {
+    ($conn:expr, $job:expr $(,)?) => {
+        $crate::Job::enqueue($job, &*$conn)
+    };
+}
+
+/// A queue defined as a type with [`queue!`].
+///
+/// Referring to queues by type instead of by name means a misspelled queue
+/// fails to compile, instead of creating a queue which no runner serves.
+pub trait Queue {
+    /// The name of the queue in the database
+    const NAME: &'static str;
+}
+
+/// Defines queues as unit structs implementing [`Queue`].
+///
+/// The name of each queue is the name of its struct, unless another one is
+/// given. The structs can be used anywhere a queue name is expected, and
+/// have a `NAME` constant for places which need a `&str`, such as
+/// [`Job::QUEUE`] or a list of queues of different types.
+///
+/// ```ignore
+/// swirl::queue!(pub Critical, pub Bulk = "bulk");
+///
+/// #[swirl::background_job(queue(Critical))]
+/// fn charge_customer(customer_id: i64) -> Result<(), swirl::PerformError> {
+///     // ...
+/// }
+///
+/// let runner = Runner::builder(env)
+///     .queues(vec![Critical::NAME, Bulk::NAME])
+///     .queue_environment(Bulk, bulk_env)
+///     .build();
+/// ```
+#[macro_export]
+macro_rules! queue {
+    ($($vis:vis $queue:ident $(= $name:literal)?),* $(,)?) => {
+        $(
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+            $vis struct $queue;
+
+            impl $queue {
+                /// The name of this queue in the database
+                #[allow(dead_code)]
+                pub const NAME: &'static str = $crate::queue!(@name $queue $($name)?);
+            }
+
+            impl $crate::Queue for $queue {
+                const NAME: &'static str = $queue::NAME;
+            }
+
+            impl ::std::convert::From<$queue> for ::std::string::String {
+                fn from(_: $queue) -> Self {
+                    $queue::NAME.into()
+                }
+            }
+        )*
+    };
+    (@name $queue:ident) => { stringify!($queue) };
+    (@name $queue:ident $name:literal) => { $name };
+}
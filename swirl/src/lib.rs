@@ -8,23 +8,72 @@ pub extern crate inventory;
 #[doc(hidden)]
 pub extern crate serde;
 
+// Allows `#[background_job]` to be used inside this crate
+#[cfg(feature = "testing")]
+extern crate self as swirl;
+
+mod backoff;
+mod batch;
+mod chain;
+mod client;
+mod codec;
+mod compression;
+mod context;
+mod inline;
 mod job;
+mod migrations;
+mod rate_limit;
+mod redact;
 mod registry;
+#[cfg(feature = "runner")]
 mod runner;
-mod storage;
 
+pub mod admin;
+pub mod audit;
+#[cfg(feature = "runner")]
+pub mod config;
 pub mod db;
 pub mod errors;
+#[cfg(feature = "runner")]
+pub mod lifecycle;
+#[cfg(feature = "runner")]
+pub mod middleware;
+#[cfg(feature = "runner")]
+pub mod notifier;
+pub mod outbox;
+pub mod payload_store;
+#[cfg(feature = "runner")]
+pub mod query_hook;
+#[cfg(feature = "schedule")]
+pub mod schedule;
 pub mod schema;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trigger;
+pub mod workflow;
 
+#[cfg(feature = "macros")]
 pub use swirl_proc_macro::*;
 
 #[doc(hidden)]
 pub use serde_derive::{Deserialize, Serialize};
 
+pub use backoff::Backoff;
+pub use batch::Batch;
+pub use chain::Chain;
+pub use client::Client;
+pub use codec::{Codec, CodecError};
+#[cfg(feature = "runner")]
+pub use config::{ConfigError, RunnerConfig};
+pub use context::JobContext;
 pub use errors::*;
+pub use inline::{perform_jobs_inline, perform_now, stop_performing_jobs_inline};
 pub use job::*;
-pub use registry::Registry;
+pub use migrations::run_migrations;
+pub use rate_limit::RateLimit;
+pub use registry::{PerformJob, Registry};
+#[cfg(feature = "runner")]
 pub use runner::*;
 
 #[doc(hidden)]
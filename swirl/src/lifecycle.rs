@@ -0,0 +1,204 @@
+//! Events emitted as jobs move through their lifecycle.
+//!
+//! Listeners can be registered with
+//! [`Builder::lifecycle_listener`](crate::Builder::lifecycle_listener) to
+//! integrate swirl with external audit or workflow systems.
+//!
+//! When the `metrics` feature is enabled, every runner also reports the
+//! number of jobs fetched, started, succeeded, failed, panicked, retried,
+//! slow, expired, cancelled, and marked dead, the number of jobs currently
+//! running, and how long each job took through the `metrics` crate. These are
+//! labelled with the `job_type` and `queue` of each job. The number of
+//! pending jobs on each queue is reported as `swirl_queue_depth`. This can be
+//! configured with [`Builder::metrics`](crate::Builder::metrics).
+//!
+//! When the `tracing` feature is enabled, every runner also emits a
+//! `tracing` event each time a job changes state, and runs each job inside a
+//! `swirl.job` span, so events emitted by the job itself carry its id, type,
+//! queue, and retry count.
+
+use serde_derive::Serialize;
+use std::sync::Arc;
+
+use crate::storage::BackgroundJob;
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "tracing")]
+mod tracing;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+#[cfg(feature = "kafka")]
+pub use self::kafka::{KafkaListener, KafkaListenerBuilder};
+#[cfg(feature = "metrics")]
+pub use self::metrics::MetricsConfig;
+#[cfg(feature = "metrics")]
+pub(crate) use self::metrics::MetricsListener;
+#[cfg(feature = "nats")]
+pub use self::nats::NatsListener;
+#[cfg(feature = "tracing")]
+pub(crate) use self::tracing::TracingListener;
+#[cfg(feature = "webhook")]
+pub use self::webhook::{WebhookListener, WebhookListenerBuilder, SIGNATURE_HEADER};
+
+/// Receives events as jobs are run.
+///
+/// This trait is implemented for any closure which takes a `&LifecycleEvent`.
+pub trait LifecycleListener: Send + Sync + 'static {
+    /// Called for every event emitted by the runner.
+    ///
+    /// This is called from the worker thread which is running the job, so
+    /// implementations should not block for long periods of time.
+    fn on_event(&self, event: &LifecycleEvent);
+}
+
+impl<F> LifecycleListener for F
+where
+    F: Fn(&LifecycleEvent) + Send + Sync + 'static,
+{
+    fn on_event(&self, event: &LifecycleEvent) {
+        self(event)
+    }
+}
+
+/// Something that happened to a job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LifecycleEvent {
+    /// The job was locked by a worker, and is about to be run
+    Started {
+        /// The job which started
+        job: JobMetadata,
+    },
+    /// The job ran successfully, and has been removed from the queue
+    Succeeded {
+        /// The job which succeeded
+        job: JobMetadata,
+        /// How long the job took to run, in milliseconds
+        duration_ms: u64,
+    },
+    /// The job returned an error or panicked. It may be retried.
+    Failed {
+        /// The job which failed
+        job: JobMetadata,
+        /// How long the job ran before failing, in milliseconds
+        duration_ms: u64,
+        /// The error the job failed with
+        error: String,
+    },
+    /// The job has been running for longer than the threshold configured with
+    /// [`Builder::slow_job_threshold`](crate::Builder::slow_job_threshold).
+    /// This is emitted while the job is still running, at most once per
+    /// attempt.
+    Slow {
+        /// The job which is running slowly
+        job: JobMetadata,
+        /// How long the job had been running for, in milliseconds
+        elapsed_ms: u64,
+        /// The threshold the job exceeded, in milliseconds
+        threshold_ms: u64,
+    },
+    /// The job was claimed after its deadline, and was not run. It is
+    /// removed from the queue or marked as dead, depending on the runner's
+    /// [`ExpiredJobPolicy`](crate::ExpiredJobPolicy). This is also emitted
+    /// for jobs which are marked as dead by
+    /// [`Runner::sweep_stale_jobs`](crate::Runner::sweep_stale_jobs).
+    Expired {
+        /// The job which expired
+        job: JobMetadata,
+    },
+    /// The job failed after it was [cancelled](crate::cancel), and has been
+    /// removed from the queue instead of being retried. This is emitted after
+    /// the `Failed` event for the attempt.
+    Cancelled {
+        /// The job which was cancelled
+        job: JobMetadata,
+    },
+    /// The job failed permanently, and will not be retried. This is emitted
+    /// after the `Failed` event for the final attempt, or after the
+    /// `Expired` event for jobs which expired.
+    Dead {
+        /// The job which was marked as dead
+        job: JobMetadata,
+        /// The error from the final attempt to run the job
+        error: String,
+    },
+    /// The job returned [`PerformError::Snooze`](crate::PerformError::Snooze),
+    /// and will be run again once the delay has passed. It is not counted as
+    /// a failure.
+    Snoozed {
+        /// The job which snoozed
+        job: JobMetadata,
+        /// How long the job ran before snoozing, in milliseconds
+        duration_ms: u64,
+        /// How long until the job is run again, in milliseconds
+        delay_ms: u64,
+    },
+}
+
+impl LifecycleEvent {
+    /// The job this event is for
+    pub fn job(&self) -> &JobMetadata {
+        match self {
+            LifecycleEvent::Started { job }
+            | LifecycleEvent::Succeeded { job, .. }
+            | LifecycleEvent::Failed { job, .. }
+            | LifecycleEvent::Slow { job, .. }
+            | LifecycleEvent::Expired { job }
+            | LifecycleEvent::Cancelled { job }
+            | LifecycleEvent::Dead { job, .. }
+            | LifecycleEvent::Snoozed { job, .. } => job,
+        }
+    }
+}
+
+/// Information about the job an event is for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobMetadata {
+    /// The id of the job
+    pub id: i64,
+    /// The type of the job
+    pub job_type: String,
+    /// The queue the job is on
+    pub queue: String,
+    /// The number of times this job has previously been retried
+    pub retries: i32,
+}
+
+impl From<&BackgroundJob> for JobMetadata {
+    fn from(job: &BackgroundJob) -> Self {
+        Self {
+            id: job.id,
+            job_type: job.job_type.clone(),
+            queue: job.queue.clone(),
+            retries: job.retries,
+        }
+    }
+}
+
+/// Sends events to every registered listener
+#[derive(Clone, Default)]
+pub(crate) struct Listeners(Vec<Arc<dyn LifecycleListener>>);
+
+impl Listeners {
+    pub(crate) fn push<L: LifecycleListener>(&mut self, listener: L) {
+        self.0.push(Arc::new(listener));
+    }
+
+    /// Emits an event to all listeners. The event is only constructed if
+    /// there are any listeners registered.
+    pub(crate) fn emit<F: FnOnce() -> LifecycleEvent>(&self, event: F) {
+        if self.0.is_empty() {
+            return;
+        }
+        let event = event();
+        for listener in &self.0 {
+            listener.on_event(&event);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use kafka::producer::{Producer, Record, RequiredAcks};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::{LifecycleEvent, LifecycleListener};
+
+/// Publishes every lifecycle event to a Kafka topic.
+///
+/// Each event is published as JSON, keyed by the ID of the job, so all events
+/// for a single job land on the same partition. Events are published from a
+/// background thread, so an unavailable broker will not slow down the runner.
+/// Events which cannot be published are logged and dropped.
+#[derive(Debug)]
+pub struct KafkaListener {
+    sender: Mutex<Sender<(i64, Vec<u8>)>>,
+}
+
+impl KafkaListener {
+    /// Create a builder for a listener which publishes events to the given
+    /// topic, using the given brokers to discover the rest of the cluster
+    pub fn builder<S: Into<String>>(hosts: Vec<String>, topic: S) -> KafkaListenerBuilder {
+        KafkaListenerBuilder {
+            hosts,
+            topic: topic.into(),
+            ack_timeout: Duration::from_secs(1),
+            required_acks: RequiredAcks::One,
+        }
+    }
+}
+
+impl LifecycleListener for KafkaListener {
+    fn on_event(&self, event: &LifecycleEvent) {
+        match serde_json::to_vec(event) {
+            Ok(body) => {
+                let _ = self.sender.lock().unwrap().send((event.job().id, body));
+            }
+            Err(e) => eprintln!("Failed to serialize lifecycle event: {}", e),
+        }
+    }
+}
+
+/// Configuration for a [`KafkaListener`]
+#[derive(Debug)]
+pub struct KafkaListenerBuilder {
+    hosts: Vec<String>,
+    topic: String,
+    ack_timeout: Duration,
+    required_acks: RequiredAcks,
+}
+
+impl KafkaListenerBuilder {
+    /// The amount of time to wait for the brokers to acknowledge each event.
+    ///
+    /// Defaults to 1 second
+    pub fn ack_timeout(mut self, ack_timeout: Duration) -> Self {
+        self.ack_timeout = ack_timeout;
+        self
+    }
+
+    /// The acknowledgements required before an event is considered published.
+    ///
+    /// Defaults to `RequiredAcks::One`
+    pub fn required_acks(mut self, required_acks: RequiredAcks) -> Self {
+        self.required_acks = required_acks;
+        self
+    }
+
+    /// Connect to the cluster and start the thread which publishes events.
+    ///
+    /// Returns an error if none of the brokers could be reached.
+    pub fn build(self) -> kafka::Result<KafkaListener> {
+        let mut producer = Producer::from_hosts(self.hosts)
+            .with_ack_timeout(self.ack_timeout)
+            .with_required_acks(self.required_acks)
+            .create()?;
+        let topic = self.topic;
+        let (sender, receiver) = channel::<(i64, Vec<u8>)>();
+
+        thread::Builder::new()
+            .name("swirl-kafka".into())
+            .spawn(move || {
+                for (job_id, body) in receiver {
+                    let key = job_id.to_string();
+                    let record = Record::from_key_value(&topic, key, body);
+                    if let Err(e) = producer.send(&record) {
+                        eprintln!("Failed to publish lifecycle event to Kafka: {}", e);
+                    }
+                }
+            })
+            .expect("Failed to spawn kafka thread");
+
+        Ok(KafkaListener {
+            sender: Mutex::new(sender),
+        })
+    }
+}
@@ -0,0 +1,200 @@
+//! Reports metrics for every job through the [`metrics`] crate facade.
+//!
+//! Nothing is recorded unless the application has installed a recorder, such
+//! as `metrics-exporter-prometheus`. Every metric about a job is labelled with
+//! the `job_type` and `queue` of the job.
+
+use metrics::{counter, gauge, histogram};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{JobMetadata, LifecycleEvent, LifecycleListener};
+
+/// How often the number of pending jobs is counted by default
+const DEFAULT_QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How a runner reports metrics, set with
+/// [`Builder::metrics`](crate::Builder::metrics).
+///
+/// ```ignore
+/// let metrics = MetricsConfig::new()
+///     .prefix("billing_jobs")
+///     .label("service", "billing")
+///     .queue_depth_interval(Some(Duration::from_secs(60)));
+/// let runner = Runner::builder(env).metrics(metrics).build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsConfig {
+    prefix: String,
+    labels: Vec<(String, String)>,
+    queue_depth_interval: Option<Duration>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "swirl".into(),
+            labels: Vec::new(),
+            queue_depth_interval: Some(DEFAULT_QUEUE_DEPTH_INTERVAL),
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Metrics named `swirl_*`, with the number of pending jobs counted every
+    /// 15 seconds
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the metrics about jobs and queues `{prefix}_*` instead of
+    /// `swirl_*`. The runner's own timings are always named `swirl_*`.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Add a label with the same value to every metric about jobs and queues,
+    /// such as the name of the service running the jobs
+    pub fn label<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+
+    /// How often to count the pending jobs on each queue for the
+    /// `swirl_queue_depth` gauge, or `None` to never count them.
+    ///
+    /// The jobs are counted by a query when the runner looks for jobs, so
+    /// with many runners this can be set on only one of them.
+    pub fn queue_depth_interval(mut self, interval: Option<Duration>) -> Self {
+        self.queue_depth_interval = interval;
+        self
+    }
+}
+
+/// Added to every runner when the `metrics` feature is enabled, unless it
+/// was disabled with [`Builder::disable_metrics`](crate::Builder::disable_metrics)
+pub(crate) struct MetricsListener {
+    config: MetricsConfig,
+    queue_depths: Mutex<QueueDepths>,
+}
+
+/// When the pending jobs were last counted, and the queues they were on
+#[derive(Default)]
+struct QueueDepths {
+    reported_at: Option<Instant>,
+    queues: HashSet<String>,
+}
+
+impl MetricsListener {
+    pub(crate) fn new(config: MetricsConfig) -> Self {
+        Self {
+            config,
+            queue_depths: Mutex::default(),
+        }
+    }
+
+    fn name(&self, metric: &str) -> String {
+        format!("{}_{}", self.config.prefix, metric)
+    }
+
+    fn labels(&self, job: &JobMetadata) -> Vec<(String, String)> {
+        let mut labels = vec![
+            ("job_type".to_string(), job.job_type.clone()),
+            ("queue".to_string(), job.queue.clone()),
+        ];
+        labels.extend(self.config.labels.iter().cloned());
+        labels
+    }
+
+    /// A job was claimed by a worker, whether or not it is run
+    pub(crate) fn fetched(&self, job: &JobMetadata) {
+        counter!(self.name("jobs_fetched_total"), &self.labels(job)).increment(1);
+    }
+
+    /// An attempt to run a job panicked
+    pub(crate) fn panicked(&self, job: &JobMetadata) {
+        counter!(self.name("jobs_panicked_total"), &self.labels(job)).increment(1);
+    }
+
+    /// A job failed, and will be retried
+    pub(crate) fn retried(&self, job: &JobMetadata) {
+        counter!(self.name("jobs_retried_total"), &self.labels(job)).increment(1);
+    }
+
+    /// Whether it is time to count the pending jobs again. Returns `true` at
+    /// most once per interval.
+    pub(crate) fn queue_depths_due(&self) -> bool {
+        let interval = match self.config.queue_depth_interval {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let mut queue_depths = self.queue_depths.lock().unwrap();
+        match queue_depths.reported_at {
+            Some(reported_at) if reported_at.elapsed() < interval => false,
+            _ => {
+                queue_depths.reported_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Records the number of pending jobs on each queue. Queues which had
+    /// pending jobs when they were last counted, but have none now, are
+    /// reported as empty.
+    pub(crate) fn queue_depths(&self, depths: &HashMap<String, i64>) {
+        let mut queue_depths = self.queue_depths.lock().unwrap();
+        let queues = depths.keys().chain(&queue_depths.queues);
+        for queue in queues.collect::<HashSet<_>>() {
+            let mut labels = vec![("queue".to_string(), queue.clone())];
+            labels.extend(self.config.labels.iter().cloned());
+            let depth = depths.get(queue).copied().unwrap_or(0);
+            gauge!(self.name("queue_depth"), &labels).set(depth as f64);
+        }
+        queue_depths.queues = depths.keys().cloned().collect();
+    }
+}
+
+impl LifecycleListener for MetricsListener {
+    fn on_event(&self, event: &LifecycleEvent) {
+        let labels = self.labels(event.job());
+
+        match event {
+            LifecycleEvent::Started { .. } => {
+                counter!(self.name("jobs_started_total"), &labels).increment(1);
+                gauge!(self.name("jobs_running"), &labels).increment(1.0);
+            }
+            LifecycleEvent::Succeeded { duration_ms, .. } => {
+                counter!(self.name("jobs_succeeded_total"), &labels).increment(1);
+                gauge!(self.name("jobs_running"), &labels).decrement(1.0);
+                histogram!(self.name("job_duration_seconds"), &labels)
+                    .record(*duration_ms as f64 / 1000.0);
+            }
+            LifecycleEvent::Failed { duration_ms, .. } => {
+                counter!(self.name("jobs_failed_total"), &labels).increment(1);
+                gauge!(self.name("jobs_running"), &labels).decrement(1.0);
+                histogram!(self.name("job_duration_seconds"), &labels)
+                    .record(*duration_ms as f64 / 1000.0);
+            }
+            LifecycleEvent::Slow { .. } => {
+                counter!(self.name("jobs_slow_total"), &labels).increment(1);
+            }
+            LifecycleEvent::Expired { .. } => {
+                counter!(self.name("jobs_expired_total"), &labels).increment(1);
+            }
+            LifecycleEvent::Cancelled { .. } => {
+                counter!(self.name("jobs_cancelled_total"), &labels).increment(1);
+            }
+            LifecycleEvent::Dead { .. } => {
+                counter!(self.name("jobs_dead_total"), &labels).increment(1);
+            }
+            LifecycleEvent::Snoozed { duration_ms, .. } => {
+                counter!(self.name("jobs_snoozed_total"), &labels).increment(1);
+                gauge!(self.name("jobs_running"), &labels).decrement(1.0);
+                histogram!(self.name("job_duration_seconds"), &labels)
+                    .record(*duration_ms as f64 / 1000.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::thread;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use super::{LifecycleEvent, LifecycleListener};
+
+/// Publishes every lifecycle event to a NATS subject.
+///
+/// Each event is published as JSON. Events are published from a background
+/// thread, which connects to the server lazily and reconnects if the
+/// connection is lost, so an unavailable server will not slow down the runner.
+#[derive(Debug)]
+pub struct NatsListener {
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+impl NatsListener {
+    /// Create a listener which publishes events to `subject` on the server at
+    /// `url`
+    pub fn new<U, S>(url: U, subject: S) -> Self
+    where
+        U: Into<String>,
+        S: Into<String>,
+    {
+        Self::with_options(async_nats::ConnectOptions::new(), url, subject)
+    }
+
+    /// Create a listener which connects to the server with the given options,
+    /// for example to configure credentials or TLS
+    pub fn with_options<U, S>(options: async_nats::ConnectOptions, url: U, subject: S) -> Self
+    where
+        U: Into<String>,
+        S: Into<String>,
+    {
+        let url = url.into();
+        let subject = subject.into();
+        let (sender, mut receiver) = unbounded_channel::<Vec<u8>>();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start NATS runtime");
+
+        thread::Builder::new()
+            .name("swirl-nats".into())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let client = match options.retry_on_initial_connect().connect(url).await {
+                        Ok(client) => client,
+                        Err(e) => {
+                            eprintln!("Failed to connect to NATS: {}", e);
+                            return;
+                        }
+                    };
+                    while let Some(body) = receiver.recv().await {
+                        if let Err(e) = client.publish(subject.clone(), body.into()).await {
+                            eprintln!("Failed to publish lifecycle event to NATS: {}", e);
+                        }
+                    }
+                    let _ = client.flush().await;
+                })
+            })
+            .expect("Failed to spawn NATS thread");
+
+        NatsListener { sender }
+    }
+}
+
+impl LifecycleListener for NatsListener {
+    fn on_event(&self, event: &LifecycleEvent) {
+        match serde_json::to_vec(event) {
+            Ok(body) => {
+                let _ = self.sender.send(body);
+            }
+            Err(e) => eprintln!("Failed to serialize lifecycle event: {}", e),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Emits a [`tracing`] event every time a job changes state.
+//!
+//! Each event has the `job.id`, `job.type`, `queue`, and `retries` of the
+//! job as fields, since events emitted once a job's transaction has ended are
+//! outside of its span.
+
+use ::tracing::{debug, error, info, warn};
+
+use super::{LifecycleEvent, LifecycleListener};
+
+/// Added to every runner when the `tracing` feature is enabled
+pub(crate) struct TracingListener;
+
+impl LifecycleListener for TracingListener {
+    fn on_event(&self, event: &LifecycleEvent) {
+        let job = event.job();
+        let (id, job_type, queue, retries) = (job.id, &*job.job_type, &*job.queue, job.retries);
+
+        match event {
+            LifecycleEvent::Started { .. } => {
+                debug!(job.id = id, job.type = job_type, queue, retries, "job started");
+            }
+            LifecycleEvent::Succeeded { duration_ms, .. } => {
+                info!(
+                    job.id = id,
+                    job.type = job_type,
+                    queue,
+                    retries,
+                    duration_ms,
+                    "job succeeded"
+                );
+            }
+            LifecycleEvent::Failed {
+                duration_ms, error, ..
+            } => {
+                warn!(
+                    job.id = id,
+                    job.type = job_type,
+                    queue,
+                    retries,
+                    duration_ms,
+                    error = &**error,
+                    "job failed"
+                );
+            }
+            LifecycleEvent::Slow {
+                elapsed_ms,
+                threshold_ms,
+                ..
+            } => {
+                warn!(
+                    job.id = id,
+                    job.type = job_type,
+                    queue,
+                    retries,
+                    elapsed_ms,
+                    threshold_ms,
+                    "job is running slowly"
+                );
+            }
+            LifecycleEvent::Expired { .. } => {
+                warn!(job.id = id, job.type = job_type, queue, retries, "job expired");
+            }
+            LifecycleEvent::Cancelled { .. } => {
+                info!(job.id = id, job.type = job_type, queue, retries, "job cancelled");
+            }
+            LifecycleEvent::Dead { error, .. } => {
+                error!(
+                    job.id = id,
+                    job.type = job_type,
+                    queue,
+                    retries,
+                    error = &**error,
+                    "job marked as dead"
+                );
+            }
+            LifecycleEvent::Snoozed {
+                duration_ms,
+                delay_ms,
+                ..
+            } => {
+                info!(
+                    job.id = id,
+                    job.type = job_type,
+                    queue,
+                    retries,
+                    duration_ms,
+                    delay_ms,
+                    "job snoozed"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::{LifecycleEvent, LifecycleListener};
+
+/// The header containing the signature of the request body, when a secret is
+/// configured.
+///
+/// The value is `sha256=` followed by the hex encoded HMAC-SHA256 of the
+/// request body.
+pub const SIGNATURE_HEADER: &str = "X-Swirl-Signature";
+
+/// Sends every lifecycle event to a webhook.
+///
+/// Each event is sent as JSON in the body of a `POST` request. Requests are
+/// sent from a background thread, so a slow or unavailable endpoint will not
+/// slow down the runner. Events are delivered in order, and failed requests
+/// are retried with exponential backoff before the event is dropped.
+#[derive(Debug)]
+pub struct WebhookListener {
+    sender: Mutex<Sender<Vec<u8>>>,
+}
+
+impl WebhookListener {
+    /// Create a builder for a listener which sends events to the given URL
+    pub fn builder<S: Into<String>>(url: S) -> WebhookListenerBuilder {
+        WebhookListenerBuilder {
+            url: url.into(),
+            secret: None,
+            max_attempts: 5,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Create a listener which sends unsigned events to the given URL
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Self::builder(url).build()
+    }
+}
+
+impl LifecycleListener for WebhookListener {
+    fn on_event(&self, event: &LifecycleEvent) {
+        match serde_json::to_vec(event) {
+            Ok(body) => {
+                let _ = self.sender.lock().unwrap().send(body);
+            }
+            Err(e) => eprintln!("Failed to serialize lifecycle event: {}", e),
+        }
+    }
+}
+
+/// Configuration for a [`WebhookListener`]
+#[derive(Debug)]
+pub struct WebhookListenerBuilder {
+    url: String,
+    secret: Option<Vec<u8>>,
+    max_attempts: u32,
+    timeout: Duration,
+}
+
+impl WebhookListenerBuilder {
+    /// Sign each request with the given secret.
+    ///
+    /// See [`SIGNATURE_HEADER`] for details.
+    pub fn secret<S: Into<Vec<u8>>>(mut self, secret: S) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// The number of times to attempt to deliver each event.
+    ///
+    /// Defaults to 5
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// The amount of time to wait for each request to complete.
+    ///
+    /// Defaults to 10 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Build the listener, starting the thread which delivers events
+    pub fn build(self) -> WebhookListener {
+        let (sender, receiver) = channel::<Vec<u8>>();
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+
+        thread::Builder::new()
+            .name("swirl-webhook".into())
+            .spawn(move || {
+                for body in receiver {
+                    self.deliver(&agent, &body);
+                }
+            })
+            .expect("Failed to spawn webhook thread");
+
+        WebhookListener {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    fn deliver(&self, agent: &ureq::Agent, body: &[u8]) {
+        let mut backoff = Duration::from_millis(100);
+        for attempt in 1..=self.max_attempts {
+            let mut request = agent
+                .post(&self.url)
+                .set("Content-Type", "application/json");
+            if let Some(secret) = &self.secret {
+                request = request.set(SIGNATURE_HEADER, &signature(secret, body));
+            }
+
+            match request.send_bytes(body) {
+                Ok(_) => return,
+                Err(e) if attempt == self.max_attempts => {
+                    eprintln!("Failed to deliver lifecycle event to webhook: {}", e);
+                }
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+}
+
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
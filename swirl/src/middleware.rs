@@ -0,0 +1,90 @@
+//! Hooks which run around every job a runner performs.
+//!
+//! Middleware is added with
+//! [`Builder::middleware`](crate::Builder::middleware), and is useful for
+//! work which applies to every job, such as opening a tracing span, reporting
+//! errors to an external service, or setting session variables for row level
+//! security:
+//!
+//! ```ignore
+//! struct Tenant;
+//!
+//! impl Middleware for Tenant {
+//!     fn before_perform(
+//!         &self,
+//!         job: &JobMetadata,
+//!         session: &mut SessionSettings,
+//!     ) -> Result<(), PerformError> {
+//!         session.set("app.job_type", job.job_type.as_str());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let runner = Runner::builder(env).middleware(Tenant).build();
+//! ```
+//!
+//! Unlike a [`LifecycleListener`](crate::lifecycle::LifecycleListener),
+//! middleware is called on the thread which runs the job, immediately around
+//! it, and can stop the job from running.
+
+use crate::errors::PerformError;
+use crate::lifecycle::JobMetadata;
+
+/// Hooks which are called around every job a runner performs.
+///
+/// When more than one middleware is added, `before_perform` is called in the
+/// order they were added, and the other hooks in the reverse order, so each
+/// middleware wraps the ones added after it. Every hook is called on the
+/// thread running the job, while its row is locked, so implementations should
+/// not block for long periods of time.
+pub trait Middleware: Send + Sync + 'static {
+    /// Called before the job is performed.
+    ///
+    /// Settings added to `session` are applied to every connection the job
+    /// checks out of the pool it is given. Returning an error fails this
+    /// attempt without performing the job, as if the job had returned the
+    /// error, and skips the `before_perform` of any later middleware.
+    fn before_perform(
+        &self,
+        job: &JobMetadata,
+        session: &mut SessionSettings,
+    ) -> Result<(), PerformError> {
+        let _ = (job, session);
+        Ok(())
+    }
+
+    /// Called after every attempt to perform the job, with the error it
+    /// failed with, if any. Redacted arguments are masked in the error.
+    fn after_perform(&self, job: &JobMetadata, error: Option<&str>) {
+        let _ = (job, error);
+    }
+
+    /// Called once a failed attempt has been recorded. `dead` is whether the
+    /// job has been marked as dead, and will not be retried.
+    fn on_failure(&self, job: &JobMetadata, error: &str, dead: bool) {
+        let _ = (job, error, dead);
+    }
+}
+
+/// Session settings to apply to the connections a job checks out of its
+/// pool, set by [`Middleware::before_perform`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SessionSettings {
+    settings: Vec<(String, String)>,
+}
+
+impl SessionSettings {
+    /// Set `name` to `value` on connections the job checks out.
+    ///
+    /// `name` can be any setting which can be changed with `SET`, or a
+    /// custom setting with a dot in its name, such as `app.tenant_id`. The
+    /// previous value is restored when the connection is returned to the
+    /// pool.
+    pub fn set<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.settings.push((name.into(), value.into()));
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<(String, String)> {
+        self.settings
+    }
+}
@@ -0,0 +1,142 @@
+//! The migrations which create and update the tables swirl uses, embedded in
+//! the crate so applications don't have to copy them.
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Text;
+use std::collections::HashSet;
+
+/// A migration in the `migrations` directory, and the SQL which runs it
+struct Migration {
+    name: &'static str,
+    up: &'static str,
+}
+
+macro_rules! migrations {
+    ($($name:literal,)*) => {
+        &[$(Migration {
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, "/up.sql")),
+        },)*]
+    };
+}
+
+/// Every migration, in the order they are run
+const MIGRATIONS: &[Migration] = migrations![
+    "2018-05-03-150523_create_jobs",
+    "2020-05-01-120000_add_queue_to_background_jobs",
+    "2020-05-02-120000_create_background_job_failures",
+    "2020-05-03-120000_add_dead_at_to_background_jobs",
+    "2020-05-04-120000_create_background_job_outbox",
+    "2020-05-05-120000_add_deadline_to_background_jobs",
+    "2020-05-06-120000_create_background_job_retry_budgets",
+    "2020-05-07-120000_add_batches_to_background_jobs",
+    "2020-05-08-120000_create_background_job_attempts",
+    "2020-05-09-120000_create_background_job_schedules",
+    "2020-05-10-120000_create_background_job_audit_log",
+    "2020-05-11-120000_create_background_job_ledger",
+    "2020-05-12-120000_add_retry_at_to_background_jobs",
+    "2020-05-13-120000_create_background_job_checkpoints",
+    "2020-05-14-120000_add_last_enqueued_at_to_background_job_schedules",
+    "2020-05-15-120000_add_priority_to_background_jobs",
+    "2020-05-16-120000_notify_when_background_jobs_are_enqueued",
+    "2020-05-17-120000_add_binary_data_to_background_jobs",
+    "2020-05-18-120000_add_unique_key_to_background_jobs",
+    "2020-05-19-120000_create_background_job_cancellations",
+    "2020-05-20-120000_add_last_error_to_background_jobs",
+    "2020-05-21-120000_create_background_job_heartbeats",
+    "2020-05-22-120000_create_background_job_results",
+    "2020-05-23-120000_create_background_job_dependencies",
+    "2020-05-24-120000_add_callbacks_to_background_job_batches",
+    "2020-05-25-120000_create_background_job_paused_queues",
+    "2020-05-26-120000_add_compressed_to_background_jobs",
+    "2020-05-27-120000_add_started_at_and_finished_at_to_background_jobs",
+    "2020-05-28-120000_create_background_jobs_archive",
+];
+
+#[derive(QueryableByName)]
+struct RunMigration {
+    #[sql_type = "Text"]
+    version: String,
+}
+
+/// Creates the tables swirl uses, or updates them to match this version of
+/// swirl. Returns the names of the migrations which were run.
+///
+/// Each migration is run in a transaction of its own, and is recorded in the
+/// `__diesel_schema_migrations` table, so migrations which have already been
+/// run are skipped, including those run by Diesel CLI from a copy of swirl's
+/// `migrations` directory. Migrations which were copied under other names
+/// must have their versions added to that table before this is first called.
+///
+/// ```ignore
+/// let conn = PgConnection::establish(&database_url)?;
+/// swirl::run_migrations(&conn)?;
+/// ```
+pub fn run_migrations(conn: &PgConnection) -> QueryResult<Vec<&'static str>> {
+    sql_query(
+        "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations ( \
+             version VARCHAR(50) PRIMARY KEY NOT NULL, \
+             run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP \
+         )",
+    )
+    .execute(conn)?;
+    let run_already = sql_query("SELECT version FROM __diesel_schema_migrations")
+        .load::<RunMigration>(conn)?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect::<HashSet<_>>();
+
+    let mut run = Vec::new();
+    for migration in MIGRATIONS {
+        let version = version(migration.name);
+        if run_already.contains(&version) {
+            continue;
+        }
+        conn.transaction(|| {
+            conn.batch_execute(migration.up)?;
+            sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ($1)")
+                .bind::<Text, _>(&version)
+                .execute(conn)
+        })?;
+        run.push(migration.name);
+    }
+    Ok(run)
+}
+
+/// The version of a migration, as Diesel CLI records it: the part of its
+/// name before the first underscore, without dashes
+fn version(name: &str) -> String {
+    name.split('_').next().unwrap_or_default().replace('-', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn every_migration_is_embedded() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../migrations");
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        let embedded = MIGRATIONS.iter().map(|m| m.name).collect::<Vec<_>>();
+        assert_eq!(names, embedded);
+    }
+
+    #[test]
+    fn versions_match_those_recorded_by_diesel_cli() {
+        assert_eq!(
+            "20200521120000",
+            version("2020-05-21-120000_create_background_job_heartbeats")
+        );
+        assert_eq!("20180503150523", version("2018-05-03-150523_create_jobs"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Notifications for jobs which have failed.
+//!
+//! A job fails permanently once it has been retried more times than allowed by
+//! [`Builder::max_retries`](crate::Builder::max_retries). A notifier can be
+//! configured with [`Builder::failure_notifier`](crate::Builder::failure_notifier)
+//! to alert someone when this happens.
+//!
+//! Every failed attempt is printed to stderr, unless an [`ErrorHandler`] is
+//! configured with [`Builder::error_handler`](crate::Builder::error_handler)
+//! to send them somewhere else.
+
+use serde_derive::Serialize;
+use std::fmt;
+
+use crate::errors::PerformError;
+use crate::storage::BackgroundJob;
+
+#[cfg(feature = "smtp")]
+mod smtp;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+#[cfg(feature = "smtp")]
+pub use self::smtp::SmtpNotifier;
+#[cfg(feature = "webhook")]
+pub use self::webhook::WebhookNotifier;
+
+/// Receives notifications when a job has failed permanently.
+///
+/// This trait is implemented for any closure which takes a `&JobFailure`.
+pub trait FailureNotifier: Send + Sync + 'static {
+    /// Called once a job has been marked as dead, and will not be retried.
+    ///
+    /// This is called from the worker thread which ran the job, so
+    /// implementations should not block for long periods of time.
+    fn notify(&self, failure: &JobFailure);
+}
+
+impl<F> FailureNotifier for F
+where
+    F: Fn(&JobFailure) + Send + Sync + 'static,
+{
+    fn notify(&self, failure: &JobFailure) {
+        self(failure)
+    }
+}
+
+/// A job which has failed permanently
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobFailure {
+    /// The id of the job
+    pub job_id: i64,
+    /// The type of the job
+    pub job_type: String,
+    /// The queue the job was on
+    pub queue: String,
+    /// The serialized arguments of the job
+    pub data: serde_json::Value,
+    /// The number of times the job was retried before it was marked as dead
+    pub retries: i32,
+    /// The error from the final attempt to run the job
+    pub error: String,
+}
+
+impl JobFailure {
+    pub(crate) fn new(job: BackgroundJob, error: String) -> Self {
+        Self {
+            job_id: job.id,
+            job_type: job.job_type,
+            queue: job.queue,
+            data: job.data,
+            retries: job.retries + 1,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for JobFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Job {} ({}) failed permanently after {} retries: {}",
+            self.job_id, self.job_type, self.retries, self.error
+        )
+    }
+}
+
+/// Handles every failed attempt to run a job, in place of printing it to
+/// stderr.
+///
+/// This trait is implemented for any closure which takes a `&JobError`.
+pub trait ErrorHandler: Send + Sync + 'static {
+    /// Called after a failed attempt has been recorded.
+    ///
+    /// This is called from the worker thread which ran the job, so
+    /// implementations should not block for long periods of time.
+    fn handle(&self, error: &JobError<'_>);
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(&JobError<'_>) + Send + Sync + 'static,
+{
+    fn handle(&self, error: &JobError<'_>) {
+        self(error)
+    }
+}
+
+/// A failed attempt to run a job
+#[derive(Debug)]
+pub struct JobError<'a> {
+    /// The id of the job
+    pub job_id: i64,
+    /// The type of the job
+    pub job_type: &'a str,
+    /// The queue the job is on
+    pub queue: &'a str,
+    /// The serialized arguments of the job, with its
+    /// [redacted fields](crate::Job::REDACTED_FIELDS) masked
+    pub data: &'a serde_json::Value,
+    /// The number of times the job had been retried before this attempt
+    pub retries: i32,
+    /// The error the job failed with. If the job panicked, this is the
+    /// panic's message.
+    ///
+    /// The values of redacted fields are not masked in this error, so it
+    /// should not be sent anywhere they shouldn't appear. Use
+    /// [`message`](Self::message) instead.
+    pub error: &'a PerformError,
+    /// The error as it was recorded, with the values of redacted fields
+    /// masked
+    pub message: &'a str,
+    /// Whether the job panicked
+    pub panicked: bool,
+    /// Whether the job has been marked as dead, and will not be retried
+    pub dead: bool,
+}
+
+impl fmt::Display for JobError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Job {} failed to run: {}", self.job_id, self.message)
+    }
+}
@@ -0,0 +1,53 @@
+use lettre::message::Mailbox;
+use lettre::{Message, SmtpTransport, Transport};
+
+use super::{FailureNotifier, JobFailure};
+
+/// Sends an email for each permanent job failure.
+#[allow(missing_debug_implementations)]
+pub struct SmtpNotifier {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl SmtpNotifier {
+    /// Create a notifier which sends mail from `from` to every address in
+    /// `to` using the given transport.
+    pub fn new(transport: SmtpTransport, from: Mailbox, to: Vec<Mailbox>) -> Self {
+        Self {
+            transport,
+            from,
+            to,
+        }
+    }
+
+    fn message(&self, failure: &JobFailure) -> Result<Message, lettre::error::Error> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(format!(
+            "[swirl] {} job failed permanently",
+            failure.job_type
+        ));
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        builder.body(format!(
+            "{}\n\nQueue: {}\nArguments: {}\n",
+            failure, failure.queue, failure.data,
+        ))
+    }
+}
+
+impl FailureNotifier for SmtpNotifier {
+    fn notify(&self, failure: &JobFailure) {
+        let result = self
+            .message(failure)
+            .map_err(|e| e.to_string())
+            .and_then(|message| self.transport.send(&message).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to send failure notification for job {}: {}",
+                failure.job_id, e
+            );
+        }
+    }
+}
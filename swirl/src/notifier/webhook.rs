@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use super::{FailureNotifier, JobFailure};
+
+/// Sends permanent job failures to a webhook.
+///
+/// Each failure is sent as a JSON encoded [`JobFailure`] in the body of a
+/// `POST` request to the given URL.
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl WebhookNotifier {
+    /// Create a notifier which sends failures to the given URL.
+    ///
+    /// Requests will time out after 10 seconds.
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Self::with_timeout(url, Duration::from_secs(10))
+    }
+
+    /// Create a notifier which sends failures to the given URL, timing out
+    /// after the given duration.
+    pub fn with_timeout<S: Into<String>>(url: S, timeout: Duration) -> Self {
+        Self {
+            url: url.into(),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+impl FailureNotifier for WebhookNotifier {
+    fn notify(&self, failure: &JobFailure) {
+        let result = self.agent.post(&self.url).send_json(failure);
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to send failure notification for job {}: {}",
+                failure.job_id, e
+            );
+        }
+    }
+}
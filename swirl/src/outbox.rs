@@ -0,0 +1,147 @@
+//! An implementation of the transactional outbox pattern.
+//!
+//! Messages are written to the outbox in the same transaction as the rest of
+//! your changes, along with a job which delivers them. If the transaction is
+//! rolled back, the message is never sent. If it commits, the message will be
+//! delivered by the runner, and retried like any other job until it succeeds.
+//!
+//! Each message is only delivered once it has been committed, and is marked
+//! as delivered in the same transaction that sends it, so a message will not
+//! be sent again once its delivery has been recorded. As with any outbox, a
+//! message may be sent more than once if the runner crashes between sending it
+//! and recording the delivery, so consumers should be prepared to see
+//! duplicates.
+//!
+//! To deliver messages, implement [`Publisher`] for your environment, and
+//! register [`DeliverMessage`] with the runner:
+//!
+//! ```ignore
+//! runner.registry().register::<swirl::outbox::DeliverMessage<Environment>>();
+//! ```
+
+use diesel::dsl::now;
+use diesel::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+use crate::db::DieselPoolObj;
+use crate::errors::{EnqueueError, PerformError};
+use crate::schema::background_job_outbox;
+use crate::storage::{self, NewJob};
+use crate::Job;
+
+/// Sends messages written to the outbox.
+///
+/// This should be implemented by the environment that [`DeliverMessage`] is
+/// run with.
+pub trait Publisher: 'static {
+    /// Send a single message to the given topic.
+    ///
+    /// If an error is returned, delivery will be retried later.
+    fn publish(&self, topic: &str, payload: &serde_json::Value) -> Result<(), PerformError>;
+}
+
+/// Writes a message to the outbox, and enqueues a job to deliver it.
+///
+/// Both are written in a single transaction. If this is called inside of
+/// another transaction, the message will only be delivered if that
+/// transaction commits. Returns the ID of the message.
+pub fn publish<T: serde::Serialize>(
+    conn: &PgConnection,
+    topic: &str,
+    payload: &T,
+) -> Result<i64, EnqueueError> {
+    let payload = serde_json::to_value(payload)?;
+    conn.transaction(|| {
+        let message_id = diesel::insert_into(background_job_outbox::table)
+            .values((
+                background_job_outbox::topic.eq(topic),
+                background_job_outbox::payload.eq(payload),
+            ))
+            .returning(background_job_outbox::id)
+            .get_result(conn)?;
+        let job = serde_json::to_value(DeliverMessage::<()>::new(message_id))?;
+        storage::insert_job(
+            conn,
+            &NewJob::new(DELIVER_MESSAGE_JOB_TYPE, "default", job, 0),
+        )?;
+        Ok(message_id)
+    })
+}
+
+const DELIVER_MESSAGE_JOB_TYPE: &str = "swirl_outbox_deliver_message";
+
+/// The job which delivers a message written by [`publish`].
+///
+/// `Env` must be the environment type of the runner which delivers messages.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct DeliverMessage<Env> {
+    message_id: i64,
+    #[serde(skip)]
+    _marker: PhantomData<Env>,
+}
+
+impl<Env> DeliverMessage<Env> {
+    fn new(message_id: i64) -> Self {
+        Self {
+            message_id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Env: Publisher> Job for DeliverMessage<Env> {
+    type Environment = Env;
+
+    const JOB_TYPE: &'static str = DELIVER_MESSAGE_JOB_TYPE;
+
+    fn perform(
+        self,
+        env: &Self::Environment,
+        pool: &dyn DieselPoolObj,
+    ) -> Result<(), PerformError> {
+        deliver(&*pool.get()?, self.message_id, env)
+    }
+}
+
+fn deliver(
+    conn: &PgConnection,
+    message_id: i64,
+    publisher: &dyn Publisher,
+) -> Result<(), PerformError> {
+    use crate::schema::background_job_outbox::dsl::*;
+
+    conn.transaction(|| {
+        let message = background_job_outbox
+            .find(message_id)
+            .select((topic, payload, delivered_at.is_not_null()))
+            .for_update()
+            .first::<(String, serde_json::Value, bool)>(conn)
+            .optional()?;
+
+        match message {
+            Some((message_topic, message_payload, false)) => {
+                publisher.publish(&message_topic, &message_payload)?;
+                diesel::update(background_job_outbox.find(message_id))
+                    .set(delivered_at.eq(now))
+                    .execute(conn)?;
+                Ok(())
+            }
+            // Already delivered, or purged from the outbox
+            Some((_, _, true)) | None => Ok(()),
+        }
+    })
+}
+
+/// Deletes messages which were delivered before the given time.
+///
+/// Returns the number of messages which were deleted.
+pub fn purge_delivered(
+    conn: &PgConnection,
+    before: chrono::DateTime<chrono::Utc>,
+) -> QueryResult<usize> {
+    use crate::schema::background_job_outbox::dsl::*;
+
+    diesel::delete(background_job_outbox.filter(delivered_at.lt(before))).execute(conn)
+}
@@ -0,0 +1,170 @@
+//! Storing large job payloads outside of the database, known as the claim
+//! check pattern.
+//!
+//! Jobs enqueued through a [`ClaimCheck`] whose serialized arguments are
+//! larger than its threshold have their arguments written to a
+//! [`PayloadStore`], and only a reference to them is stored in the queue.
+//! A runner configured with the same store using
+//! [`Builder::payload_store`](crate::Builder::payload_store) fetches the
+//! arguments before running the job, and deletes them once the job has
+//! succeeded.
+//!
+//! ```ignore
+//! let store = Arc::new(DirectoryPayloadStore::new("/mnt/shared/payloads"));
+//! let claim_check = ClaimCheck::new(store.clone(), 64 * 1024);
+//! claim_check.enqueue(&conn, render_report(rows))?;
+//!
+//! let runner = Runner::builder(env)
+//!     .payload_store(store)
+//!     .build();
+//! ```
+//!
+//! Payloads are written before the job is inserted, so a payload may be left
+//! behind if the transaction the job was enqueued in is rolled back. Payloads
+//! of jobs which are marked as dead are kept, so the job can be inspected.
+
+use diesel::PgConnection;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::EnqueueError;
+use crate::storage::{self, NewJob};
+use crate::{Job, JobId};
+
+/// The key of the object stored in place of an offloaded payload. This is
+/// not a valid Rust identifier, so it can't be confused with a job argument.
+const REFERENCE_KEY: &str = "$swirl_payload";
+
+/// An error reading or writing a payload
+pub type PayloadError = Box<dyn Error + Send + Sync>;
+
+/// Somewhere large job payloads can be stored, such as an object store.
+///
+/// Every process which enqueues or runs jobs with a store must be able to
+/// read the payloads written by the others.
+pub trait PayloadStore: Send + Sync + 'static {
+    /// Store a payload, returning the key it can be fetched with
+    fn put(&self, payload: &[u8]) -> Result<String, PayloadError>;
+
+    /// Fetch a payload which was previously stored
+    fn get(&self, key: &str) -> Result<Vec<u8>, PayloadError>;
+
+    /// Delete a payload. Deleting a payload which doesn't exist is not an
+    /// error.
+    fn delete(&self, key: &str) -> Result<(), PayloadError>;
+}
+
+/// Enqueues jobs, offloading the arguments of large ones to a
+/// [`PayloadStore`]
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct ClaimCheck {
+    store: Arc<dyn PayloadStore>,
+    threshold: usize,
+}
+
+impl ClaimCheck {
+    /// Offload the arguments of jobs which are larger than `threshold` bytes
+    /// once serialized
+    pub fn new(store: Arc<dyn PayloadStore>, threshold: usize) -> Self {
+        Self { store, threshold }
+    }
+
+    /// Enqueue a job, storing its arguments in the payload store if they are
+    /// over the threshold. Returns the job's id.
+    ///
+    /// Jobs with a [`Codec`](crate::Codec), or whose arguments were
+    /// [compressed](Job::COMPRESS_OVER), are always stored in the database.
+    pub fn enqueue<T: Job>(&self, conn: &PgConnection, job: T) -> Result<JobId, EnqueueError> {
+        let mut new_job = NewJob::of(&job)?;
+        if new_job.binary_data.is_none() {
+            let payload = serde_json::to_vec(&new_job.data)?;
+            if payload.len() > self.threshold {
+                let key = self
+                    .store
+                    .put(&payload)
+                    .map_err(EnqueueError::PayloadStoreError)?;
+                new_job.data = serde_json::json!({ REFERENCE_KEY: key });
+            }
+        }
+        Ok(storage::insert_or_find_job(conn, &new_job)?)
+    }
+}
+
+/// The key of the offloaded payload, if the job's data is a reference to one
+#[cfg(feature = "runner")]
+pub(crate) fn reference(data: &serde_json::Value) -> Option<&str> {
+    let map = data.as_object()?;
+    if map.len() == 1 {
+        map.get(REFERENCE_KEY)?.as_str()
+    } else {
+        None
+    }
+}
+
+/// Fetch and parse an offloaded payload
+#[cfg(feature = "runner")]
+pub(crate) fn fetch(
+    store: &dyn PayloadStore,
+    key: &str,
+) -> Result<serde_json::Value, PayloadError> {
+    let payload = store.get(key)?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Stores payloads as files in a directory, which may be a network mount
+/// shared by every process.
+#[derive(Debug, Clone)]
+pub struct DirectoryPayloadStore {
+    directory: PathBuf,
+}
+
+impl DirectoryPayloadStore {
+    /// Store payloads in the given directory, which must already exist
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, PayloadError> {
+        // Keys come from the database, so they must not be able to escape
+        // the directory
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("Invalid payload key {:?}", key).into());
+        }
+        Ok(self.directory.join(format!("{}.json", key)))
+    }
+}
+
+impl PayloadStore for DirectoryPayloadStore {
+    fn put(&self, payload: &[u8]) -> Result<String, PayloadError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let key = format!(
+            "{}-{}-{}",
+            nanos,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        fs::write(self.path(&key)?, payload)?;
+        Ok(key)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, PayloadError> {
+        Ok(fs::read(self.path(key)?)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), PayloadError> {
+        match fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
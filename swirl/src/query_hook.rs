@@ -0,0 +1,87 @@
+//! Observing the queries the runner makes to manage the job queue.
+//!
+//! A [`QueryHook`] can be registered with
+//! [`Builder::query_hook`](crate::Builder::query_hook) to find out how long
+//! the runner spends claiming and updating jobs. This is useful for detecting
+//! when claiming jobs has become a bottleneck under contention, and how often
+//! queries have to be retried because of serialization failures or
+//! deadlocks.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Receives the duration of every query the runner makes to manage the queue.
+///
+/// This trait is implemented for any closure which takes a `StorageQuery` and
+/// a `Duration`.
+pub trait QueryHook: Send + Sync + 'static {
+    /// Called after each query completes, whether or not it succeeded.
+    ///
+    /// This is called from the worker thread which made the query, so
+    /// implementations should not block for long periods of time.
+    fn on_query(&self, query: StorageQuery, duration: Duration);
+
+    /// Called when a query failed with a serialization failure or deadlock,
+    /// and is about to be retried. `attempt` is the number of the attempt
+    /// which failed, starting at 1.
+    ///
+    /// See [`Builder::storage_retries`](crate::Builder::storage_retries).
+    fn on_retry(&self, query: StorageQuery, attempt: u32, error: &diesel::result::Error) {
+        let _ = (query, attempt, error);
+    }
+}
+
+impl<F> QueryHook for F
+where
+    F: Fn(StorageQuery, Duration) + Send + Sync + 'static,
+{
+    fn on_query(&self, query: StorageQuery, duration: Duration) {
+        self(query, duration)
+    }
+}
+
+/// A query made by the runner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum StorageQuery {
+    /// Finding and locking the next job to run
+    Claim,
+    /// Deleting a job which ran successfully
+    Delete,
+    /// Recording that a job failed, and scheduling its retry
+    UpdateFailed,
+}
+
+impl StorageQuery {
+    /// A short name for this query, suitable for use as a metric label
+    pub fn name(self) -> &'static str {
+        match self {
+            StorageQuery::Claim => "claim",
+            StorageQuery::Delete => "delete",
+            StorageQuery::UpdateFailed => "update_failed",
+        }
+    }
+}
+
+impl fmt::Display for StorageQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Runs `f`, reporting how long it took to the hook if there is one
+pub(crate) fn timed<T, F: FnOnce() -> T>(
+    hook: Option<&dyn QueryHook>,
+    query: StorageQuery,
+    f: F,
+) -> T {
+    match hook {
+        Some(hook) => {
+            let started = Instant::now();
+            let result = f();
+            hook.on_query(query, started.elapsed());
+            result
+        }
+        None => f(),
+    }
+}
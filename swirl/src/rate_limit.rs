@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+/// How many jobs of a type may be started in a period of time.
+///
+/// Each runner keeps a bucket of `jobs` tokens for the job type, which is
+/// refilled evenly over `period`, and takes a token each time it starts a
+/// job. A runner which was idle can start a burst of up to `jobs` jobs at
+/// once. The limit applies to each runner separately, so a service with a
+/// quota shared by several runners should be given a share of it.
+///
+/// ```ignore
+/// const GEOCODING_QUOTA: RateLimit = RateLimit::per_minute(60);
+///
+/// #[swirl::background_job(rate_limit(GEOCODING_QUOTA))]
+/// fn geocode(env: &Env, address_id: i64) -> Result<(), PerformError> {
+///     // ...
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    jobs: u32,
+    period: Duration,
+}
+
+impl RateLimit {
+    /// Start at most `jobs` jobs in each `period`
+    pub const fn new(jobs: u32, period: Duration) -> Self {
+        Self { jobs, period }
+    }
+
+    /// Start at most `jobs` jobs each second
+    pub const fn per_second(jobs: u32) -> Self {
+        Self::new(jobs, Duration::from_secs(1))
+    }
+
+    /// Start at most `jobs` jobs each minute
+    pub const fn per_minute(jobs: u32) -> Self {
+        Self::new(jobs, Duration::from_secs(60))
+    }
+
+    /// Start at most `jobs` jobs each hour
+    pub const fn per_hour(jobs: u32) -> Self {
+        Self::new(jobs, Duration::from_secs(60 * 60))
+    }
+
+    /// The most jobs which can be started in each period
+    pub fn jobs(&self) -> u32 {
+        self.jobs
+    }
+
+    /// The period the jobs are spread over
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
@@ -0,0 +1,45 @@
+//! Masking of job arguments marked with `#[redact]`.
+
+use serde_json::Value;
+
+const MASK: &str = "[REDACTED]";
+
+/// Replaces the value of each of `fields` in a job's data
+pub(crate) fn data(data: &mut Value, fields: &[&str]) {
+    if let Value::Object(map) = data {
+        for &field in fields {
+            if let Some(value) = map.get_mut(field) {
+                *value = Value::String(MASK.into());
+            }
+        }
+    }
+}
+
+/// Replaces every string in the redacted fields of `data` which appears in
+/// `error`
+pub(crate) fn error(error: &str, data: &Value, fields: &[&str]) -> String {
+    let mut secrets = Vec::new();
+    for &field in fields {
+        if let Some(value) = data.get(field) {
+            strings(value, &mut secrets);
+        }
+    }
+    // Longer secrets first, so one containing another is masked completely
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+
+    secrets
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .fold(error.to_string(), |error, secret| {
+            error.replace(secret, MASK)
+        })
+}
+
+fn strings<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+    match value {
+        Value::String(s) => out.push(s),
+        Value::Array(values) => values.iter().for_each(|v| strings(v, out)),
+        Value::Object(map) => map.values().for_each(|v| strings(v, out)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
@@ -3,17 +3,28 @@
 use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::RwLock;
+use std::time::Duration;
 
 use crate::db::DieselPoolObj;
 use crate::errors::PerformError;
-use crate::Job;
+#[cfg(feature = "schedule")]
+use crate::schedule::{self, ScheduleError};
+use crate::{redact, Backoff, Job, RateLimit};
 
 #[derive(Default)]
 #[allow(missing_debug_implementations)] // Can't derive debug
 /// A registry of background jobs, used to map job types to concrete perform
 /// functions at runtime.
+///
+/// Jobs can be added to the registry after the runner has been built, which
+/// allows applications that load job handlers dynamically to register them
+/// without rebuilding the runner.
 pub struct Registry<Env> {
-    jobs: HashMap<&'static str, JobVTable>,
+    jobs: RwLock<HashMap<&'static str, JobVTable>>,
+    /// Schedules registered since the runner last saved them
+    #[cfg(feature = "schedule")]
+    recurring: RwLock<HashMap<&'static str, String>>,
     _marker: PhantomData<Env>,
 }
 
@@ -28,17 +39,115 @@ impl<Env: 'static> Registry<Env> {
             .collect();
 
         Self {
-            jobs: jobs,
+            jobs: RwLock::new(jobs),
+            #[cfg(feature = "schedule")]
+            recurring: RwLock::default(),
             _marker: PhantomData,
         }
     }
 
+    /// Register a job with this registry.
+    ///
+    /// Any job previously registered with the same job type will be replaced.
+    /// Jobs registered with [`register_job!`] do not need to be registered
+    /// again.
+    pub fn register<T: Job<Environment = Env>>(&self) {
+        self.jobs
+            .write()
+            .unwrap()
+            .insert(T::JOB_TYPE, JobVTable::from_job::<T>());
+    }
+
+    /// Register a job which takes no arguments, and run it on the schedule
+    /// given by a cron expression, in UTC.
+    ///
+    /// The runner saves the schedule the next time it looks for jobs,
+    /// replacing any schedule the job type already had unless it is the
+    /// same. A schedule which has been paused stays paused. See
+    /// [`schedule`](crate::schedule) to use another time zone, or to manage
+    /// schedules at runtime.
+    ///
+    /// ```ignore
+    /// runner.registry().register_recurring::<refresh_stats::Job>("0 */5 * * * *")?;
+    /// ```
+    #[cfg(feature = "schedule")]
+    pub fn register_recurring<T: Job<Environment = Env>>(
+        &self,
+        cron: &str,
+    ) -> Result<(), ScheduleError> {
+        schedule::validate(cron, "UTC")?;
+        let data = serde_json::Value::Object(Default::default());
+        if serde_json::from_value::<T>(data).is_err() {
+            return Err(ScheduleError::TakesArguments(T::JOB_TYPE.into()));
+        }
+        self.register::<T>();
+        self.recurring
+            .write()
+            .unwrap()
+            .insert(T::JOB_TYPE, cron.into());
+        Ok(())
+    }
+
+    /// Takes the schedules registered since this was last called
+    #[cfg(all(feature = "schedule", feature = "runner"))]
+    pub(crate) fn take_recurring(&self) -> HashMap<&'static str, String> {
+        std::mem::take(&mut *self.recurring.write().unwrap())
+    }
+
+    /// Puts back schedules which could not be saved, unless they have been
+    /// registered again since
+    #[cfg(all(feature = "schedule", feature = "runner"))]
+    pub(crate) fn restore_recurring(&self, schedules: HashMap<&'static str, String>) {
+        let mut recurring = self.recurring.write().unwrap();
+        for (job_type, cron) in schedules {
+            recurring.entry(job_type).or_insert(cron);
+        }
+    }
+
+    /// The queue and priority each registered job type is enqueued with
+    #[cfg(all(feature = "schedule", feature = "runner"))]
+    pub(crate) fn job_types(&self) -> HashMap<&'static str, (&'static str, i16)> {
+        self.jobs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&job_type, vtable)| (job_type, (vtable.queue, vtable.priority)))
+            .collect()
+    }
+
+    /// The registered job types which have a [`RATE_LIMIT`](Job::RATE_LIMIT)
+    #[cfg(feature = "runner")]
+    pub(crate) fn rate_limits(&self) -> Vec<(&'static str, RateLimit)> {
+        self.jobs
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(&job_type, vtable)| Some((job_type, vtable.rate_limit?)))
+            .collect()
+    }
+
+    /// The registered job types which have a
+    /// [`MAX_CONCURRENCY`](Job::MAX_CONCURRENCY)
+    #[cfg(feature = "runner")]
+    pub(crate) fn concurrency_limits(&self) -> Vec<(&'static str, usize)> {
+        self.jobs
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(&job_type, vtable)| Some((job_type, vtable.max_concurrency?)))
+            .collect()
+    }
+
     /// Get the perform function for a given job type
     pub fn get(&self, job_type: &str) -> Option<PerformJob<Env>> {
-        self.jobs.get(job_type).map(|&vtable| PerformJob {
-            vtable,
-            _marker: PhantomData,
-        })
+        self.jobs
+            .read()
+            .unwrap()
+            .get(job_type)
+            .map(|&vtable| PerformJob {
+                vtable,
+                _marker: PhantomData,
+            })
     }
 }
 
@@ -54,22 +163,56 @@ macro_rules! register_job {
     };
 }
 
+/// Deserializes a job's arguments from JSON and performs it
+type PerformFn = fn(serde_json::Value, &dyn Any, &dyn DieselPoolObj) -> Result<(), PerformError>;
+
+/// Decodes a job's arguments from bytes written by its codec and performs it
+type PerformBinaryFn = fn(&[u8], &dyn Any, &dyn DieselPoolObj) -> Result<(), PerformError>;
+
 #[doc(hidden)]
 #[derive(Clone, Copy)]
 pub struct JobVTable {
     env_type: TypeId,
     job_type: &'static str,
-    perform: fn(serde_json::Value, &dyn Any, &dyn DieselPoolObj) -> Result<(), PerformError>,
+    queue: &'static str,
+    priority: i16,
+    statement_timeout: Option<Duration>,
+    max_retries: Option<u32>,
+    backoff: Option<Backoff>,
+    rate_limit: Option<RateLimit>,
+    max_concurrency: Option<usize>,
+    redacted_fields: &'static [&'static str],
+    perform: PerformFn,
+    perform_binary: PerformBinaryFn,
 }
 
 inventory::collect!(JobVTable);
 
+/// The [redacted fields](Job::REDACTED_FIELDS) of a job type registered with
+/// [`register_job!`] for any environment, or `None` if it isn't registered in
+/// this binary
+pub(crate) fn redacted_fields(job_type: &str) -> Option<&'static [&'static str]> {
+    inventory::iter::<JobVTable>
+        .into_iter()
+        .find(|vtable| vtable.job_type == job_type)
+        .map(|vtable| vtable.redacted_fields)
+}
+
 impl JobVTable {
     pub fn from_job<T: Job>() -> Self {
         Self {
             env_type: TypeId::of::<T::Environment>(),
             job_type: T::JOB_TYPE,
+            queue: T::QUEUE,
+            priority: T::PRIORITY,
+            statement_timeout: T::STATEMENT_TIMEOUT,
+            max_retries: T::MAX_RETRIES,
+            backoff: T::BACKOFF,
+            rate_limit: T::RATE_LIMIT,
+            max_concurrency: T::MAX_CONCURRENCY,
+            redacted_fields: T::REDACTED_FIELDS,
             perform: perform_job::<T>,
+            perform_binary: perform_binary_job::<T>,
         }
     }
 }
@@ -79,21 +222,88 @@ fn perform_job<T: Job>(
     env: &dyn Any,
     pool: &dyn DieselPoolObj,
 ) -> Result<(), PerformError> {
-    let environment = env.downcast_ref().ok_or_else::<PerformError, _>(|| {
+    let data = serde_json::from_value(data)?;
+    T::perform(data, environment::<T>(env)?, pool)
+}
+
+fn perform_binary_job<T: Job>(
+    bytes: &[u8],
+    env: &dyn Any,
+    pool: &dyn DieselPoolObj,
+) -> Result<(), PerformError> {
+    let data =
+        T::decode_binary(bytes).map_err(|e| PerformError::from(e as Box<dyn std::error::Error>))?;
+    T::perform(data, environment::<T>(env)?, pool)
+}
+
+fn environment<T: Job>(env: &dyn Any) -> Result<&T::Environment, PerformError> {
+    env.downcast_ref().ok_or_else::<PerformError, _>(|| {
         "Incorrect environment type. This should never happen. \
          Please open an issue at https://github.com/sgrif/swirl/issues/new"
             .into()
-    })?;
-    let data = serde_json::from_value(data)?;
-    T::perform(data, environment, pool)
+    })
 }
 
+/// The perform function of a job type, returned by [`Registry::get`]
+#[allow(missing_debug_implementations)]
 pub struct PerformJob<Env> {
     vtable: JobVTable,
     _marker: PhantomData<Env>,
 }
 
 impl<Env: 'static> PerformJob<Env> {
+    /// See [`Job::QUEUE`]
+    pub fn queue(&self) -> &'static str {
+        self.vtable.queue
+    }
+
+    /// See [`Job::PRIORITY`]
+    pub fn priority(&self) -> i16 {
+        self.vtable.priority
+    }
+
+    /// See [`Job::STATEMENT_TIMEOUT`]
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.vtable.statement_timeout
+    }
+
+    /// See [`Job::MAX_RETRIES`]
+    pub fn max_retries(&self) -> Option<u32> {
+        self.vtable.max_retries
+    }
+
+    /// See [`Job::BACKOFF`]
+    pub fn backoff(&self) -> Option<Backoff> {
+        self.vtable.backoff
+    }
+
+    /// See [`Job::RATE_LIMIT`]
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.vtable.rate_limit
+    }
+
+    /// See [`Job::MAX_CONCURRENCY`]
+    pub fn max_concurrency(&self) -> Option<usize> {
+        self.vtable.max_concurrency
+    }
+
+    /// See [`Job::REDACTED_FIELDS`]
+    pub fn redacted_fields(&self) -> &'static [&'static str] {
+        self.vtable.redacted_fields
+    }
+
+    /// Mask the redacted arguments in a job's data
+    pub fn redact_data(&self, data: &mut serde_json::Value) {
+        redact::data(data, self.vtable.redacted_fields)
+    }
+
+    /// Mask the values of the redacted arguments in `data` wherever they
+    /// appear in an error message
+    pub fn redact_error(&self, error: &str, data: &serde_json::Value) -> String {
+        redact::error(error, data, self.vtable.redacted_fields)
+    }
+
+    /// Deserialize a job's data and run it
     pub fn perform(
         &self,
         data: serde_json::Value,
@@ -103,4 +313,16 @@ impl<Env: 'static> PerformJob<Env> {
         let perform_fn = self.vtable.perform;
         perform_fn(data, env, pool)
     }
+
+    /// Decode the arguments of a job with a [`Codec`](crate::Codec), and run
+    /// it
+    pub fn perform_binary(
+        &self,
+        bytes: &[u8],
+        env: &Env,
+        pool: &dyn DieselPoolObj,
+    ) -> Result<(), PerformError> {
+        let perform_fn = self.vtable.perform_binary;
+        perform_fn(bytes, env, pool)
+    }
 }
@@ -1,42 +1,188 @@
+use chrono::Utc;
 use diesel::prelude::*;
 #[cfg(feature = "r2d2")]
 use diesel::r2d2;
-use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
-use std::panic::{catch_unwind, AssertUnwindSafe, PanicInfo, RefUnwindSafe, UnwindSafe};
-use std::sync::Arc;
-use std::time::Duration;
+use std::panic::{AssertUnwindSafe, PanicInfo};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 
+use crate::admin::AttemptOutcome;
+use crate::config::{ConfigError, RunnerConfig};
 use crate::db::*;
 use crate::errors::*;
-use crate::{storage, Registry};
+#[cfg(feature = "tracing")]
+use crate::lifecycle::TracingListener;
+use crate::lifecycle::{JobMetadata, LifecycleEvent, LifecycleListener, Listeners};
+#[cfg(feature = "metrics")]
+use crate::lifecycle::{MetricsConfig, MetricsListener};
+use crate::middleware::{Middleware, SessionSettings};
+use crate::notifier::{ErrorHandler, FailureNotifier, JobError, JobFailure};
+use crate::payload_store::{self, PayloadStore};
+use crate::query_hook::{QueryHook, StorageQuery};
+#[cfg(feature = "schedule")]
+use crate::schedule;
+use crate::{redact, storage, Backoff, JobContext, Registry};
+use control::Control;
+use counters::Counters;
+use drain::InFlight;
 use event::*;
+use maintenance::MaintenanceCallback;
+use panic_hook::{catch_unwind, CaughtPanic};
+use rate_limits::RateLimits;
+use slots::Slots;
+use slow_jobs::{SlowJobCallback, SlowJobThresholds};
+use storage_retry::retrying;
+use summary::Tally;
+use timings::Timings;
+use update_errors::UpdateErrors;
+
+pub use control::{Acknowledgement, Command, RunnerHandle};
+pub use counters::JobCounts;
+pub use drain::{DrainReport, InterruptedJob};
+pub use group::RunnerGroup;
+pub use maintenance::{MaintenancePolicy, MaintenanceReport};
+pub use stats::RunnerStats;
+pub use storage_retry::StorageRetryPolicy;
+pub use summary::RunSummary;
+pub use timings::{LoopTimings, Timing};
 
 mod channel;
+mod control;
+mod counters;
+mod drain;
 mod event;
+mod group;
+mod heartbeat;
+#[cfg(feature = "listen")]
+mod listener;
+mod maintenance;
+mod panic_hook;
+mod rate_limits;
+mod session;
+#[cfg(feature = "signals")]
+mod signals;
+mod slots;
+mod slow_jobs;
+mod stats;
+mod storage_retry;
+mod summary;
+mod timings;
+mod update_errors;
+
+/// The most rounds of jobs [`Runner::run_pending_jobs_until_empty`] runs
+const MAX_ROUNDS_UNTIL_EMPTY: usize = 100;
+
+/// How long job results are kept by default
+const DEFAULT_RESULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub struct NoConnectionPoolGiven;
 
+/// What the runner does with jobs which are claimed after their deadline.
+///
+/// See [`Job::enqueue_with_deadline`](crate::Job::enqueue_with_deadline).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpiredJobPolicy {
+    /// Remove the job from the queue without running it
+    #[default]
+    Drop,
+    /// Mark the job as dead without running it, so it can be inspected or
+    /// retried later
+    DeadLetter,
+    /// Run the job as if it had no deadline
+    Run,
+}
+
+/// What the runner does with jobs which panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Retry the job like any other failure
+    #[default]
+    Retry,
+    /// Mark the job as dead right away, as if it returned
+    /// [`PerformError::Fatal`]
+    DeadLetter,
+}
+
 #[allow(missing_debug_implementations)]
 pub struct Builder<Env, ConnectionPoolBuilder> {
     connection_pool_or_builder: ConnectionPoolBuilder,
     environment: Env,
+    queue_environments: HashMap<String, Env>,
     thread_count: Option<usize>,
+    worker_stack_size: Option<usize>,
     job_start_timeout: Option<Duration>,
+    poll_interval: Option<Duration>,
+    queues: Option<Vec<String>>,
+    queue_threads: HashMap<String, usize>,
+    #[cfg(feature = "listen")]
+    listen_url: Option<String>,
+    #[cfg(feature = "signals")]
+    signal_drain_timeout: Option<Duration>,
+    max_retries: Option<u32>,
+    backoff: Option<Backoff>,
+    failure_notifier: Option<Arc<dyn FailureNotifier>>,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
+    listeners: Listeners,
+    #[cfg(feature = "metrics")]
+    metrics: Option<MetricsConfig>,
+    middleware: Vec<Box<dyn Middleware>>,
+    connection_customizer: Option<Arc<dyn ConnectionCustomizer>>,
+    job_application_names: bool,
+    query_hook: Option<Arc<dyn QueryHook>>,
+    slow_job_thresholds: SlowJobThresholds,
+    on_slow_job: Option<SlowJobCallback>,
+    heartbeat_interval: Option<Duration>,
+    expired_job_policy: ExpiredJobPolicy,
+    panic_policy: PanicPolicy,
+    job_ttls: HashMap<String, Duration>,
+    result_ttl: Option<Duration>,
+    archive_retention: Option<Duration>,
+    maintenance: Option<MaintenancePolicy>,
+    on_maintenance: Option<MaintenanceCallback>,
+    retry_budgets: HashMap<String, u32>,
+    payload_store: Option<Arc<dyn PayloadStore>>,
+    storage_retry_policy: StorageRetryPolicy,
 }
 
 impl<Env, ConnectionPoolBuilder> Builder<Env, ConnectionPoolBuilder> {
+    /// Set the environment given to jobs on the given queue.
+    ///
+    /// Jobs on queues without an environment set here are given the
+    /// environment passed to [`Runner::builder`].
+    pub fn queue_environment<S: Into<String>>(mut self, queue: S, environment: Env) -> Self {
+        self.queue_environments.insert(queue.into(), environment);
+        self
+    }
+
     /// Set the number of threads to be used to run jobs concurrently.
     ///
-    /// Defaults to 5
+    /// Defaults to 5, or to the total of the threads given to each queue
+    /// with [`queue`](Self::queue).
     pub fn thread_count(mut self, thread_count: usize) -> Self {
         self.thread_count = Some(thread_count);
         self
     }
 
     fn get_thread_count(&self) -> usize {
-        self.thread_count.unwrap_or(5)
+        self.thread_count.unwrap_or_else(|| {
+            if self.queue_threads.is_empty() {
+                5
+            } else {
+                self.queue_threads.values().sum()
+            }
+        })
+    }
+
+    /// Set the stack size of the threads used to run jobs, in bytes.
+    ///
+    /// Jobs which use a lot of stack space, such as deeply recursive ones, may
+    /// need more than the platform's default.
+    pub fn worker_stack_size(mut self, bytes: usize) -> Self {
+        self.worker_stack_size = Some(bytes);
+        self
     }
 
     /// The amount of time to wait for a job to start before assuming an error
@@ -48,14 +194,443 @@ impl<Env, ConnectionPoolBuilder> Builder<Env, ConnectionPoolBuilder> {
         self
     }
 
+    /// How long to wait before looking for more jobs when the queue is empty.
+    ///
+    /// The runner does not sleep on its own. This is exposed through
+    /// [`Runner::poll_interval`] for the loop which calls
+    /// [`Runner::run_all_pending_jobs`]. Defaults to 1 second.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// Only run jobs on the given queues.
+    ///
+    /// By default, jobs on every queue are run.
+    pub fn queues<I, S>(mut self, queues: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.queues = Some(queues.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Run jobs on `queue`, using at most `threads` threads at once.
+    ///
+    /// Each call adds to the queues set with [`queues`](Self::queues), so
+    /// `.queue("mailers", 4).queue("default", 2)` runs jobs on those two
+    /// queues, and a backlog on either of them leaves the other's threads
+    /// free. Queues added without a limit can use any thread.
+    pub fn queue<S: Into<String>>(mut self, queue: S, threads: usize) -> Self {
+        let queue = queue.into();
+        let queues = self.queues.get_or_insert_with(Vec::new);
+        if !queues.contains(&queue) {
+            queues.push(queue.clone());
+        }
+        self.queue_threads.insert(queue, threads);
+        self
+    }
+
+    /// Start jobs as soon as they are enqueued, instead of on the next poll.
+    ///
+    /// The runner keeps a connection to `database_url` open on a thread of
+    /// its own, which waits for the notification sent on the `swirl_jobs`
+    /// channel whenever a job is enqueued. Jobs which become ready later,
+    /// such as retries, are still picked up by polling. While the connection
+    /// is down, the runner polls as usual and the listener reconnects.
+    #[cfg(feature = "listen")]
+    pub fn listen_for_jobs<S: Into<String>>(mut self, database_url: S) -> Self {
+        self.listen_url = Some(database_url.into());
+        self
+    }
+
+    /// Shut down gracefully when the process receives SIGTERM or SIGINT.
+    ///
+    /// When either signal is received, the runner stops looking for jobs, as
+    /// if [`RunnerHandle::shutdown`] had been called with `drain_timeout`, and
+    /// [`Runner::run`] returns once running jobs have finished or the timeout
+    /// has elapsed. Under Kubernetes, `drain_timeout` should be shorter than
+    /// the pod's `terminationGracePeriodSeconds`, so jobs aren't killed by the
+    /// SIGKILL which follows.
+    ///
+    /// The handlers replace any others for these signals, and are installed
+    /// for the whole process when the first runner which uses them is built.
+    /// Every such runner is shut down by a signal. A second signal stops the
+    /// process immediately.
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signals(mut self, drain_timeout: Duration) -> Self {
+        self.signal_drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    /// Apply the settings from a [`RunnerConfig`].
+    ///
+    /// Only settings which are present in the config are applied. Returns an
+    /// error if the config is invalid.
+    pub fn config(mut self, config: &RunnerConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        if let Some(thread_count) = config.thread_count {
+            self = self.thread_count(thread_count);
+        }
+        if let Some(ms) = config.poll_interval_ms {
+            self = self.poll_interval(Duration::from_millis(ms));
+        }
+        if let Some(queues) = &config.queues {
+            self = self.queues(queues.iter().cloned());
+        }
+        if let Some(max_retries) = config.max_retries {
+            self = self.max_retries(max_retries);
+        }
+        if let Some(ms) = config.job_start_timeout_ms {
+            self = self.job_start_timeout(Duration::from_millis(ms));
+        }
+        Ok(self)
+    }
+
+    /// The number of times a job will be retried before it fails permanently.
+    ///
+    /// Jobs which fail permanently are marked as dead, and will not be run
+    /// again unless they are requeued with
+    /// [`admin::requeue_dead_job`](crate::admin::requeue_dead_job). Jobs can
+    /// set their own limit with [`Job::MAX_RETRIES`]. By default, jobs are
+    /// retried indefinitely.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// How long to wait before retrying jobs which fail.
+    ///
+    /// Jobs can set their own backoff with [`Job::BACKOFF`]. By default, jobs
+    /// are retried after `2 ^ {retry_count}` minutes.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// Limit the number of times jobs of the given type can be retried per
+    /// minute, across every runner using the database.
+    ///
+    /// Once the budget for the current minute has been spent, jobs of this
+    /// type which fail are marked as dead instead of being retried. This
+    /// protects the database and downstream services when every job of a
+    /// type is failing, such as when a service it depends on is down. Dead
+    /// jobs can be retried once the problem has been fixed.
+    ///
+    /// By default, retries are not limited.
+    pub fn retry_budget<S: Into<String>>(mut self, job_type: S, retries_per_minute: u32) -> Self {
+        self.retry_budgets
+            .insert(job_type.into(), retries_per_minute);
+        self
+    }
+
+    /// Fetch the arguments of jobs which were enqueued with a
+    /// [`ClaimCheck`](crate::payload_store::ClaimCheck) from this store.
+    ///
+    /// Payloads are deleted once their job succeeds. Without a store, jobs
+    /// with offloaded arguments fail to deserialize.
+    pub fn payload_store(mut self, store: Arc<dyn PayloadStore>) -> Self {
+        self.payload_store = Some(store);
+        self
+    }
+
+    /// How to retry claiming, deleting, and updating jobs when Postgres
+    /// aborts the query with a serialization failure or a deadlock.
+    ///
+    /// By default, each query is tried up to 3 times. Retries are reported
+    /// to the [query hook](Self::query_hook), if there is one.
+    pub fn storage_retries(mut self, policy: StorageRetryPolicy) -> Self {
+        self.storage_retry_policy = policy;
+        self
+    }
+
+    /// Set a notifier which is called whenever a job fails permanently.
+    ///
+    /// See [`max_retries`](Self::max_retries) for when jobs fail permanently.
+    pub fn failure_notifier<N: FailureNotifier>(mut self, notifier: N) -> Self {
+        self.failure_notifier = Some(Arc::new(notifier));
+        self
+    }
+
+    /// Set a handler which is called whenever an attempt to run a job fails,
+    /// instead of printing the error to stderr.
+    pub fn error_handler<H: ErrorHandler>(mut self, handler: H) -> Self {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Add a listener which is notified as jobs are started, succeed, or fail.
+    ///
+    /// Any number of listeners can be added. They are called in the order
+    /// they were added.
+    pub fn lifecycle_listener<L: LifecycleListener>(mut self, listener: L) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Configure the metrics reported through the `metrics` crate.
+    ///
+    /// Metrics are reported with the default [`MetricsConfig`] unless this or
+    /// [`disable_metrics`](Self::disable_metrics) is called.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, config: MetricsConfig) -> Self {
+        self.metrics = Some(config);
+        self
+    }
+
+    /// Don't report any metrics about jobs or queues from this runner, such
+    /// as when it runs in the same process as another runner which reports
+    /// them already
+    #[cfg(feature = "metrics")]
+    pub fn disable_metrics(mut self) -> Self {
+        self.metrics = None;
+        self
+    }
+
+    /// Add middleware which is called around every job.
+    ///
+    /// See [`Middleware`] for the order multiple middleware are called in.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Set a hook which is run on every connection the runner checks out.
+    ///
+    /// See [`ConnectionCustomizer`] for details.
+    pub fn connection_customizer<C: ConnectionCustomizer>(mut self, customizer: C) -> Self {
+        self.connection_customizer = Some(Arc::new(customizer));
+        self
+    }
+
+    /// Set `application_name` to `swirl:<job_type>:<job_id>` on the
+    /// connections used by each job while it runs.
+    ///
+    /// This applies to the connection which holds the lock on the job, and to
+    /// every connection the job checks out of the pool it is given. The
+    /// previous application name is restored once the job is done with each
+    /// connection. This allows slow query logs and `pg_stat_activity` to
+    /// identify which job is responsible for a query, at the cost of a few
+    /// extra queries per job.
+    ///
+    /// Defaults to `false`
+    pub fn job_application_names(mut self, enabled: bool) -> Self {
+        self.job_application_names = enabled;
+        self
+    }
+
+    /// Set a hook which is told how long each query the runner makes to
+    /// claim and update jobs takes.
+    ///
+    /// See [`QueryHook`] for details.
+    pub fn query_hook<H: QueryHook>(mut self, hook: H) -> Self {
+        self.query_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Report jobs of the given type which have been running for longer than
+    /// `threshold`.
+    ///
+    /// Slow jobs are reported while they are still running, once per attempt,
+    /// by calling the callback given to [`on_slow_job`](Self::on_slow_job)
+    /// and emitting [`LifecycleEvent::Slow`] to every lifecycle listener. This
+    /// overrides [`default_slow_job_threshold`](Self::default_slow_job_threshold)
+    /// for this job type.
+    pub fn slow_job_threshold<S: Into<String>>(mut self, job_type: S, threshold: Duration) -> Self {
+        self.slow_job_thresholds
+            .by_job_type
+            .insert(job_type.into(), threshold);
+        self
+    }
+
+    /// Report jobs of any type which have been running for longer than
+    /// `threshold`.
+    ///
+    /// See [`slow_job_threshold`](Self::slow_job_threshold) for details. By
+    /// default, slow jobs are not reported.
+    pub fn default_slow_job_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_job_thresholds.default = Some(threshold);
+        self
+    }
+
+    /// Set a callback which is called with each job that exceeds its slow job
+    /// threshold, and how long it has been running for.
+    ///
+    /// The callback is called from a background thread while the job is still
+    /// running, so it should not block for long.
+    pub fn on_slow_job<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&JobMetadata, Duration) + Send + Sync + 'static,
+    {
+        self.on_slow_job = Some(Arc::new(callback));
+        self
+    }
+
+    /// Record which worker is running each job in the
+    /// `background_job_heartbeats` table, updating it every `interval` while
+    /// the job runs.
+    ///
+    /// A job whose process is killed while running it is run again once its
+    /// row is unlocked, but nothing is recorded about the attempt which was
+    /// cut short. With heartbeats, such attempts can be found with
+    /// [`admin::abandoned_jobs`](crate::admin::abandoned_jobs), and jobs
+    /// whose worker has stopped responding with
+    /// [`admin::stalled_jobs`](crate::admin::stalled_jobs). Heartbeats are
+    /// written from a thread of their own, using a connection from the pool.
+    /// By default, no heartbeats are recorded.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// What to do with jobs which are claimed after their deadline.
+    ///
+    /// Expired jobs emit [`LifecycleEvent::Expired`] unless they are run.
+    /// Jobs which are marked as dead also emit [`LifecycleEvent::Dead`], but
+    /// are not reported to the [`failure_notifier`](Self::failure_notifier),
+    /// since they never failed.
+    ///
+    /// Defaults to [`ExpiredJobPolicy::Drop`]
+    pub fn on_expired(mut self, policy: ExpiredJobPolicy) -> Self {
+        self.expired_job_policy = policy;
+        self
+    }
+
+    /// What to do with jobs which panic.
+    ///
+    /// The stored error includes where the job panicked, and a backtrace if
+    /// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set.
+    ///
+    /// Defaults to [`PanicPolicy::Retry`]
+    pub fn on_panic(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Mark jobs of the given type as dead once they have been in the queue
+    /// for longer than `ttl`.
+    ///
+    /// This keeps obsolete work from piling up while jobs cannot be run, such
+    /// as during a long outage. Unlike a deadline, which is only checked when
+    /// a job is claimed, stale jobs are swept by
+    /// [`Runner::sweep_stale_jobs`], which should be called periodically.
+    pub fn job_ttl<S: Into<String>>(mut self, job_type: S, ttl: Duration) -> Self {
+        self.job_ttls.insert(job_type.into(), ttl);
+        self
+    }
+
+    /// How long the results jobs set with
+    /// [`JobContext::set_result`](crate::JobContext::set_result) are kept
+    /// after the job succeeds, for [`job_result`](crate::job_result) to
+    /// find.
+    ///
+    /// Expired results are deleted as other jobs complete. Defaults to one
+    /// day.
+    pub fn result_ttl(mut self, ttl: Duration) -> Self {
+        self.result_ttl = Some(ttl);
+        self
+    }
+
+    /// Keep jobs which succeed in `background_jobs_archive` instead of
+    /// deleting them, so there is a record of what ran.
+    ///
+    /// Archived jobs are kept for `retention` after they finish, and are
+    /// deleted by [`Runner::prune_archive`], which should be called
+    /// periodically. By default, jobs which succeed are deleted.
+    pub fn archive_completed_jobs(mut self, retention: Duration) -> Self {
+        self.archive_retention = Some(retention);
+        self
+    }
+
+    /// Prune old jobs from [`Runner::run`], by calling
+    /// [`Runner::run_maintenance`] once every `policy.interval`.
+    ///
+    /// By default, [`Runner::run`] doesn't prune anything.
+    pub fn maintenance(mut self, policy: MaintenancePolicy) -> Self {
+        self.maintenance = Some(policy);
+        self
+    }
+
+    /// Called with the report of each run of maintenance started by
+    /// [`Runner::run`], such as to export how bloated the tables are.
+    pub fn on_maintenance<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&MaintenanceReport) + Send + Sync + 'static,
+    {
+        self.on_maintenance = Some(Arc::new(callback));
+        self
+    }
+
     /// Provide a connection pool to be used by the runner
     pub fn connection_pool<NewPool>(self, pool: NewPool) -> Builder<Env, NewPool> {
-        Builder {
+        self.replace_connection_pool(pool).1
+    }
+
+    fn replace_connection_pool<NewPool>(
+        self,
+        pool: NewPool,
+    ) -> (ConnectionPoolBuilder, Builder<Env, NewPool>) {
+        let builder = Builder {
             connection_pool_or_builder: pool,
             environment: self.environment,
+            queue_environments: self.queue_environments,
             thread_count: self.thread_count,
+            worker_stack_size: self.worker_stack_size,
             job_start_timeout: self.job_start_timeout,
-        }
+            poll_interval: self.poll_interval,
+            queues: self.queues,
+            queue_threads: self.queue_threads,
+            #[cfg(feature = "listen")]
+            listen_url: self.listen_url,
+            #[cfg(feature = "signals")]
+            signal_drain_timeout: self.signal_drain_timeout,
+            max_retries: self.max_retries,
+            backoff: self.backoff,
+            failure_notifier: self.failure_notifier,
+            error_handler: self.error_handler,
+            listeners: self.listeners,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            middleware: self.middleware,
+            connection_customizer: self.connection_customizer,
+            job_application_names: self.job_application_names,
+            query_hook: self.query_hook,
+            slow_job_thresholds: self.slow_job_thresholds,
+            on_slow_job: self.on_slow_job,
+            heartbeat_interval: self.heartbeat_interval,
+            expired_job_policy: self.expired_job_policy,
+            panic_policy: self.panic_policy,
+            job_ttls: self.job_ttls,
+            result_ttl: self.result_ttl,
+            archive_retention: self.archive_retention,
+            maintenance: self.maintenance,
+            on_maintenance: self.on_maintenance,
+            retry_budgets: self.retry_budgets,
+            payload_store: self.payload_store,
+            storage_retry_policy: self.storage_retry_policy,
+        };
+        (self.connection_pool_or_builder, builder)
+    }
+}
+
+impl<Env: 'static> Builder<Env, NoConnectionPoolGiven> {
+    /// Create a builder for a job runner, configured from a [`RunnerConfig`].
+    ///
+    /// This is equivalent to calling [`Builder::config`] on the result of
+    /// [`Runner::builder`].
+    pub fn from_config(environment: Env, config: &RunnerConfig) -> Result<Self, ConfigError> {
+        Runner::builder(environment).config(config)
+    }
+
+    /// Create a builder for a job runner, configured from environment
+    /// variables.
+    ///
+    /// See [`RunnerConfig::with_env_overrides`] for the variables which are
+    /// read. To combine a config file with environment variables, use
+    /// `RunnerConfig::with_env_overrides` and [`Builder::from_config`] instead.
+    pub fn from_env(environment: Env) -> Result<Self, ConfigError> {
+        Self::from_config(environment, &RunnerConfig::from_env()?)
     }
 }
 
@@ -96,17 +671,11 @@ impl<Env> Builder<Env, R2d2Builder> {
 
     /// Build the runner with an r2d2 connection pool.
     pub fn build(self) -> Runner<Env, r2d2::Pool<r2d2::ConnectionManager<PgConnection>>> {
-        let thread_count = self.get_thread_count();
-        let connection_pool_size = thread_count as u32 * 2;
-        let connection_pool = self.connection_pool_or_builder.build(connection_pool_size);
-
-        Runner {
-            connection_pool,
-            thread_pool: ThreadPool::new(thread_count),
-            environment: Arc::new(self.environment),
-            registry: Arc::new(Registry::load()),
-            job_start_timeout: self.job_start_timeout.unwrap_or(Duration::from_secs(10)),
-        }
+        let connection_pool_size = self.get_thread_count() as u32 * 2;
+        let (pool_builder, builder) = self.replace_connection_pool(NoConnectionPoolGiven);
+        builder
+            .connection_pool(pool_builder.build(connection_pool_size))
+            .build()
     }
 }
 
@@ -116,12 +685,87 @@ where
 {
     /// Build the runner
     pub fn build(self) -> Runner<Env, ConnectionPool> {
+        let mut thread_pool = threadpool::Builder::new().num_threads(self.get_thread_count());
+        if let Some(bytes) = self.worker_stack_size {
+            thread_pool = thread_pool.thread_stack_size(bytes);
+        }
+        let thread_pool = thread_pool.build();
+        let counters = Arc::new(Counters::default());
+        let mut listeners = self.listeners;
+        let listener = Arc::clone(&counters);
+        listeners.push(move |event: &LifecycleEvent| listener.on_event(event));
+        #[cfg(feature = "metrics")]
+        let metrics = self
+            .metrics
+            .map(|config| Arc::new(MetricsListener::new(config)));
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &metrics {
+            let listener = Arc::clone(metrics);
+            listeners.push(move |event: &LifecycleEvent| listener.on_event(event));
+        }
+        #[cfg(feature = "tracing")]
+        listeners.push(TracingListener);
+        let listeners = Arc::new(listeners);
+        let in_flight = Arc::default();
+        let control = Control::new();
+        #[cfg(feature = "listen")]
+        let listener = self
+            .listen_url
+            .map(|database_url| listener::spawn(database_url, control.handle()));
+        #[cfg(feature = "signals")]
+        let signal_watcher = self
+            .signal_drain_timeout
+            .map(|timeout| signals::watch(control.handle(), timeout));
+        slow_jobs::spawn_watchdog(
+            &in_flight,
+            self.slow_job_thresholds,
+            Arc::clone(&listeners),
+            self.on_slow_job,
+        );
+
         Runner {
-            thread_pool: ThreadPool::new(self.get_thread_count()),
+            thread_pool,
             connection_pool: self.connection_pool_or_builder,
-            environment: Arc::new(self.environment),
+            environments: Arc::new(Environments::new(self.environment, self.queue_environments)),
             registry: Arc::new(Registry::load()),
             job_start_timeout: self.job_start_timeout.unwrap_or(Duration::from_secs(10)),
+            poll_interval: self.poll_interval.unwrap_or(Duration::from_secs(1)),
+            queues: self.queues.map(Arc::from),
+            slots: Arc::new(Slots::new(self.queue_threads)),
+            rate_limits: Arc::default(),
+            max_retries: self.max_retries,
+            backoff: self.backoff,
+            failure_notifier: self.failure_notifier,
+            error_handler: self.error_handler,
+            listeners,
+            #[cfg(feature = "metrics")]
+            metrics,
+            middleware: Arc::new(self.middleware),
+            counters,
+            timings: Arc::default(),
+            in_flight,
+            update_errors: Arc::default(),
+            control,
+            #[cfg(feature = "listen")]
+            _listener: listener,
+            #[cfg(feature = "signals")]
+            _signal_watcher: signal_watcher,
+            connection_customizer: self.connection_customizer,
+            job_application_names: self.job_application_names,
+            query_hook: self.query_hook,
+            heartbeat_interval: self.heartbeat_interval,
+            heartbeat: Once::new(),
+            expired_job_policy: self.expired_job_policy,
+            panic_policy: self.panic_policy,
+            job_ttls: self.job_ttls,
+            result_ttl: self.result_ttl.unwrap_or(DEFAULT_RESULT_TTL),
+            archive_retention: self.archive_retention,
+            maintenance: self.maintenance,
+            on_maintenance: self.on_maintenance,
+            last_maintenance: Mutex::new(None),
+            retry_budgets: Arc::new(self.retry_budgets),
+            payload_store: self.payload_store,
+            storage_retry_policy: self.storage_retry_policy,
         }
     }
 }
@@ -131,9 +775,51 @@ where
 pub struct Runner<Env: 'static, ConnectionPool> {
     connection_pool: ConnectionPool,
     thread_pool: ThreadPool,
-    environment: Arc<Env>,
+    environments: Arc<Environments<Env>>,
     registry: Arc<Registry<Env>>,
     job_start_timeout: Duration,
+    poll_interval: Duration,
+    queues: Option<Arc<[String]>>,
+    slots: Arc<Slots>,
+    rate_limits: Arc<RateLimits>,
+    max_retries: Option<u32>,
+    backoff: Option<Backoff>,
+    failure_notifier: Option<Arc<dyn FailureNotifier>>,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
+    listeners: Arc<Listeners>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<MetricsListener>>,
+    middleware: Arc<Vec<Box<dyn Middleware>>>,
+    counters: Arc<Counters>,
+    timings: Arc<Timings>,
+    in_flight: Arc<InFlight>,
+    /// Errors updating jobs which have run, which haven't been reported yet
+    update_errors: Arc<UpdateErrors>,
+    control: Control,
+    /// Stops listening for jobs when the runner is dropped
+    #[cfg(feature = "listen")]
+    _listener: Option<listener::Listener>,
+    /// Stops watching for signals when the runner is dropped
+    #[cfg(feature = "signals")]
+    _signal_watcher: Option<signals::SignalWatcher>,
+    connection_customizer: Option<Arc<dyn ConnectionCustomizer>>,
+    job_application_names: bool,
+    query_hook: Option<Arc<dyn QueryHook>>,
+    heartbeat_interval: Option<Duration>,
+    /// Starts recording heartbeats the first time the runner looks for jobs
+    heartbeat: Once,
+    expired_job_policy: ExpiredJobPolicy,
+    panic_policy: PanicPolicy,
+    job_ttls: HashMap<String, Duration>,
+    result_ttl: Duration,
+    archive_retention: Option<Duration>,
+    maintenance: Option<MaintenancePolicy>,
+    on_maintenance: Option<MaintenanceCallback>,
+    /// When [`Runner::run`] last ran maintenance
+    last_maintenance: Mutex<Option<Instant>>,
+    retry_budgets: Arc<HashMap<String, u32>>,
+    payload_store: Option<Arc<dyn PayloadStore>>,
+    storage_retry_policy: StorageRetryPolicy,
 }
 
 impl<Env> Runner<Env, NoConnectionPoolGiven> {
@@ -147,8 +833,41 @@ impl<Env> Runner<Env, NoConnectionPoolGiven> {
         Builder {
             connection_pool_or_builder: NoConnectionPoolGiven,
             environment,
+            queue_environments: HashMap::new(),
             thread_count: None,
+            worker_stack_size: None,
             job_start_timeout: None,
+            poll_interval: None,
+            queues: None,
+            queue_threads: HashMap::new(),
+            #[cfg(feature = "listen")]
+            listen_url: None,
+            #[cfg(feature = "signals")]
+            signal_drain_timeout: None,
+            max_retries: None,
+            backoff: None,
+            failure_notifier: None,
+            error_handler: None,
+            listeners: Listeners::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Some(MetricsConfig::default()),
+            middleware: Vec::new(),
+            connection_customizer: None,
+            job_application_names: false,
+            query_hook: None,
+            slow_job_thresholds: SlowJobThresholds::default(),
+            on_slow_job: None,
+            heartbeat_interval: None,
+            expired_job_policy: ExpiredJobPolicy::default(),
+            panic_policy: PanicPolicy::default(),
+            job_ttls: HashMap::new(),
+            result_ttl: None,
+            archive_retention: None,
+            maintenance: None,
+            on_maintenance: None,
+            retry_budgets: HashMap::new(),
+            payload_store: None,
+            storage_retry_policy: StorageRetryPolicy::default(),
         }
     }
 }
@@ -159,11 +878,54 @@ impl<Env, ConnectionPool> Runner<Env, ConnectionPool> {
     pub fn connection_pool(&self) -> &ConnectionPool {
         &self.connection_pool
     }
+
+    /// How long to wait before calling
+    /// [`run_all_pending_jobs`](Self::run_all_pending_jobs) again once the
+    /// queue is empty.
+    ///
+    /// See [`Builder::poll_interval`].
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Create a handle which can be used to control this runner from other
+    /// threads.
+    pub fn handle(&self) -> RunnerHandle {
+        self.control.handle()
+    }
+
+    /// How many jobs of each type this runner has started, and what happened
+    /// to them, keyed by job type.
+    ///
+    /// These are counted in memory since the runner was built, so they are
+    /// reset when the process restarts.
+    pub fn counters(&self) -> HashMap<String, JobCounts> {
+        self.counters.snapshot()
+    }
+
+    /// How long this runner has spent polling for jobs, waiting for
+    /// connections, and claiming jobs since it was built.
+    ///
+    /// Unlike the durations of jobs, these show whether the runner itself is
+    /// being held up, such as by a connection pool which is too small. With
+    /// the `metrics` feature, they are also recorded in the
+    /// `swirl_poll_duration_seconds`, `swirl_connection_wait_seconds`, and
+    /// `swirl_claim_duration_seconds` histograms.
+    pub fn loop_timings(&self) -> LoopTimings {
+        self.timings.snapshot()
+    }
+
+    /// The registry used to look up jobs run by this runner.
+    ///
+    /// Additional jobs can be registered here while the runner is in use.
+    pub fn registry(&self) -> &Registry<Env> {
+        &self.registry
+    }
 }
 
 impl<Env, ConnectionPool> Runner<Env, ConnectionPool>
 where
-    Env: RefUnwindSafe + Send + Sync + 'static,
+    Env: Send + Sync + 'static,
     ConnectionPool: DieselPool + 'static,
 {
     /// Runs all pending jobs in the queue
@@ -171,8 +933,83 @@ where
     /// This function will return once all jobs in the queue have begun running,
     /// but does not wait for them to complete. When this function returns, at
     /// least one thread will have tried to acquire a new job, and found there
-    /// were none in the queue.
-    pub fn run_all_pending_jobs(&self) -> Result<(), FetchError<ConnectionPool>> {
+    /// were none in the queue. Returns how many jobs were claimed, and how
+    /// many of those had finished by then. Jobs which are still running are
+    /// only counted as claimed, and a worker which was waiting for a thread
+    /// when the queue ran out is only counted if it claims a job before this
+    /// returns.
+    ///
+    /// With the `schedule` feature, jobs whose
+    /// [schedule](crate::schedule) has come due are enqueued first.
+    pub fn run_all_pending_jobs(&self) -> Result<RunSummary, FetchError<ConnectionPool>> {
+        let tally = Arc::new(Tally::default());
+        self.run_pending_jobs(&tally)?;
+        Ok(tally.summary())
+    }
+
+    /// Runs pending jobs until the queue is empty, and waits for them to
+    /// finish.
+    ///
+    /// [`run_all_pending_jobs`](Self::run_all_pending_jobs) returns as soon
+    /// as it finds the queue empty once, so jobs enqueued by the jobs it ran
+    /// can be left behind. This runs jobs in rounds instead, waiting for each
+    /// round to finish before starting the next, until a round finds no jobs
+    /// to run. This is usually what tests want. Jobs which fail are only run
+    /// again once their backoff has passed, like any other retry.
+    ///
+    /// Jobs which keep enqueueing more jobs, or which fail without a backoff,
+    /// would keep the queue from ever being empty, so this gives up with
+    /// [`FetchError::QueueNotEmptied`] after 100 rounds. The returned summary
+    /// counts the jobs of every round.
+    pub fn run_pending_jobs_until_empty(&self) -> Result<RunSummary, FetchError<ConnectionPool>> {
+        let tally = Arc::new(Tally::default());
+        for _ in 0..MAX_ROUNDS_UNTIL_EMPTY {
+            let claimed = tally.summary().claimed;
+            self.run_pending_jobs(&tally)?;
+            self.thread_pool.join();
+            self.report_update_errors()?;
+            if tally.summary().claimed == claimed {
+                return Ok(tally.summary());
+            }
+        }
+        Err(FetchError::QueueNotEmptied {
+            rounds: MAX_ROUNDS_UNTIL_EMPTY,
+        })
+    }
+
+    fn run_pending_jobs(&self, tally: &Arc<Tally>) -> Result<(), FetchError<ConnectionPool>> {
+        if self.apply_commands(None).is_some() {
+            return Ok(());
+        }
+        if let Some(interval) = self.heartbeat_interval {
+            self.heartbeat.call_once(|| {
+                heartbeat::spawn(&self.in_flight, self.connection_pool.clone(), interval)
+            });
+        }
+
+        #[cfg(feature = "metrics")]
+        self.report_queue_depths();
+        #[cfg(feature = "schedule")]
+        self.enqueue_scheduled_jobs()?;
+
+        let started = Instant::now();
+        let result = self.poll_once(tally);
+        self.timings.poll(started.elapsed());
+        result?;
+        self.report_update_errors()
+    }
+
+    /// Returns the oldest error updating a job which ran, if there was one
+    /// which hasn't been returned yet
+    fn report_update_errors(&self) -> Result<(), FetchError<ConnectionPool>> {
+        match self.update_errors.take() {
+            Some(e) => Err(FetchError::FailedUpdatingJob(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Claims jobs until the queue is empty
+    fn poll_once(&self, tally: &Arc<Tally>) -> Result<(), FetchError<ConnectionPool>> {
         use std::cmp::max;
 
         let max_threads = self.thread_pool.max_count();
@@ -191,13 +1028,30 @@ where
             };
 
             for _ in 0..jobs_to_queue {
-                self.run_single_job(sender.clone());
+                self.run_single_job(sender.clone(), Arc::clone(tally));
             }
 
             pending_messages += jobs_to_queue;
             match receiver.recv_timeout(self.job_start_timeout) {
                 Ok(Event::Working) => pending_messages -= 1,
-                Ok(Event::NoJobAvailable) => return Ok(()),
+                Ok(Event::NoJobAvailable) => {
+                    // Other workers may have claimed the last jobs at the
+                    // same time, so the ones which have started are given
+                    // until the timeout to report it before the summary is
+                    // taken. Workers still queued behind running jobs won't
+                    // report until those jobs finish, so they aren't waited
+                    // for, and only count once they claim a job.
+                    let deadline = Instant::now() + self.job_start_timeout;
+                    let mut outstanding = pending_messages - 1;
+                    while outstanding > self.thread_pool.queued_count() {
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        if receiver.recv_timeout(timeout).is_err() {
+                            break;
+                        }
+                        outstanding -= 1;
+                    }
+                    return Ok(());
+                }
                 Ok(Event::ErrorLoadingJob(e)) => return Err(FetchError::FailedLoadingJob(e)),
                 Ok(Event::FailedToAcquireConnection(e)) => {
                     return Err(FetchError::NoDatabaseConnection(e));
@@ -207,89 +1061,721 @@ where
         }
     }
 
-    fn run_single_job(&self, sender: EventSender<ConnectionPool>) {
-        let environment = Arc::clone(&self.environment);
+    /// Runs jobs until [`RunnerHandle::shutdown`] is called.
+    ///
+    /// Once the queue is empty, the runner waits for the
+    /// [poll interval](Builder::poll_interval) before looking for more jobs,
+    /// or until a command is sent through a [`RunnerHandle`]. When shut down,
+    /// this waits for running jobs to finish before returning. Errors looking
+    /// for jobs are returned immediately, and `run` can be called again to
+    /// continue.
+    pub fn run(&self) -> Result<DrainReport, FetchError<ConnectionPool>> {
+        loop {
+            self.run_all_pending_jobs()?;
+            self.run_maintenance_if_due()?;
+            if let Some(deadline) = self.apply_commands(Some(self.poll_interval)) {
+                return Ok(self.drain(deadline.saturating_duration_since(Instant::now())));
+            }
+        }
+    }
+
+    /// Applies commands sent through a [`RunnerHandle`]. Returns the
+    /// shutdown deadline if the runner has been shut down.
+    fn apply_commands(&self, wait: Option<Duration>) -> Option<Instant> {
+        let mut applied = self.control.apply(wait);
+        if let Some(thread_count) = applied.thread_count {
+            // The pool's state is shared between clones
+            self.thread_pool.clone().set_num_threads(thread_count);
+        }
+        applied.acknowledge();
+        applied.shutdown
+    }
+
+    fn run_single_job(&self, sender: EventSender<ConnectionPool>, tally: Arc<Tally>) {
+        let environments = Arc::clone(&self.environments);
         let registry = Arc::clone(&self.registry);
-        // FIXME: https://github.com/sfackler/r2d2/pull/70
-        let connection_pool = AssertUnwindSafe(self.connection_pool().clone());
-        self.get_single_job(sender, move |job| {
+        let connection_pool = self.connection_pool().clone();
+        let job_application_names = self.job_application_names;
+        let shutting_down = self.control.shutting_down();
+        let max_retries = self.max_retries;
+        self.get_single_job(sender, tally, move |job, session| {
             let perform_job = registry
                 .get(&job.job_type)
                 .ok_or_else(|| PerformError::from(format!("Unknown job type {}", job.job_type)))?;
-            perform_job.perform(job.data, &environment, &connection_pool.0)
+            let environment = environments.for_queue(&job.queue);
+            let context = JobContext::for_job(&job)
+                .max_retries(perform_job.max_retries().or(max_retries))
+                .shutdown_flag(shutting_down);
+
+            let mut settings = Vec::new();
+            if job_application_names {
+                let name = session::application_name(&job.job_type, job.id);
+                settings.push(("application_name".into(), name));
+            }
+            if let Some(timeout) = perform_job.statement_timeout() {
+                let timeout = session::statement_timeout(timeout);
+                settings.push(("statement_timeout".into(), timeout));
+            }
+            settings.extend(session.into_vec());
+
+            let perform = |pool: &dyn DieselPoolObj| match job.binary_data {
+                Some(bytes) => perform_job.perform_binary(&bytes, environment, pool),
+                None => perform_job.perform(job.data, environment, pool),
+            };
+            context.enter(|| {
+                if settings.is_empty() {
+                    perform(&connection_pool)
+                } else {
+                    let pool = session::ConfiguredPool {
+                        inner: &connection_pool,
+                        settings,
+                    };
+                    perform(&pool)
+                }
+            })?;
+            Ok(context.take_result())
         })
     }
 
-    fn get_single_job<F>(&self, sender: EventSender<ConnectionPool>, f: F)
+    fn get_single_job<F>(&self, sender: EventSender<ConnectionPool>, tally: Arc<Tally>, f: F)
     where
-        F: FnOnce(storage::BackgroundJob) -> Result<(), PerformError> + Send + UnwindSafe + 'static,
+        F: FnOnce(
+                storage::BackgroundJob,
+                SessionSettings,
+            ) -> Result<Option<serde_json::Value>, PerformError>
+            + Send
+            + 'static,
     {
         use diesel::result::Error::RollbackTransaction;
 
         // The connection may not be `Send` so we need to clone the pool instead
         let pool = self.connection_pool.clone();
+        let max_retries = self.max_retries;
+        let backoff = self.backoff;
+        let failure_notifier = self.failure_notifier.clone();
+        let error_handler = self.error_handler.clone();
+        let listeners = Arc::clone(&self.listeners);
+        #[cfg(feature = "metrics")]
+        let metrics = self.metrics.clone();
+        let middleware = Arc::clone(&self.middleware);
+        // Held until the transaction has been committed, so the job's row is
+        // no longer locked once it stops being reported as in flight
+        let mut worker = self.in_flight.worker();
+        let queues = self.queues.clone();
+        let slots = Arc::clone(&self.slots);
+        let rate_limits = Arc::clone(&self.rate_limits);
+        let paused_queues = self.control.paused_queues();
+        let connection_customizer = self.connection_customizer.clone();
+        let job_application_names = self.job_application_names;
+        let query_hook = self.query_hook.clone();
+        let expired_job_policy = self.expired_job_policy;
+        let panic_policy = self.panic_policy;
+        let registry = Arc::clone(&self.registry);
+        let retry_budgets = Arc::clone(&self.retry_budgets);
+        let payload_store = self.payload_store.clone();
+        let storage_retry_policy = self.storage_retry_policy;
+        let timings = Arc::clone(&self.timings);
+        let update_errors = Arc::clone(&self.update_errors);
+        let result_ttl = self.result_ttl;
+        let archive = self.archive_retention.is_some();
+        let heartbeats = self.heartbeat_interval.is_some();
         self.thread_pool.execute(move || {
-            let conn = match pool.get() {
+            let query_hook = query_hook.as_deref();
+            let started = Instant::now();
+            let conn = pool.get();
+            timings.connection_wait(started.elapsed());
+            let conn = match conn {
                 Ok(conn) => conn,
                 Err(e) => {
                     sender.send(Event::FailedToAcquireConnection(e));
                     return;
                 }
             };
+            if let Some(customizer) = &connection_customizer {
+                if let Err(e) = customizer.on_checkout(&conn) {
+                    sender.send(Event::ErrorLoadingJob(e));
+                    return;
+                }
+            }
 
+            // Set once a job is claimed, so its payload can be deleted once
+            // the job has been removed from the queue
+            let mut payload_key = None;
+            // Holds one of the threads of a limited queue or job type until
+            // the job's transaction ends
+            let mut slot = None;
             let job_run_result = conn.transaction::<_, diesel::result::Error, _>(|| {
-                let job = match storage::find_next_unlocked_job(&conn).optional() {
-                    Ok(Some(j)) => {
+                let started = Instant::now();
+                let concurrency_limits = registry.concurrency_limits();
+                let next_job = slots.claim(&concurrency_limits, |full_queues, full_types| {
+                    let excluded = [&paused_queues[..], full_queues].concat();
+                    rate_limits.claim(&registry.rate_limits(), |limited_types| {
+                        let excluded_types = [full_types, limited_types].concat();
+                        retrying(
+                            &conn,
+                            storage_retry_policy,
+                            query_hook,
+                            StorageQuery::Claim,
+                            || {
+                                storage::claim_next_within_limits(
+                                    &conn,
+                                    queues.as_deref(),
+                                    &excluded,
+                                    &excluded_types,
+                                    &concurrency_limits,
+                                )
+                            },
+                        )
+                    })
+                });
+                timings.claim(started.elapsed());
+                let mut job = match next_job {
+                    Ok(Some((j, job_slot))) => {
+                        slot = job_slot;
+                        worker.started(JobMetadata::from(&j));
+                        tally.claimed();
                         sender.send(Event::Working);
                         j
                     }
                     Ok(None) => {
                         sender.send(Event::NoJobAvailable);
-                        return Ok(());
+                        return Ok(None);
                     }
                     Err(e) => {
                         sender.send(Event::ErrorLoadingJob(e));
                         return Err(RollbackTransaction);
                     }
                 };
-                let job_id = job.id;
+                let metadata = JobMetadata::from(&job);
+                // Entered until the job's transaction ends, so anything the
+                // job emits is recorded inside it
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!(
+                    "swirl.job",
+                    job.id = metadata.id,
+                    job.type = &*metadata.job_type,
+                    queue = &*metadata.queue,
+                    retries = metadata.retries,
+                )
+                .entered();
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = &metrics {
+                    metrics.fetched(&metadata);
+                }
+                if payload_store.is_some() {
+                    payload_key = payload_store::reference(&job.data).map(String::from);
+                }
+                if expired_job_policy != ExpiredJobPolicy::Run && job.is_expired() {
+                    let dead = expired_job_policy == ExpiredJobPolicy::DeadLetter;
+                    if dead {
+                        storage::mark_expired_job_dead(&conn, job.id, &job.job_type)?;
+                    } else {
+                        storage::discard(&conn, job.id)?;
+                    }
+                    return Ok(Some(RunReport {
+                        job: metadata,
+                        duration: Duration::default(),
+                        outcome: Outcome::Expired { dead },
+                    }));
+                }
+                if job_application_names {
+                    // Reset automatically when the transaction ends
+                    let name = session::application_name(&job.job_type, job.id);
+                    session::set_local(&conn, "application_name", &name)?;
+                }
+                let fetched = match (&payload_store, &payload_key) {
+                    (Some(store), Some(key)) => {
+                        payload_store::fetch(&**store, key).map(|data| job.data = data)
+                    }
+                    _ => Ok(()),
+                };
+                let fetched = fetched.and_then(|()| job.decompress());
+                let notification = failure_notifier.as_ref().map(|_| job.clone());
+                let handler_data = error_handler.as_ref().map(|_| job.data.clone());
+                let perform_job = registry.get(&job.job_type);
+                let redacted = perform_job
+                    .as_ref()
+                    .map_or(&[][..], |perform_job| perform_job.redacted_fields());
+                let max_retries = perform_job
+                    .as_ref()
+                    .and_then(|perform_job| perform_job.max_retries())
+                    .or(max_retries);
+                let backoff = perform_job
+                    .as_ref()
+                    .and_then(|perform_job| perform_job.backoff())
+                    .or(backoff);
+                // Kept to find the redacted values in any error
+                let data = if redacted.is_empty() {
+                    None
+                } else {
+                    Some(job.data.clone())
+                };
+                listeners.emit(|| LifecycleEvent::Started {
+                    job: metadata.clone(),
+                });
 
-                let result = catch_unwind(|| f(job))
-                    .map_err(|e| try_to_extract_panic_info(&e))
-                    .and_then(|r| r);
+                let started_at = Utc::now();
+                let started = Instant::now();
+                let mut panicked = false;
+                let mut session = SessionSettings::default();
+                let outcome = fetched
+                    .map_err(|e| PerformError::from(e as Box<dyn Error>))
+                    .and_then(|()| {
+                        middleware
+                            .iter()
+                            .try_for_each(|m| m.before_perform(&metadata, &mut session))
+                    });
+                let outcome = outcome.and_then(|()| {
+                    // The job and the closure running it are owned by this
+                    // call, and are dropped if it panics, so nothing left
+                    // half updated by the panic is used again. State shared
+                    // with other jobs, such as the environment, is treated
+                    // like state shared between threads, which doesn't need
+                    // to be `UnwindSafe` either.
+                    catch_unwind(AssertUnwindSafe(move || f(job, session)))
+                        .map_err(|e| {
+                            panicked = true;
+                            try_to_extract_panic_info(&e)
+                        })
+                        .and_then(|r| r)
+                });
+                let duration = started.elapsed();
+                let worker = worker_name();
+                let attempt = |outcome: AttemptOutcome, error| storage::NewAttempt {
+                    job_id: metadata.id,
+                    job_type: &metadata.job_type,
+                    attempt: metadata.retries + 1,
+                    worker: &worker,
+                    outcome: outcome.as_str(),
+                    error,
+                    started_at,
+                    duration_ms: duration.as_millis() as i64,
+                };
 
-                match result {
-                    Ok(_) => storage::delete_successful_job(&conn, job_id)?,
-                    Err(e) => {
-                        eprintln!("Job {} failed to run: {}", job_id, e);
-                        storage::update_failed_job(&conn, job_id);
+                if let Err(PerformError::Snooze(delay)) = outcome {
+                    for m in middleware.iter().rev() {
+                        m.after_perform(&metadata, None);
                     }
+                    storage::snooze_job(&conn, metadata.id, delay)?;
+                    storage::record_attempt(&conn, &attempt(AttemptOutcome::Snoozed, None))?;
+                    return Ok(Some(RunReport {
+                        job: metadata,
+                        duration,
+                        outcome: Outcome::Snoozed { delay },
+                    }));
                 }
-                Ok(())
+                let (result, failure) = match outcome {
+                    Ok(result) => (result, None),
+                    Err(e) => (None, Some(e)),
+                };
+                let failure = failure.map(|e| {
+                    let error = match &data {
+                        Some(data) => redact::error(&e.to_string(), data, redacted),
+                        None => e.to_string(),
+                    };
+                    (e, error)
+                });
+                for m in middleware.iter().rev() {
+                    m.after_perform(&metadata, failure.as_ref().map(|(_, e)| e.as_str()));
+                }
+
+                let outcome = match failure {
+                    None => {
+                        retrying(
+                            &conn,
+                            storage_retry_policy,
+                            query_hook,
+                            StorageQuery::Delete,
+                            || {
+                                if archive {
+                                    storage::archive(&conn, metadata.id)
+                                } else {
+                                    storage::complete(&conn, metadata.id)
+                                }
+                            },
+                        )?;
+                        if let Some(result) = &result {
+                            storage::save_result(
+                                &conn,
+                                metadata.id,
+                                &metadata.job_type,
+                                result,
+                                result_ttl,
+                            )?;
+                        }
+                        storage::record_attempt(&conn, &attempt(AttemptOutcome::Succeeded, None))?;
+                        Outcome::Succeeded
+                    }
+                    Some((e, error)) => {
+                        let outcome = if panicked {
+                            AttemptOutcome::Panicked
+                        } else {
+                            AttemptOutcome::Failed
+                        };
+                        #[cfg(feature = "metrics")]
+                        if let (Some(metrics), true) = (&metrics, panicked) {
+                            metrics.panicked(&metadata);
+                        }
+                        storage::record_attempt(&conn, &attempt(outcome, Some(&error)))?;
+                        if storage::cancel_requested(&conn, metadata.id)? {
+                            storage::discard(&conn, metadata.id)?;
+                            return Ok(Some(RunReport {
+                                job: metadata,
+                                duration,
+                                outcome: Outcome::Cancelled { error },
+                            }));
+                        }
+                        let (max_retries, backoff) = match &e {
+                            _ if panicked && panic_policy == PanicPolicy::DeadLetter => {
+                                (Some(0), backoff)
+                            }
+                            PerformError::Fatal(_) => (Some(0), backoff),
+                            PerformError::Retryable {
+                                after: Some(after), ..
+                            } => (max_retries, Some(Backoff::fixed(*after))),
+                            _ => (max_retries, backoff),
+                        };
+                        // If this still fails, the transaction is rolled
+                        // back, so the job is left as it was, and will be
+                        // run again
+                        let dead = retrying(
+                            &conn,
+                            storage_retry_policy,
+                            query_hook,
+                            StorageQuery::UpdateFailed,
+                            || {
+                                storage::update_failed_job(
+                                    &conn,
+                                    metadata.id,
+                                    &metadata.job_type,
+                                    &error,
+                                    max_retries,
+                                    retry_budgets.get(&metadata.job_type).copied(),
+                                    backoff,
+                                )
+                            },
+                        )?;
+                        for m in middleware.iter().rev() {
+                            m.on_failure(&metadata, &error, dead);
+                        }
+                        let mut job_error = JobError {
+                            job_id: metadata.id,
+                            job_type: &metadata.job_type,
+                            queue: &metadata.queue,
+                            data: &serde_json::Value::Null,
+                            retries: metadata.retries,
+                            error: &e,
+                            message: &error,
+                            panicked,
+                            dead,
+                        };
+                        match (&error_handler, handler_data) {
+                            (Some(handler), Some(mut data)) => {
+                                redact::data(&mut data, redacted);
+                                job_error.data = &data;
+                                handler.handle(&job_error);
+                            }
+                            _ => eprintln!("{}", job_error),
+                        }
+                        if dead {
+                            let failure = notification.map(|mut job| {
+                                redact::data(&mut job.data, redacted);
+                                JobFailure::new(job, error.clone())
+                            });
+                            Outcome::Dead { error, failure }
+                        } else {
+                            #[cfg(feature = "metrics")]
+                            if let Some(metrics) = &metrics {
+                                metrics.retried(&metadata);
+                            }
+                            Outcome::Failed { error }
+                        }
+                    }
+                };
+                Ok(Some(RunReport {
+                    job: metadata,
+                    duration,
+                    outcome,
+                }))
             });
 
+            drop(slot);
+            let finished_job = worker.finish();
+            if let (Some(job_id), true) = (finished_job, heartbeats) {
+                if let Err(e) = storage::delete_heartbeat(&conn, job_id) {
+                    eprintln!("Failed to delete heartbeat of job {}: {}", job_id, e);
+                }
+            }
+
             match job_run_result {
-                Ok(_) | Err(RollbackTransaction) => {}
-                Err(e) => {
-                    panic!("Failed to update job: {:?}", e);
+                Ok(Some(report)) => {
+                    if let (Some(store), Some(key)) = (&payload_store, &payload_key) {
+                        let removed = matches!(
+                            report.outcome,
+                            Outcome::Succeeded
+                                | Outcome::Cancelled { .. }
+                                | Outcome::Expired { dead: false }
+                        );
+                        if removed {
+                            if let Err(e) = store.delete(key) {
+                                eprintln!(
+                                    "Failed to delete payload of job {}: {}",
+                                    report.job.id, e
+                                );
+                            }
+                        }
+                    }
+                    report.tally(&tally);
+                    report.emit(&listeners, failure_notifier.as_deref());
                 }
+                Ok(None) | Err(RollbackTransaction) => {}
+                // The transaction was rolled back, so the job is left as it
+                // was, and will be run again
+                Err(e) => update_errors.push(e),
             }
         })
     }
 
+    /// Enqueues each registered job whose schedule has come due. Returns how
+    /// many jobs were enqueued.
+    ///
+    /// Schedules registered with
+    /// [`Registry::register_recurring`](crate::Registry::register_recurring)
+    /// are saved first. This is called by
+    /// [`run_all_pending_jobs`](Self::run_all_pending_jobs), so it only needs
+    /// to be called directly by runners which claim jobs some other way.
+    #[cfg(feature = "schedule")]
+    pub fn enqueue_scheduled_jobs(&self) -> Result<usize, FetchError<ConnectionPool>> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(FetchError::NoDatabaseConnection)?;
+
+        let recurring = self.registry.take_recurring();
+        let registered = conn.transaction(|| {
+            recurring
+                .iter()
+                .try_for_each(|(job_type, cron)| schedule::register(&conn, job_type, cron, "UTC"))
+        });
+        if let Err(e) = registered {
+            self.registry.restore_recurring(recurring);
+            return Err(FetchError::FailedLoadingJob(e));
+        }
+
+        schedule::enqueue_due(&conn, &self.registry.job_types(), Utc::now())
+            .map_err(FetchError::FailedLoadingJob)
+    }
+
+    /// Marks jobs which have been in the queue for longer than their TTL as
+    /// dead.
+    ///
+    /// See [`Builder::job_ttl`]. Jobs which are currently running are not
+    /// swept. Each swept job emits [`LifecycleEvent::Expired`] followed by
+    /// [`LifecycleEvent::Dead`]. Returns the number of jobs which were swept.
+    pub fn sweep_stale_jobs(&self) -> Result<usize, FetchError<ConnectionPool>> {
+        if self.job_ttls.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(FetchError::NoDatabaseConnection)?;
+        let mut swept = 0;
+        for (job_type, &ttl) in &self.job_ttls {
+            let jobs = storage::sweep_stale_jobs(&conn, job_type, ttl)
+                .map_err(FetchError::FailedLoadingJob)?;
+            swept += jobs.len();
+            for job in &jobs {
+                let job = JobMetadata::from(job);
+                self.listeners
+                    .emit(|| LifecycleEvent::Expired { job: job.clone() });
+                self.listeners.emit(|| LifecycleEvent::Dead {
+                    job: job.clone(),
+                    error: storage::STALE_ERROR.into(),
+                });
+            }
+        }
+        Ok(swept)
+    }
+
+    /// Deletes archived jobs which finished longer ago than the retention
+    /// given to [`Builder::archive_completed_jobs`]. Returns the number of
+    /// jobs which were deleted.
+    pub fn prune_archive(&self) -> Result<usize, FetchError<ConnectionPool>> {
+        let retention = match self.archive_retention {
+            Some(retention) => retention,
+            None => return Ok(0),
+        };
+
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(FetchError::NoDatabaseConnection)?;
+        storage::prune_archive(&conn, retention, self.maintenance_batch_size())
+            .map_err(FetchError::FailedLoadingJob)
+    }
+
+    /// Deletes dead jobs and archived jobs which are older than their
+    /// retention, a batch at a time, and measures how large the tables are
+    /// afterwards.
+    ///
+    /// Dead jobs are only deleted if the [`MaintenancePolicy`] given to
+    /// [`Builder::maintenance`] has a `dead_job_retention`, and archived jobs
+    /// if [`Builder::archive_completed_jobs`] was called. [`Runner::run`]
+    /// calls this periodically if a policy was given.
+    pub fn run_maintenance(&self) -> Result<MaintenanceReport, FetchError<ConnectionPool>> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(FetchError::NoDatabaseConnection)?;
+        let batch_size = self.maintenance_batch_size();
+        let dead_jobs_pruned = match self.maintenance.and_then(|m| m.dead_job_retention) {
+            Some(retention) => storage::prune_dead_jobs(&conn, retention, batch_size)
+                .map_err(FetchError::FailedLoadingJob)?,
+            None => 0,
+        };
+        let archived_jobs_pruned = match self.archive_retention {
+            Some(retention) => storage::prune_archive(&conn, retention, batch_size)
+                .map_err(FetchError::FailedLoadingJob)?,
+            None => 0,
+        };
+        let tables = storage::table_stats(&conn).map_err(FetchError::FailedLoadingJob)?;
+        Ok(MaintenanceReport {
+            dead_jobs_pruned,
+            archived_jobs_pruned,
+            tables,
+        })
+    }
+
+    /// Runs maintenance if a policy was given, and it hasn't been run in the
+    /// policy's interval
+    fn run_maintenance_if_due(&self) -> Result<(), FetchError<ConnectionPool>> {
+        let interval = match self.maintenance {
+            Some(policy) => policy.interval,
+            None => return Ok(()),
+        };
+        {
+            let mut last = self.last_maintenance.lock().unwrap();
+            if last.is_some_and(|last| last.elapsed() < interval) {
+                return Ok(());
+            }
+            *last = Some(Instant::now());
+        }
+
+        let report = self.run_maintenance()?;
+        if let Some(callback) = &self.on_maintenance {
+            callback(&report);
+        }
+        Ok(())
+    }
+
+    fn maintenance_batch_size(&self) -> i64 {
+        self.maintenance
+            .map_or(maintenance::DEFAULT_BATCH_SIZE, |m| m.batch_size)
+    }
+
+    /// The pending jobs on each queue, the number of failed jobs, how long
+    /// the oldest pending job has been waiting, and what this runner is
+    /// doing.
+    ///
+    /// The queue is counted with a few queries, so this is suited to health
+    /// checks and dashboards which call it every few seconds, rather than to
+    /// being called for every job.
+    pub fn stats(&self) -> Result<RunnerStats, FetchError<ConnectionPool>> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(FetchError::NoDatabaseConnection)?;
+        let pending = storage::queue_depths(&conn).map_err(FetchError::FailedLoadingJob)?;
+        let failed = storage::failed_job_count(&conn).map_err(FetchError::FailedLoadingJob)?;
+        let oldest_pending_age = storage::oldest_pending_job(&conn)
+            .map_err(FetchError::FailedLoadingJob)?
+            .map(|ready_at| (Utc::now() - ready_at).to_std().unwrap_or_default());
+        let processed = self
+            .counters
+            .snapshot()
+            .values()
+            .map(|counts| counts.succeeded + counts.failed + counts.expired)
+            .sum();
+        Ok(RunnerStats {
+            pending,
+            failed,
+            oldest_pending_age,
+            active_threads: self.in_flight.running(),
+            processed,
+        })
+    }
+
+    /// Checks that the runner can get a database connection, and that no job
+    /// has been waiting to run for longer than `max_pending_age`.
+    ///
+    /// This is meant to be called from a liveness or readiness probe. Jobs on
+    /// every queue are considered, including queues this runner doesn't
+    /// run and queues which are paused, and jobs which are waiting out a
+    /// backoff or are scheduled for later are not. See
+    /// [`storage::oldest_pending_job`](crate::storage::oldest_pending_job).
+    ///
+    /// ```ignore
+    /// match runner.check_health(Duration::from_secs(5 * 60)) {
+    ///     Ok(()) => Response::new(200),
+    ///     Err(e) => Response::new(503).with_body(e.to_string()),
+    /// }
+    /// ```
+    pub fn check_health(
+        &self,
+        max_pending_age: Duration,
+    ) -> Result<(), HealthError<ConnectionPool>> {
+        let conn = self
+            .connection_pool
+            .get()
+            .map_err(HealthError::NoDatabaseConnection)?;
+        let oldest_pending_job =
+            storage::oldest_pending_job(&conn).map_err(HealthError::DatabaseError)?;
+        let oldest_pending_age = match oldest_pending_job {
+            Some(ready_at) => (Utc::now() - ready_at).to_std().unwrap_or_default(),
+            None => return Ok(()),
+        };
+        if oldest_pending_age > max_pending_age {
+            return Err(HealthError::JobsBacklogged {
+                oldest_pending_age,
+                max_pending_age,
+            });
+        }
+        Ok(())
+    }
+
     fn connection(&self) -> Result<DieselPooledConn<ConnectionPool>, Box<dyn Error + Send + Sync>> {
         self.connection_pool.get().map_err(Into::into)
     }
 
+    /// Reports the number of pending jobs on each queue, if the configured
+    /// interval has passed since they were last counted. Failing to count
+    /// them doesn't stop the runner from looking for jobs.
+    #[cfg(feature = "metrics")]
+    fn report_queue_depths(&self) {
+        let metrics = match &self.metrics {
+            Some(metrics) if metrics.queue_depths_due() => metrics,
+            _ => return,
+        };
+        let depths = self
+            .connection()
+            .and_then(|conn| Ok(storage::queue_depths(&conn)?));
+        match depths {
+            Ok(depths) => metrics.queue_depths(&depths),
+            Err(e) => eprintln!("Failed to count pending jobs: {}", e),
+        }
+    }
+
     /// Waits for all running jobs to complete, and returns an error if any
     /// failed
     ///
     /// This function is intended for use in tests. If any jobs have failed, it
     /// will return `swirl::JobsFailed` with the number of jobs that failed.
     ///
-    /// If any other unexpected errors occurred, such as panicked worker threads
-    /// or an error loading the job count from the database, an opaque error
-    /// will be returned.
+    /// If any other unexpected errors occurred, such as panicked worker threads,
+    /// a job which ran but could not be updated, or an error loading the job
+    /// count from the database, an opaque error will be returned.
     pub fn check_for_failed_jobs(&self) -> Result<(), FailedJobsError> {
         self.wait_for_jobs()?;
         let failed_jobs = storage::failed_job_count(&*self.connection()?)?;
@@ -300,43 +1786,194 @@ where
         }
     }
 
+    /// Waits up to `timeout` for all running jobs to complete.
+    ///
+    /// This should be called during a graceful shutdown, after the runner has
+    /// stopped being asked to run new jobs. Any jobs which are still running
+    /// when the timeout expires are listed in the returned report.
+    ///
+    /// Interrupted jobs do not need to be cleaned up. Their rows stay locked
+    /// by the transaction they are running in, so when the process exits and
+    /// its connections are closed, the locks are released without the retry
+    /// count being updated, and the jobs will be run again by the next runner.
+    pub fn drain(&self, timeout: Duration) -> DrainReport {
+        self.in_flight.wait(timeout)
+    }
+
     fn wait_for_jobs(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
         self.thread_pool.join();
         let panic_count = self.thread_pool.panic_count();
-        if panic_count == 0 {
-            Ok(())
-        } else {
-            Err(format!("{} threads panicked", panic_count).into())
+        if panic_count != 0 {
+            return Err(format!("{} threads panicked", panic_count).into());
         }
+        match self.update_errors.take() {
+            Some(e) => Err(format!("Failed to update job: {}", e).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What happened when a job was run. This is reported once the transaction
+/// which updated the job has been committed.
+struct RunReport {
+    job: JobMetadata,
+    duration: Duration,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Succeeded,
+    Failed {
+        error: String,
+    },
+    Dead {
+        error: String,
+        /// Only present if a failure notifier is configured
+        failure: Option<JobFailure>,
+    },
+    /// The job failed after it was cancelled, and was removed from the queue
+    Cancelled {
+        error: String,
+    },
+    /// The job was claimed after its deadline, and was not run
+    Expired {
+        dead: bool,
+    },
+    /// The job asked to be run again after `delay`
+    Snoozed {
+        delay: Duration,
+    },
+}
+
+impl RunReport {
+    fn tally(&self, tally: &Tally) {
+        match self.outcome {
+            Outcome::Succeeded => tally.succeeded(),
+            Outcome::Failed { .. } | Outcome::Dead { .. } | Outcome::Cancelled { .. } => {
+                tally.failed()
+            }
+            Outcome::Expired { .. } => tally.skipped(),
+            Outcome::Snoozed { .. } => tally.snoozed(),
+        }
+    }
+
+    fn emit(self, listeners: &Listeners, failure_notifier: Option<&dyn FailureNotifier>) {
+        let Self {
+            job,
+            duration,
+            outcome,
+        } = self;
+        let duration_ms = duration.as_millis() as u64;
+
+        match outcome {
+            Outcome::Succeeded => listeners.emit(|| LifecycleEvent::Succeeded { job, duration_ms }),
+            Outcome::Failed { error } => listeners.emit(|| LifecycleEvent::Failed {
+                job,
+                duration_ms,
+                error,
+            }),
+            Outcome::Dead { error, failure } => {
+                listeners.emit(|| LifecycleEvent::Failed {
+                    job: job.clone(),
+                    duration_ms,
+                    error: error.clone(),
+                });
+                listeners.emit(|| LifecycleEvent::Dead { job, error });
+                if let (Some(notifier), Some(failure)) = (failure_notifier, failure) {
+                    notifier.notify(&failure);
+                }
+            }
+            Outcome::Cancelled { error } => {
+                listeners.emit(|| LifecycleEvent::Failed {
+                    job: job.clone(),
+                    duration_ms,
+                    error,
+                });
+                listeners.emit(|| LifecycleEvent::Cancelled { job });
+            }
+            Outcome::Snoozed { delay } => listeners.emit(|| LifecycleEvent::Snoozed {
+                job,
+                duration_ms,
+                delay_ms: delay.as_millis() as u64,
+            }),
+            Outcome::Expired { dead } => {
+                listeners.emit(|| LifecycleEvent::Expired { job: job.clone() });
+                if dead {
+                    listeners.emit(|| LifecycleEvent::Dead {
+                        job,
+                        error: storage::EXPIRED_ERROR.into(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The environments jobs are run with, keyed by queue
+struct Environments<Env> {
+    default: Env,
+    by_queue: HashMap<String, Env>,
+}
+
+impl<Env> Environments<Env> {
+    fn new(default: Env, by_queue: HashMap<String, Env>) -> Self {
+        Self { default, by_queue }
+    }
+
+    fn for_queue(&self, queue: &str) -> &Env {
+        self.by_queue.get(queue).unwrap_or(&self.default)
     }
 }
 
+/// Identifies the process and thread which ran a job in its attempt history
+/// and heartbeats
+fn worker_name() -> String {
+    format!(
+        "pid {} {:?}",
+        std::process::id(),
+        std::thread::current().id()
+    )
+}
+
 /// Try to figure out what's in the box, and print it if we can.
 ///
 /// The actual error type we will get from `panic::catch_unwind` is really poorly documented.
 /// However, the `panic::set_hook` functions deal with a `PanicInfo` type, and its payload is
 /// documented as "commonly but not always `&'static str` or `String`". So we can try all of those,
 /// and give up if we didn't get one of those three types.
-fn try_to_extract_panic_info(info: &(dyn Any + Send + 'static)) -> PerformError {
+///
+/// If our panic hook saw the panic, the location and backtrace (if enabled) are
+/// included as well.
+fn try_to_extract_panic_info(panic: &CaughtPanic) -> PerformError {
+    let info = &*panic.payload;
+    let mut message = String::from("job panicked");
+
+    if let Some(location) = panic.details.as_ref().and_then(|d| d.location.as_ref()) {
+        message += &format!(" at {}", location);
+    }
+
     if let Some(x) = info.downcast_ref::<PanicInfo>() {
-        format!("job panicked: {}", x).into()
+        message += &format!(": {}", x);
     } else if let Some(x) = info.downcast_ref::<&'static str>() {
-        format!("job panicked: {}", x).into()
+        message += &format!(": {}", x);
     } else if let Some(x) = info.downcast_ref::<String>() {
-        format!("job panicked: {}", x).into()
-    } else {
-        "job panicked".into()
+        message += &format!(": {}", x);
     }
+
+    if let Some(backtrace) = panic.details.as_ref().and_then(|d| d.backtrace.as_ref()) {
+        message += &format!("\nstack backtrace:\n{}", backtrace);
+    }
+
+    message.into()
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "r2d2"))]
 mod tests {
     use diesel::prelude::*;
     use diesel::r2d2;
 
     use super::*;
     use crate::schema::background_jobs::dsl::*;
-    use std::panic::AssertUnwindSafe;
     use std::sync::{Arc, Barrier, Mutex, MutexGuard};
 
     #[test]
@@ -346,23 +1983,23 @@ mod tests {
         let runner = runner();
         let first_job_id = create_dummy_job(&runner).id;
         let second_job_id = create_dummy_job(&runner).id;
-        let fetch_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
+        let fetch_barrier = Arc::new(Barrier::new(2));
         let fetch_barrier2 = fetch_barrier.clone();
-        let return_barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
+        let return_barrier = Arc::new(Barrier::new(2));
         let return_barrier2 = return_barrier.clone();
 
-        runner.get_single_job(channel::dummy_sender(), move |job| {
-            fetch_barrier.0.wait(); // Tell thread 2 it can lock its job
+        runner.get_single_job(channel::dummy_sender(), Arc::default(), move |job, _| {
+            fetch_barrier.wait(); // Tell thread 2 it can lock its job
             assert_eq!(first_job_id, job.id);
-            return_barrier.0.wait(); // Wait for thread 2 to lock its job
-            Ok(())
+            return_barrier.wait(); // Wait for thread 2 to lock its job
+            Ok(None)
         });
 
-        fetch_barrier2.0.wait(); // Wait until thread 1 locks its job
-        runner.get_single_job(channel::dummy_sender(), move |job| {
+        fetch_barrier2.wait(); // Wait until thread 1 locks its job
+        runner.get_single_job(channel::dummy_sender(), Arc::default(), move |job, _| {
             assert_eq!(second_job_id, job.id);
-            return_barrier2.0.wait(); // Tell thread 1 it can unlock its job
-            Ok(())
+            return_barrier2.wait(); // Tell thread 1 it can unlock its job
+            Ok(None)
         });
 
         runner.wait_for_jobs().unwrap();
@@ -375,7 +2012,7 @@ mod tests {
         let runner = runner();
         create_dummy_job(&runner);
 
-        runner.get_single_job(channel::dummy_sender(), |_| Ok(()));
+        runner.get_single_job(channel::dummy_sender(), Arc::default(), |_, _| Ok(None));
         runner.wait_for_jobs().unwrap();
 
         let remaining_jobs = background_jobs
@@ -390,18 +2027,18 @@ mod tests {
 
         let runner = runner();
         create_dummy_job(&runner);
-        let barrier = Arc::new(AssertUnwindSafe(Barrier::new(2)));
+        let barrier = Arc::new(Barrier::new(2));
         let barrier2 = barrier.clone();
 
-        runner.get_single_job(channel::dummy_sender(), move |_| {
-            barrier.0.wait();
+        runner.get_single_job(channel::dummy_sender(), Arc::default(), move |_, _| {
+            barrier.wait();
             // error so the job goes back into the queue
             Err("nope".into())
         });
 
         let conn = runner.connection().unwrap();
         // Wait for the first thread to acquire the lock
-        barrier2.0.wait();
+        barrier2.wait();
         // We are intentionally not using `get_single_job` here.
         // `SKIP LOCKED` is intentionally omitted here, so we block until
         // the lock on the first job is released.
@@ -432,7 +2069,7 @@ mod tests {
         let runner = runner();
         let job_id = create_dummy_job(&runner).id;
 
-        runner.get_single_job(channel::dummy_sender(), |_| panic!());
+        runner.get_single_job(channel::dummy_sender(), Arc::default(), |_, _| panic!());
         runner.wait_for_jobs().unwrap();
 
         let tries = background_jobs
@@ -444,6 +2081,17 @@ mod tests {
         assert_eq!(1, tries);
     }
 
+    #[test]
+    fn panic_messages_include_the_location_of_the_panic() {
+        let line = line!() + 1;
+        let panic = catch_unwind(|| panic!("oh no")).unwrap_err();
+        let message = try_to_extract_panic_info(&panic).to_string();
+
+        let expected = format!("job panicked at {}:{}:", file!(), line);
+        assert!(message.starts_with(&expected), "{}", message);
+        assert!(message.contains(": oh no"), "{}", message);
+    }
+
     lazy_static::lazy_static! {
         // Since these tests deal with behavior concerning multiple connections
         // running concurrently, they have to run outside of a transaction.
@@ -464,9 +2112,11 @@ mod tests {
 
     impl<'a> Drop for TestGuard<'a> {
         fn drop(&mut self) {
-            ::diesel::sql_query("TRUNCATE TABLE background_jobs")
-                .execute(&*runner().connection().unwrap())
-                .unwrap();
+            ::diesel::sql_query(
+                "TRUNCATE TABLE background_jobs, background_job_failures, background_job_attempts",
+            )
+            .execute(&*runner().connection().unwrap())
+            .unwrap();
         }
     }
 
@@ -485,7 +2135,21 @@ mod tests {
     fn create_dummy_job(runner: &Runner<()>) -> storage::BackgroundJob {
         ::diesel::insert_into(background_jobs)
             .values((job_type.eq("Foo"), data.eq(serde_json::json!(null))))
-            .returning((id, job_type, data))
+            .returning((
+                id,
+                job_type,
+                data,
+                queue,
+                retries,
+                deadline,
+                binary_data,
+                storage::enqueued_at(),
+                last_error,
+                failed_at,
+                compressed,
+                started_at,
+                finished_at,
+            ))
             .get_result(&*runner.connection().unwrap())
             .unwrap()
     }
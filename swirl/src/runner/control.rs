@@ -0,0 +1,243 @@
+//! Controlling a runner from other threads while it is running.
+
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A command sent to a runner through a [`RunnerHandle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Stop running jobs on the given queue. Jobs on the queue which are
+    /// already running are not interrupted.
+    PauseQueue(String),
+    /// Start running jobs on a queue which was paused again
+    ResumeQueue(String),
+    /// Change the number of threads used to run jobs. This must not be 0.
+    SetThreadCount(usize),
+    /// Look for jobs immediately, instead of waiting for the poll interval to
+    /// elapse
+    PollNow,
+    /// Stop looking for jobs. [`Runner::run`](crate::Runner::run) waits until
+    /// the given deadline for running jobs to finish, and then returns.
+    Shutdown(Instant),
+}
+
+/// A cheap, cloneable handle used to control a [`Runner`](crate::Runner)
+/// from other threads.
+///
+/// Commands sent through a handle are applied each time the runner looks for
+/// jobs. A runner which is sleeping in [`Runner::run`](crate::Runner::run) is
+/// woken up as soon as a command is sent. Each command returns an
+/// [`Acknowledgement`], which can be used to wait for the runner to apply it.
+///
+/// The handle of a [`RunnerGroup`](crate::RunnerGroup) sends each command to
+/// every runner in the group.
+#[derive(Debug, Clone)]
+pub struct RunnerHandle {
+    senders: Vec<Sender<(Command, Sender<()>)>>,
+}
+
+/// Returned for each command sent through a [`RunnerHandle`]
+#[derive(Debug)]
+pub struct Acknowledgement {
+    receivers: Vec<Receiver<()>>,
+}
+
+impl Acknowledgement {
+    /// Wait up to `timeout` for the runner to apply the command. For the
+    /// handle of a group, waits for every runner in the group.
+    ///
+    /// Returns [`RecvTimeoutError::Disconnected`] if the runner was dropped
+    /// without applying it. For [`Command::Shutdown`], the command has been
+    /// applied once the runner stops looking for new jobs. Jobs which were
+    /// already running may not have finished yet.
+    pub fn wait(&self, timeout: Duration) -> Result<(), RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        for receiver in &self.receivers {
+            receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+        }
+        Ok(())
+    }
+}
+
+impl RunnerHandle {
+    /// Stop running jobs on the given queue.
+    ///
+    /// Jobs on the queue which are already running are not interrupted. This
+    /// only pauses the queue for this runner, until it exits. Use
+    /// [`storage::pause_queue`](crate::storage::pause_queue) to pause it for
+    /// every runner.
+    pub fn pause_queue<S: Into<String>>(&self, queue: S) -> Acknowledgement {
+        self.send(Command::PauseQueue(queue.into()))
+    }
+
+    /// Start running jobs on a queue which was paused again
+    pub fn resume_queue<S: Into<String>>(&self, queue: S) -> Acknowledgement {
+        self.send(Command::ResumeQueue(queue.into()))
+    }
+
+    /// Change the number of threads used to run jobs. For the handle of a
+    /// group, this is the number of threads used by each runner.
+    ///
+    /// The size of the connection pool is not changed, so it may need to be
+    /// configured with room for additional threads up front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `thread_count` is 0.
+    pub fn set_thread_count(&self, thread_count: usize) -> Acknowledgement {
+        self.send(Command::SetThreadCount(thread_count))
+    }
+
+    /// Look for jobs immediately, instead of waiting for the poll interval
+    /// to elapse.
+    pub fn poll_now(&self) -> Acknowledgement {
+        self.send(Command::PollNow)
+    }
+
+    /// Stop looking for jobs, and wait up to `timeout` for running jobs to
+    /// finish. [`Runner::run`](crate::Runner::run) returns once this is done.
+    pub fn shutdown(&self, timeout: Duration) -> Acknowledgement {
+        self.send(Command::Shutdown(Instant::now() + timeout))
+    }
+
+    /// Send a command to the runner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the command is [`Command::SetThreadCount`] with 0 threads.
+    pub fn send(&self, command: Command) -> Acknowledgement {
+        if let Command::SetThreadCount(thread_count) = command {
+            assert!(thread_count > 0, "A runner needs at least one thread");
+        }
+        let receivers = self
+            .senders
+            .iter()
+            .map(|sender| {
+                let (ack, receiver) = channel();
+                // If the runner has been dropped, the acknowledgement reports it
+                let _ = sender.send((command.clone(), ack));
+                receiver
+            })
+            .collect();
+        Acknowledgement { receivers }
+    }
+
+    /// A handle which sends each command to every one of `handles`
+    pub(super) fn combine<I: IntoIterator<Item = RunnerHandle>>(handles: I) -> Self {
+        Self {
+            senders: handles.into_iter().flat_map(|h| h.senders).collect(),
+        }
+    }
+}
+
+/// The receiving end of every [`RunnerHandle`], and the state the commands
+/// sent through them have changed
+pub(super) struct Control {
+    sender: Sender<(Command, Sender<()>)>,
+    receiver: Mutex<Receiver<(Command, Sender<()>)>>,
+    state: Mutex<State>,
+    /// Set once the runner has been shut down, so running jobs can see it
+    shutting_down: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct State {
+    paused_queues: BTreeSet<String>,
+    /// A snapshot of `paused_queues`, which is cheap to hand to each worker
+    paused: Arc<[String]>,
+    shutdown: Option<Instant>,
+}
+
+/// What the runner needs to do after applying commands
+pub(super) struct Applied {
+    pub(super) thread_count: Option<usize>,
+    pub(super) shutdown: Option<Instant>,
+    acks: Vec<Sender<()>>,
+}
+
+impl Applied {
+    /// Tell the senders of each command that it has been applied
+    pub(super) fn acknowledge(&mut self) {
+        for ack in self.acks.drain(..) {
+            let _ = ack.send(());
+        }
+    }
+}
+
+impl Control {
+    pub(super) fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+            state: Mutex::default(),
+            shutting_down: Arc::default(),
+        }
+    }
+
+    /// A flag which is set once the runner has been shut down
+    pub(super) fn shutting_down(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutting_down)
+    }
+
+    pub(super) fn handle(&self) -> RunnerHandle {
+        RunnerHandle {
+            senders: vec![self.sender.clone()],
+        }
+    }
+
+    /// The queues which are currently paused
+    pub(super) fn paused_queues(&self) -> Arc<[String]> {
+        Arc::clone(&self.state.lock().unwrap().paused)
+    }
+
+    /// Apply every command which has been sent. If `wait` is given, waits up
+    /// to that long for a command to arrive if none have been sent yet.
+    pub(super) fn apply(&self, wait: Option<Duration>) -> Applied {
+        // Once shut down, there is nothing left to wait for
+        let wait = wait.filter(|_| self.state.lock().unwrap().shutdown.is_none());
+        let receiver = self.receiver.lock().unwrap();
+        let mut commands = Vec::new();
+        if let Some(wait) = wait {
+            if let Ok(command) = receiver.recv_timeout(wait) {
+                commands.push(command);
+            }
+        }
+        commands.extend(receiver.try_iter());
+        drop(receiver);
+
+        let mut state = self.state.lock().unwrap();
+        let mut thread_count = None;
+        let mut paused_changed = false;
+        let mut acks = Vec::with_capacity(commands.len());
+        for (command, ack) in commands {
+            acks.push(ack);
+            match command {
+                Command::PauseQueue(queue) => {
+                    paused_changed |= state.paused_queues.insert(queue);
+                }
+                Command::ResumeQueue(queue) => {
+                    paused_changed |= state.paused_queues.remove(&queue);
+                }
+                Command::SetThreadCount(count) => thread_count = Some(count),
+                Command::PollNow => {}
+                Command::Shutdown(deadline) => {
+                    state.shutdown = Some(deadline);
+                    self.shutting_down.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        if paused_changed {
+            state.paused = state.paused_queues.iter().cloned().collect();
+        }
+
+        Applied {
+            thread_count,
+            shutdown: state.shutdown,
+            acks,
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! In-process counts of the jobs a runner has handled, for applications which
+//! don't report metrics anywhere else.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::lifecycle::{LifecycleEvent, LifecycleListener};
+
+/// How many times jobs of a single type have reached each stage of their
+/// lifecycle since the runner was built.
+///
+/// See [`Runner::counters`](crate::Runner::counters).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JobCounts {
+    /// Jobs which were started
+    pub started: u64,
+    /// Jobs which ran successfully
+    pub succeeded: u64,
+    /// Attempts to run a job which returned an error or panicked
+    pub failed: u64,
+    /// Jobs which were not run because they expired
+    pub expired: u64,
+    /// Jobs which were removed from the queue after being cancelled
+    pub cancelled: u64,
+    /// Jobs which were marked as dead
+    pub dead: u64,
+    /// Attempts to run a job which [snoozed](crate::PerformError::Snooze)
+    pub snoozed: u64,
+}
+
+/// Registered as a listener on every runner
+#[derive(Default)]
+pub(super) struct Counters(Mutex<HashMap<String, JobCounts>>);
+
+impl Counters {
+    pub(super) fn snapshot(&self) -> HashMap<String, JobCounts> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl LifecycleListener for Counters {
+    fn on_event(&self, event: &LifecycleEvent) {
+        let mut counters = self.0.lock().unwrap();
+        let counts = counters.entry(event.job().job_type.clone()).or_default();
+        match event {
+            LifecycleEvent::Started { .. } => counts.started += 1,
+            LifecycleEvent::Succeeded { .. } => counts.succeeded += 1,
+            LifecycleEvent::Failed { .. } => counts.failed += 1,
+            LifecycleEvent::Slow { .. } => {}
+            LifecycleEvent::Expired { .. } => counts.expired += 1,
+            LifecycleEvent::Cancelled { .. } => counts.cancelled += 1,
+            LifecycleEvent::Dead { .. } => counts.dead += 1,
+            LifecycleEvent::Snoozed { .. } => counts.snoozed += 1,
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Tracking of the jobs which are currently running, so the runner can wait
+//! for them to finish when it is shut down, report them while they are still
+//! running if they are slow, and record heartbeats for them.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::slow_jobs::SlowJobThresholds;
+use crate::lifecycle::JobMetadata;
+use crate::storage::Heartbeat;
+
+/// The jobs which were still running when [`Runner::drain`] gave up waiting
+/// for them.
+///
+/// [`Runner::drain`]: crate::Runner::drain
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrainReport {
+    /// The jobs which were interrupted, ordered by ID
+    pub interrupted: Vec<InterruptedJob>,
+}
+
+impl DrainReport {
+    /// Returns `true` if every job finished before the deadline
+    pub fn is_clean(&self) -> bool {
+        self.interrupted.is_empty()
+    }
+}
+
+/// A job which was still running when the runner was drained
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterruptedJob {
+    /// The job which was running
+    pub job: JobMetadata,
+    /// How long the job had been running for
+    pub elapsed: Duration,
+}
+
+#[derive(Default)]
+pub(super) struct InFlight {
+    state: Mutex<State>,
+    /// Notified whenever a job starts or a worker finishes
+    changed: Condvar,
+    /// Held while heartbeats are being recorded
+    recording: Mutex<()>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Workers which are looking for a job, or running one
+    workers: usize,
+    jobs: HashMap<i64, RunningJob>,
+}
+
+struct RunningJob {
+    job: JobMetadata,
+    started: Instant,
+    reported_slow: bool,
+    /// The worker running the job
+    worker: String,
+    last_heartbeat: Option<Instant>,
+}
+
+impl InFlight {
+    /// Record that a worker has been queued to look for a job. The worker is
+    /// considered finished when the returned guard is dropped.
+    pub(super) fn worker(self: &Arc<Self>) -> Worker {
+        self.state.lock().unwrap().workers += 1;
+        Worker {
+            in_flight: Arc::clone(self),
+            job_id: None,
+        }
+    }
+
+    /// Wait for every worker to finish, or until `timeout` has elapsed
+    pub(super) fn wait(&self, timeout: Duration) -> DrainReport {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |state| state.workers > 0)
+            .unwrap();
+
+        let mut interrupted = state
+            .jobs
+            .values()
+            .map(|running| InterruptedJob {
+                job: running.job.clone(),
+                elapsed: running.started.elapsed(),
+            })
+            .collect::<Vec<_>>();
+        interrupted.sort_by_key(|i| i.job.id);
+        DrainReport { interrupted }
+    }
+
+    /// The number of jobs which are running
+    pub(super) fn running(&self) -> usize {
+        self.state.lock().unwrap().jobs.len()
+    }
+
+    /// Returns the running jobs which have exceeded their threshold since the
+    /// last call. If there are none, waits until the next job is due to
+    /// exceed its threshold, a job starts, or `max_wait` has elapsed, and
+    /// returns an empty list.
+    pub(super) fn slow_jobs(
+        &self,
+        thresholds: &SlowJobThresholds,
+        max_wait: Duration,
+    ) -> Vec<(JobMetadata, Duration)> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut slow = Vec::new();
+        let mut wait = max_wait;
+
+        for running in state.jobs.values_mut().filter(|r| !r.reported_slow) {
+            if let Some(threshold) = thresholds.for_job_type(&running.job.job_type) {
+                let elapsed = now - running.started;
+                if elapsed >= threshold {
+                    running.reported_slow = true;
+                    slow.push((running.job.clone(), elapsed));
+                } else {
+                    wait = wait.min(threshold - elapsed);
+                }
+            }
+        }
+
+        if slow.is_empty() {
+            let _ = self.changed.wait_timeout(state, wait).unwrap();
+        }
+        slow
+    }
+
+    /// Returns the running jobs which are due a heartbeat, because they have
+    /// just started or their last one was at least `interval` ago. If there
+    /// are none, waits until the next one is due, a job starts, or `max_wait`
+    /// has elapsed, and returns an empty list.
+    pub(super) fn due_heartbeats(&self, interval: Duration, max_wait: Duration) -> Vec<Heartbeat> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let mut due = Vec::new();
+        let mut wait = max_wait;
+
+        for running in state.jobs.values_mut() {
+            match running.last_heartbeat {
+                Some(last) if now - last < interval => wait = wait.min(interval - (now - last)),
+                _ => {
+                    running.last_heartbeat = Some(now);
+                    due.push(Heartbeat {
+                        job_id: running.job.id,
+                        attempt: running.job.retries,
+                        locked_by: running.worker.clone(),
+                    });
+                }
+            }
+        }
+
+        if due.is_empty() {
+            let _ = self.changed.wait_timeout(state, wait).unwrap();
+        }
+        due
+    }
+
+    /// Records `heartbeats` with `record`, skipping those whose job has
+    /// finished since they were due. A worker whose job has finished waits
+    /// for this in [`Worker::finish`] before deleting its job's heartbeat, so
+    /// the heartbeat can't be recorded again afterwards.
+    pub(super) fn record_heartbeats<E>(
+        &self,
+        mut heartbeats: Vec<Heartbeat>,
+        record: impl FnOnce(&[Heartbeat]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let _recording = self.recording.lock().unwrap_or_else(|e| e.into_inner());
+        let state = self.state.lock().unwrap();
+        heartbeats.retain(|heartbeat| state.jobs.contains_key(&heartbeat.job_id));
+        drop(state);
+        if heartbeats.is_empty() {
+            return Ok(());
+        }
+        record(&heartbeats)
+    }
+}
+
+pub(super) struct Worker {
+    in_flight: Arc<InFlight>,
+    job_id: Option<i64>,
+}
+
+impl Worker {
+    /// Record that this worker has started running a job
+    pub(super) fn started(&mut self, job: JobMetadata) {
+        self.job_id = Some(job.id);
+        let mut state = self.in_flight.state.lock().unwrap();
+        let running = RunningJob {
+            job,
+            started: Instant::now(),
+            reported_slow: false,
+            worker: super::worker_name(),
+            last_heartbeat: None,
+        };
+        state.jobs.insert(running.job.id, running);
+        self.in_flight.changed.notify_all();
+    }
+
+    /// Record that this worker has finished, and return the id of the job it
+    /// ran, if any, once no heartbeat can still be being recorded for it
+    pub(super) fn finish(self) -> Option<i64> {
+        let in_flight = Arc::clone(&self.in_flight);
+        let job_id = self.job_id;
+        drop(self);
+        if job_id.is_some() {
+            drop(
+                in_flight
+                    .recording
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner()),
+            );
+        }
+        job_id
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let mut state = self
+            .in_flight
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        state.workers -= 1;
+        if let Some(id) = self.job_id {
+            state.jobs.remove(&id);
+        }
+        self.in_flight.changed.notify_all();
+    }
+}
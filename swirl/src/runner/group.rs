@@ -0,0 +1,132 @@
+//! Running several runners in one process, such as one per queue with its
+//! own thread count, as a single unit.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Builder, DrainReport, Runner, RunnerHandle};
+use crate::db::DieselPool;
+
+/// A set of runners which share a connection pool, and are run, controlled,
+/// and drained together.
+///
+/// ```ignore
+/// let group = RunnerGroup::new(connection_pool)
+///     .runner(Runner::builder(env.clone()).queues(vec!["critical"]).thread_count(8))
+///     .runner(Runner::builder(env).queues(vec!["bulk"]).thread_count(2));
+///
+/// let handle = group.handle();
+/// // Stop every runner in the group on SIGTERM, giving jobs 30s to finish
+/// on_sigterm(move || handle.shutdown(Duration::from_secs(30)));
+/// let report = group.run();
+/// ```
+#[allow(missing_debug_implementations)]
+pub struct RunnerGroup<Env: 'static, ConnectionPool> {
+    connection_pool: ConnectionPool,
+    runners: Vec<Runner<Env, ConnectionPool>>,
+}
+
+impl<Env, ConnectionPool> RunnerGroup<Env, ConnectionPool>
+where
+    ConnectionPool: DieselPool,
+{
+    /// Create an empty group whose runners use `connection_pool`.
+    ///
+    /// The pool needs to be large enough for the threads of every runner in
+    /// the group.
+    pub fn new(connection_pool: ConnectionPool) -> Self {
+        Self {
+            connection_pool,
+            runners: Vec::new(),
+        }
+    }
+
+    /// Build a runner using the group's connection pool, and add it to the
+    /// group. Any connection pool given to the builder is replaced.
+    pub fn runner<B>(mut self, builder: Builder<Env, B>) -> Self {
+        let runner = builder
+            .connection_pool(self.connection_pool.clone())
+            .build();
+        self.runners.push(runner);
+        self
+    }
+
+    /// The runners in the group, in the order they were added
+    pub fn runners(&self) -> &[Runner<Env, ConnectionPool>] {
+        &self.runners
+    }
+
+    /// Create a handle which sends each command to every runner in the group
+    pub fn handle(&self) -> RunnerHandle {
+        RunnerHandle::combine(self.runners.iter().map(Runner::handle))
+    }
+}
+
+impl<Env, ConnectionPool> RunnerGroup<Env, ConnectionPool>
+where
+    Env: Send + Sync + 'static,
+    ConnectionPool: DieselPool + Sync + 'static,
+{
+    /// Runs every runner in the group on its own thread until the group's
+    /// handle is used to shut them down.
+    ///
+    /// Unlike [`Runner::run`], an error looking for jobs does not stop the
+    /// runner. It is logged, and the runner tries again after its poll
+    /// interval, so one runner losing its connection does not stop the rest
+    /// of the group. Returns the jobs of every runner which did not finish
+    /// before the shutdown deadline.
+    pub fn run(&self) -> DrainReport {
+        thread::scope(|scope| {
+            let threads = self
+                .runners
+                .iter()
+                .map(|runner| scope.spawn(move || supervise(runner)))
+                .collect::<Vec<_>>();
+            let reports = threads
+                .into_iter()
+                .map(|thread| thread.join().expect("runner thread panicked"))
+                .collect();
+            merge(reports)
+        })
+    }
+
+    /// Waits up to `timeout` for the running jobs of every runner to complete.
+    ///
+    /// See [`Runner::drain`].
+    pub fn drain(&self, timeout: Duration) -> DrainReport {
+        let deadline = Instant::now() + timeout;
+        let reports = self
+            .runners
+            .iter()
+            .map(|runner| runner.drain(deadline.saturating_duration_since(Instant::now())))
+            .collect();
+        merge(reports)
+    }
+}
+
+fn supervise<Env, ConnectionPool>(runner: &Runner<Env, ConnectionPool>) -> DrainReport
+where
+    Env: Send + Sync + 'static,
+    ConnectionPool: DieselPool + 'static,
+{
+    loop {
+        match runner.run() {
+            Ok(report) => return report,
+            Err(e) => {
+                eprintln!("Runner failed to look for jobs, retrying: {}", e);
+                if let Some(deadline) = runner.apply_commands(Some(runner.poll_interval)) {
+                    return runner.drain(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+        }
+    }
+}
+
+fn merge(reports: Vec<DrainReport>) -> DrainReport {
+    let mut interrupted = reports
+        .into_iter()
+        .flat_map(|report| report.interrupted)
+        .collect::<Vec<_>>();
+    interrupted.sort_by_key(|i| i.job.id);
+    DrainReport { interrupted }
+}
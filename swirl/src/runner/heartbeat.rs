@@ -0,0 +1,62 @@
+//! Recording which worker is running each job, so jobs whose worker died
+//! while running them can be found.
+//!
+//! A job's row stays locked while it runs, and the lock is released if its
+//! worker's connection is closed, so a job whose process was killed is run
+//! again without anything being recorded about the attempt which was cut
+//! short. While heartbeats are enabled, a thread records the worker running
+//! each job in `background_job_heartbeats`, using a connection of its own so
+//! the record outlives the job's transaction. The worker deletes the record
+//! once its job has finished, after any heartbeat being recorded for it.
+
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::drain::InFlight;
+use crate::db::DieselPool;
+use crate::storage;
+
+/// The longest the thread waits before checking whether the runner has been
+/// dropped
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// Starts a thread which records a heartbeat for each running job every
+/// `interval`, and as soon as the job starts.
+///
+/// The thread exits once the runner and all of its workers have been dropped.
+pub(super) fn spawn<ConnectionPool>(
+    in_flight: &Arc<InFlight>,
+    connection_pool: ConnectionPool,
+    interval: Duration,
+) where
+    ConnectionPool: DieselPool + 'static,
+{
+    let in_flight = Arc::downgrade(in_flight);
+    thread::Builder::new()
+        .name("swirl-heartbeat".into())
+        .spawn(move || beat(in_flight, connection_pool, interval))
+        .expect("Failed to spawn heartbeat thread");
+}
+
+fn beat<ConnectionPool: DieselPool>(
+    in_flight: Weak<InFlight>,
+    connection_pool: ConnectionPool,
+    interval: Duration,
+) {
+    while let Some(in_flight) = in_flight.upgrade() {
+        let heartbeats = in_flight.due_heartbeats(interval, MAX_WAIT.min(interval));
+        if heartbeats.is_empty() {
+            continue;
+        }
+
+        let recorded = in_flight.record_heartbeats(heartbeats, |heartbeats| {
+            let conn = connection_pool.get().map_err(|e| e.to_string())?;
+            storage::record_heartbeats(&conn, heartbeats).map_err(|e| e.to_string())
+        });
+        drop(in_flight);
+        if let Err(e) = recorded {
+            eprintln!("Failed to record heartbeats: {}", e);
+        }
+    }
+}
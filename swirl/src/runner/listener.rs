@@ -0,0 +1,195 @@
+//! Waking the runner as soon as a job is enqueued, using Postgres'
+//! `LISTEN`/`NOTIFY`.
+//!
+//! Diesel can't wait for notifications, so the listener keeps a libpq
+//! connection of its own on a separate thread. A trigger on `background_jobs`
+//! sends a notification on the `swirl_jobs` channel whenever a job is
+//! enqueued which can run right away, and the listener tells the runner to
+//! poll. If the connection is lost, the runner keeps polling on its interval
+//! while the listener reconnects.
+
+use pq_sys::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::control::RunnerHandle;
+
+/// The channel notifications are sent on when a job is enqueued
+const CHANNEL: &str = "swirl_jobs";
+
+/// The `application_name` of the listener's connection
+const APPLICATION_NAME: &str = "swirl-listener";
+
+/// How often the listener checks whether the runner has been dropped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before reconnecting after the connection fails. This is
+/// doubled after each failure in a row.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Stops the listener's thread when dropped
+pub(super) struct Listener {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+pub(super) fn spawn(database_url: String, handle: RunnerHandle) -> Listener {
+    let stopped = Arc::new(AtomicBool::new(false));
+    let listener = Listener {
+        stopped: Arc::clone(&stopped),
+    };
+    thread::Builder::new()
+        .name("swirl-listener".into())
+        .spawn(move || listen(&database_url, &handle, &stopped))
+        .expect("Failed to spawn listener thread");
+    listener
+}
+
+fn listen(database_url: &str, handle: &RunnerHandle, stopped: &AtomicBool) {
+    let mut delay = RECONNECT_DELAY;
+    while !stopped.load(Ordering::SeqCst) {
+        match Connection::listen(database_url) {
+            Ok(conn) => {
+                delay = RECONNECT_DELAY;
+                // Jobs enqueued while the listener was connecting were not
+                // announced
+                handle.poll_now();
+                if let Err(e) = conn.wait_for_notifications(handle, stopped) {
+                    eprintln!("Lost the connection listening for jobs: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to listen for jobs: {}", e),
+        }
+        if !stopped.load(Ordering::SeqCst) {
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+/// A libpq connection which is listening on [`CHANNEL`]
+struct Connection(*mut PGconn);
+
+impl Connection {
+    fn listen(database_url: &str) -> Result<Self, String> {
+        let database_url = CString::new(database_url).map_err(|e| e.to_string())?;
+        let keywords = [
+            b"dbname\0".as_ptr().cast(),
+            b"application_name\0".as_ptr().cast(),
+            ptr::null(),
+        ];
+        let application_name = CString::new(APPLICATION_NAME).unwrap();
+        let values = [
+            database_url.as_ptr(),
+            application_name.as_ptr(),
+            ptr::null(),
+        ];
+        // Safety: both arrays are null terminated, and outlive the call
+        let conn = Self(unsafe { PQconnectdbParams(keywords.as_ptr(), values.as_ptr(), 1) });
+        if conn.0.is_null() {
+            return Err("Out of memory".into());
+        }
+        // Safety: `conn.0` is a connection returned by libpq
+        if unsafe { PQstatus(conn.0) } != CONNECTION_OK {
+            return Err(conn.error_message());
+        }
+
+        let query = CString::new(format!("LISTEN {}", CHANNEL)).unwrap();
+        // Safety: the result is freed before it goes out of scope
+        unsafe {
+            let result = PQexec(conn.0, query.as_ptr());
+            let status = PQresultStatus(result);
+            PQclear(result);
+            if status != PGRES_COMMAND_OK {
+                return Err(conn.error_message());
+            }
+        }
+        Ok(conn)
+    }
+
+    /// Tells the runner to poll whenever a notification arrives, until
+    /// `stopped` is set or the connection fails
+    fn wait_for_notifications(
+        &self,
+        handle: &RunnerHandle,
+        stopped: &AtomicBool,
+    ) -> Result<(), String> {
+        // Safety: `self.0` is a connection returned by libpq
+        let socket = unsafe { PQsocket(self.0) };
+        if socket < 0 {
+            return Err(self.error_message());
+        }
+        let mut poll_fd = libc::pollfd {
+            fd: socket,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        while !stopped.load(Ordering::SeqCst) {
+            let timeout = STOP_CHECK_INTERVAL.as_millis() as libc::c_int;
+            // Safety: `poll_fd` is a single valid `pollfd`
+            if unsafe { libc::poll(&mut poll_fd, 1, timeout) } < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error.to_string());
+            }
+            if poll_fd.revents == 0 {
+                continue;
+            }
+            // Safety: `self.0` is a connection returned by libpq
+            if unsafe { PQconsumeInput(self.0) } == 0 {
+                return Err(self.error_message());
+            }
+            if self.take_notifications() > 0 {
+                handle.poll_now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the notifications which have been received, returning how
+    /// many there were
+    fn take_notifications(&self) -> usize {
+        let mut count = 0;
+        loop {
+            // Safety: each notification is freed once it has been counted
+            unsafe {
+                let notification = PQnotifies(self.0);
+                if notification.is_null() {
+                    return count;
+                }
+                PQfreemem(notification.cast());
+            }
+            count += 1;
+        }
+    }
+
+    fn error_message(&self) -> String {
+        // Safety: libpq returns a null terminated string owned by the
+        // connection, which is copied before the connection is used again
+        unsafe { CStr::from_ptr(PQerrorMessage(self.0) as *const c_char) }
+            .to_string_lossy()
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Safety: `self.0` is a connection returned by libpq, which is not
+        // used again
+        unsafe { PQfinish(self.0) }
+    }
+}
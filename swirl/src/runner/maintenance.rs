@@ -0,0 +1,48 @@
+//! Pruning old jobs from the runner's loop, so the tables don't grow without
+//! bound on queues with a lot of churn.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::storage::TableStats;
+
+pub(super) type MaintenanceCallback = Arc<dyn Fn(&MaintenanceReport) + Send + Sync>;
+
+/// The most rows deleted by each statement by default
+pub(super) const DEFAULT_BATCH_SIZE: i64 = 1000;
+
+/// How often [`Runner::run`](crate::Runner::run) prunes old jobs, and which
+/// jobs it prunes.
+///
+/// See [`Builder::maintenance`](crate::Builder::maintenance).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenancePolicy {
+    /// How long to wait between runs
+    pub interval: Duration,
+    /// How long jobs are kept after they are marked as dead, or `None` to
+    /// keep them until they are retried or deleted
+    pub dead_job_retention: Option<Duration>,
+    /// The most rows deleted by each statement
+    pub batch_size: i64,
+}
+
+impl Default for MaintenancePolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            dead_job_retention: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// What [`Runner::run_maintenance`](crate::Runner::run_maintenance) did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// How many dead jobs were deleted
+    pub dead_jobs_pruned: usize,
+    /// How many archived jobs were deleted
+    pub archived_jobs_pruned: usize,
+    /// The size of each of swirl's tables after pruning
+    pub tables: Vec<TableStats>,
+}
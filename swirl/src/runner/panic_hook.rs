@@ -0,0 +1,72 @@
+//! A panic hook which captures the location and backtrace of panics that
+//! occur while a job is running.
+//!
+//! The hook is installed once per process, and only captures panics which
+//! happen inside of [`catch_unwind`]. All other panics are passed to whichever
+//! hook was installed before it.
+
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::panic::{self, UnwindSafe};
+use std::sync::Once;
+
+static INSTALL_HOOK: Once = Once::new();
+
+thread_local! {
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
+/// A panic caught by [`catch_unwind`]
+pub struct CaughtPanic {
+    /// The value the job panicked with
+    pub payload: Box<dyn Any + Send + 'static>,
+    /// The location and backtrace of the panic, if our hook saw it
+    pub details: Option<PanicDetails>,
+}
+
+pub struct PanicDetails {
+    pub location: Option<String>,
+    /// Only present if backtraces are enabled through `RUST_BACKTRACE` or
+    /// `RUST_LIB_BACKTRACE`
+    pub backtrace: Option<Backtrace>,
+}
+
+/// Invokes a closure, capturing the cause of an unwinding panic if one occurs.
+///
+/// Panics which occur inside the closure are not printed by the default panic
+/// hook, as they are reported through the returned error instead.
+pub fn catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, CaughtPanic> {
+    install_hook();
+
+    let was_catching = CATCHING_PANICS.with(|c| c.replace(true));
+    let result = panic::catch_unwind(f);
+    CATCHING_PANICS.with(|c| c.set(was_catching));
+
+    result.map_err(|payload| CaughtPanic {
+        payload,
+        details: LAST_PANIC.with(|p| p.borrow_mut().take()),
+    })
+}
+
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING_PANICS.with(Cell::get) {
+                let backtrace = Backtrace::capture();
+                let details = PanicDetails {
+                    location: info.location().map(ToString::to_string),
+                    backtrace: match backtrace.status() {
+                        BacktraceStatus::Captured => Some(backtrace),
+                        _ => None,
+                    },
+                };
+                LAST_PANIC.with(|p| *p.borrow_mut() = Some(details));
+            } else {
+                previous_hook(info);
+            }
+        }));
+    });
+}
@@ -0,0 +1,81 @@
+//! Token buckets for job types with a [`RateLimit`], so runners skip those
+//! types while they have started as many jobs as their limit allows.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::storage::BackgroundJob;
+use crate::RateLimit;
+
+#[derive(Default)]
+pub(super) struct RateLimits {
+    buckets: Mutex<HashMap<&'static str, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit) -> Self {
+        Self {
+            tokens: f64::from(limit.jobs()),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit) {
+        let now = Instant::now();
+        let period = limit.period().as_secs_f64();
+        let jobs = f64::from(limit.jobs());
+        let refilled = if period > 0.0 {
+            now.duration_since(self.refilled_at).as_secs_f64() / period * jobs
+        } else {
+            jobs
+        };
+        self.tokens = (self.tokens + refilled).min(jobs);
+        self.refilled_at = now;
+    }
+}
+
+impl RateLimits {
+    /// Claims a job using `claim`, which is given the job types with no
+    /// tokens left to skip, and takes a token for the job it claims.
+    ///
+    /// Workers take turns claiming jobs while any type is limited, so two of
+    /// them can't take the last token of a type at once.
+    pub(super) fn claim<E, F>(
+        &self,
+        limits: &[(&'static str, RateLimit)],
+        claim: F,
+    ) -> Result<Option<BackgroundJob>, E>
+    where
+        F: FnOnce(&[String]) -> Result<Option<BackgroundJob>, E>,
+    {
+        if limits.is_empty() {
+            return claim(&[]);
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let limited = limits
+            .iter()
+            .filter(|&&(job_type, limit)| {
+                let bucket = buckets
+                    .entry(job_type)
+                    .or_insert_with(|| Bucket::full(limit));
+                bucket.refill(limit);
+                bucket.tokens < 1.0
+            })
+            .map(|(job_type, _)| job_type.to_string())
+            .collect::<Vec<_>>();
+        let job = claim(&limited)?;
+        if let Some(job) = &job {
+            if let Some(bucket) = buckets.get_mut(&*job.job_type) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(job)
+    }
+}
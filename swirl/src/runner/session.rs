@@ -0,0 +1,83 @@
+//! Session settings applied to the connections used by a job while it runs,
+//! such as `application_name` and `statement_timeout`.
+
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Nullable, Text};
+use std::error::Error;
+use std::ops::Deref;
+use std::time::Duration;
+
+use crate::db::DieselPoolObj;
+use crate::errors::PerformError;
+
+sql_function!(fn set_config(name: Text, value: Text, is_local: Bool) -> Text);
+sql_function!(fn current_setting(name: Text, missing_ok: Bool) -> Nullable<Text>);
+
+/// The application name used for connections while a job is running
+pub(super) fn application_name(job_type: &str, job_id: i64) -> String {
+    format!("swirl:{}:{}", job_type, job_id)
+}
+
+/// The value of `statement_timeout` for the given duration
+pub(super) fn statement_timeout(timeout: Duration) -> String {
+    format!("{}ms", timeout.as_millis())
+}
+
+/// Sets a setting until the end of the current transaction
+pub(super) fn set_local(conn: &PgConnection, name: &str, value: &str) -> QueryResult<()> {
+    diesel::select(set_config(name, value, true)).execute(conn)?;
+    Ok(())
+}
+
+/// Wraps the connection pool given to a job. Connections checked out from it
+/// have the given settings applied, and restored when they are returned.
+pub(super) struct ConfiguredPool<'a> {
+    pub(super) inner: &'a dyn DieselPoolObj,
+    pub(super) settings: Vec<(String, String)>,
+}
+
+impl DieselPoolObj for ConfiguredPool<'_> {
+    fn get(&self) -> Result<Box<dyn Deref<Target = PgConnection> + '_>, Box<dyn Error>> {
+        let conn = self.inner.get()?;
+        let mut previous = Vec::with_capacity(self.settings.len());
+        for (name, value) in &self.settings {
+            previous.push((
+                name.as_str(),
+                diesel::select(current_setting(name, true)).get_result(&**conn)?,
+            ));
+            diesel::select(set_config(name, value, false)).execute(&**conn)?;
+        }
+        Ok(Box::new(ConfiguredConnection { conn, previous }))
+    }
+
+    fn with_connection(
+        &self,
+        f: &dyn Fn(&PgConnection) -> Result<(), PerformError>,
+    ) -> Result<(), PerformError> {
+        let conn = self.get()?;
+        f(&conn)
+    }
+}
+
+struct ConfiguredConnection<'a> {
+    conn: Box<dyn Deref<Target = PgConnection> + 'a>,
+    /// Custom settings which weren't set before are restored as empty
+    previous: Vec<(&'a str, Option<String>)>,
+}
+
+impl Deref for ConfiguredConnection<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        &self.conn
+    }
+}
+
+impl Drop for ConfiguredConnection<'_> {
+    fn drop(&mut self) {
+        for (name, value) in &self.previous {
+            let value = value.as_deref().unwrap_or_default();
+            let _ = diesel::select(set_config(*name, value, false)).execute(&**self.conn);
+        }
+    }
+}
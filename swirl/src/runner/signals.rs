@@ -0,0 +1,73 @@
+//! Shutting the runner down gracefully when the process is asked to stop.
+//!
+//! Very little can safely be done in a signal handler, so the handler only
+//! sets a flag. Each runner which handles signals has a thread which checks
+//! the flag, and shuts the runner down through its handle once it is set.
+//! The handler also restores the default handling of both signals, so a
+//! second signal stops a process whose jobs won't finish.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+use super::control::RunnerHandle;
+
+/// The signals which shut runners down
+const SIGNALS: [libc::c_int; 2] = [libc::SIGTERM, libc::SIGINT];
+
+/// How often the flag set by the handler is checked
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set once one of [`SIGNALS`] has been received
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+static INSTALL: Once = Once::new();
+
+/// Stops the watcher's thread when dropped
+pub(super) struct SignalWatcher {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for SignalWatcher {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Installs the signal handlers, if they haven't been already, and shuts the
+/// runner down with `drain_timeout` once a signal is received
+pub(super) fn watch(handle: RunnerHandle, drain_timeout: Duration) -> SignalWatcher {
+    INSTALL.call_once(|| {
+        for &signal in &SIGNALS {
+            let handler = handle_signal as extern "C" fn(libc::c_int);
+            unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+        }
+    });
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let watcher = SignalWatcher {
+        stopped: Arc::clone(&stopped),
+    };
+    thread::Builder::new()
+        .name("swirl-signals".into())
+        .spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if RECEIVED.load(Ordering::SeqCst) {
+                    handle.shutdown(drain_timeout);
+                    return;
+                }
+                thread::sleep(CHECK_INTERVAL);
+            }
+        })
+        .expect("Failed to spawn signal watcher thread");
+    watcher
+}
+
+extern "C" fn handle_signal(_: libc::c_int) {
+    RECEIVED.store(true, Ordering::SeqCst);
+    for &signal in &SIGNALS {
+        // `signal` is async-signal-safe
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
@@ -0,0 +1,118 @@
+//! Limits on how many of the runner's threads can run jobs from each queue,
+//! or of each job type, so a backlog of one can't take every thread.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::storage::BackgroundJob;
+
+#[derive(Default)]
+pub(super) struct Slots {
+    /// How many threads each queue may use. Queues without a limit can use
+    /// every thread.
+    queue_limits: HashMap<String, usize>,
+    running: Mutex<Running>,
+}
+
+/// How many threads are running a job from each limited queue, and of each
+/// limited job type
+#[derive(Default)]
+struct Running {
+    queues: HashMap<String, usize>,
+    job_types: HashMap<String, usize>,
+}
+
+/// The threads taken by a job from a limited queue, or of a limited job type,
+/// which are given back when this is dropped
+pub(super) struct Slot {
+    slots: Arc<Slots>,
+    queue: Option<String>,
+    job_type: Option<String>,
+}
+
+impl Slots {
+    pub(super) fn new(queue_limits: HashMap<String, usize>) -> Self {
+        Self {
+            queue_limits,
+            running: Mutex::default(),
+        }
+    }
+
+    /// Claims a job using `claim`, which is given the queues and the job
+    /// types which have no threads left to skip. Job types are limited by
+    /// `job_type_limits`, since job types can be registered after the runner
+    /// is built.
+    ///
+    /// Workers take turns claiming jobs while anything is limited, so two of
+    /// them can't take the last thread of a queue or job type at once. The
+    /// returned slot should be held until the job has finished.
+    pub(super) fn claim<E, F>(
+        self: &Arc<Self>,
+        job_type_limits: &[(&'static str, usize)],
+        claim: F,
+    ) -> Result<Option<(BackgroundJob, Option<Slot>)>, E>
+    where
+        F: FnOnce(&[String], &[String]) -> Result<Option<BackgroundJob>, E>,
+    {
+        if self.queue_limits.is_empty() && job_type_limits.is_empty() {
+            return Ok(claim(&[], &[])?.map(|job| (job, None)));
+        }
+
+        let mut running = self.running.lock().unwrap();
+        let full_queues = self
+            .queue_limits
+            .iter()
+            .filter(|&(queue, &limit)| count(&running.queues, queue) >= limit)
+            .map(|(queue, _)| queue.clone())
+            .collect::<Vec<_>>();
+        let full_job_types = job_type_limits
+            .iter()
+            .filter(|&&(job_type, limit)| count(&running.job_types, job_type) >= limit)
+            .map(|&(job_type, _)| job_type.to_owned())
+            .collect::<Vec<_>>();
+        let job = match claim(&full_queues, &full_job_types)? {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        let queue = if self.queue_limits.contains_key(&job.queue) {
+            *running.queues.entry(job.queue.clone()).or_insert(0) += 1;
+            Some(job.queue.clone())
+        } else {
+            None
+        };
+        let job_type = if job_type_limits.iter().any(|&(t, _)| t == job.job_type) {
+            *running.job_types.entry(job.job_type.clone()).or_insert(0) += 1;
+            Some(job.job_type.clone())
+        } else {
+            None
+        };
+        let slot = if queue.is_some() || job_type.is_some() {
+            Some(Slot {
+                slots: Arc::clone(self),
+                queue,
+                job_type,
+            })
+        } else {
+            None
+        };
+        Ok(Some((job, slot)))
+    }
+}
+
+fn count(running: &HashMap<String, usize>, key: &str) -> usize {
+    running.get(key).copied().unwrap_or(0)
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut running = self.slots.running.lock().unwrap();
+        release(&mut running.queues, self.queue.as_deref());
+        release(&mut running.job_types, self.job_type.as_deref());
+    }
+}
+
+fn release(running: &mut HashMap<String, usize>, key: Option<&str>) {
+    if let Some(count) = key.and_then(|key| running.get_mut(key)) {
+        *count = count.saturating_sub(1);
+    }
+}
@@ -0,0 +1,73 @@
+//! Reporting jobs which have been running for longer than expected, while they
+//! are still running.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::drain::InFlight;
+use crate::lifecycle::{JobMetadata, LifecycleEvent, Listeners};
+
+pub(super) type SlowJobCallback = Arc<dyn Fn(&JobMetadata, Duration) + Send + Sync>;
+
+/// How long each type of job can run before it is considered slow
+#[derive(Default)]
+pub(super) struct SlowJobThresholds {
+    pub(super) by_job_type: HashMap<String, Duration>,
+    pub(super) default: Option<Duration>,
+}
+
+impl SlowJobThresholds {
+    pub(super) fn for_job_type(&self, job_type: &str) -> Option<Duration> {
+        self.by_job_type.get(job_type).copied().or(self.default)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_job_type.is_empty() && self.default.is_none()
+    }
+}
+
+/// Starts a thread which reports slow jobs, if any thresholds are configured.
+///
+/// The thread exits once the runner and all of its workers have been dropped.
+pub(super) fn spawn_watchdog(
+    in_flight: &Arc<InFlight>,
+    thresholds: SlowJobThresholds,
+    listeners: Arc<Listeners>,
+    callback: Option<SlowJobCallback>,
+) {
+    if thresholds.is_empty() {
+        return;
+    }
+
+    let in_flight = Arc::downgrade(in_flight);
+    thread::Builder::new()
+        .name("swirl-slow-jobs".into())
+        .spawn(move || watch(in_flight, thresholds, listeners, callback))
+        .expect("Failed to spawn slow job thread");
+}
+
+fn watch(
+    in_flight: Weak<InFlight>,
+    thresholds: SlowJobThresholds,
+    listeners: Arc<Listeners>,
+    callback: Option<SlowJobCallback>,
+) {
+    while let Some(in_flight) = in_flight.upgrade() {
+        let slow_jobs = in_flight.slow_jobs(&thresholds, Duration::from_secs(1));
+        drop(in_flight);
+
+        for (job, elapsed) in slow_jobs {
+            if let Some(callback) = &callback {
+                callback(&job, elapsed);
+            }
+            let threshold = thresholds.for_job_type(&job.job_type).unwrap_or_default();
+            listeners.emit(|| LifecycleEvent::Slow {
+                job,
+                elapsed_ms: elapsed.as_millis() as u64,
+                threshold_ms: threshold.as_millis() as u64,
+            });
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! A snapshot of the queue and of a runner, for health checks and dashboards
+//! which would otherwise query the table themselves.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The state of the queue, and of the runner it was taken from.
+///
+/// See [`Runner::stats`](crate::Runner::stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunnerStats {
+    /// The jobs on each queue which haven't been marked as dead, including
+    /// jobs which are running or waiting to be retried. Queues without any
+    /// such jobs are left out.
+    pub pending: HashMap<String, i64>,
+    /// Jobs which have failed at least once, including those which have been
+    /// marked as dead
+    pub failed: i64,
+    /// How long the job which has waited longest to run has been ready to
+    /// run, or `None` if no jobs are waiting. Jobs which are running, marked
+    /// as dead, or scheduled for later are not waiting.
+    pub oldest_pending_age: Option<Duration>,
+    /// How many of this runner's threads are running a job
+    pub active_threads: usize,
+    /// How many times this runner has finished with a job since it was built,
+    /// whether the job succeeded, failed, or expired
+    pub processed: u64,
+}
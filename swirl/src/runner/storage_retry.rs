@@ -0,0 +1,86 @@
+//! Retrying the runner's queries when Postgres aborts them because of a
+//! serialization failure or a deadlock.
+//!
+//! Each attempt runs in a savepoint, so a failed attempt can be rolled back
+//! without losing the lock on the job, or anything else the transaction has
+//! done.
+
+use diesel::connection::Connection;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::thread;
+use std::time::Duration;
+
+use crate::query_hook::{timed, QueryHook, StorageQuery};
+
+/// How the runner retries its queries when they fail with a serialization
+/// failure or a deadlock.
+///
+/// See [`Builder::storage_retries`](crate::Builder::storage_retries).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageRetryPolicy {
+    /// How many times a query is tried before its error is returned. A value
+    /// of 1 disables retries.
+    pub max_attempts: u32,
+    /// How long to wait before the first retry. This is doubled before each
+    /// retry after it.
+    pub backoff: Duration,
+}
+
+impl Default for StorageRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+impl StorageRetryPolicy {
+    /// Never retry queries
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::default(),
+        }
+    }
+}
+
+/// Whether an error is likely to go away if the query is tried again
+fn is_transient(error: &DieselError) -> bool {
+    match error {
+        DieselError::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => true,
+        // Diesel doesn't expose the SQLSTATE of other errors
+        DieselError::DatabaseError(_, info) => info.message().starts_with("deadlock detected"),
+        _ => false,
+    }
+}
+
+/// Runs `f` in a savepoint, retrying it according to `policy` if it fails
+/// with a transient error
+pub(super) fn retrying<T, F>(
+    conn: &PgConnection,
+    policy: StorageRetryPolicy,
+    hook: Option<&dyn QueryHook>,
+    query: StorageQuery,
+    f: F,
+) -> QueryResult<T>
+where
+    F: Fn() -> QueryResult<T>,
+{
+    let mut attempt = 1;
+    let mut backoff = policy.backoff;
+    loop {
+        match timed(hook, query, || conn.transaction(&f)) {
+            Err(e) if attempt < policy.max_attempts && is_transient(&e) => {
+                if let Some(hook) = hook {
+                    hook.on_retry(query, attempt, &e);
+                }
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
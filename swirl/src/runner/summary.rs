@@ -0,0 +1,84 @@
+//! Counting the jobs handled by a single call to
+//! [`Runner::run_all_pending_jobs`](crate::Runner::run_all_pending_jobs) or
+//! [`Runner::run_pending_jobs_until_empty`](crate::Runner::run_pending_jobs_until_empty).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The jobs handled by a call to
+/// [`Runner::run_all_pending_jobs`](crate::Runner::run_all_pending_jobs).
+///
+/// That function returns once every job has started, so jobs which were still
+/// running when it returned are only counted as claimed.
+/// [`Runner::run_pending_jobs_until_empty`](crate::Runner::run_pending_jobs_until_empty)
+/// waits for every job to finish, so none of the jobs it counts are running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    /// Jobs which were claimed from the queue
+    pub claimed: usize,
+    /// Jobs which ran successfully
+    pub succeeded: usize,
+    /// Jobs which returned an error or panicked, including those which were
+    /// marked as dead or cancelled
+    pub failed: usize,
+    /// Jobs which were claimed after their deadline, and were not run
+    pub skipped: usize,
+    /// Jobs which [snoozed](crate::PerformError::Snooze), and will be run
+    /// again later
+    pub snoozed: usize,
+}
+
+impl RunSummary {
+    /// Jobs which were claimed, but had not finished yet
+    pub fn running(&self) -> usize {
+        self.claimed - self.succeeded - self.failed - self.skipped - self.snoozed
+    }
+}
+
+/// Shared between the workers queued by one call to `run_all_pending_jobs`,
+/// or by every round of `run_pending_jobs_until_empty`
+#[derive(Default)]
+pub(super) struct Tally {
+    claimed: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    skipped: AtomicUsize,
+    snoozed: AtomicUsize,
+}
+
+impl Tally {
+    pub(super) fn claimed(&self) {
+        self.claimed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn succeeded(&self) {
+        self.succeeded.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn snoozed(&self) {
+        self.snoozed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn summary(&self) -> RunSummary {
+        // Finished jobs are read first, so a job finishing concurrently is
+        // never counted as finished without also being counted as claimed
+        let succeeded = self.succeeded.load(Ordering::SeqCst);
+        let failed = self.failed.load(Ordering::SeqCst);
+        let skipped = self.skipped.load(Ordering::SeqCst);
+        let snoozed = self.snoozed.load(Ordering::SeqCst);
+        RunSummary {
+            claimed: self.claimed.load(Ordering::SeqCst),
+            succeeded,
+            failed,
+            skipped,
+            snoozed,
+        }
+    }
+}
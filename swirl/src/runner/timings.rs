@@ -0,0 +1,82 @@
+//! Timings of the runner's own work, as opposed to the jobs it runs.
+//!
+//! A pool which is too small shows up as time spent waiting for connections,
+//! and contention on the queue shows up as slow claims, even when every job
+//! runs quickly.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long the runner has spent on each part of looking for jobs since it
+/// was built.
+///
+/// See [`Runner::loop_timings`](crate::Runner::loop_timings).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoopTimings {
+    /// Calls to [`Runner::run_all_pending_jobs`](crate::Runner::run_all_pending_jobs),
+    /// including those made by [`Runner::run`](crate::Runner::run)
+    pub polls: Timing,
+    /// Waiting for a connection from the pool before claiming a job
+    pub connection_waits: Timing,
+    /// The query which finds and locks the next job, whether or not one was
+    /// found
+    pub claims: Timing,
+}
+
+/// The durations of one kind of operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// How many times the operation happened
+    pub count: u64,
+    /// How long all of them took together
+    pub total: Duration,
+    /// How long the slowest one took
+    pub max: Duration,
+}
+
+impl Timing {
+    /// How long the operation took on average, or `None` if it never
+    /// happened
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(Duration::from_nanos(
+                (self.total.as_nanos() / u128::from(self.count)) as u64,
+            ))
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Timings(Mutex<LoopTimings>);
+
+impl Timings {
+    pub(super) fn snapshot(&self) -> LoopTimings {
+        *self.0.lock().unwrap()
+    }
+
+    pub(super) fn poll(&self, duration: Duration) {
+        self.0.lock().unwrap().polls.record(duration);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("swirl_poll_duration_seconds").record(duration.as_secs_f64());
+    }
+
+    pub(super) fn connection_wait(&self, duration: Duration) {
+        self.0.lock().unwrap().connection_waits.record(duration);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("swirl_connection_wait_seconds").record(duration.as_secs_f64());
+    }
+
+    pub(super) fn claim(&self, duration: Duration) {
+        self.0.lock().unwrap().claims.record(duration);
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("swirl_claim_duration_seconds").record(duration.as_secs_f64());
+    }
+}
@@ -0,0 +1,22 @@
+//! Errors updating jobs once they have run. These happen after the worker
+//! has reported that it is working, and often after the call to
+//! `run_all_pending_jobs` which started the job has returned, so they are
+//! kept for the runner to report the next time it is called.
+
+use diesel::result::Error as DieselError;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub(super) struct UpdateErrors(Mutex<VecDeque<DieselError>>);
+
+impl UpdateErrors {
+    pub(super) fn push(&self, error: DieselError) {
+        self.0.lock().unwrap().push_back(error);
+    }
+
+    /// The oldest error which hasn't been reported yet
+    pub(super) fn take(&self) -> Option<DieselError> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
@@ -0,0 +1,360 @@
+//! Schedules for recurring jobs.
+//!
+//! Each job type can have one schedule, written as a cron expression with a
+//! leading seconds field. The expression is evaluated in the schedule's time
+//! zone, so a job scheduled for `0 0 9 * * *` in `Europe/Berlin` is planned
+//! for 9am local time whether or not daylight saving time is in effect.
+//!
+//! Runners enqueue each job whose planned time has come, as long as the job
+//! is in their [`Registry`](crate::Registry). When several runners share a
+//! database, only one of them enqueues each run. Runs which were missed while
+//! no runner was running are not made up for, except for the most recent
+//! one. Jobs can be given a schedule in code with
+//! [`Registry::register_recurring`](crate::Registry::register_recurring).
+//!
+//! Schedules can be paused indefinitely, or until a given time. Use
+//! [`upcoming`] to check when a job will run after changing its schedule:
+//!
+//! ```ignore
+//! schedule::set(&conn, "send_digest", "0 0 9 * * Mon-Fri", "America/New_York")?;
+//! for run_at in schedule::upcoming(&conn, "send_digest", 5)? {
+//!     println!("{}", run_at);
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use diesel::dsl::now;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+#[cfg(feature = "runner")]
+use diesel::sql_types::Text;
+use diesel::{delete, insert_into, update};
+#[cfg(feature = "runner")]
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::schema::background_job_schedules;
+#[cfg(feature = "runner")]
+use crate::storage::{self, NewJob};
+
+/// The schedule of a recurring job
+#[derive(Queryable, Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// The type of job which is run on this schedule
+    pub job_type: String,
+    /// The cron expression the job is run on, including seconds
+    pub cron: String,
+    /// The name of the time zone the cron expression is evaluated in, such as
+    /// `UTC` or `Europe/Berlin`
+    pub timezone: String,
+    /// Whether the schedule is paused until it is resumed
+    pub paused: bool,
+    /// Runs up to and including this time are skipped
+    pub paused_until: Option<DateTime<Utc>>,
+    /// When the schedule was last changed
+    pub updated_at: DateTime<Utc>,
+    /// The planned run a runner most recently enqueued the job for
+    pub last_enqueued_at: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    /// The next `n` times this job is planned to run after `after`.
+    ///
+    /// A schedule which is paused indefinitely has no upcoming runs.
+    pub fn upcoming_after(
+        &self,
+        after: DateTime<Utc>,
+        n: usize,
+    ) -> Result<Vec<DateTime<Utc>>, ScheduleError> {
+        let (cron, timezone) = parse(&self.cron, &self.timezone)?;
+        if self.paused {
+            return Ok(Vec::new());
+        }
+        let after = match self.paused_until {
+            Some(paused_until) if paused_until > after => paused_until,
+            _ => after,
+        };
+        Ok(cron
+            .after(&after.with_timezone(&timezone))
+            .take(n)
+            .map(|run_at| run_at.with_timezone(&Utc))
+            .collect())
+    }
+
+    /// The most recent planned run up to `time` which has not been enqueued.
+    ///
+    /// Runs planned before the schedule was last changed, or while it was
+    /// paused, are skipped.
+    #[cfg(feature = "runner")]
+    fn due_at(&self, time: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, ScheduleError> {
+        let (cron, timezone) = parse(&self.cron, &self.timezone)?;
+        if self.paused {
+            return Ok(None);
+        }
+        let after = [self.last_enqueued_at, self.paused_until]
+            .iter()
+            .flatten()
+            .fold(self.updated_at, |after, &time| after.max(time));
+        Ok(cron
+            .after(&time.with_timezone(&timezone))
+            .next_back()
+            .map(|run_at| run_at.with_timezone(&Utc))
+            .filter(|&run_at| run_at > after && run_at <= time))
+    }
+}
+
+fn parse(expression: &str, timezone: &str) -> Result<(cron::Schedule, Tz), ScheduleError> {
+    let cron = cron::Schedule::from_str(expression).map_err(|e| ScheduleError::InvalidCron {
+        expression: expression.into(),
+        message: e.to_string(),
+    })?;
+    let timezone = timezone
+        .parse::<Tz>()
+        .map_err(|_| ScheduleError::InvalidTimezone(timezone.into()))?;
+    Ok((cron, timezone))
+}
+
+/// Checks that a cron expression and time zone can be used in a schedule
+pub(crate) fn validate(cron: &str, timezone: &str) -> Result<(), ScheduleError> {
+    parse(cron, timezone).map(|_| ())
+}
+
+/// Create or replace the schedule for a job type.
+///
+/// Returns an error without changing anything if the cron expression or time
+/// zone are invalid. Changing a schedule does not resume it if it was paused.
+pub fn set(
+    conn: &PgConnection,
+    job_type: &str,
+    cron: &str,
+    timezone: &str,
+) -> Result<(), ScheduleError> {
+    use crate::schema::background_job_schedules::dsl;
+
+    parse(cron, timezone)?;
+    insert_into(background_job_schedules::table)
+        .values((
+            dsl::job_type.eq(job_type),
+            dsl::cron.eq(cron),
+            dsl::timezone.eq(timezone),
+        ))
+        .on_conflict(dsl::job_type)
+        .do_update()
+        .set((
+            dsl::cron.eq(cron),
+            dsl::timezone.eq(timezone),
+            dsl::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Create the schedule for a job type registered with
+/// [`Registry::register_recurring`](crate::Registry::register_recurring), or
+/// replace it if its expression or time zone have changed.
+///
+/// Every runner registers the same schedules when it starts, so a schedule
+/// which is unchanged is left alone instead of being restarted.
+#[cfg(feature = "runner")]
+pub(crate) fn register(
+    conn: &PgConnection,
+    job_type: &str,
+    cron: &str,
+    timezone: &str,
+) -> QueryResult<()> {
+    diesel::sql_query(
+        "INSERT INTO background_job_schedules (job_type, cron, timezone) \
+         VALUES ($1, $2, $3) \
+         ON CONFLICT (job_type) DO UPDATE \
+         SET cron = excluded.cron, timezone = excluded.timezone, updated_at = NOW() \
+         WHERE background_job_schedules.cron <> excluded.cron \
+         OR background_job_schedules.timezone <> excluded.timezone",
+    )
+    .bind::<Text, _>(job_type)
+    .bind::<Text, _>(cron)
+    .bind::<Text, _>(timezone)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Enqueues each job in `job_types`, a map of job types to the queue and
+/// priority they are enqueued with, which was planned to run by `time`. Returns how many jobs were
+/// enqueued.
+///
+/// Schedules which another runner is enqueueing are skipped.
+#[cfg(feature = "runner")]
+pub(crate) fn enqueue_due(
+    conn: &PgConnection,
+    job_types: &HashMap<&'static str, (&'static str, i16)>,
+    time: DateTime<Utc>,
+) -> QueryResult<usize> {
+    use crate::schema::background_job_schedules::dsl;
+
+    conn.transaction(|| {
+        let schedules = background_job_schedules::table
+            .filter(dsl::job_type.eq_any(job_types.keys().collect::<Vec<_>>()))
+            .filter(dsl::paused.eq(false))
+            .for_update()
+            .skip_locked()
+            .load::<Schedule>(conn)?;
+        let mut enqueued = 0;
+        for schedule in schedules {
+            // Schedules can only be saved with a valid expression
+            let due_at = match schedule.due_at(time) {
+                Ok(Some(due_at)) => due_at,
+                _ => continue,
+            };
+            let (queue, priority) = job_types[schedule.job_type.as_str()];
+            let data = serde_json::Value::Object(Default::default());
+            let job = NewJob::new(&schedule.job_type, queue, data, priority);
+            storage::insert_job(conn, &job)?;
+            update(background_job_schedules::table.find(&schedule.job_type))
+                .set(dsl::last_enqueued_at.eq(due_at))
+                .execute(conn)?;
+            enqueued += 1;
+        }
+        Ok(enqueued)
+    })
+}
+
+/// Load the schedule for a job type, if it has one
+pub fn get(conn: &PgConnection, job_type: &str) -> QueryResult<Option<Schedule>> {
+    background_job_schedules::table
+        .find(job_type)
+        .first(conn)
+        .optional()
+}
+
+/// Remove the schedule for a job type. Returns whether it had one.
+pub fn remove(conn: &PgConnection, job_type: &str) -> QueryResult<bool> {
+    let deleted = delete(background_job_schedules::table.find(job_type)).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Pause the schedule for a job type, until `until` if given, or until it is
+/// resumed otherwise.
+pub fn pause(
+    conn: &PgConnection,
+    job_type: &str,
+    until: Option<DateTime<Utc>>,
+) -> Result<(), ScheduleError> {
+    use crate::schema::background_job_schedules::dsl;
+
+    let updated = update(background_job_schedules::table.find(job_type))
+        .set((
+            dsl::paused.eq(until.is_none()),
+            dsl::paused_until.eq(until),
+            dsl::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+    found(updated, job_type)
+}
+
+/// Resume the schedule for a job type which was paused
+pub fn resume(conn: &PgConnection, job_type: &str) -> Result<(), ScheduleError> {
+    use crate::schema::background_job_schedules::dsl;
+
+    let updated = update(background_job_schedules::table.find(job_type))
+        .set((
+            dsl::paused.eq(false),
+            dsl::paused_until.eq(None::<DateTime<Utc>>),
+            dsl::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+    found(updated, job_type)
+}
+
+fn found(updated: usize, job_type: &str) -> Result<(), ScheduleError> {
+    if updated == 0 {
+        Err(ScheduleError::NotFound(job_type.into()))
+    } else {
+        Ok(())
+    }
+}
+
+/// The next `n` times a job type is planned to run, taking its time zone and
+/// any pause into account.
+///
+/// See [`Schedule::upcoming_after`].
+pub fn upcoming(
+    conn: &PgConnection,
+    job_type: &str,
+    n: usize,
+) -> Result<Vec<DateTime<Utc>>, ScheduleError> {
+    get(conn, job_type)?
+        .ok_or_else(|| ScheduleError::NotFound(job_type.into()))?
+        .upcoming_after(Utc::now(), n)
+}
+
+/// An error changing or evaluating a schedule
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScheduleError {
+    /// The cron expression could not be parsed
+    InvalidCron {
+        /// The expression which was given
+        expression: String,
+        /// Why it could not be parsed
+        message: String,
+    },
+
+    /// The time zone is not a known IANA time zone name
+    InvalidTimezone(String),
+
+    /// The job type does not have a schedule
+    NotFound(String),
+
+    /// The job type takes arguments, so runners can't enqueue it on a
+    /// schedule
+    TakesArguments(String),
+
+    /// An error occurred loading or saving the schedule
+    DatabaseError(DieselError),
+
+    /// A connection could not be retrieved from the
+    /// [`Client`](crate::Client)'s connection pool
+    NoDatabaseConnection(Box<dyn Error + Send + Sync>),
+}
+
+impl From<DieselError> for ScheduleError {
+    fn from(e: DieselError) -> Self {
+        ScheduleError::DatabaseError(e)
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::InvalidCron {
+                expression,
+                message,
+            } => write!(f, "Invalid cron expression `{}`: {}", expression, message),
+            ScheduleError::InvalidTimezone(timezone) => {
+                write!(f, "Unknown time zone `{}`", timezone)
+            }
+            ScheduleError::NotFound(job_type) => {
+                write!(f, "The job type `{}` does not have a schedule", job_type)
+            }
+            ScheduleError::TakesArguments(job_type) => write!(
+                f,
+                "The job type `{}` takes arguments, so it can't be run on a schedule",
+                job_type
+            ),
+            ScheduleError::DatabaseError(e) => e.fmt(f),
+            ScheduleError::NoDatabaseConnection(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ScheduleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScheduleError::DatabaseError(e) => Some(e),
+            ScheduleError::NoDatabaseConnection(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
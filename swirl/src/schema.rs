@@ -6,5 +6,169 @@ table! {
         retries -> Int4,
         last_retry -> Timestamp,
         created_at -> Timestamp,
+        queue -> Text,
+        dead_at -> Nullable<Timestamptz>,
+        deadline -> Nullable<Timestamptz>,
+        batch_id -> Nullable<Text>,
+        retry_at -> Nullable<Timestamptz>,
+        priority -> Int2,
+        binary_data -> Nullable<Bytea>,
+        unique_key -> Nullable<Text>,
+        last_error -> Nullable<Text>,
+        failed_at -> Nullable<Timestamptz>,
+        compressed -> Bool,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    background_job_attempts (id) {
+        id -> Int8,
+        job_id -> Int8,
+        job_type -> Text,
+        attempt -> Int4,
+        worker -> Text,
+        outcome -> Text,
+        error -> Nullable<Text>,
+        started_at -> Timestamptz,
+        duration_ms -> Int8,
+    }
+}
+
+table! {
+    background_job_schedules (job_type) {
+        job_type -> Text,
+        cron -> Text,
+        timezone -> Text,
+        paused -> Bool,
+        paused_until -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+        last_enqueued_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    background_job_audit_log (id) {
+        id -> Int8,
+        action -> Text,
+        actor -> Text,
+        reason -> Nullable<Text>,
+        job_id -> Nullable<Int8>,
+        job_type -> Nullable<Text>,
+        details -> Jsonb,
+        affected -> Int8,
+        performed_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_cancellations (job_id) {
+        job_id -> Int8,
+        requested_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_heartbeats (job_id) {
+        job_id -> Int8,
+        attempt -> Int4,
+        locked_by -> Text,
+        heartbeat_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_checkpoints (job_id) {
+        job_id -> Int8,
+        state -> Jsonb,
+        saved_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_dependencies (job_id, depends_on) {
+        job_id -> Int8,
+        depends_on -> Int8,
+    }
+}
+
+table! {
+    background_job_paused_queues (queue) {
+        queue -> Text,
+        paused_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_results (job_id) {
+        job_id -> Int8,
+        job_type -> Text,
+        result -> Jsonb,
+        completed_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_ledger (key) {
+        key -> Text,
+        job_id -> Int8,
+        job_type -> Text,
+        completed_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_batches (batch_id) {
+        batch_id -> Text,
+        succeeded -> Int8,
+        on_success -> Nullable<Int8>,
+        on_failure -> Nullable<Int8>,
+    }
+}
+
+table! {
+    background_job_failures (id) {
+        id -> Int8,
+        job_id -> Int8,
+        job_type -> Text,
+        error -> Text,
+        fingerprint -> Text,
+        failed_at -> Timestamptz,
+    }
+}
+
+table! {
+    background_job_outbox (id) {
+        id -> Int8,
+        topic -> Text,
+        payload -> Jsonb,
+        created_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    background_job_retry_budgets (job_type, window_start) {
+        job_type -> Text,
+        window_start -> Timestamptz,
+        retries -> Int4,
+    }
+}
+
+table! {
+    background_jobs_archive (id) {
+        id -> Int8,
+        job_type -> Text,
+        queue -> Text,
+        data -> Jsonb,
+        binary_data -> Nullable<Bytea>,
+        compressed -> Bool,
+        retries -> Int4,
+        batch_id -> Nullable<Text>,
+        enqueued_at -> Timestamptz,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Timestamptz,
     }
 }
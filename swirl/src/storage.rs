@@ -1,57 +1,866 @@
+//! Low level access to the job queue, for building custom runners.
+//!
+//! [`Runner`](crate::Runner) is built on these functions, and most
+//! applications should use it instead. They are useful when jobs need to be
+//! run from an existing event loop, or with scheduling that the runner does
+//! not support, while keeping the same locking behavior.
+//!
+//! A job is claimed by locking its row, and stays locked until the
+//! transaction it was claimed in ends. The job should be run, and
+//! [`complete`] or [`fail`] called, inside of that same transaction:
+//!
+//! ```ignore
+//! conn.transaction(|| {
+//!     if let Some(job) = storage::claim_one(&conn, None)? {
+//!         let perform = registry.get(&job.job_type).expect("unknown job type");
+//!         match perform.perform(job.data.clone(), &env, &pool) {
+//!             Ok(()) => storage::complete(&conn, job.id)?,
+//!             Err(e) => {
+//!                 storage::fail(&conn, &job, &e.to_string(), None);
+//!             }
+//!         }
+//!     }
+//!     Ok(())
+//! })
+//! ```
+//!
+//! If the process exits before the transaction commits, the lock is released
+//! and the job will be claimed again.
+//!
+//! The state of a job can be looked up with [`job_status`], and the jobs in
+//! the queue listed with [`list_jobs`]. Queues can be paused for every
+//! runner with [`pause_queue`]. Jobs which succeed can be kept in
+//! `background_jobs_archive` by calling [`archive`] instead of [`complete`].
+//! Old dead and archived jobs are deleted with [`prune_dead_jobs`] and
+//! [`prune_archive`].
+
+use chrono::{DateTime, Utc};
 use diesel::dsl::now;
+use diesel::dsl::sql;
+use diesel::dsl::{exists, select};
+use diesel::expression::SqlLiteral;
 use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::sql_types::{Bool, Integer, Interval};
-use diesel::{delete, insert_into, update};
+use diesel::sql_types::{
+    BigInt, Bool, Bytea, Double, Integer, Interval, Jsonb, Nullable, Text, Timestamptz,
+};
+use diesel::{delete, insert_into, sql_query, update};
 use serde_json;
+#[cfg(feature = "runner")]
+use std::time::Duration;
 
+use crate::backoff::Backoff;
+use crate::codec::CodecError;
+use crate::compression;
 use crate::errors::EnqueueError;
-use crate::schema::background_jobs;
-use crate::Job;
+#[cfg(feature = "runner")]
+use crate::schema::background_job_attempts;
+use crate::schema::{
+    background_job_cancellations, background_job_checkpoints, background_job_dependencies,
+    background_job_heartbeats, background_job_paused_queues, background_jobs,
+};
+use crate::{inline, Cancellation, Job, JobId};
+
+pub use self::maintenance::{prune_archive, prune_dead_jobs, table_stats, TableStats};
+pub use self::status::{
+    job_status, list_jobs, oldest_pending_job, queue_depths, JobFilter, JobStatus, QueuedJob,
+};
+
+mod maintenance;
+mod status;
 
-#[derive(Queryable, Identifiable, Debug, Clone)]
+/// A job which has been claimed from the queue
+#[derive(Queryable, QueryableByName, Identifiable, Debug, Clone)]
+#[table_name = "background_jobs"]
 pub struct BackgroundJob {
+    /// The id of the job
     pub id: i64,
+    /// The type of the job, used to look it up in the [`Registry`](crate::Registry)
     pub job_type: String,
+    /// The serialized arguments of the job
     pub data: serde_json::Value,
+    /// The queue the job is on
+    pub queue: String,
+    /// The number of times this job has previously been retried
+    pub retries: i32,
+    /// The time after which the job should not be run, if any
+    pub deadline: Option<DateTime<Utc>>,
+    /// The arguments of the job, if its type stores them with a
+    /// [`Codec`](crate::Codec). `data` is `null` when this is set, and the
+    /// job is run with [`PerformJob::perform_binary`](crate::PerformJob::perform_binary).
+    pub binary_data: Option<Vec<u8>>,
+    /// When the job was enqueued
+    #[column_name = "created_at"]
+    #[sql_type = "Timestamptz"]
+    pub enqueued_at: DateTime<Utc>,
+    /// The error the job last failed with, if it has failed
+    pub last_error: Option<String>,
+    /// When the job last failed, if it has
+    pub failed_at: Option<DateTime<Utc>>,
+    /// Whether the job's arguments were
+    /// [compressed](Job::COMPRESS_OVER), in which case they are in
+    /// `binary_data` until [`decompress`](Self::decompress) is called
+    pub compressed: bool,
+    /// When the job was last claimed to be run. For a job which was just
+    /// claimed, this is when it was claimed.
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the job last finished running without succeeding, if it has.
+    /// Jobs which succeed are removed from the queue.
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl BackgroundJob {
+    /// Decompress the job's arguments into `data`, if they were compressed
+    /// when it was enqueued. Does nothing if they weren't.
+    pub fn decompress(&mut self) -> Result<(), CodecError> {
+        if self.compressed {
+            let bytes = self.binary_data.as_deref().unwrap_or_default();
+            self.data = serde_json::from_slice(&compression::decompress(bytes)?)?;
+            self.binary_data = None;
+            self.compressed = false;
+        }
+        Ok(())
+    }
+
+    /// Whether the job was claimed after its deadline
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| deadline < Utc::now())
+    }
 }
 
 /// Enqueues a job to be run as soon as possible.
-pub fn enqueue_job<T: Job>(conn: &PgConnection, job: T) -> Result<(), EnqueueError> {
+pub(crate) fn enqueue_job<T: Job>(conn: &PgConnection, job: T) -> Result<JobId, EnqueueError> {
+    enqueue_job_with_deadline(conn, job, None)
+}
+
+/// Enqueues a job, unless a pending job of the same type has the same unique
+/// key. Returns whether the job was enqueued.
+pub(crate) fn enqueue_job_unique<T: Job>(
+    conn: &PgConnection,
+    job: T,
+) -> Result<bool, EnqueueError> {
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job).map(|_| true);
+    }
+    Ok(insert_job(conn, &NewJob::of(&job)?)?.is_some())
+}
+
+/// Enqueues a job which should not be run after `deadline`.
+pub(crate) fn enqueue_job_with_deadline<T: Job>(
+    conn: &PgConnection,
+    job: T,
+    deadline: Option<DateTime<Utc>>,
+) -> Result<JobId, EnqueueError> {
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job);
+    }
+    let job = NewJob {
+        deadline,
+        ..NewJob::of(&job)?
+    };
+    Ok(insert_or_find_job(conn, &job)?)
+}
+
+/// Enqueues a job which should not be run before `run_at`.
+pub(crate) fn enqueue_job_at<T: Job>(
+    conn: &PgConnection,
+    job: T,
+    run_at: DateTime<Utc>,
+) -> Result<JobId, EnqueueError> {
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job);
+    }
+    let job = NewJob {
+        run_at: Some(run_at),
+        ..NewJob::of(&job)?
+    };
+    Ok(insert_or_find_job(conn, &job)?)
+}
+
+/// Enqueues a job with a priority other than [`Job::PRIORITY`].
+pub(crate) fn enqueue_job_with_priority<T: Job>(
+    conn: &PgConnection,
+    job: T,
+    priority: i16,
+) -> Result<JobId, EnqueueError> {
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job);
+    }
+    let job = NewJob {
+        priority,
+        ..NewJob::of(&job)?
+    };
+    Ok(insert_or_find_job(conn, &job)?)
+}
+
+/// Enqueues a job, unless one with the same type and data is already
+/// pending. Returns whether the job was enqueued.
+pub(crate) fn enqueue_job_unless_pending<T: Job>(
+    conn: &PgConnection,
+    job: T,
+) -> Result<bool, EnqueueError> {
+    use crate::schema::background_jobs::dsl::*;
+
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job).map(|_| true);
+    }
+    let new_job = NewJob::of(&job)?;
+    conn.transaction(|| {
+        // Two callers enqueueing the same job at the same time would both
+        // see that it isn't pending, so they take turns instead
+        sql_query(
+            "SELECT pg_advisory_xact_lock(hashtext($1 || $2::text || COALESCE(md5($3), '')))",
+        )
+        .bind::<Text, _>(T::JOB_TYPE)
+        .bind::<Jsonb, _>(&new_job.data)
+        .bind::<Nullable<Bytea>, _>(&new_job.binary_data)
+        .execute(conn)?;
+        let pending = select(exists(
+            background_jobs
+                .filter(job_type.eq(T::JOB_TYPE))
+                .filter(data.eq(&new_job.data))
+                .filter(binary_data.is_not_distinct_from(&new_job.binary_data))
+                .filter(dead_at.is_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        if !pending {
+            insert_job(conn, &new_job)?;
+        }
+        Ok(!pending)
+    })
+}
+
+/// Enqueues a job tagged with `batch_id`
+pub(crate) fn enqueue_job_in_batch<T: Job>(
+    conn: &PgConnection,
+    job: T,
+    batch_id: &str,
+) -> Result<JobId, EnqueueError> {
+    if let Some(inline) = inline::environment::<T>() {
+        return inline.perform(conn, job);
+    }
+    let job = NewJob {
+        batch_id: Some(batch_id),
+        ..NewJob::of(&job)?
+    };
+    Ok(insert_or_find_job(conn, &job)?)
+}
+
+/// Enqueues a job which is not run until the jobs `after` have succeeded.
+/// They must be enqueued in the same transaction, or may have already
+/// succeeded.
+pub(crate) fn enqueue_job_after(
+    conn: &PgConnection,
+    job: &NewJob<'_>,
+    after: &[JobId],
+) -> QueryResult<JobId> {
+    use crate::schema::background_job_dependencies::dsl::*;
+
+    let waiting = insert_or_find_job(conn, job)?;
+    if !after.is_empty() {
+        let dependencies = after
+            .iter()
+            .map(|after| (job_id.eq(waiting), depends_on.eq(after)))
+            .collect::<Vec<_>>();
+        insert_into(background_job_dependencies)
+            .values(&dependencies)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(waiting)
+}
+
+/// Deletes the pending jobs with the same type and data as `job`. Jobs which
+/// are running, or have been marked as dead, are left alone. Returns how many
+/// jobs were deleted.
+pub(crate) fn cancel_pending_jobs<T: Job>(
+    conn: &PgConnection,
+    job: T,
+) -> Result<usize, EnqueueError> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let new_job = NewJob::of(&job)?;
+    let cancelled = conn.transaction(|| {
+        // Running jobs are locked by the runner, so they are skipped
+        let ids = background_jobs
+            .select(id)
+            .filter(job_type.eq(T::JOB_TYPE))
+            .filter(data.eq(&new_job.data))
+            .filter(binary_data.is_not_distinct_from(&new_job.binary_data))
+            .filter(dead_at.is_null())
+            .for_update()
+            .skip_locked()
+            .load::<i64>(conn)?;
+        delete(
+            background_job_checkpoints::table
+                .filter(background_job_checkpoints::job_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        discard_dependents(conn, &ids)?;
+        delete(background_jobs.filter(id.eq_any(ids))).execute(conn)
+    })?;
+    Ok(cancelled)
+}
+
+/// Deletes a job if it is pending, or asks it to stop if it is running. Jobs
+/// which have been marked as dead are left alone.
+pub(crate) fn cancel_job(conn: &PgConnection, job_id: JobId) -> QueryResult<Cancellation> {
+    use crate::schema::background_jobs::dsl::*;
+
+    conn.transaction(|| {
+        // Running jobs are locked by the runner, so they are skipped
+        let pending = background_jobs
+            .find(job_id)
+            .select(id)
+            .filter(dead_at.is_null())
+            .for_update()
+            .skip_locked()
+            .first::<i64>(conn)
+            .optional()?;
+        if pending.is_some() {
+            discard(conn, job_id.0)?;
+            return Ok(Cancellation::Removed);
+        }
+
+        let running = select(exists(
+            background_jobs.find(job_id).filter(dead_at.is_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        if !running {
+            return Ok(Cancellation::NotFound);
+        }
+        // The job's own row is locked by the runner, so cancellations are
+        // kept in a table of their own
+        insert_into(background_job_cancellations::table)
+            .values(background_job_cancellations::job_id.eq(job_id))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(Cancellation::Requested)
+    })
+}
+
+/// Whether a running job has been asked to stop by [`cancel_job`]
+pub(crate) fn cancel_requested(conn: &PgConnection, job_id: i64) -> QueryResult<bool> {
+    select(exists(background_job_cancellations::table.find(job_id))).get_result(conn)
+}
+
+/// A job which has already been serialized, and is about to be enqueued
+#[derive(Insertable)]
+#[table_name = "background_jobs"]
+pub(crate) struct NewJob<'a> {
+    pub(crate) job_type: &'a str,
+    pub(crate) queue: &'a str,
+    pub(crate) data: serde_json::Value,
+    /// The arguments of jobs with a [`Codec`](crate::Codec), in which case
+    /// `data` is `null`
+    pub(crate) binary_data: Option<Vec<u8>>,
+    pub(crate) priority: i16,
+    pub(crate) deadline: Option<DateTime<Utc>>,
+    pub(crate) batch_id: Option<&'a str>,
+    /// The job is not run before then
+    #[column_name = "retry_at"]
+    pub(crate) run_at: Option<DateTime<Utc>>,
+    pub(crate) unique_key: Option<String>,
+    pub(crate) compressed: bool,
+}
+
+impl<'a> NewJob<'a> {
+    /// A job to be run as soon as possible, with no deadline or batch
+    pub(crate) fn new(
+        job_type: &'a str,
+        queue: &'a str,
+        data: serde_json::Value,
+        priority: i16,
+    ) -> Self {
+        Self {
+            job_type,
+            queue,
+            data,
+            binary_data: None,
+            priority,
+            deadline: None,
+            batch_id: None,
+            run_at: None,
+            unique_key: None,
+            compressed: false,
+        }
+    }
+
+    /// `job`, on its queue and with its priority
+    pub(crate) fn of<T: Job>(job: &T) -> Result<Self, EnqueueError> {
+        let binary_data = job.encode_binary().map_err(EnqueueError::EncodingError)?;
+        let data = match binary_data {
+            Some(_) => serde_json::Value::Null,
+            None => serde_json::to_value(job)?,
+        };
+        let mut new_job = Self {
+            binary_data,
+            unique_key: job.unique_key(),
+            ..Self::new(T::JOB_TYPE, T::QUEUE, data, T::PRIORITY)
+        };
+        if T::COMPRESS_OVER.is_none() && T::MAX_PAYLOAD_SIZE.is_none() {
+            return Ok(new_job);
+        }
+
+        let size = match &new_job.binary_data {
+            Some(bytes) => bytes.len(),
+            None => {
+                let json = serde_json::to_vec(&new_job.data)?;
+                match T::COMPRESS_OVER {
+                    Some(threshold) if json.len() > threshold => {
+                        let compressed =
+                            compression::compress(&json).map_err(EnqueueError::EncodingError)?;
+                        new_job.data = serde_json::Value::Null;
+                        new_job.binary_data = Some(compressed);
+                        new_job.compressed = true;
+                        new_job.binary_data.as_ref().map_or(0, Vec::len)
+                    }
+                    _ => json.len(),
+                }
+            }
+        };
+        if let Some(limit) = T::MAX_PAYLOAD_SIZE {
+            if size > limit {
+                return Err(EnqueueError::PayloadTooLarge { size, limit });
+            }
+        }
+        Ok(new_job)
+    }
+}
+
+/// Enqueues a job which has already been serialized. Returns its id, or
+/// `None` if a pending job has the same unique key.
+pub(crate) fn insert_job(conn: &PgConnection, job: &NewJob<'_>) -> QueryResult<Option<JobId>> {
+    insert_into(background_jobs::table)
+        .values(job)
+        .on_conflict_do_nothing()
+        .returning(background_jobs::id)
+        .get_result(conn)
+        .optional()
+}
+
+/// Enqueues a job which has already been serialized. Returns its id, or the
+/// id of the pending job with the same unique key if it wasn't enqueued.
+pub(crate) fn insert_or_find_job(conn: &PgConnection, job: &NewJob<'_>) -> QueryResult<JobId> {
     use crate::schema::background_jobs::dsl::*;
 
-    let job_data = serde_json::to_value(job)?;
-    insert_into(background_jobs)
-        .values((job_type.eq(T::JOB_TYPE), data.eq(job_data)))
+    sql_function!(fn md5(x: Nullable<Text>) -> Nullable<Text>);
+
+    loop {
+        if let Some(job_id) = insert_job(conn, job)? {
+            return Ok(job_id);
+        }
+        let pending = background_jobs
+            .select(id)
+            .filter(job_type.eq(job.job_type))
+            .filter(md5(unique_key).eq(md5(&job.unique_key)))
+            .filter(dead_at.is_null())
+            .first(conn)
+            .optional()?;
+        // Otherwise the pending job finished after the insert was skipped,
+        // and the key is free again
+        if let Some(job_id) = pending {
+            return Ok(job_id);
+        }
+    }
+}
+
+/// The most jobs inserted by a single statement, which keeps the number of
+/// bind parameters under Postgres' limit of 65535
+const MAX_JOBS_PER_INSERT: usize = 5000;
+
+/// Enqueues many jobs, inserting up to [`MAX_JOBS_PER_INSERT`] at a time.
+/// Returns the ids of the jobs which were enqueued.
+pub(crate) fn enqueue_jobs<T, I>(conn: &PgConnection, jobs: I) -> Result<Vec<JobId>, EnqueueError>
+where
+    T: Job,
+    I: IntoIterator<Item = T>,
+{
+    if let Some(inline) = inline::environment::<T>() {
+        return jobs
+            .into_iter()
+            .map(|job| inline.perform(conn, job))
+            .collect();
+    }
+    let jobs = jobs
+        .into_iter()
+        .map(|job| NewJob::of(&job))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(conn.transaction(|| insert_jobs(conn, &jobs))?)
+}
+
+/// Inserts many jobs which have already been serialized, up to
+/// [`MAX_JOBS_PER_INSERT`] at a time. Returns the ids of the jobs which were
+/// inserted.
+fn insert_jobs(conn: &PgConnection, jobs: &[NewJob<'_>]) -> QueryResult<Vec<JobId>> {
+    let mut ids = Vec::with_capacity(jobs.len());
+    for chunk in jobs.chunks(MAX_JOBS_PER_INSERT) {
+        let inserted = insert_into(background_jobs::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .returning(background_jobs::id)
+            .get_results::<JobId>(conn)?;
+        ids.extend(inserted);
+    }
+    Ok(ids)
+}
+
+/// Enqueues `jobs` in the batch `batch`, along with jobs which are held until
+/// the batch finishes. `on_success` is run once every job in the batch has
+/// succeeded, and `on_failure` once any of them is marked as dead. Any
+/// callbacks the batch already had are replaced. Returns the ids of the jobs
+/// in the batch which were enqueued.
+pub(crate) fn enqueue_batch_with_callbacks(
+    conn: &PgConnection,
+    batch: &str,
+    jobs: &[NewJob<'_>],
+    on_success: Option<NewJob<'_>>,
+    on_failure: Option<NewJob<'_>>,
+) -> QueryResult<Vec<JobId>> {
+    use crate::schema::background_job_batches::dsl;
+
+    conn.transaction(|| {
+        let ids = insert_jobs(conn, jobs)?;
+        let on_success = on_success.map(|job| hold_job(conn, job)).transpose()?;
+        let on_failure = on_failure.map(|job| hold_job(conn, job)).transpose()?;
+        let replaced = dsl::background_job_batches
+            .find(batch)
+            .select((dsl::on_success, dsl::on_failure))
+            .for_update()
+            .first::<(Option<i64>, Option<i64>)>(conn)
+            .optional()?;
+        if let Some((success, failure)) = replaced {
+            for callback in success.into_iter().chain(failure) {
+                discard(conn, callback)?;
+            }
+        }
+        insert_into(dsl::background_job_batches)
+            .values((
+                dsl::batch_id.eq(batch),
+                dsl::on_success.eq(on_success),
+                dsl::on_failure.eq(on_failure),
+            ))
+            .on_conflict(dsl::batch_id)
+            .do_update()
+            .set((
+                dsl::on_success.eq(on_success),
+                dsl::on_failure.eq(on_failure),
+            ))
+            .execute(conn)?;
+        // A batch with no jobs left to run has already succeeded
+        finish_batch(conn, batch, false)?;
+        Ok(ids)
+    })
+}
+
+/// Enqueues a job which is held until [`release_job`] is called for it, by
+/// making it wait for itself. Its unique key is ignored, so it is always
+/// enqueued.
+fn hold_job(conn: &PgConnection, job: NewJob<'_>) -> QueryResult<i64> {
+    use crate::schema::background_job_dependencies::dsl::*;
+
+    let held = insert_into(background_jobs::table)
+        .values(&NewJob {
+            unique_key: None,
+            ..job
+        })
+        .returning(background_jobs::id)
+        .get_result::<i64>(conn)?;
+    insert_into(background_job_dependencies)
+        .values((job_id.eq(held), depends_on.eq(held)))
         .execute(conn)?;
+    Ok(held)
+}
+
+/// Lets a job which was held by [`hold_job`] be claimed
+fn release_job(conn: &PgConnection, held: i64) -> QueryResult<()> {
+    use crate::schema::background_job_dependencies::dsl::*;
+
+    delete(background_job_dependencies.filter(job_id.eq(held))).execute(conn)?;
     Ok(())
 }
 
+/// Releases the callback for how a batch finished, and deletes the other
+/// one. A batch has finished once any of its jobs is marked as dead, which
+/// `failed` reports, or once none of its jobs are left. Each batch's
+/// callbacks are only run once, since its row is locked while they are
+/// taken.
+fn finish_batch(conn: &PgConnection, batch: &str, failed: bool) -> QueryResult<()> {
+    use crate::schema::background_job_batches::dsl;
+
+    let callbacks = dsl::background_job_batches
+        .find(batch)
+        .select((dsl::on_success, dsl::on_failure))
+        .for_update()
+        .first::<(Option<i64>, Option<i64>)>(conn)
+        .optional()?;
+    let (on_success, on_failure) = match callbacks {
+        Some((None, None)) | None => return Ok(()),
+        Some(callbacks) => callbacks,
+    };
+    if !failed {
+        let remaining = select(exists(
+            background_jobs::table.filter(background_jobs::batch_id.eq(batch)),
+        ))
+        .get_result::<bool>(conn)?;
+        if remaining {
+            return Ok(());
+        }
+    }
+    update(dsl::background_job_batches.find(batch))
+        .set((
+            dsl::on_success.eq(None::<i64>),
+            dsl::on_failure.eq(None::<i64>),
+        ))
+        .execute(conn)?;
+    let (run, skipped) = if failed {
+        (on_failure, on_success)
+    } else {
+        (on_success, on_failure)
+    };
+    if let Some(run) = run {
+        release_job(conn, run)?;
+    }
+    if let Some(skipped) = skipped {
+        discard(conn, skipped)?;
+    }
+    Ok(())
+}
+
+/// When a job was enqueued. `created_at` has no time zone, and is in the time
+/// zone of the connection which enqueued the job.
+pub(crate) fn enqueued_at() -> SqlLiteral<Timestamptz> {
+    sql("background_jobs.created_at AT TIME ZONE current_setting('TimeZone')")
+}
+
+/// The current time. Unlike `NOW()`, this isn't the time the transaction
+/// started, which for the runner is when the job was claimed.
+fn clock_timestamp() -> SqlLiteral<Nullable<Timestamptz>> {
+    sql("clock_timestamp()")
+}
+
 fn retriable() -> Box<dyn BoxableExpression<background_jobs::table, Pg, SqlType = Bool>> {
     use crate::schema::background_jobs::dsl::*;
     use diesel::dsl::*;
 
     sql_function!(fn power(x: Integer, y: Integer) -> Integer);
 
-    Box::new(last_retry.lt(now - 1.minute().into_sql::<Interval>() * power(2, retries)))
+    // Jobs enqueued for later, or retried with a backoff, have their next
+    // attempt scheduled, and other jobs back off exponentially from their
+    // last attempt
+    Box::new(
+        retry_at.le(now).or(retry_at
+            .is_null()
+            .and(last_retry.lt(now - 1.minute().into_sql::<Interval>() * power(2, retries)))),
+    )
+}
+
+/// Jobs which aren't waiting for another job to succeed
+fn not_waiting() -> SqlLiteral<Bool> {
+    sql("NOT EXISTS (SELECT 1 FROM background_job_dependencies \
+         WHERE background_job_dependencies.job_id = background_jobs.id)")
+}
+
+/// Jobs which aren't on a queue paused with [`pause_queue`]
+fn not_paused() -> SqlLiteral<Bool> {
+    sql("background_jobs.queue NOT IN (SELECT queue FROM background_job_paused_queues)")
 }
 
-/// Finds the next job that is unlocked, and ready to be retried. If a row is
-/// found, it will be locked.
-pub fn find_next_unlocked_job(conn: &PgConnection) -> QueryResult<BackgroundJob> {
+fn in_queues(
+    queues: Option<&[String]>,
+) -> Box<dyn BoxableExpression<background_jobs::table, Pg, SqlType = Bool>> {
+    use crate::schema::background_jobs::dsl::*;
+
+    match queues {
+        Some(queues) => Box::new(queue.eq_any(queues.to_vec())),
+        None => Box::new(true.into_sql::<Bool>()),
+    }
+}
+
+/// Claims the next job that is unlocked, and ready to be run or retried.
+///
+/// Jobs with a higher [priority](Job::PRIORITY) are claimed first, and jobs
+/// with the same priority are claimed in the order they were enqueued. Jobs
+/// which have been marked as dead are never returned. If `queues` is
+/// given, only jobs on those queues are returned. If a job is found, its row
+/// is locked until the end of the current transaction, so this must be called
+/// inside of a transaction which stays open until the job has finished.
+pub fn claim_one(
+    conn: &PgConnection,
+    queues: Option<&[String]>,
+) -> QueryResult<Option<BackgroundJob>> {
+    claim_next(conn, queues, &[], &[])
+}
+
+/// Like [`claim_one`], but never claims jobs on the `excluded` queues, or of
+/// the `excluded_types`
+pub(crate) fn claim_next(
+    conn: &PgConnection,
+    queues: Option<&[String]>,
+    excluded: &[String],
+    excluded_types: &[String],
+) -> QueryResult<Option<BackgroundJob>> {
     use crate::schema::background_jobs::dsl::*;
 
     background_jobs
-        .select((id, job_type, data))
+        .select((
+            id,
+            job_type,
+            data,
+            queue,
+            retries,
+            deadline,
+            binary_data,
+            enqueued_at(),
+            last_error,
+            failed_at,
+            compressed,
+            started_at,
+            finished_at,
+        ))
+        .filter(dead_at.is_null())
         .filter(retriable())
-        .order(id)
+        .filter(not_waiting())
+        .filter(not_paused())
+        .filter(in_queues(queues))
+        .filter(queue.ne_all(excluded.to_vec()))
+        .filter(job_type.ne_all(excluded_types.to_vec()))
+        .order((priority.desc(), id))
         .for_update()
         .skip_locked()
         .first::<BackgroundJob>(conn)
+        .optional()?
+        .map(|mut job| {
+            job.started_at = update(background_jobs.find(job.id))
+                .set(started_at.eq(clock_timestamp()))
+                .returning(started_at)
+                .get_result(conn)?;
+            Ok(job)
+        })
+        .transpose()
+}
+
+/// Like [`claim_next`], but only claims a job of a type in
+/// `concurrency_limits` if fewer than its limit are running on every runner
+/// using the database.
+///
+/// Each running job of a limited type holds one of its type's numbered
+/// advisory locks until the end of the current transaction. A job whose type
+/// has no lock free is given back by rolling back to a savepoint, which also
+/// releases its row, and its type is skipped for the rest of the claim.
+#[cfg(feature = "runner")]
+pub(crate) fn claim_next_within_limits(
+    conn: &PgConnection,
+    queues: Option<&[String]>,
+    excluded: &[String],
+    excluded_types: &[String],
+    concurrency_limits: &[(&'static str, usize)],
+) -> QueryResult<Option<BackgroundJob>> {
+    if concurrency_limits.is_empty() {
+        return claim_next(conn, queues, excluded, excluded_types);
+    }
+
+    let mut excluded_types = excluded_types.to_vec();
+    loop {
+        let claimed = conn.transaction(|| {
+            let job = match claim_next(conn, queues, excluded, &excluded_types)? {
+                Some(job) => job,
+                None => return Ok(None),
+            };
+            match concurrency_limits.iter().find(|&&(t, _)| t == job.job_type) {
+                Some(&(_, limit)) if !take_concurrency_slot(conn, &job.job_type, limit)? => {
+                    Err(ClaimError::AtLimit(job.job_type))
+                }
+                _ => Ok(Some(job)),
+            }
+        });
+        match claimed {
+            Ok(job) => return Ok(job),
+            Err(ClaimError::AtLimit(full_type)) => excluded_types.push(full_type),
+            Err(ClaimError::Query(e)) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "runner")]
+enum ClaimError {
+    /// Every runner together is already running as many jobs of this type
+    /// as its limit allows
+    AtLimit(String),
+    Query(diesel::result::Error),
+}
+
+#[cfg(feature = "runner")]
+impl From<diesel::result::Error> for ClaimError {
+    fn from(e: diesel::result::Error) -> Self {
+        ClaimError::Query(e)
+    }
+}
+
+#[derive(QueryableByName)]
+#[cfg(feature = "runner")]
+struct Locked {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// Takes one of the `limit` advisory locks for `slot_job_type`, which is held
+/// until the end of the current transaction. Returns `false` if they are all
+/// held already.
+#[cfg(feature = "runner")]
+fn take_concurrency_slot(
+    conn: &PgConnection,
+    slot_job_type: &str,
+    limit: usize,
+) -> QueryResult<bool> {
+    for slot in 0..limit.min(i32::MAX as usize) as i32 {
+        let taken =
+            sql_query("SELECT pg_try_advisory_xact_lock(hashtext('swirl:' || $1), $2) AS locked")
+                .bind::<Text, _>(slot_job_type)
+                .bind::<Integer, _>(slot)
+                .get_result::<Locked>(conn)?
+                .locked;
+        if taken {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Stops every runner using the database from claiming jobs on `queue`,
+/// until [`resume_queue`] is called. Returns `false` if it was already
+/// paused.
+///
+/// Jobs can still be enqueued on a paused queue, and jobs which were already
+/// running finish as normal. Unlike
+/// [`RunnerHandle::pause_queue`](crate::RunnerHandle::pause_queue), this
+/// lasts until the queue is resumed, even across restarts.
+pub fn pause_queue(conn: &PgConnection, queue: &str) -> QueryResult<bool> {
+    let paused = insert_into(background_job_paused_queues::table)
+        .values(background_job_paused_queues::queue.eq(queue))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(paused > 0)
+}
+
+/// Lets runners claim jobs on a queue paused with [`pause_queue`] again.
+/// Returns `false` if it wasn't paused.
+pub fn resume_queue(conn: &PgConnection, queue: &str) -> QueryResult<bool> {
+    let resumed = delete(background_job_paused_queues::table.find(queue)).execute(conn)?;
+    Ok(resumed > 0)
+}
+
+/// The queues paused with [`pause_queue`], in order of their names
+pub fn paused_queues(conn: &PgConnection) -> QueryResult<Vec<String>> {
+    background_job_paused_queues::table
+        .select(background_job_paused_queues::queue)
+        .order(background_job_paused_queues::queue)
+        .load(conn)
 }
 
 /// The number of jobs that have failed at least once
-pub fn failed_job_count(conn: &PgConnection) -> QueryResult<i64> {
+#[cfg(feature = "runner")]
+pub(crate) fn failed_job_count(conn: &PgConnection) -> QueryResult<i64> {
     use crate::schema::background_jobs::dsl::*;
 
     background_jobs
@@ -60,22 +869,457 @@ pub fn failed_job_count(conn: &PgConnection) -> QueryResult<i64> {
         .get_result(conn)
 }
 
-/// Deletes a job that has successfully completed running
-pub fn delete_successful_job(conn: &PgConnection, job_id: i64) -> QueryResult<()> {
+/// Deletes a job that has successfully completed running.
+///
+/// If the job was part of a batch, it is counted towards the batch's
+/// succeeded jobs, and the batch's success callback is released if it was
+/// the last job left. Jobs which were waiting for it to succeed can be
+/// claimed once the transaction commits.
+pub fn complete(conn: &PgConnection, job_id: i64) -> QueryResult<()> {
+    use crate::schema::background_job_batches;
+
+    delete(
+        background_job_dependencies::table
+            .filter(background_job_dependencies::depends_on.eq(job_id)),
+    )
+    .execute(conn)?;
+    if let Some(batch) = discard(conn, job_id)? {
+        insert_into(background_job_batches::table)
+            .values((
+                background_job_batches::batch_id.eq(&batch),
+                background_job_batches::succeeded.eq(1),
+            ))
+            .on_conflict(background_job_batches::batch_id)
+            .do_update()
+            .set(background_job_batches::succeeded.eq(background_job_batches::succeeded + 1))
+            .execute(conn)?;
+        finish_batch(conn, &batch, false)?;
+    }
+    Ok(())
+}
+
+/// Like [`complete`], but keeps a copy of the job in
+/// `background_jobs_archive`, as a record of what ran.
+///
+/// Archived jobs are kept until they are removed by [`prune_archive`].
+pub fn archive(conn: &PgConnection, job_id: i64) -> QueryResult<()> {
+    sql_query(
+        "INSERT INTO background_jobs_archive \
+             (id, job_type, queue, data, binary_data, compressed, retries, batch_id, \
+             enqueued_at, started_at, finished_at) \
+         SELECT id, job_type, queue, data, binary_data, compressed, retries, batch_id, \
+             created_at AT TIME ZONE current_setting('TimeZone'), started_at, clock_timestamp() \
+         FROM background_jobs WHERE id = $1 \
+         ON CONFLICT (id) DO NOTHING",
+    )
+    .bind::<BigInt, _>(job_id)
+    .execute(conn)?;
+    complete(conn, job_id)
+}
+
+/// Saves the result of a job which has completed, to be kept for `ttl`, and
+/// deletes any results which have expired.
+#[cfg(feature = "runner")]
+pub(crate) fn save_result(
+    conn: &PgConnection,
+    job_id: i64,
+    job_type: &str,
+    result: &serde_json::Value,
+    ttl: Duration,
+) -> QueryResult<()> {
+    use crate::schema::background_job_results::dsl;
+
+    delete(dsl::background_job_results.filter(dsl::expires_at.le(now))).execute(conn)?;
+    sql_query(
+        "INSERT INTO background_job_results (job_id, job_type, result, expires_at) \
+         VALUES ($1, $2, $3, NOW() + $4 * INTERVAL '1 millisecond') \
+         ON CONFLICT (job_id) DO UPDATE \
+         SET result = excluded.result, completed_at = NOW(), expires_at = excluded.expires_at",
+    )
+    .bind::<BigInt, _>(job_id)
+    .bind::<Text, _>(job_type)
+    .bind::<Jsonb, _>(result)
+    .bind::<BigInt, _>(ttl.as_millis() as i64)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The result saved by the job with the id `job_id`, unless it has expired
+pub(crate) fn load_result(
+    conn: &PgConnection,
+    job_id: JobId,
+) -> QueryResult<Option<serde_json::Value>> {
+    use crate::schema::background_job_results::dsl;
+
+    dsl::background_job_results
+        .find(job_id.0)
+        .filter(dsl::expires_at.gt(now))
+        .select(dsl::result)
+        .first(conn)
+        .optional()
+}
+
+/// Deletes a job without counting it as succeeded, along with any jobs
+/// waiting for it. Returns the batch the job was part of, if any.
+pub(crate) fn discard(conn: &PgConnection, job_id: i64) -> QueryResult<Option<String>> {
     use crate::schema::background_jobs::dsl::*;
 
-    delete(background_jobs.find(job_id)).execute(conn)?;
+    discard_dependents(conn, &[job_id])?;
+    delete(background_job_checkpoints::table.find(job_id)).execute(conn)?;
+    delete(background_job_cancellations::table.find(job_id)).execute(conn)?;
+    delete(background_job_heartbeats::table.find(job_id)).execute(conn)?;
+    let batch = delete(background_jobs.find(job_id))
+        .returning(batch_id)
+        .get_result::<Option<String>>(conn)
+        .optional()?;
+    Ok(batch.flatten())
+}
+
+/// Deletes what the jobs `job_ids` were waiting for, and the jobs waiting
+/// for them, which would otherwise never be run
+fn discard_dependents(conn: &PgConnection, job_ids: &[i64]) -> QueryResult<()> {
+    use crate::schema::background_job_dependencies::dsl::*;
+
+    delete(background_job_dependencies.filter(job_id.eq_any(job_ids))).execute(conn)?;
+    let waiting = delete(background_job_dependencies.filter(depends_on.eq_any(job_ids)))
+        .returning(job_id)
+        .get_results::<i64>(conn)?;
+    for waiting_id in waiting {
+        discard(conn, waiting_id)?;
+    }
     Ok(())
 }
 
-/// Marks that we just tried and failed to run a job.
+/// Marks that a claimed job failed to run, and records the error in the
+/// failure history.
+///
+/// The job will be retried with exponential backoff, unless it has now been
+/// retried more than `max_retries` times, in which case it is marked as dead.
+/// Returns whether the job was marked as dead. Any database errors are
+/// ignored, since the job will be retried when the transaction is rolled
+/// back.
+pub fn fail(
+    conn: &PgConnection,
+    job: &BackgroundJob,
+    error: &str,
+    max_retries: Option<u32>,
+) -> bool {
+    update_failed_job(conn, job.id, &job.job_type, error, max_retries, None, None).unwrap_or(false)
+}
+
+/// Marks a job which was claimed after its deadline as dead, without running
+/// it, and records why in the failure history.
+#[cfg(feature = "runner")]
+pub(crate) fn mark_expired_job_dead(
+    conn: &PgConnection,
+    job_id: i64,
+    expired_job_type: &str,
+) -> QueryResult<()> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let batch = update(background_jobs.find(job_id))
+        .set((
+            dead_at.eq(now),
+            last_error.eq(EXPIRED_ERROR),
+            failed_at.eq(now),
+            finished_at.eq(clock_timestamp()),
+        ))
+        .returning(batch_id)
+        .get_result::<Option<String>>(conn)?;
+    if let Some(batch) = batch {
+        finish_batch(conn, &batch, true)?;
+    }
+    record_failure(conn, job_id, expired_job_type, EXPIRED_ERROR)
+}
+
+/// The error recorded for jobs which expired before they could be run
+#[cfg(feature = "runner")]
+pub(crate) const EXPIRED_ERROR: &str = "Job was not run before its deadline";
+
+/// The error recorded for jobs which were swept after their TTL
+#[cfg(feature = "runner")]
+pub(crate) const STALE_ERROR: &str = "Job was not run before its TTL elapsed";
+
+/// Marks jobs of the given type which were enqueued more than `ttl` ago as
+/// dead, and records why in the failure history. Jobs which are currently
+/// running are skipped. Returns the jobs which were marked as dead.
+#[cfg(feature = "runner")]
+pub(crate) fn sweep_stale_jobs(
+    conn: &PgConnection,
+    stale_job_type: &str,
+    ttl: Duration,
+) -> QueryResult<Vec<BackgroundJob>> {
+    conn.transaction(|| {
+        let swept = sql_query(
+            "UPDATE background_jobs \
+             SET dead_at = NOW(), last_error = $3, failed_at = NOW() \
+             WHERE id IN ( \
+                 SELECT id FROM background_jobs \
+                 WHERE job_type = $1 AND dead_at IS NULL \
+                 AND created_at < NOW() - $2 * INTERVAL '1 millisecond' \
+                 FOR UPDATE SKIP LOCKED \
+             ) \
+             RETURNING id, job_type, data, queue, retries, deadline, binary_data, \
+                 created_at AT TIME ZONE current_setting('TimeZone') AS created_at, \
+                 last_error, failed_at, compressed, started_at, finished_at",
+        )
+        .bind::<Text, _>(stale_job_type)
+        .bind::<BigInt, _>(ttl.as_millis() as i64)
+        .bind::<Text, _>(STALE_ERROR)
+        .load::<BackgroundJob>(conn)?;
+        for job in &swept {
+            record_failure(conn, job.id, &job.job_type, STALE_ERROR)?;
+        }
+        let batches = background_jobs::table
+            .select(background_jobs::batch_id)
+            .filter(background_jobs::id.eq_any(swept.iter().map(|job| job.id).collect::<Vec<_>>()))
+            .filter(background_jobs::batch_id.is_not_null())
+            .distinct()
+            .load::<Option<String>>(conn)?;
+        for batch in batches.into_iter().flatten() {
+            finish_batch(conn, &batch, true)?;
+        }
+        Ok(swept)
+    })
+}
+
+/// Marks that we just tried and failed to run a job, and records the error in
+/// the failure history.
 ///
-/// Ignores any database errors that may have occurred. If the DB has gone away,
-/// we assume that just trying again with a new connection will succeed.
-pub fn update_failed_job(conn: &PgConnection, job_id: i64) {
+/// If the job has now been retried more than `max_retries` times, or jobs of
+/// this type have been retried more than `retry_budget` times in the current
+/// minute, it is marked as dead and will not be run again. Returns whether the
+/// job was marked as dead.
+///
+/// Otherwise, the job is retried after the delay given by `backoff`, or after
+/// `2 ^ {retry_count}` minutes if there is none.
+pub(crate) fn update_failed_job(
+    conn: &PgConnection,
+    job_id: i64,
+    failed_job_type: &str,
+    error: &str,
+    max_retries: Option<u32>,
+    retry_budget: Option<u32>,
+    backoff: Option<Backoff>,
+) -> QueryResult<bool> {
     use crate::schema::background_jobs::dsl::*;
 
-    let _ = update(background_jobs.find(job_id))
-        .set((retries.eq(retries + 1), last_retry.eq(now)))
-        .execute(conn);
+    // The time a delayed job was enqueued to run at has passed, so without a
+    // backoff it is retried after the default delay instead of right away
+    let retry_count = update(background_jobs.find(job_id))
+        .set((
+            retries.eq(retries + 1),
+            last_retry.eq(now),
+            last_error.eq(error),
+            failed_at.eq(now),
+            retry_at.eq(None::<DateTime<Utc>>),
+            finished_at.eq(clock_timestamp()),
+        ))
+        .returning(retries)
+        .get_result::<i32>(conn)?;
+    record_failure(conn, job_id, failed_job_type, error)?;
+    delete(background_job_heartbeats::table.find(job_id)).execute(conn)?;
+
+    let out_of_retries =
+        max_retries.is_some_and(|max_retries| i64::from(retry_count) > i64::from(max_retries));
+    let over_budget = match retry_budget {
+        Some(budget) if !out_of_retries => {
+            spend_retry_budget(conn, failed_job_type)? > i64::from(budget)
+        }
+        _ => false,
+    };
+
+    if out_of_retries || over_budget {
+        let batch = update(background_jobs.find(job_id))
+            .set(dead_at.eq(now))
+            .returning(batch_id)
+            .get_result::<Option<String>>(conn)?;
+        if let Some(batch) = batch {
+            finish_batch(conn, &batch, true)?;
+        }
+        Ok(true)
+    } else {
+        if let Some(backoff) = backoff {
+            schedule_retry(conn, job_id, backoff, retry_count)?;
+        }
+        Ok(false)
+    }
+}
+
+/// Runs a job which [snoozed](crate::PerformError::Snooze) again after
+/// `delay`, without counting it as a failure
+#[cfg(feature = "runner")]
+pub(crate) fn snooze_job(conn: &PgConnection, job_id: i64, delay: Duration) -> QueryResult<()> {
+    sql_query(
+        "UPDATE background_jobs \
+         SET retry_at = NOW() + $1 * INTERVAL '1 millisecond', finished_at = clock_timestamp() \
+         WHERE id = $2",
+    )
+    .bind::<BigInt, _>(delay.as_millis().min(i64::MAX as u128) as i64)
+    .bind::<BigInt, _>(job_id)
+    .execute(conn)?;
+    delete(background_job_heartbeats::table.find(job_id)).execute(conn)?;
+    Ok(())
+}
+
+/// Sets when a job which has failed `retry_count` times will next be retried
+fn schedule_retry(
+    conn: &PgConnection,
+    job_id: i64,
+    backoff: Backoff,
+    retry_count: i32,
+) -> QueryResult<()> {
+    let delay = backoff.delay(retry_count.max(0) as u32);
+    sql_query(
+        "UPDATE background_jobs \
+         SET retry_at = NOW() + $1 * (1 - $2 * random()) * INTERVAL '1 millisecond' \
+         WHERE id = $3",
+    )
+    .bind::<BigInt, _>(delay.as_millis().min(i64::MAX as u128) as i64)
+    .bind::<Double, _>(backoff.jitter_fraction())
+    .bind::<BigInt, _>(job_id)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct RetryBudget {
+    #[sql_type = "Integer"]
+    retries: i32,
+}
+
+/// Counts a retry of the given job type against the current minute's budget,
+/// and returns the number of retries in this minute so far.
+///
+/// The budget is shared by every runner using the database. Windows from
+/// previous minutes are removed when a new one is started.
+fn spend_retry_budget(conn: &PgConnection, budget_job_type: &str) -> QueryResult<i64> {
+    let spent = sql_query(
+        "INSERT INTO background_job_retry_budgets (job_type, window_start, retries) \
+         VALUES ($1, date_trunc('minute', NOW()), 1) \
+         ON CONFLICT (job_type, window_start) \
+         DO UPDATE SET retries = background_job_retry_budgets.retries + 1 \
+         RETURNING retries",
+    )
+    .bind::<Text, _>(budget_job_type)
+    .get_result::<RetryBudget>(conn)?
+    .retries;
+
+    if spent == 1 {
+        sql_query(
+            "DELETE FROM background_job_retry_budgets \
+             WHERE job_type = $1 AND window_start < date_trunc('minute', NOW())",
+        )
+        .bind::<Text, _>(budget_job_type)
+        .execute(conn)?;
+    }
+    Ok(i64::from(spent))
+}
+
+fn record_failure(
+    conn: &PgConnection,
+    failed_job_id: i64,
+    failed_job_type: &str,
+    failure: &str,
+) -> QueryResult<()> {
+    use crate::schema::background_job_failures::dsl::*;
+
+    insert_into(background_job_failures)
+        .values((
+            job_id.eq(failed_job_id),
+            job_type.eq(failed_job_type),
+            error.eq(failure),
+            fingerprint.eq(error_fingerprint(failure)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// An attempt to run a job, recorded in the attempt history
+#[cfg(feature = "runner")]
+#[derive(Insertable)]
+#[table_name = "background_job_attempts"]
+pub(crate) struct NewAttempt<'a> {
+    pub(crate) job_id: i64,
+    pub(crate) job_type: &'a str,
+    pub(crate) attempt: i32,
+    pub(crate) worker: &'a str,
+    pub(crate) outcome: &'a str,
+    pub(crate) error: Option<&'a str>,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) duration_ms: i64,
+}
+
+/// Records an attempt to run a job
+#[cfg(feature = "runner")]
+pub(crate) fn record_attempt(conn: &PgConnection, attempt: &NewAttempt<'_>) -> QueryResult<()> {
+    insert_into(background_job_attempts::table)
+        .values(attempt)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// A job which is being run by a worker, recorded by [`record_heartbeats`]
+#[cfg(feature = "runner")]
+pub(crate) struct Heartbeat {
+    pub(crate) job_id: i64,
+    /// The retry count of the job when the attempt started
+    pub(crate) attempt: i32,
+    pub(crate) locked_by: String,
+}
+
+/// Records that each job is still being run by its worker. Jobs which have
+/// already been removed from the queue are skipped.
+#[cfg(feature = "runner")]
+pub(crate) fn record_heartbeats(conn: &PgConnection, heartbeats: &[Heartbeat]) -> QueryResult<()> {
+    use diesel::sql_types::Array;
+
+    sql_query(
+        "INSERT INTO background_job_heartbeats (job_id, attempt, locked_by) \
+         SELECT beats.job_id, beats.attempt, beats.locked_by \
+         FROM unnest($1, $2, $3) AS beats (job_id, attempt, locked_by) \
+         WHERE EXISTS (SELECT 1 FROM background_jobs WHERE id = beats.job_id) \
+         ON CONFLICT (job_id) DO UPDATE SET \
+             attempt = EXCLUDED.attempt, \
+             locked_by = EXCLUDED.locked_by, \
+             heartbeat_at = NOW()",
+    )
+    .bind::<Array<BigInt>, _>(heartbeats.iter().map(|h| h.job_id).collect::<Vec<_>>())
+    .bind::<Array<Integer>, _>(heartbeats.iter().map(|h| h.attempt).collect::<Vec<_>>())
+    .bind::<Array<Text>, _>(heartbeats.iter().map(|h| &h.locked_by).collect::<Vec<_>>())
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Deletes the heartbeat of a job which its worker has finished running.
+///
+/// The job's transaction deletes it too, but a heartbeat which was being
+/// recorded as the transaction committed can be left behind.
+#[cfg(feature = "runner")]
+pub(crate) fn delete_heartbeat(conn: &PgConnection, job_id: i64) -> QueryResult<()> {
+    delete(background_job_heartbeats::table.find(job_id)).execute(conn)?;
+    Ok(())
+}
+
+/// Reduces an error message to something which is the same for every
+/// occurrence of the same underlying error.
+///
+/// Only the first line of the message is used, and any numbers in it (ids,
+/// durations, line numbers, etc) are replaced with `N`.
+fn error_fingerprint(error: &str) -> String {
+    let first_line = error.lines().next().unwrap_or_default();
+    let mut fingerprint = String::with_capacity(first_line.len());
+    let mut in_number = false;
+    for c in first_line.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                fingerprint.push('N');
+            }
+            in_number = true;
+        } else {
+            fingerprint.push(c);
+            in_number = false;
+        }
+    }
+    fingerprint
 }
@@ -0,0 +1,140 @@
+//! Keeping the tables small on queues with a lot of churn, by deleting old
+//! dead and archived jobs a batch at a time, and reporting how bloated the
+//! tables are.
+//!
+//! Deleting in batches keeps each statement short, so it doesn't hold locks
+//! for long, and autovacuum can reclaim the space between batches. Each batch
+//! is committed on its own, unless these are called inside of a transaction.
+
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Bool, Text};
+use std::time::Duration;
+
+/// Deletes jobs which were marked as dead more than `retention` ago,
+/// `batch_size` at a time. Returns the number of jobs which were deleted.
+///
+/// Each job is deleted like [`admin::delete_job`](crate::admin::delete_job)
+/// would, so the jobs waiting for it in a chain or workflow, which could
+/// never run, are deleted with it, but not counted.
+pub fn prune_dead_jobs(
+    conn: &PgConnection,
+    retention: Duration,
+    batch_size: i64,
+) -> QueryResult<usize> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let dead_before = sql::<Bool>(&format!(
+        "background_jobs.dead_at < NOW() - INTERVAL '{} milliseconds'",
+        retention_ms(retention)
+    ));
+    let batch_size = batch_size.max(1);
+    let mut pruned = 0;
+    loop {
+        let batch = conn.transaction(|| {
+            let dead = background_jobs
+                .select(id)
+                .filter(dead_before.clone())
+                .limit(batch_size)
+                .for_update()
+                .skip_locked()
+                .load::<i64>(conn)?;
+            for &job_id in &dead {
+                super::discard(conn, job_id)?;
+            }
+            Ok::<_, diesel::result::Error>(dead.len())
+        })?;
+        pruned += batch;
+        if (batch as i64) < batch_size {
+            return Ok(pruned);
+        }
+    }
+}
+
+/// Deletes archived jobs which finished more than `retention` ago,
+/// `batch_size` at a time. Returns the number of jobs which were deleted.
+pub fn prune_archive(
+    conn: &PgConnection,
+    retention: Duration,
+    batch_size: i64,
+) -> QueryResult<usize> {
+    delete_in_batches(
+        conn,
+        "DELETE FROM background_jobs_archive WHERE id IN ( \
+             SELECT id FROM background_jobs_archive \
+             WHERE finished_at < NOW() - $1 * INTERVAL '1 millisecond' \
+             LIMIT $2 \
+         )",
+        retention,
+        batch_size,
+    )
+}
+
+/// Runs `query`, which deletes up to `$2` rows older than `$1` milliseconds,
+/// until it deletes fewer rows than that
+fn delete_in_batches(
+    conn: &PgConnection,
+    query: &str,
+    retention: Duration,
+    batch_size: i64,
+) -> QueryResult<usize> {
+    let retention_ms = retention_ms(retention);
+    let batch_size = batch_size.max(1);
+    let mut deleted = 0;
+    loop {
+        let batch = sql_query(query)
+            .bind::<BigInt, _>(retention_ms)
+            .bind::<BigInt, _>(batch_size)
+            .execute(conn)?;
+        deleted += batch;
+        if (batch as i64) < batch_size {
+            return Ok(deleted);
+        }
+    }
+}
+
+fn retention_ms(retention: Duration) -> i64 {
+    retention.as_millis().min(i64::MAX as u128) as i64
+}
+
+/// The size of one of swirl's tables, as last estimated by Postgres
+#[derive(QueryableByName, Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+    /// The name of the table
+    #[sql_type = "Text"]
+    pub name: String,
+    /// About how many rows are in the table
+    #[sql_type = "BigInt"]
+    pub live_rows: i64,
+    /// About how many deleted or updated rows have not been vacuumed yet
+    #[sql_type = "BigInt"]
+    pub dead_rows: i64,
+    /// The space the table takes on disk, including its indexes, in bytes
+    #[sql_type = "BigInt"]
+    pub total_bytes: i64,
+}
+
+impl TableStats {
+    /// The fraction of the table's rows which are dead, from 0 to 1
+    pub fn bloat(&self) -> f64 {
+        let rows = self.live_rows + self.dead_rows;
+        if rows == 0 {
+            0.0
+        } else {
+            self.dead_rows as f64 / rows as f64
+        }
+    }
+}
+
+/// The size of each of swirl's tables, by name
+pub fn table_stats(conn: &PgConnection) -> QueryResult<Vec<TableStats>> {
+    sql_query(
+        "SELECT relname::TEXT AS name, n_live_tup AS live_rows, n_dead_tup AS dead_rows, \
+             pg_total_relation_size(relid) AS total_bytes \
+         FROM pg_stat_user_tables \
+         WHERE schemaname = current_schema() AND relname LIKE 'background\\_job%' \
+         ORDER BY relname",
+    )
+    .load(conn)
+}
@@ -0,0 +1,306 @@
+//! Looking up the jobs in the queue, for admin tools and for callers which
+//! want to know what happened to a job they enqueued.
+
+use chrono::{DateTime, Utc};
+use diesel::dsl::{exists, select};
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::{enqueued_at, retriable};
+use crate::{redact, registry, JobId};
+
+/// The most jobs [`list_jobs`] returns by default
+const DEFAULT_LIMIT: i64 = 100;
+
+/// Where a job is in its lifecycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// The job is waiting to be run for the first time
+    Pending,
+    /// The job is being run by a runner
+    Running,
+    /// The job has failed, and is waiting to be retried or has been marked as
+    /// dead
+    Failed {
+        /// The number of times the job has failed
+        retries: i32,
+        /// The error the job last failed with, if it was recorded
+        last_error: Option<String>,
+        /// Whether the job has been marked as dead, and will not be retried
+        dead: bool,
+    },
+    /// The job is no longer in the queue. It succeeded, or was cancelled or
+    /// dropped after its deadline. Ids which were never used are also
+    /// reported as done.
+    Done,
+}
+
+/// Where the job with the id `job_id` is in its lifecycle.
+///
+/// A job is running if its row is locked, which is checked without waiting
+/// for the lock. The row is briefly share locked while it is checked, which
+/// can make a runner skip over the job for a moment.
+pub fn job_status(conn: &PgConnection, job_id: JobId) -> QueryResult<JobStatus> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let unlocked = background_jobs
+        .find(job_id)
+        .select((retries, dead_at.is_not_null(), last_error))
+        .for_key_share()
+        .skip_locked()
+        .first::<(i32, bool, Option<String>)>(conn)
+        .optional()?;
+    Ok(match unlocked {
+        Some((0, false, _)) => JobStatus::Pending,
+        Some((job_retries, dead, job_last_error)) => JobStatus::Failed {
+            retries: job_retries,
+            last_error: job_last_error,
+            dead,
+        },
+        None => {
+            let running = select(exists(background_jobs.find(job_id))).get_result(conn)?;
+            if running {
+                JobStatus::Running
+            } else {
+                JobStatus::Done
+            }
+        }
+    })
+}
+
+/// A job in the queue, as listed by [`list_jobs`]
+#[derive(Queryable, Debug, Clone, PartialEq)]
+pub struct QueuedJob {
+    /// The id of the job
+    pub id: JobId,
+    /// The type of the job
+    pub job_type: String,
+    /// The queue the job is on
+    pub queue: String,
+    /// The serialized arguments of the job, with the values of
+    /// [redacted fields](crate::Job::REDACTED_FIELDS) masked. This is `null`
+    /// for jobs stored with a [`Codec`](crate::Codec).
+    pub data: serde_json::Value,
+    /// The priority the job is claimed with
+    pub priority: i16,
+    /// The number of times the job has failed
+    pub retries: i32,
+    /// When the job was enqueued
+    pub enqueued_at: DateTime<Utc>,
+    /// When the job will next be run, if it was enqueued for later or is
+    /// waiting out a [backoff](crate::Backoff)
+    pub run_at: Option<DateTime<Utc>>,
+    /// The time after which the job should not be run, if any
+    pub deadline: Option<DateTime<Utc>>,
+    /// The batch the job was enqueued in, if any
+    pub batch_id: Option<String>,
+    /// When the job was marked as dead, if it has been
+    pub dead_at: Option<DateTime<Utc>>,
+    /// The error the job last failed with, if it has failed
+    pub last_error: Option<String>,
+    /// When the job last failed, if it has
+    pub failed_at: Option<DateTime<Utc>>,
+    /// When the job was last claimed to be run, if it has been
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the job last finished running, if it has
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Which jobs [`list_jobs`] returns.
+///
+/// Jobs are listed in the order they were enqueued, a page at a time. To get
+/// the next page, pass the id of the last job on this one to
+/// [`after`](Self::after):
+///
+/// ```ignore
+/// let filter = JobFilter::new().job_type("send_email").dead(true).limit(50);
+/// let page = storage::list_jobs(&conn, &filter)?;
+/// if let Some(last) = page.last() {
+///     let next_page = storage::list_jobs(&conn, &filter.after(last.id))?;
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobFilter {
+    id: Option<JobId>,
+    job_type: Option<String>,
+    queue: Option<String>,
+    batch_id: Option<String>,
+    dead: Option<bool>,
+    failed: Option<bool>,
+    after: Option<JobId>,
+    limit: i64,
+}
+
+impl Default for JobFilter {
+    fn default() -> Self {
+        Self {
+            id: None,
+            job_type: None,
+            queue: None,
+            batch_id: None,
+            dead: None,
+            failed: None,
+            after: None,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl JobFilter {
+    /// Every job in the queue, up to 100 at a time
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only list the job with this id
+    pub fn id(mut self, job_id: JobId) -> Self {
+        self.id = Some(job_id);
+        self
+    }
+
+    /// Only list jobs of this type
+    pub fn job_type<S: Into<String>>(mut self, job_type: S) -> Self {
+        self.job_type = Some(job_type.into());
+        self
+    }
+
+    /// Only list jobs on this queue
+    pub fn queue<S: Into<String>>(mut self, queue: S) -> Self {
+        self.queue = Some(queue.into());
+        self
+    }
+
+    /// Only list jobs enqueued in this batch
+    pub fn batch<S: Into<String>>(mut self, batch_id: S) -> Self {
+        self.batch_id = Some(batch_id.into());
+        self
+    }
+
+    /// Only list jobs which have been marked as dead, or only those which
+    /// haven't
+    pub fn dead(mut self, dead: bool) -> Self {
+        self.dead = Some(dead);
+        self
+    }
+
+    /// Only list jobs which have failed at least once, including dead jobs,
+    /// or only those which haven't
+    pub fn failed(mut self, failed: bool) -> Self {
+        self.failed = Some(failed);
+        self
+    }
+
+    /// Only list jobs enqueued after the job with this id
+    pub fn after(mut self, job_id: JobId) -> Self {
+        self.after = Some(job_id);
+        self
+    }
+
+    /// List at most this many jobs
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+/// The jobs in the queue which match `filter`, oldest first
+pub fn list_jobs(conn: &PgConnection, filter: &JobFilter) -> QueryResult<Vec<QueuedJob>> {
+    use crate::schema::background_jobs::dsl::*;
+
+    let mut query = background_jobs
+        .select((
+            id,
+            job_type,
+            queue,
+            data,
+            priority,
+            retries,
+            enqueued_at(),
+            retry_at,
+            deadline,
+            batch_id,
+            dead_at,
+            last_error,
+            failed_at,
+            started_at,
+            finished_at,
+        ))
+        .order(id)
+        .limit(filter.limit)
+        .into_boxed();
+    if let Some(filter_id) = filter.id {
+        query = query.filter(id.eq(filter_id));
+    }
+    if let Some(filter_job_type) = &filter.job_type {
+        query = query.filter(job_type.eq(filter_job_type));
+    }
+    if let Some(filter_queue) = &filter.queue {
+        query = query.filter(queue.eq(filter_queue));
+    }
+    if let Some(filter_batch_id) = &filter.batch_id {
+        query = query.filter(batch_id.eq(filter_batch_id));
+    }
+    match filter.dead {
+        Some(true) => query = query.filter(dead_at.is_not_null()),
+        Some(false) => query = query.filter(dead_at.is_null()),
+        None => {}
+    }
+    match filter.failed {
+        Some(true) => query = query.filter(retries.gt(0)),
+        Some(false) => query = query.filter(retries.eq(0)),
+        None => {}
+    }
+    if let Some(after) = filter.after {
+        query = query.filter(id.gt(after));
+    }
+    let mut jobs = query.load::<QueuedJob>(conn)?;
+    for job in &mut jobs {
+        redact::data(
+            &mut job.data,
+            registry::redacted_fields(&job.job_type).unwrap_or_default(),
+        );
+    }
+    Ok(jobs)
+}
+
+/// The number of jobs on each queue which haven't been marked as dead,
+/// including jobs which are running or waiting to be retried. Queues without
+/// any such jobs are left out.
+pub fn queue_depths(conn: &PgConnection) -> QueryResult<HashMap<String, i64>> {
+    use crate::schema::background_jobs::dsl::*;
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+
+    let depths = background_jobs
+        .filter(dead_at.is_null())
+        .group_by(queue)
+        .select((queue, sql::<BigInt>("COUNT(*)")))
+        .load::<(String, i64)>(conn)?;
+    Ok(depths.into_iter().collect())
+}
+
+/// When the job which has waited longest to run became ready to run, or
+/// `None` if no jobs are waiting.
+///
+/// A job is ready once it is enqueued, or once the time it was enqueued for
+/// or its retry is due has come. Jobs which are running, marked as dead, or
+/// scheduled for later are not waiting.
+pub fn oldest_pending_job(conn: &PgConnection) -> QueryResult<Option<DateTime<Utc>>> {
+    use crate::schema::background_jobs::dsl::*;
+    use diesel::dsl::sql;
+    use diesel::sql_types::Timestamptz;
+
+    let ready_at = sql::<Timestamptz>(
+        "COALESCE(background_jobs.retry_at, \
+         background_jobs.created_at AT TIME ZONE current_setting('TimeZone'))",
+    );
+    background_jobs
+        .select(ready_at.clone())
+        .filter(dead_at.is_null())
+        .filter(retriable())
+        .order(ready_at)
+        .for_key_share()
+        .skip_locked()
+        .first(conn)
+        .optional()
+}
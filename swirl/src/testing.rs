@@ -0,0 +1,204 @@
+//! Fixtures for integration tests which run jobs against a real database.
+//!
+//! This module is only available with the `testing` feature. It provides the
+//! same guarantees swirl's own test suite relies on:
+//!
+//! - Only one [`TestGuard`] exists at a time, so tests which run jobs on
+//!   several connections at once do not see each other's jobs, without having
+//!   to run the whole suite with `--test-threads 1`.
+//! - Every table used by swirl is truncated when the guard is dropped, even
+//!   if the test panicked.
+//!
+//! Code which only enqueues jobs, and jobs which don't use the database, can
+//! be tested without a database with a [`TestRunner`] instead.
+//!
+//! The database given by `TEST_DATABASE_URL` is used, and must already have
+//! swirl's migrations applied. Since the tables are truncated after every
+//! test, this should not be a database you care about the contents of.
+//!
+//! ```ignore
+//! use swirl::testing::{jobs::*, TestGuard};
+//!
+//! #[test]
+//! fn failed_jobs_are_reported() {
+//!     let runner = TestGuard::builder(())
+//!         .configure(|builder| builder.thread_count(1))
+//!         .build();
+//!     let conn = runner.connection_pool().get().unwrap();
+//!     failure_job().enqueue(&conn).unwrap();
+//!
+//!     runner.run_all_pending_jobs().unwrap();
+//!     assert!(runner.check_for_failed_jobs().is_err());
+//! }
+//! ```
+
+use diesel::prelude::*;
+use diesel::r2d2;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::db::R2d2Builder;
+use crate::{Builder, Runner};
+
+pub mod jobs;
+mod sync;
+mod test_runner;
+
+pub use self::sync::Barrier;
+pub use self::test_runner::TestRunner;
+
+/// The connection pool used by runners created for tests
+pub type TestPool = r2d2::Pool<r2d2::ConnectionManager<PgConnection>>;
+
+/// Every table which is truncated when a [`TestGuard`] is dropped
+pub const TABLES: &[&str] = &[
+    "background_jobs",
+    "background_job_failures",
+    "background_job_outbox",
+    "background_job_retry_budgets",
+    "background_job_batches",
+    "background_job_attempts",
+    "background_job_schedules",
+    "background_job_audit_log",
+    "background_job_ledger",
+    "background_job_checkpoints",
+    "background_job_cancellations",
+    "background_job_heartbeats",
+    "background_job_results",
+    "background_job_dependencies",
+    "background_job_paused_queues",
+    "background_jobs_archive",
+];
+
+// Since tests using a guard deal with behavior concerning multiple connections
+// running concurrently, they have to run outside of a transaction. Therefore
+// we can't run more than one at a time.
+static TEST_MUTEX: Mutex<()> = Mutex::new(());
+
+/// The value of the `TEST_DATABASE_URL` environment variable.
+///
+/// # Panics
+///
+/// Panics if the variable is not set.
+pub fn database_url() -> String {
+    std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run tests")
+}
+
+/// A connection pool builder suitable for tests.
+///
+/// Connections are only opened when they are needed, and queries time out
+/// after a second, so a test which deadlocks fails instead of hanging.
+pub fn pool_builder() -> r2d2::Builder<r2d2::ConnectionManager<PgConnection>> {
+    r2d2::Pool::builder()
+        .min_idle(Some(0))
+        .connection_customizer(Box::new(SetStatementTimeout(1000)))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SetStatementTimeout(u64);
+
+impl r2d2::CustomizeConnection<PgConnection, r2d2::Error> for SetStatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        diesel::sql_query(format!("SET statement_timeout = {}", self.0))
+            .execute(conn)
+            .map_err(r2d2::Error::QueryError)?;
+        Ok(())
+    }
+}
+
+/// A runner which has exclusive use of the test database until it is
+/// dropped.
+///
+/// Dereferences to the [`Runner`] it wraps.
+#[allow(missing_debug_implementations)]
+pub struct TestGuard<Env: 'static> {
+    runner: Runner<Env, TestPool>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl<Env> TestGuard<Env> {
+    /// Start building a runner connected to the test database
+    pub fn builder(env: Env) -> GuardBuilder<Env> {
+        let builder = Runner::builder(env).connection_pool_builder(database_url(), pool_builder());
+        GuardBuilder { builder }
+    }
+
+    /// Create a runner with the default configuration
+    pub fn runner(env: Env) -> Self {
+        Self::builder(env).build()
+    }
+}
+
+impl TestGuard<()> {
+    /// Create a runner for jobs which do not need an environment
+    pub fn dummy_runner() -> Self {
+        Self::builder(()).build()
+    }
+}
+
+/// Builds a [`TestGuard`]
+#[allow(missing_debug_implementations)]
+pub struct GuardBuilder<Env: 'static> {
+    builder: Builder<Env, R2d2Builder>,
+}
+
+impl<Env> GuardBuilder<Env> {
+    /// Configure the runner.
+    ///
+    /// The builder is already connected to the test database, so anything
+    /// other than the connection pool can be changed.
+    pub fn configure<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(Builder<Env, R2d2Builder>) -> Builder<Env, R2d2Builder>,
+    {
+        self.builder = f(self.builder);
+        self
+    }
+
+    /// Wait for any other guard to be dropped, and build the runner
+    pub fn build(self) -> TestGuard<Env> {
+        TestGuard {
+            _lock: TEST_MUTEX.lock().unwrap_or_else(PoisonError::into_inner),
+            runner: self.builder.build(),
+        }
+    }
+}
+
+impl<Env> Deref for TestGuard<Env> {
+    type Target = Runner<Env, TestPool>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.runner
+    }
+}
+
+impl<Env> DerefMut for TestGuard<Env> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.runner
+    }
+}
+
+impl<Env> Drop for TestGuard<Env> {
+    fn drop(&mut self) {
+        let result = self
+            .runner
+            .connection_pool()
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| {
+                diesel::sql_query(format!("TRUNCATE TABLE {}", TABLES.join(", ")))
+                    .execute(&conn)
+                    .map_err(|e| e.to_string())
+            });
+
+        if let Err(e) = result {
+            // Panicking while already panicking would abort, and hide the
+            // original failure
+            if std::thread::panicking() {
+                eprintln!("Failed to clean up the test database: {}", e);
+            } else {
+                panic!("Failed to clean up the test database: {}", e);
+            }
+        }
+    }
+}
@@ -1,25 +1,32 @@
-pub use swirl::Job;
+//! Jobs with predictable behavior, for testing code which runs jobs.
 
-use swirl::errors::PerformError;
+pub use crate::Job;
 
-use crate::sync::Barrier;
+use super::Barrier;
+use crate::errors::PerformError;
 
 /// A job which takes a barrier as its environment and calls wait on it before
 /// succeeding
-#[swirl::background_job]
+#[crate::background_job]
 pub fn barrier_job(env: &Barrier) -> Result<(), PerformError> {
     env.wait();
     Ok(())
 }
 
+/// A job which always succeeds
+#[crate::background_job]
+pub fn succeeding_job() -> Result<(), PerformError> {
+    Ok(())
+}
+
 /// A job which always fails
-#[swirl::background_job]
+#[crate::background_job]
 pub fn failure_job() -> Result<(), PerformError> {
     Err("failed".into())
 }
 
-#[swirl::background_job]
 /// A job which panics
+#[crate::background_job]
 pub fn panic_job() -> Result<(), PerformError> {
     panic!()
 }
@@ -0,0 +1,25 @@
+use std::sync::{Arc, Barrier as StdBarrier, BarrierWaitResult};
+
+/// A [`std::sync::Barrier`] which can be cloned, and used as the environment
+/// of a runner.
+///
+/// This allows a test to control when a job finishes, such as to observe
+/// the runner while the job is still running.
+#[derive(Debug, Clone)]
+pub struct Barrier {
+    inner: Arc<StdBarrier>,
+}
+
+impl Barrier {
+    /// Create a barrier which blocks until `n` threads are waiting on it
+    pub fn new(n: usize) -> Self {
+        Self {
+            inner: Arc::new(StdBarrier::new(n)),
+        }
+    }
+
+    /// Block until `n` threads are waiting on this barrier
+    pub fn wait(&self) -> BarrierWaitResult {
+        self.inner.wait()
+    }
+}
@@ -0,0 +1,206 @@
+//! Running jobs in memory, for tests which don't need a database.
+
+use diesel::PgConnection;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+use crate::db::DieselPoolObj;
+use crate::errors::{EnqueueError, PerformError};
+use crate::{Job, JobContext, JobId};
+
+/// The error returned to jobs run by a [`TestRunner`] which use the database
+const NO_DATABASE: &str = "Jobs run by a TestRunner can't use the database. \
+                           Test this job with a TestGuard instead.";
+
+/// Records jobs in memory instead of enqueueing them, and runs them on the
+/// current thread.
+///
+/// Tests can enqueue jobs with a `TestRunner` in place of a connection,
+/// check which jobs were enqueued, and run them against a test environment.
+/// Nothing is written to a database, so `TEST_DATABASE_URL` doesn't need to
+/// be set.
+///
+/// ```ignore
+/// let runner = TestRunner::new(AppEnv::fake());
+/// runner.enqueue(send_welcome_email(user.id))?;
+///
+/// runner.assert_enqueued(&send_welcome_email(user.id));
+/// runner.run_all_pending_jobs()?;
+/// ```
+///
+/// Jobs are serialized when they are enqueued, and deserialized when they
+/// are run, as they would be by a [`Runner`](crate::Runner). They are given
+/// a connection pool which never has a connection to give out, so jobs which
+/// use the database should be tested with a [`TestGuard`](super::TestGuard).
+#[allow(missing_debug_implementations)]
+pub struct TestRunner<Env: 'static> {
+    environment: Env,
+    state: Mutex<State<Env>>,
+}
+
+struct State<Env> {
+    next_id: i64,
+    pending: VecDeque<EnqueuedJob<Env>>,
+}
+
+struct EnqueuedJob<Env> {
+    id: JobId,
+    job_type: &'static str,
+    queue: &'static str,
+    data: serde_json::Value,
+    binary_data: Option<Vec<u8>>,
+    unique_key: Option<String>,
+    perform: fn(&EnqueuedJob<Env>, &Env, &dyn DieselPoolObj) -> Result<(), PerformError>,
+}
+
+impl<Env> TestRunner<Env> {
+    /// A runner which runs jobs with `environment`
+    pub fn new(environment: Env) -> Self {
+        Self {
+            environment,
+            state: Mutex::new(State {
+                next_id: 1,
+                pending: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// The environment jobs are run with
+    pub fn environment(&self) -> &Env {
+        &self.environment
+    }
+
+    /// Record a job as pending, returning its id.
+    ///
+    /// As with [`Job::enqueue`], if a pending job of the same type has the
+    /// same [`unique_key`](Job::unique_key), the job is not recorded, and the
+    /// id of the pending job is returned instead.
+    pub fn enqueue<J>(&self, job: J) -> Result<JobId, EnqueueError>
+    where
+        J: Job<Environment = Env>,
+    {
+        let data = serde_json::to_value(&job)?;
+        let binary_data = job.encode_binary().map_err(EnqueueError::EncodingError)?;
+        let unique_key = job.unique_key();
+
+        let mut state = self.state.lock().unwrap();
+        if unique_key.is_some() {
+            let duplicate = state.pending.iter().find(|pending| {
+                pending.job_type == J::JOB_TYPE && pending.unique_key == unique_key
+            });
+            if let Some(duplicate) = duplicate {
+                return Ok(duplicate.id);
+            }
+        }
+        let id = JobId(state.next_id);
+        state.next_id += 1;
+        state.pending.push_back(EnqueuedJob {
+            id,
+            job_type: J::JOB_TYPE,
+            queue: J::QUEUE,
+            data,
+            binary_data,
+            unique_key,
+            perform: perform::<J>,
+        });
+        Ok(id)
+    }
+
+    /// The pending jobs of type `J`, in the order they were enqueued
+    pub fn enqueued<J: Job>(&self) -> Vec<J> {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .filter(|pending| pending.job_type == J::JOB_TYPE)
+            .map(|pending| {
+                serde_json::from_value(pending.data.clone())
+                    .expect("an enqueued job could not be deserialized")
+            })
+            .collect()
+    }
+
+    /// The number of jobs which are pending, of any type
+    pub fn pending_job_count(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    /// Assert that a job of the same type with the same arguments as `job` is
+    /// pending.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such job, listing the arguments of the pending
+    /// jobs of that type.
+    pub fn assert_enqueued<J: Job>(&self, job: &J) {
+        let expected = serde_json::to_value(job).expect("the job could not be serialized");
+        let state = self.state.lock().unwrap();
+        let enqueued = state
+            .pending
+            .iter()
+            .filter(|pending| pending.job_type == J::JOB_TYPE)
+            .map(|pending| &pending.data)
+            .collect::<Vec<_>>();
+        assert!(
+            enqueued.contains(&&expected),
+            "Expected a {} job with the arguments {} to be enqueued, but the pending {} jobs were {:?}",
+            J::JOB_TYPE,
+            expected,
+            J::JOB_TYPE,
+            enqueued,
+        );
+    }
+
+    /// Run the pending jobs one at a time in the order they were enqueued,
+    /// until none are left.
+    ///
+    /// Each job is removed before it is run, and failed jobs are not retried.
+    /// The first job which fails stops the run, and its error is returned.
+    /// The jobs after it are still pending, and are run by calling this
+    /// again. A job which panics fails the test.
+    pub fn run_all_pending_jobs(&self) -> Result<(), PerformError> {
+        loop {
+            // The lock is released before the job runs, so it can enqueue
+            // more jobs
+            let job = match self.state.lock().unwrap().pending.pop_front() {
+                Some(job) => job,
+                None => return Ok(()),
+            };
+            let context = JobContext::new(job.id.0, job.job_type.into()).on_queue(job.queue);
+            context.enter(|| (job.perform)(&job, &self.environment, &NoDatabase))?;
+        }
+    }
+}
+
+fn perform<J: Job>(
+    job: &EnqueuedJob<J::Environment>,
+    env: &J::Environment,
+    pool: &dyn DieselPoolObj,
+) -> Result<(), PerformError> {
+    let job = match &job.binary_data {
+        Some(bytes) => {
+            J::decode_binary(bytes).map_err(|e| PerformError::from(e as Box<dyn Error>))?
+        }
+        None => serde_json::from_value::<J>(job.data.clone())?,
+    };
+    job.perform(env, pool)
+}
+
+/// The connection pool given to jobs run by a [`TestRunner`]
+struct NoDatabase;
+
+impl DieselPoolObj for NoDatabase {
+    fn get(&self) -> Result<Box<dyn Deref<Target = PgConnection> + '_>, Box<dyn Error>> {
+        Err(NO_DATABASE.into())
+    }
+
+    fn with_connection(
+        &self,
+        _: &dyn Fn(&PgConnection) -> Result<(), PerformError>,
+    ) -> Result<(), PerformError> {
+        Err(NO_DATABASE.into())
+    }
+}
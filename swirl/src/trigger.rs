@@ -0,0 +1,189 @@
+//! Enqueue jobs from the database whenever rows in a table change.
+//!
+//! [`EnqueueTrigger`] generates a Postgres trigger which inserts a job into
+//! the queue for every row which is inserted, updated, or deleted. This is
+//! useful for simple pipelines, such as reindexing a row whenever it changes,
+//! without needing to enqueue jobs everywhere the table is written to.
+//!
+//! The generated SQL is intended to be placed in a migration:
+//!
+//! ```ignore
+//! let trigger = EnqueueTrigger::new::<reindex_post::Job>("posts");
+//! println!("{}", trigger.up_sql());
+//! println!("{}", trigger.down_sql());
+//! ```
+//!
+//! Alternatively, [`EnqueueTrigger::create`] can be called from code which
+//! runs your migrations.
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use std::fmt::Write;
+
+use crate::Job;
+
+/// A change to a row which causes a job to be enqueued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEvent {
+    /// A row was inserted
+    Insert,
+    /// A row was updated
+    Update,
+    /// A row was deleted
+    Delete,
+}
+
+impl TriggerEvent {
+    fn keyword(self) -> &'static str {
+        match self {
+            TriggerEvent::Insert => "INSERT",
+            TriggerEvent::Update => "UPDATE",
+            TriggerEvent::Delete => "DELETE",
+        }
+    }
+}
+
+/// A trigger which enqueues a job whenever rows in a table change.
+///
+/// The data of each job is a JSON object built from the changed row. By
+/// default this is only the `id` column, so `#[background_job] fn
+/// reindex_post(env: &Environment, id: i32)` would be enqueued with the ID of
+/// the row which changed. For deleted rows, the values from before the row was
+/// deleted are used.
+#[derive(Debug, Clone)]
+pub struct EnqueueTrigger {
+    name: String,
+    table: String,
+    job_type: String,
+    queue: String,
+    priority: i16,
+    events: Vec<TriggerEvent>,
+    columns: Vec<String>,
+}
+
+impl EnqueueTrigger {
+    /// Create a trigger which enqueues `J` whenever a row in `table` is
+    /// inserted, updated, or deleted
+    pub fn new<J: Job>(table: &str) -> Self {
+        Self {
+            name: format!("swirl_enqueue_{}_on_{}", J::JOB_TYPE, table),
+            table: table.into(),
+            job_type: J::JOB_TYPE.into(),
+            queue: J::QUEUE.into(),
+            priority: J::PRIORITY,
+            events: vec![
+                TriggerEvent::Insert,
+                TriggerEvent::Update,
+                TriggerEvent::Delete,
+            ],
+            columns: vec!["id".into()],
+        }
+    }
+
+    /// The name of the trigger and the function it calls.
+    ///
+    /// Defaults to `swirl_enqueue_{job_type}_on_{table}`
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// The changes which cause a job to be enqueued.
+    ///
+    /// Defaults to inserts, updates, and deletes
+    pub fn events(mut self, events: &[TriggerEvent]) -> Self {
+        self.events = events.to_vec();
+        self
+    }
+
+    /// The columns of the changed row which are copied into the job's data.
+    ///
+    /// Each column becomes a key of the JSON object the job is deserialized
+    /// from, so these should match the fields of the job. Defaults to `id`.
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = columns.iter().map(|&c| c.into()).collect();
+        self
+    }
+
+    /// The SQL which creates the trigger
+    pub fn up_sql(&self) -> String {
+        let name = quote_identifier(&self.name);
+        let data = self
+            .columns
+            .iter()
+            .map(|c| format!("{}, changed_row.{}", quote_literal(c), quote_identifier(c)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let events = self
+            .events
+            .iter()
+            .map(|e| e.keyword())
+            .collect::<Vec<_>>()
+            .join(" OR ");
+
+        let mut sql = String::new();
+        writeln!(sql, "CREATE FUNCTION {}() RETURNS trigger AS $$", name).unwrap();
+        writeln!(sql, "DECLARE\n  changed_row RECORD;\nBEGIN").unwrap();
+        writeln!(sql, "  IF TG_OP = 'DELETE' THEN").unwrap();
+        writeln!(
+            sql,
+            "    changed_row := OLD;\n  ELSE\n    changed_row := NEW;"
+        )
+        .unwrap();
+        writeln!(sql, "  END IF;").unwrap();
+        writeln!(
+            sql,
+            "  INSERT INTO background_jobs (job_type, data, queue, priority)"
+        )
+        .unwrap();
+        writeln!(
+            sql,
+            "    VALUES ({}, jsonb_build_object({}), {}, {});",
+            quote_literal(&self.job_type),
+            data,
+            quote_literal(&self.queue),
+            self.priority,
+        )
+        .unwrap();
+        writeln!(sql, "  RETURN NULL;\nEND\n$$ LANGUAGE plpgsql;\n").unwrap();
+        writeln!(
+            sql,
+            "CREATE TRIGGER {} AFTER {} ON {} FOR EACH ROW EXECUTE PROCEDURE {}();",
+            name,
+            events,
+            quote_identifier(&self.table),
+            name,
+        )
+        .unwrap();
+        sql
+    }
+
+    /// The SQL which removes the trigger
+    pub fn down_sql(&self) -> String {
+        let name = quote_identifier(&self.name);
+        format!(
+            "DROP TRIGGER {} ON {};\nDROP FUNCTION {}();\n",
+            name,
+            quote_identifier(&self.table),
+            name,
+        )
+    }
+
+    /// Create the trigger
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<()> {
+        conn.batch_execute(&self.up_sql())
+    }
+
+    /// Remove the trigger
+    pub fn drop(&self, conn: &PgConnection) -> QueryResult<()> {
+        conn.batch_execute(&self.down_sql())
+    }
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn quote_literal(literal: &str) -> String {
+    format!("'{}'", literal.replace('\'', "''"))
+}
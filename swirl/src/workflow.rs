@@ -0,0 +1,170 @@
+//! Sets of jobs which depend on each other.
+//!
+//! A [`Workflow`] is built up one job at a time, and each job can name the
+//! jobs added before it which must succeed before it is run. Every job is
+//! enqueued in a single transaction, and runners don't claim a job until all
+//! of its prerequisites have succeeded. A prerequisite's jobs are released in
+//! the transaction which removes it from the queue, so there is no window
+//! where a job can run early, or be forgotten.
+//!
+//! ```ignore
+//! let mut workflow = Workflow::new();
+//! let upload = workflow.add(upload_video(video_id));
+//! let thumbnails = SIZES
+//!     .iter()
+//!     .map(|&size| workflow.add_after(thumbnail(video_id, size), &[upload]))
+//!     .collect::<Vec<_>>();
+//! workflow.add_after(publish(video_id), &thumbnails);
+//! workflow.enqueue(&conn)?;
+//! ```
+//!
+//! A job which fails is retried as normal, and the jobs which depend on it
+//! wait while it does, including while it is dead. If a job is cancelled, or
+//! dropped after its deadline, every job which depends on it is deleted.
+
+use diesel::{Connection, PgConnection};
+use std::fmt;
+
+use crate::errors::EnqueueError;
+use crate::inline::{self, INLINE_JOB_ID};
+use crate::storage::{self, NewJob};
+use crate::{Job, JobId};
+
+/// Jobs to be enqueued together, which are each run once the jobs they
+/// depend on have succeeded
+#[derive(Default)]
+pub struct Workflow {
+    steps: Vec<Step>,
+}
+
+/// A job which has been added to a [`Workflow`], which later jobs can depend
+/// on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StepId(usize);
+
+struct Step {
+    job: Box<dyn ErasedJob>,
+    after: Vec<StepId>,
+}
+
+impl Workflow {
+    /// Create a workflow with no jobs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job which can be run right away
+    pub fn add<T: Job + 'static>(&mut self, job: T) -> StepId {
+        self.add_after(job, &[])
+    }
+
+    /// Add a job which is run once every job in `after` has succeeded.
+    ///
+    /// Since jobs can only depend on jobs which were added before them, a
+    /// workflow can't have cycles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a step in `after` was not added to this workflow.
+    pub fn add_after<T: Job + 'static>(&mut self, job: T, after: &[StepId]) -> StepId {
+        let id = StepId(self.steps.len());
+        assert!(
+            after.iter().all(|step| *step < id),
+            "a job can only depend on jobs added to the same workflow before it"
+        );
+        self.steps.push(Step {
+            job: Box::new(job),
+            after: after.to_vec(),
+        });
+        id
+    }
+
+    /// The number of jobs in the workflow
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns `true` if no jobs have been added
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Enqueue every job in the workflow, returning their ids in the order
+    /// they were added.
+    ///
+    /// Jobs whose environment is being
+    /// [performed inline](crate::perform_jobs_inline) are performed right
+    /// away once the jobs they depend on have been performed. Jobs which
+    /// depend on a job which was enqueued are enqueued too.
+    pub fn enqueue(self, conn: &PgConnection) -> Result<Vec<JobId>, EnqueueError> {
+        conn.transaction(|| {
+            // `None` for jobs which were performed inline, which have
+            // nothing to wait for
+            let mut enqueued = Vec::<Option<JobId>>::with_capacity(self.steps.len());
+            for step in self.steps {
+                let after = step
+                    .after
+                    .iter()
+                    .filter_map(|step| enqueued[step.0])
+                    .collect::<Vec<_>>();
+                enqueued.push(step.job.enqueue_after(conn, &after)?);
+            }
+            Ok(enqueued
+                .into_iter()
+                .map(|id| id.unwrap_or(INLINE_JOB_ID))
+                .collect())
+        })
+    }
+}
+
+impl fmt::Debug for Workflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.steps
+                    .iter()
+                    .map(|step| (step.job.job_type(), &step.after)),
+            )
+            .finish()
+    }
+}
+
+/// A job in a [`Workflow`] or a [`Batch`](crate::Batch), whose type has
+/// been erased
+pub(crate) trait ErasedJob {
+    fn job_type(&self) -> &'static str;
+
+    fn new_job(&self) -> Result<NewJob<'static>, EnqueueError>;
+
+    /// Enqueue the job to run once the jobs `after` have succeeded. Returns
+    /// `None` if it was performed inline instead.
+    fn enqueue_after(
+        self: Box<Self>,
+        conn: &PgConnection,
+        after: &[JobId],
+    ) -> Result<Option<JobId>, EnqueueError>;
+}
+
+impl<T: Job> ErasedJob for T {
+    fn job_type(&self) -> &'static str {
+        T::JOB_TYPE
+    }
+
+    fn new_job(&self) -> Result<NewJob<'static>, EnqueueError> {
+        NewJob::of(self)
+    }
+
+    fn enqueue_after(
+        self: Box<Self>,
+        conn: &PgConnection,
+        after: &[JobId],
+    ) -> Result<Option<JobId>, EnqueueError> {
+        if after.is_empty() {
+            if let Some(inline) = inline::environment::<T>() {
+                return inline.perform(conn, *self).map(|_| None);
+            }
+        }
+        let job = self.new_job()?;
+        Ok(Some(storage::enqueue_job_after(conn, &job, after)?))
+    }
+}
@@ -0,0 +1,380 @@
+//! `swirl`, a command line tool for inspecting and managing the jobs in a
+//! swirl queue, without writing SQL by hand.
+//!
+//! It connects to the database in `--database-url`, or in `DATABASE_URL`,
+//! which can also be set in a `.env` file. Changes to the queue are recorded
+//! in the audit log as made by `--actor`, or by `$USER`.
+
+use chrono::Utc;
+use diesel::prelude::*;
+use std::env;
+use std::error::Error;
+use std::process;
+
+use swirl::audit::Auditor;
+use swirl::storage::{self, JobFilter, JobStatus, QueuedJob};
+use swirl::JobId;
+
+const USAGE: &str = "\
+Usage: swirl [--database-url URL] [--actor NAME] [--reason TEXT] <command> [options]
+
+Options:
+    --database-url URL  the database to connect to (default $DATABASE_URL)
+    --actor NAME        who is making changes, for the audit log (default $USER)
+    --reason TEXT       why changes are being made, for the audit log
+
+Commands:
+    list        List the jobs in the queue, oldest first
+                    --type TYPE    only jobs of this type
+                    --queue QUEUE  only jobs on this queue
+                    --batch BATCH  only jobs enqueued in this batch
+                    --pending      only jobs which haven't failed yet
+                    --failed       only jobs which have failed at least once
+                    --dead         only jobs which have been marked as dead
+                    --limit N      list at most N jobs (default 100)
+                    --after ID     only jobs enqueued after the job ID
+    status ID   Show where the job ID is in its lifecycle
+    retry       Make dead jobs pending again
+                    ID             the job ID
+                    --type TYPE    every dead job of this type
+    delete      Remove jobs which aren't running from the queue
+                    ID             the job ID
+                    --type TYPE    every job of this type
+    queues      Show the number of pending jobs on each queue
+    pause QUEUE Stop every runner from starting jobs on QUEUE
+    resume QUEUE
+                Start running jobs on a paused QUEUE again
+    migrate     Create or update swirl's tables, running the migrations
+                which haven't been run yet
+    help        Show this message";
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    List(JobFilter),
+    Status(JobId),
+    Retry(Target),
+    Delete(Target),
+    Queues,
+    Pause(String),
+    Resume(String),
+    Migrate,
+    Help,
+}
+
+/// The jobs `retry` and `delete` act on
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Job(i64),
+    JobType(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Args {
+    database_url: Option<String>,
+    actor: Option<String>,
+    reason: Option<String>,
+    command: Command,
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> CliResult<()> {
+    if args.command == Command::Help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let database_url = match args.database_url {
+        Some(database_url) => database_url,
+        None => env::var("DATABASE_URL")
+            .map_err(|_| "DATABASE_URL must be set, or passed with --database-url")?,
+    };
+    let conn = PgConnection::establish(&database_url)?;
+    let actor = args
+        .actor
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_else(|| "swirl".into());
+    let mut auditor = Auditor::new(&conn, &actor);
+    if let Some(reason) = &args.reason {
+        auditor = auditor.reason(reason);
+    }
+
+    match args.command {
+        Command::List(filter) => list(&conn, &filter),
+        Command::Status(job_id) => status(&conn, job_id),
+        Command::Retry(Target::Job(job_id)) => {
+            if auditor.requeue_dead_job(job_id)? {
+                println!("Requeued job {}", job_id);
+                Ok(())
+            } else {
+                Err(format!("Job {} is not dead", job_id).into())
+            }
+        }
+        Command::Retry(Target::JobType(job_type)) => {
+            let requeued = auditor.requeue_dead_jobs(&job_type)?;
+            println!("Requeued {} {} jobs", requeued, job_type);
+            Ok(())
+        }
+        Command::Delete(Target::Job(job_id)) => {
+            if auditor.delete_job(job_id)? {
+                println!("Deleted job {}", job_id);
+                Ok(())
+            } else {
+                Err(format!("Job {} doesn't exist, or is running", job_id).into())
+            }
+        }
+        Command::Delete(Target::JobType(job_type)) => {
+            let deleted = auditor.delete_jobs(&job_type)?;
+            println!("Deleted {} {} jobs", deleted, job_type);
+            Ok(())
+        }
+        Command::Queues => queues(&conn),
+        Command::Pause(queue) => {
+            if auditor.pause_queue(&queue)? {
+                println!("Paused {}", queue);
+            } else {
+                println!("{} is already paused", queue);
+            }
+            Ok(())
+        }
+        Command::Resume(queue) => {
+            if auditor.resume_queue(&queue)? {
+                println!("Resumed {}", queue);
+                Ok(())
+            } else {
+                Err(format!("{} is not paused", queue).into())
+            }
+        }
+        Command::Migrate => {
+            let run = swirl::run_migrations(&conn)?;
+            if run.is_empty() {
+                println!("No migrations to run");
+            }
+            for migration in run {
+                println!("Ran {}", migration);
+            }
+            Ok(())
+        }
+        Command::Help => unreachable!(),
+    }
+}
+
+fn list(conn: &PgConnection, filter: &JobFilter) -> CliResult<()> {
+    let jobs = storage::list_jobs(conn, filter)?;
+    println!(
+        "{:<10} {:<30} {:<12} {:<8} {:<7} {:<20} LAST ERROR",
+        "ID", "TYPE", "QUEUE", "STATE", "RETRIES", "ENQUEUED AT"
+    );
+    for job in &jobs {
+        println!(
+            "{:<10} {:<30} {:<12} {:<8} {:<7} {:<20} {}",
+            job.id,
+            job.job_type,
+            job.queue,
+            state(job),
+            job.retries,
+            job.enqueued_at.format("%Y-%m-%d %H:%M:%S"),
+            job.last_error
+                .as_deref()
+                .and_then(|e| e.lines().next())
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn state(job: &QueuedJob) -> &'static str {
+    if job.dead_at.is_some() {
+        "dead"
+    } else if job.retries > 0 {
+        "failed"
+    } else {
+        "pending"
+    }
+}
+
+fn status(conn: &PgConnection, job_id: JobId) -> CliResult<()> {
+    match storage::job_status(conn, job_id)? {
+        JobStatus::Pending => println!("Job {} is pending", job_id),
+        JobStatus::Running => println!("Job {} is running", job_id),
+        JobStatus::Failed {
+            retries,
+            last_error,
+            dead,
+        } => {
+            let state = if dead {
+                "dead"
+            } else {
+                "waiting to be retried"
+            };
+            println!(
+                "Job {} has failed {} times, and is {}",
+                job_id, retries, state
+            );
+            if let Some(last_error) = last_error {
+                println!("Last error: {}", last_error);
+            }
+        }
+        JobStatus::Done => println!("Job {} is no longer in the queue", job_id),
+    }
+    Ok(())
+}
+
+fn queues(conn: &PgConnection) -> CliResult<()> {
+    let mut depths = storage::queue_depths(conn)?.into_iter().collect::<Vec<_>>();
+    depths.sort();
+    println!("{:<20} PENDING", "QUEUE");
+    for (queue, depth) in depths {
+        println!("{:<20} {}", queue, depth);
+    }
+    let paused = storage::paused_queues(conn)?;
+    if !paused.is_empty() {
+        println!("Paused: {}", paused.join(", "));
+    }
+    if let Some(ready_at) = storage::oldest_pending_job(conn)? {
+        let age = (Utc::now() - ready_at).num_seconds().max(0);
+        println!("The oldest pending job has been waiting for {}s", age);
+    }
+    Ok(())
+}
+
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+    let mut args = args.into_iter().peekable();
+    let mut database_url = None;
+    let mut actor = None;
+    let mut reason = None;
+    while let Some(option) = args.peek().filter(|arg| arg.starts_with("--")).cloned() {
+        let option_value = match option.as_str() {
+            "--database-url" => &mut database_url,
+            "--actor" => &mut actor,
+            "--reason" => &mut reason,
+            _ => break,
+        };
+        args.next();
+        *option_value = Some(value(&mut args, &option)?);
+    }
+
+    let command = match args.next().as_deref() {
+        Some("list") => {
+            let mut filter = JobFilter::new();
+            while let Some(arg) = args.next() {
+                filter = match arg.as_str() {
+                    "--type" => filter.job_type(value(&mut args, &arg)?),
+                    "--queue" => filter.queue(value(&mut args, &arg)?),
+                    "--batch" => filter.batch(value(&mut args, &arg)?),
+                    "--pending" => filter.failed(false),
+                    "--failed" => filter.failed(true),
+                    "--dead" => filter.dead(true),
+                    "--limit" => filter.limit(number(&value(&mut args, &arg)?)?),
+                    "--after" => filter.after(JobId(number(&value(&mut args, &arg)?)?)),
+                    _ => return Err(format!("Unknown option {}", arg)),
+                };
+            }
+            Command::List(filter)
+        }
+        Some("status") => {
+            let job_id = args.next().ok_or("status needs a job id")?;
+            Command::Status(JobId(number(&job_id)?))
+        }
+        Some("retry") => Command::Retry(target(&mut args, "retry")?),
+        Some("delete") => Command::Delete(target(&mut args, "delete")?),
+        Some("queues") => Command::Queues,
+        Some("pause") => Command::Pause(args.next().ok_or("pause needs a queue")?),
+        Some("resume") => Command::Resume(args.next().ok_or("resume needs a queue")?),
+        Some("migrate") => Command::Migrate,
+        Some("help") | Some("--help") | Some("-h") | None => Command::Help,
+        Some(command) => return Err(format!("Unknown command {}", command)),
+    };
+    if let Some(arg) = args.next() {
+        return Err(format!("Unexpected argument {}", arg));
+    }
+    Ok(Args {
+        database_url,
+        actor,
+        reason,
+        command,
+    })
+}
+
+/// The value of an option which takes one
+fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} needs a value", option))
+}
+
+fn number(arg: &str) -> Result<i64, String> {
+    arg.parse().map_err(|_| format!("{} is not a number", arg))
+}
+
+fn target<I: Iterator<Item = String>>(args: &mut I, command: &str) -> Result<Target, String> {
+    match args.next().as_deref() {
+        Some("--type") => Ok(Target::JobType(value(args, "--type")?)),
+        Some(job_id) => Ok(Target::Job(number(job_id)?)),
+        None => Err(format!("{} needs a job id, or --type", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn commands_and_their_options_are_parsed() {
+        let args = parse(&["list", "--type", "send_email", "--dead", "--limit", "5"]).unwrap();
+        assert_eq!(None, args.database_url);
+        assert_eq!(
+            Command::List(JobFilter::new().job_type("send_email").dead(true).limit(5)),
+            args.command
+        );
+
+        let args = parse(&["--database-url", "postgres://localhost/app", "retry", "12"]).unwrap();
+        assert_eq!(Some("postgres://localhost/app".into()), args.database_url);
+        assert_eq!(Command::Retry(Target::Job(12)), args.command);
+
+        let args = parse(&["--actor", "alice", "--reason", "INC-1234", "delete", "12"]).unwrap();
+        assert_eq!(Some("alice".into()), args.actor);
+        assert_eq!(Some("INC-1234".into()), args.reason);
+        assert_eq!(Command::Delete(Target::Job(12)), args.command);
+
+        let args = parse(&["delete", "--type", "send_email"]).unwrap();
+        assert_eq!(None, args.actor);
+        assert_eq!(
+            Command::Delete(Target::JobType("send_email".into())),
+            args.command
+        );
+        let args = parse(&["pause", "mailers"]).unwrap();
+        assert_eq!(Command::Pause("mailers".into()), args.command);
+        assert_eq!(Command::Help, parse(&[]).unwrap().command);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert!(parse(&["list", "--limit"]).is_err());
+        assert!(parse(&["list", "--limit", "many"]).is_err());
+        assert!(parse(&["list", "--verbose"]).is_err());
+        assert!(parse(&["status"]).is_err());
+        assert!(parse(&["retry"]).is_err());
+        assert!(parse(&["queues", "default"]).is_err());
+        assert!(parse(&["pause"]).is_err());
+        assert!(parse(&["--actor"]).is_err());
+        assert!(parse(&["launch"]).is_err());
+    }
+}
@@ -0,0 +1,523 @@
+//! A web dashboard for inspecting and managing the jobs in a swirl queue.
+//!
+//! It shows the pending jobs on each queue, how many jobs succeeded and
+//! failed in each of the last 24 hours, and lists of pending, failed, and
+//! dead jobs, which can be retried or deleted. Queues can be paused and
+//! resumed. Everything is read from the same tables the runner uses.
+//! Arguments marked with `#[redact]` are masked, and a job's arguments are
+//! only shown if its job type is registered in the binary serving the
+//! dashboard, since otherwise it isn't known which of them are secret.
+//!
+//! [`Dashboard::handle`] takes an [`http::Request`] and returns an
+//! [`http::Response`], so it can be mounted in any framework built on the
+//! `http` crate, such as axum or actix-web:
+//!
+//! ```ignore
+//! let dashboard = Dashboard::new().base_path("/admin/jobs");
+//! let response = dashboard.handle(&conn, &request);
+//! ```
+//!
+//! It can also be served on its own with the `swirl-dashboard` binary. The
+//! dashboard has no authentication, so it should only be mounted behind
+//! whatever protects the rest of an application's admin pages. Actions sent
+//! from other sites are rejected, so a page an operator visits can't make
+//! changes with their credentials.
+//!
+//! Actions are recorded in swirl's [audit log](swirl::audit), as made by the
+//! [`Actor`] in the request's extensions, which an application's
+//! authentication middleware can insert, or by the dashboard's
+//! [default actor](Dashboard::actor).
+
+#![deny(warnings)]
+
+use chrono::Utc;
+use diesel::prelude::*;
+use http::{header, Method, Request, Response, StatusCode};
+use std::error::Error;
+
+use swirl::audit::Auditor;
+use swirl::storage::{self, JobFilter};
+use swirl::{admin, JobId};
+
+mod pages;
+
+/// The actor actions are recorded as if none is configured
+const DEFAULT_ACTOR: &str = "swirl-dashboard";
+
+/// Who is making a request, for the audit log, such as an operator's email
+/// address. Insert it into a request's extensions to record the request's
+/// actions as made by them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor(pub String);
+
+/// The dashboard's pages and actions, served under a base path
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard {
+    base_path: String,
+    actor: Option<String>,
+}
+
+impl Dashboard {
+    /// A dashboard served from the root of the site
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve the dashboard under `path`, such as `/admin/jobs`. Requests for
+    /// paths outside of it are not found.
+    pub fn base_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.base_path = path.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Record actions in the audit log as made by `actor` when a request has
+    /// no [`Actor`]. Defaults to `swirl-dashboard`.
+    pub fn actor<S: Into<String>>(mut self, actor: S) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Responds to a request for one of the dashboard's pages or actions.
+    ///
+    /// Pages are requested with `GET`, and actions, such as retrying a job,
+    /// with `POST`. Actions redirect to the page showing their result. The
+    /// body of the request is never read.
+    ///
+    /// Actions whose `Sec-Fetch-Site` or `Origin` header shows they were sent
+    /// from another site are forbidden. A proxy in front of the dashboard must
+    /// pass on the `Host` header it was sent for `Origin` to match it.
+    pub fn handle<B>(&self, conn: &PgConnection, request: &Request<B>) -> Response<String> {
+        if request.method() == Method::POST && !is_same_origin(request) {
+            return error(StatusCode::FORBIDDEN, "Cross-site requests are not allowed");
+        }
+        let route = request
+            .uri()
+            .path()
+            .strip_prefix(&self.base_path)
+            // `/admin/jobsjobs` isn't under `/admin/jobs`
+            .filter(|path| path.is_empty() || path.starts_with('/'))
+            .ok_or(StatusCode::NOT_FOUND)
+            .and_then(|path| route(request.method(), path, request.uri().query()));
+        let actor = match request.extensions().get::<Actor>() {
+            Some(Actor(actor)) => actor,
+            None => self.actor.as_deref().unwrap_or(DEFAULT_ACTOR),
+        };
+        let response = match route {
+            Ok(route) => self.respond(conn, Auditor::new(conn, actor), route),
+            Err(status) => Ok(error(status, "")),
+        };
+        response.unwrap_or_else(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
+    }
+
+    fn respond(
+        &self,
+        conn: &PgConnection,
+        auditor: Auditor<'_>,
+        route: Route,
+    ) -> Result<Response<String>, Box<dyn Error>> {
+        let base = &self.base_path;
+        Ok(match route {
+            Route::Overview => {
+                let mut queues = storage::queue_depths(conn)?.into_iter().collect::<Vec<_>>();
+                let paused = storage::paused_queues(conn)?;
+                for queue in &paused {
+                    if !queues.iter().any(|(q, _)| q == queue) {
+                        queues.push((queue.clone(), 0));
+                    }
+                }
+                queues.sort();
+                let overview = pages::Overview {
+                    queues,
+                    paused,
+                    counts: job_counts(conn)?,
+                    throughput: admin::throughput(conn, Utc::now() - chrono::Duration::hours(24))?,
+                };
+                html(pages::overview(base, &overview))
+            }
+            Route::Jobs(list) => {
+                let jobs = storage::list_jobs(conn, &list.filter())?;
+                html(pages::jobs(base, &list, &jobs))
+            }
+            Route::Job(job_id) => {
+                let job = storage::list_jobs(conn, &JobFilter::new().id(JobId(job_id)))?
+                    .into_iter()
+                    .next();
+                let status = storage::job_status(conn, JobId(job_id))?;
+                let attempts = admin::job_attempts(conn, job_id)?;
+                html(pages::job(base, job_id, &status, job.as_ref(), &attempts))
+            }
+            Route::Retry(job_id) => {
+                if auditor.requeue_dead_job(job_id)? {
+                    redirect(&format!("{}/jobs/{}", base, job_id))
+                } else {
+                    error(StatusCode::CONFLICT, &format!("Job {} is not dead", job_id))
+                }
+            }
+            Route::Delete(job_id) => {
+                if auditor.delete_job(job_id)? {
+                    redirect(&format!("{}/jobs", base))
+                } else {
+                    let message = format!("Job {} doesn't exist, or is running", job_id);
+                    error(StatusCode::CONFLICT, &message)
+                }
+            }
+            Route::Pause(queue) => {
+                auditor.pause_queue(&queue)?;
+                redirect(&format!("{}/", base))
+            }
+            Route::Resume(queue) => {
+                auditor.resume_queue(&queue)?;
+                redirect(&format!("{}/", base))
+            }
+        })
+    }
+}
+
+/// A page or action of the dashboard
+#[derive(Debug, Clone, PartialEq)]
+enum Route {
+    Overview,
+    Jobs(JobList),
+    Job(i64),
+    Retry(i64),
+    Delete(i64),
+    Pause(String),
+    Resume(String),
+}
+
+/// Which jobs are listed on the jobs page
+#[derive(Debug, Clone, Default, PartialEq)]
+struct JobList {
+    state: Option<State>,
+    queue: Option<String>,
+    job_type: Option<String>,
+    after: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Jobs which haven't failed yet
+    Pending,
+    /// Jobs which have failed, and will be retried
+    Failed,
+    Dead,
+}
+
+impl State {
+    const ALL: [State; 3] = [State::Pending, State::Failed, State::Dead];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Pending => "pending",
+            State::Failed => "failed",
+            State::Dead => "dead",
+        }
+    }
+}
+
+/// The most jobs listed on each page
+const PAGE_SIZE: i64 = 100;
+
+impl JobList {
+    fn parse(query: Option<&str>) -> Result<Self, StatusCode> {
+        let mut list = JobList::default();
+        for (key, value) in query.into_iter().flat_map(parse_query) {
+            match &*key {
+                "state" => {
+                    let state = State::ALL.iter().find(|s| s.as_str() == value);
+                    list.state = Some(*state.ok_or(StatusCode::BAD_REQUEST)?);
+                }
+                "queue" => list.queue = Some(value),
+                "type" => list.job_type = Some(value),
+                "after" => list.after = Some(value.parse().map_err(|_| StatusCode::BAD_REQUEST)?),
+                _ => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+        Ok(list)
+    }
+
+    fn filter(&self) -> JobFilter {
+        let mut filter = JobFilter::new().limit(PAGE_SIZE);
+        filter = match self.state {
+            Some(State::Pending) => filter.dead(false).failed(false),
+            Some(State::Failed) => filter.dead(false).failed(true),
+            Some(State::Dead) => filter.dead(true),
+            None => filter,
+        };
+        if let Some(queue) = &self.queue {
+            filter = filter.queue(queue.as_str());
+        }
+        if let Some(job_type) = &self.job_type {
+            filter = filter.job_type(job_type.as_str());
+        }
+        if let Some(after) = self.after {
+            filter = filter.after(JobId(after));
+        }
+        filter
+    }
+
+    /// The query string which lists these jobs, with `after` replaced
+    fn query(&self, after: Option<i64>) -> String {
+        let mut params = Vec::new();
+        if let Some(state) = self.state {
+            params.push(format!("state={}", state.as_str()));
+        }
+        if let Some(queue) = &self.queue {
+            params.push(format!("queue={}", encode(queue)));
+        }
+        if let Some(job_type) = &self.job_type {
+            params.push(format!("type={}", encode(job_type)));
+        }
+        if let Some(after) = after {
+            params.push(format!("after={}", after));
+        }
+        params.join("&")
+    }
+}
+
+fn route(method: &Method, path: &str, query: Option<&str>) -> Result<Route, StatusCode> {
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let parse_id = |id: &str| match id.parse::<i64>() {
+        Ok(id) if id > 0 => Ok(id),
+        _ => Err(StatusCode::NOT_FOUND),
+    };
+    match (method, segments.as_slice()) {
+        (&Method::GET, []) => Ok(Route::Overview),
+        (&Method::GET, ["jobs"]) => JobList::parse(query).map(Route::Jobs),
+        (&Method::GET, ["jobs", id]) => parse_id(id).map(Route::Job),
+        (&Method::POST, ["jobs", id, "retry"]) => parse_id(id).map(Route::Retry),
+        (&Method::POST, ["jobs", id, "delete"]) => parse_id(id).map(Route::Delete),
+        (&Method::POST, ["queues", queue, "pause"]) => Ok(Route::Pause(decode(queue))),
+        (&Method::POST, ["queues", queue, "resume"]) => Ok(Route::Resume(decode(queue))),
+        (_, [])
+        | (_, ["jobs"])
+        | (_, ["jobs", _])
+        | (_, ["jobs", _, "retry"])
+        | (_, ["jobs", _, "delete"])
+        | (_, ["queues", _, "pause"])
+        | (_, ["queues", _, "resume"]) => Err(StatusCode::METHOD_NOT_ALLOWED),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Whether a request was sent from one of the dashboard's own pages, or by
+/// something other than a browser. Browsers send `Sec-Fetch-Site` or `Origin`
+/// with every `POST`.
+fn is_same_origin<B>(request: &Request<B>) -> bool {
+    let headers = request.headers();
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return site == "same-origin" || site == "none";
+    }
+    let origin = match headers.get(header::ORIGIN) {
+        Some(origin) => origin.to_str().unwrap_or_default(),
+        None => return true,
+    };
+    let origin_host = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"));
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    match (origin_host, host) {
+        (Some(origin_host), Some(host)) => origin_host.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+/// The number of pending, failed, and dead jobs
+fn job_counts(conn: &PgConnection) -> QueryResult<pages::Counts> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::BigInt;
+    use swirl::schema::background_jobs::dsl::*;
+
+    let (pending, failed, dead) = background_jobs
+        .select(sql::<(BigInt, BigInt, BigInt)>(
+            "COUNT(*) FILTER (WHERE dead_at IS NULL AND retries = 0), \
+             COUNT(*) FILTER (WHERE dead_at IS NULL AND retries > 0), \
+             COUNT(*) FILTER (WHERE dead_at IS NOT NULL)",
+        ))
+        .get_result(conn)?;
+    Ok(pages::Counts {
+        pending,
+        failed,
+        dead,
+    })
+}
+
+fn html(body: String) -> Response<String> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(body)
+        .expect("the response is valid")
+}
+
+fn redirect(location: &str) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, location)
+        .body(String::new())
+        .expect("the response is valid")
+}
+
+fn error(status: StatusCode, message: &str) -> Response<String> {
+    let reason = status.canonical_reason().unwrap_or("Error");
+    let mut body = format!("{} {}", status.as_u16(), reason);
+    if !message.is_empty() {
+        body += &format!(": {}", message);
+    }
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body + "\n")
+        .expect("the response is valid")
+}
+
+/// The keys and values of a query string, decoded
+fn parse_query(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query.split('&').filter(|p| !p.is_empty()).map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        let key = decode(parts.next().unwrap_or_default());
+        let value = decode(parts.next().unwrap_or_default());
+        (key, value)
+    })
+}
+
+/// Percent encodes everything but unreserved characters, so `value` can be
+/// put in a path segment or query string
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    encoded
+}
+
+/// Decodes a percent encoded path segment or query string component, in
+/// which `+` is a space. Invalid escapes are left as they are.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_and_actions_are_routed() {
+        assert_eq!(Ok(Route::Overview), route(&Method::GET, "/", None));
+        assert_eq!(Ok(Route::Overview), route(&Method::GET, "", None));
+        assert_eq!(Ok(Route::Job(12)), route(&Method::GET, "/jobs/12", None));
+        assert_eq!(
+            Ok(Route::Retry(12)),
+            route(&Method::POST, "/jobs/12/retry", None)
+        );
+        assert_eq!(
+            Ok(Route::Pause("mail ers".into())),
+            route(&Method::POST, "/queues/mail%20ers/pause", None)
+        );
+
+        let list = JobList {
+            state: Some(State::Dead),
+            job_type: Some("send_email".into()),
+            after: Some(5),
+            ..JobList::default()
+        };
+        let query = "state=dead&type=send_email&after=5";
+        assert_eq!(
+            Ok(Route::Jobs(list.clone())),
+            route(&Method::GET, "/jobs", Some(query))
+        );
+        assert_eq!(query, list.query(Some(5)));
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let get = |path, query| route(&Method::GET, path, query);
+        assert_eq!(Err(StatusCode::NOT_FOUND), get("/launch", None));
+        assert_eq!(Err(StatusCode::NOT_FOUND), get("/jobs/twelve", None));
+        assert_eq!(Err(StatusCode::NOT_FOUND), get("/jobs/0", None));
+        assert_eq!(
+            Err(StatusCode::NOT_FOUND),
+            get("/jobs/-9223372036854775808", None)
+        );
+        assert_eq!(
+            Err(StatusCode::METHOD_NOT_ALLOWED),
+            get("/jobs/12/delete", None)
+        );
+        assert_eq!(
+            Err(StatusCode::METHOD_NOT_ALLOWED),
+            route(&Method::POST, "/jobs", None)
+        );
+        assert_eq!(
+            Err(StatusCode::BAD_REQUEST),
+            get("/jobs", Some("state=asleep"))
+        );
+        assert_eq!(Err(StatusCode::BAD_REQUEST), get("/jobs", Some("after=x")));
+    }
+
+    #[test]
+    fn actions_from_other_sites_are_rejected() {
+        let post = |headers: &[(&str, &str)]| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("/jobs/1/delete");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            is_same_origin(&request.body(()).unwrap())
+        };
+        assert!(post(&[]));
+        assert!(post(&[("Sec-Fetch-Site", "same-origin")]));
+        assert!(!post(&[("Sec-Fetch-Site", "cross-site")]));
+        assert!(post(&[
+            ("Origin", "http://localhost:8080"),
+            ("Host", "localhost:8080")
+        ]));
+        assert!(!post(&[
+            ("Origin", "https://example.com"),
+            ("Host", "localhost:8080")
+        ]));
+        assert!(!post(&[("Origin", "null"), ("Host", "localhost:8080")]));
+        assert!(!post(&[("Origin", "http://localhost:8080")]));
+    }
+
+    #[test]
+    fn values_are_percent_encoded_and_decoded() {
+        assert_eq!("send_email", encode("send_email"));
+        assert_eq!("a%20b%2Fc%26", encode("a b/c&"));
+        assert_eq!("a b/c&", decode("a%20b%2Fc%26"));
+        assert_eq!("a b", decode("a+b"));
+        assert_eq!("100%", decode("100%"));
+    }
+}
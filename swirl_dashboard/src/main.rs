@@ -0,0 +1,202 @@
+//! `swirl-dashboard`, a standalone server for swirl's web dashboard.
+//!
+//! It connects to the database in `--database-url`, or in `DATABASE_URL`,
+//! which can also be set in a `.env` file. Requests are handled one at a
+//! time, which is plenty for a handful of operators.
+
+use diesel::prelude::*;
+use std::env;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+
+use swirl_dashboard::Dashboard;
+
+const USAGE: &str = "\
+Usage: swirl-dashboard [options]
+
+Options:
+    --database-url URL  the database to connect to (default $DATABASE_URL)
+    --bind ADDRESS      the address to listen on (default 127.0.0.1:8080)
+    --base-path PATH    serve the dashboard under PATH (default /)
+    --actor NAME        who changes are recorded as made by in the audit log
+                        (default swirl-dashboard)
+    --help              show this message";
+
+/// The largest request head which is read
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+type ServerResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Args {
+    database_url: Option<String>,
+    bind: String,
+    base_path: String,
+    actor: Option<String>,
+    help: bool,
+}
+
+fn main() {
+    dotenv::dotenv().ok();
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> ServerResult<()> {
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let database_url = match args.database_url {
+        Some(database_url) => database_url,
+        None => env::var("DATABASE_URL")
+            .map_err(|_| "DATABASE_URL must be set, or passed with --database-url")?,
+    };
+    let mut dashboard = Dashboard::new().base_path(args.base_path);
+    if let Some(actor) = args.actor {
+        dashboard = dashboard.actor(actor);
+    }
+    let listener = TcpListener::bind(&args.bind)?;
+    println!("Serving the dashboard on http://{}", listener.local_addr()?);
+
+    let mut conn = PgConnection::establish(&database_url)?;
+    for stream in listener.incoming() {
+        let result = stream.map_err(Into::into).and_then(|stream| {
+            // Reconnect if the connection was lost since the last request
+            if conn.execute("SELECT 1").is_err() {
+                conn = PgConnection::establish(&database_url)?;
+            }
+            serve(&dashboard, &conn, stream)
+        });
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a request from `stream`, and writes the dashboard's response to it
+fn serve(dashboard: &Dashboard, conn: &PgConnection, mut stream: TcpStream) -> ServerResult<()> {
+    let request = match read_request(&mut stream)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    let response = dashboard.handle(conn, &request);
+
+    let status = response.status();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+    for (name, value) in response.headers() {
+        head += &format!("{}: {}\r\n", name, value.to_str()?);
+    }
+    head += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body().len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body().as_bytes())?;
+    Ok(())
+}
+
+/// Reads the head of a request. The body is ignored, since the dashboard
+/// never reads it. Returns `None` if the connection was closed first.
+fn read_request(stream: &mut TcpStream) -> ServerResult<Option<http::Request<()>>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Request::new(&mut headers);
+        if parsed.parse(&buf)?.is_complete() {
+            let mut request = http::Request::builder()
+                .method(parsed.method.unwrap_or("GET"))
+                .uri(parsed.path.unwrap_or("/"));
+            for header in parsed.headers.iter() {
+                request = request.header(header.name, header.value);
+            }
+            return Ok(Some(request.body(())?));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err("request head is too large".into());
+        }
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut parsed = Args {
+        database_url: None,
+        bind: "127.0.0.1:8080".into(),
+        base_path: String::new(),
+        actor: None,
+        help: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match &*arg {
+            "--database-url" => parsed.database_url = Some(value()?),
+            "--bind" => parsed.bind = value()?,
+            "--base-path" => parsed.base_path = value()?,
+            "--actor" => parsed.actor = Some(value()?),
+            "--help" | "-h" => parsed.help = true,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let args = parse(&[
+            "--bind",
+            "0.0.0.0:3000",
+            "--base-path",
+            "/jobs",
+            "--actor",
+            "ops",
+        ])
+        .unwrap();
+        assert_eq!(Some("ops".into()), args.actor);
+        assert_eq!(None, args.database_url);
+        assert_eq!("0.0.0.0:3000", args.bind);
+        assert_eq!("/jobs", args.base_path);
+
+        let args = parse(&[]).unwrap();
+        assert_eq!("127.0.0.1:8080", args.bind);
+        assert!(!args.help);
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        assert!(parse(&["--bind"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["serve"]).is_err());
+    }
+}
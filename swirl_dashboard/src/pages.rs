@@ -0,0 +1,318 @@
+//! Rendering the dashboard's pages as HTML.
+//!
+//! Pages are plain HTML with a little inline CSS, and no JavaScript, so the
+//! dashboard works without serving any other files. Everything which came
+//! from the database is escaped.
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use std::fmt::Write;
+
+use swirl::admin::{self, Attempt, Throughput};
+use swirl::storage::{JobStatus, QueuedJob};
+
+use super::{encode, JobList, State, PAGE_SIZE};
+
+/// The number of pending, failed, and dead jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Counts {
+    pub(super) pending: i64,
+    pub(super) failed: i64,
+    pub(super) dead: i64,
+}
+
+pub(super) struct Overview {
+    /// The jobs on each queue which haven't been marked as dead, by queue
+    pub(super) queues: Vec<(String, i64)>,
+    pub(super) paused: Vec<String>,
+    pub(super) counts: Counts,
+    /// The attempts in each of the last 24 hours which had any
+    pub(super) throughput: Vec<Throughput>,
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { text-align: left; padding: 0.3em 1em 0.3em 0; vertical-align: top; }
+th { border-bottom: 1px solid #ccc; }
+pre { background: #f4f4f4; padding: 0.5em; white-space: pre-wrap; }
+form { display: inline; }
+nav a { margin-right: 1em; }
+.failed { color: #b00; }";
+
+fn layout(base: &str, title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title} - swirl</title>\n\
+         <style>\n{style}\n</style>\n</head>\n<body>\n<nav>\
+         <a href=\"{base}/\">Overview</a>\
+         <a href=\"{base}/jobs?state=pending\">Pending</a>\
+         <a href=\"{base}/jobs?state=failed\">Failed</a>\
+         <a href=\"{base}/jobs?state=dead\">Dead</a>\
+         </nav>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        style = STYLE,
+        base = base,
+        body = body,
+    )
+}
+
+pub(super) fn overview(base: &str, overview: &Overview) -> String {
+    let counts = overview.counts;
+    let mut body = format!(
+        "<p><a href=\"{base}/jobs?state=pending\">{} pending</a>, \
+         <a href=\"{base}/jobs?state=failed\">{} waiting to be retried</a>, \
+         <a href=\"{base}/jobs?state=dead\">{} dead</a></p>\n",
+        counts.pending,
+        counts.failed,
+        counts.dead,
+        base = base,
+    );
+
+    body += "<h2>Queues</h2>\n<table>\n<tr><th>Queue</th><th>Jobs</th><th></th></tr>\n";
+    for (queue, depth) in &overview.queues {
+        let paused = overview.paused.contains(queue);
+        let (action, label) = if paused {
+            ("resume", "Resume")
+        } else {
+            ("pause", "Pause")
+        };
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"{base}/jobs?queue={encoded}\">{queue}</a>{paused}</td><td>{depth}</td>\
+             <td>{button}</td></tr>",
+            base = base,
+            encoded = encode(queue),
+            queue = escape(queue),
+            paused = if paused { " (paused)" } else { "" },
+            depth = depth,
+            button = button(&format!("{}/queues/{}/{}", base, encode(queue), action), label),
+        );
+    }
+    body += "</table>\n";
+
+    body += "<h2>Throughput</h2>\n";
+    body += &throughput_chart(&overview.throughput, Utc::now());
+    layout(base, "Jobs", &body)
+}
+
+/// The width of each hour's bar in the throughput chart, in pixels
+const BAR_WIDTH: i64 = 20;
+/// The height of the throughput chart, in pixels
+const CHART_HEIGHT: i64 = 120;
+
+/// A bar chart of the attempts which succeeded and failed in each of the 24
+/// hours up to `now`, with the failures stacked on top
+fn throughput_chart(throughput: &[Throughput], now: DateTime<Utc>) -> String {
+    let current_hour = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+    let max = throughput
+        .iter()
+        .map(|t| t.succeeded + t.failed)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut chart = format!(
+        "<svg width=\"{}\" height=\"{}\" role=\"img\">\n",
+        BAR_WIDTH * 24,
+        CHART_HEIGHT
+    );
+    for i in 0..24 {
+        let hour = current_hour - Duration::hours(23 - i);
+        let (succeeded, failed) = throughput
+            .iter()
+            .find(|t| t.hour == hour)
+            .map_or((0, 0), |t| (t.succeeded, t.failed));
+        let succeeded_height = succeeded * CHART_HEIGHT / max;
+        let failed_height = failed * CHART_HEIGHT / max;
+        let x = i * BAR_WIDTH;
+        let _ = writeln!(
+            chart,
+            "<g><title>{}: {} succeeded, {} failed</title>\
+             <rect x=\"{x}\" y=\"{}\" width=\"{w}\" height=\"{}\" fill=\"#4a4\"/>\
+             <rect x=\"{x}\" y=\"{}\" width=\"{w}\" height=\"{}\" fill=\"#c33\"/></g>",
+            hour.format("%Y-%m-%d %H:00 UTC"),
+            succeeded,
+            failed,
+            CHART_HEIGHT - succeeded_height,
+            succeeded_height,
+            CHART_HEIGHT - succeeded_height - failed_height,
+            failed_height,
+            x = x,
+            w = BAR_WIDTH - 2,
+        );
+    }
+    chart += "</svg>\n<p>Attempts to run jobs in each of the last 24 hours. \
+              Failures are in red.</p>\n";
+    chart
+}
+
+pub(super) fn jobs(base: &str, list: &JobList, jobs: &[QueuedJob]) -> String {
+    let title = match list.state {
+        Some(State::Pending) => "Pending jobs",
+        Some(State::Failed) => "Failed jobs",
+        Some(State::Dead) => "Dead jobs",
+        None => "All jobs",
+    };
+    let mut body = String::new();
+    if jobs.is_empty() {
+        body += "<p>No jobs</p>\n";
+    } else {
+        body += "<table>\n<tr><th>ID</th><th>Type</th><th>Queue</th><th>Retries</th>\
+                 <th>Enqueued at</th><th>Last error</th><th></th></tr>\n";
+    }
+    for job in jobs {
+        let mut buttons = String::new();
+        if job.dead_at.is_some() {
+            buttons += &button(&format!("{}/jobs/{}/retry", base, job.id.0), "Retry");
+        }
+        buttons += &button(&format!("{}/jobs/{}/delete", base, job.id.0), "Delete");
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"{base}/jobs/{id}\">{id}</a></td><td>{job_type}</td><td>{queue}</td>\
+             <td>{retries}</td><td>{enqueued_at}</td><td class=\"failed\">{error}</td>\
+             <td>{buttons}</td></tr>",
+            base = base,
+            id = job.id.0,
+            job_type = escape(&job.job_type),
+            queue = escape(&job.queue),
+            retries = job.retries,
+            enqueued_at = job.enqueued_at.format("%Y-%m-%d %H:%M:%S"),
+            error = escape(
+                job.last_error
+                    .as_deref()
+                    .and_then(|e| e.lines().next())
+                    .unwrap_or_default()
+            ),
+            buttons = buttons,
+        );
+    }
+    if !jobs.is_empty() {
+        body += "</table>\n";
+    }
+    if let (Some(last), true) = (jobs.last(), jobs.len() as i64 == PAGE_SIZE) {
+        let _ = writeln!(
+            body,
+            "<p><a href=\"{}/jobs?{}\">Next page</a></p>",
+            base,
+            list.query(Some(last.id.0)).replace('&', "&amp;"),
+        );
+    }
+    layout(base, title, &body)
+}
+
+pub(super) fn job(
+    base: &str,
+    job_id: i64,
+    status: &JobStatus,
+    job: Option<&QueuedJob>,
+    attempts: &[Attempt],
+) -> String {
+    let status = match status {
+        JobStatus::Pending => "Pending".to_owned(),
+        JobStatus::Running => "Running".to_owned(),
+        JobStatus::Failed { dead: true, .. } => "Dead".to_owned(),
+        JobStatus::Failed { retries, .. } => {
+            format!("Failed {} times, waiting to be retried", retries)
+        }
+        JobStatus::Done => "No longer in the queue".to_owned(),
+    };
+    let mut body = format!("<p>{}</p>\n", status);
+
+    if let Some(job) = job {
+        let time = |t: Option<DateTime<Utc>>| {
+            t.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default()
+        };
+        let _ = writeln!(
+            body,
+            "<table>\n<tr><th>Type</th><td>{}</td></tr>\n<tr><th>Queue</th><td>{}</td></tr>\n\
+             <tr><th>Priority</th><td>{}</td></tr>\n<tr><th>Retries</th><td>{}</td></tr>\n\
+             <tr><th>Enqueued at</th><td>{}</td></tr>\n<tr><th>Last started at</th><td>{}</td></tr>\n\
+             <tr><th>Next run at</th><td>{}</td></tr>\n<tr><th>Deadline</th><td>{}</td></tr>\n\
+             <tr><th>Batch</th><td>{}</td></tr>\n</table>",
+            escape(&job.job_type),
+            escape(&job.queue),
+            job.priority,
+            job.retries,
+            time(Some(job.enqueued_at)),
+            time(job.started_at),
+            time(job.run_at),
+            time(job.deadline),
+            escape(job.batch_id.as_deref().unwrap_or_default()),
+        );
+        if job.dead_at.is_some() {
+            body += &button(&format!("{}/jobs/{}/retry", base, job_id), "Retry");
+        }
+        body += &button(&format!("{}/jobs/{}/delete", base, job_id), "Delete");
+        // Arguments marked with `#[redact]` are only masked for job types
+        // which are registered here
+        if admin::redacted_fields(&job.job_type).is_some() {
+            let data = serde_json::to_string_pretty(&job.data).unwrap_or_default();
+            let _ = writeln!(body, "\n<h2>Arguments</h2>\n<pre>{}</pre>", escape(&data));
+        } else {
+            body += "\n<h2>Arguments</h2>\n<p>Not shown, since this job type isn't registered here.</p>\n";
+        }
+        if let Some(error) = &job.last_error {
+            let _ = writeln!(
+                body,
+                "<h2>Last error</h2>\n<pre class=\"failed\">{}</pre>",
+                escape(error)
+            );
+        }
+    }
+
+    if !attempts.is_empty() {
+        body += "<h2>Attempts</h2>\n<table>\n<tr><th>#</th><th>Outcome</th><th>Started at</th>\
+                 <th>Duration</th><th>Worker</th><th>Error</th></tr>\n";
+        for attempt in attempts {
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td><td>{}</td>\
+                 <td class=\"failed\">{}</td></tr>",
+                attempt.attempt,
+                attempt.outcome.as_str(),
+                attempt.started_at.format("%Y-%m-%d %H:%M:%S"),
+                attempt.duration_ms,
+                escape(&attempt.worker),
+                escape(attempt.error.as_deref().unwrap_or_default()),
+            );
+        }
+        body += "</table>\n";
+    }
+    layout(base, &format!("Job {}", job_id), &body)
+}
+
+/// A button which posts to `action`
+fn button(action: &str, label: &str) -> String {
+    format!(
+        "<form method=\"post\" action=\"{}\"><button>{}</button></form>",
+        action, label
+    )
+}
+
+/// Escapes text to be put in HTML, including in attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_from_the_database_is_escaped() {
+        assert_eq!(
+            "&lt;script&gt;alert(&quot;hi&quot; &amp; &#39;bye&#39;)&lt;/script&gt;",
+            escape("<script>alert(\"hi\" & 'bye')</script>")
+        );
+    }
+}
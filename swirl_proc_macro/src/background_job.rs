@@ -5,7 +5,8 @@ use std::borrow::Cow;
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
 
-pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
+pub fn expand(attr: syn::AttributeArgs, item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
+    let options = JobOptions::try_from(attr)?;
     let job = BackgroundJob::try_from(item)?;
 
     let attrs = job.attrs;
@@ -21,8 +22,92 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
     let struct_def = job.args.struct_def();
     let struct_assign = job.args.struct_assign();
     let arg_names = job.args.names();
+    let redacted = job.args.redacted.iter().map(|ident| ident.to_string());
     let return_type = job.return_type;
     let body = connection_arg.wrap(job.body);
+    let body = match &job.args.context_arg {
+        Some((pat, ty)) => quote! {
+            let #pat: &#ty = &swirl::JobContext::current()
+                .ok_or("This job can only be performed by a runner, since it takes a JobContext")?;
+            #body
+        },
+        None => body,
+    };
+    let queue = options.queue.iter().map(|queue| match queue {
+        QueueOption::Name(name) => quote!(#name),
+        QueueOption::Type(ty) => quote!(<#ty as swirl::Queue>::NAME),
+    });
+    let statement_timeout_ms = options.statement_timeout_ms.iter();
+    let max_retries = options.max_retries.iter();
+    let priority = options.priority.iter();
+    let backoff = options.backoff.iter();
+    let rate_limit = options.rate_limit.iter();
+    let max_concurrency = options.max_concurrency.iter();
+    let max_payload_size = options.max_payload_size.iter();
+    let compress_over = options.compress_over.iter();
+    let unique = match &options.unique {
+        Some(fields) => {
+            let names = job.args.names().collect::<Vec<_>>();
+            if let Some(field) = fields.iter().find(|field| !names.contains(field)) {
+                return Err(field.span().error("No argument with this name"));
+            }
+            let fields = if fields.is_empty() { &names } else { fields };
+            quote! {
+                fn unique_key(&self) -> Option<String> {
+                    swirl::unique_key_of(&(#(&self.#fields,)*))
+                }
+            }
+        }
+        None => quote!(),
+    };
+    let codec = options.codec.iter().map(|codec| {
+        quote! {
+            fn encode_binary(&self) -> Result<Option<Vec<u8>>, swirl::CodecError> {
+                <#codec as swirl::Codec>::encode(self).map(Some)
+            }
+
+            fn decode_binary(bytes: &[u8]) -> Result<Self, swirl::CodecError> {
+                <#codec as swirl::Codec>::decode(bytes)
+            }
+        }
+    });
+
+    // With an environment given, the job's argument is only the part of it
+    // the job uses, which tests can pass to `perform_with` directly
+    let (environment, perform, perform_with) = match &options.environment {
+        None => (
+            quote!(#env_type),
+            quote! {
+                #fn_token perform(self, #env_pat: &Self::Environment, #pool_pat: &#pool_ty) #return_type {
+                    let Self { #(#arg_names),* } = self;
+                    #body
+                }
+            },
+            quote!(),
+        ),
+        Some(environment) => (
+            quote!(#environment),
+            quote! {
+                #fn_token perform(
+                    self,
+                    __swirl_environment: &Self::Environment,
+                    __swirl_pool: &#pool_ty,
+                ) #return_type {
+                    self.perform_with(swirl::FromEnv::from_env(__swirl_environment), __swirl_pool)
+                }
+            },
+            quote! {
+                impl #name :: Job {
+                    /// Run this job with only the part of the environment it
+                    /// uses
+                    pub #fn_token perform_with(self, #env_pat: &#env_type, #pool_pat: &#pool_ty) #return_type {
+                        let Self { #(#arg_names),* } = self;
+                        #body
+                    }
+                }
+            },
+        ),
+    };
 
     let res = quote! {
         #(#attrs)*
@@ -33,15 +118,29 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
         }
 
         impl swirl::Job for #name :: Job {
-            type Environment = #env_type;
+            type Environment = #environment;
             const JOB_TYPE: &'static str = stringify!(#name);
-
-            #fn_token perform(self, #env_pat: &Self::Environment, #pool_pat: &#pool_ty) #return_type {
-                let Self { #(#arg_names),* } = self;
-                #body
-            }
+            #(const QUEUE: &'static str = #queue;)*
+            #(const PRIORITY: i16 = #priority;)*
+            const REDACTED_FIELDS: &'static [&'static str] = &[#(#redacted),*];
+            #(
+                const STATEMENT_TIMEOUT: Option<std::time::Duration> =
+                    Some(std::time::Duration::from_millis(#statement_timeout_ms));
+            )*
+            #(const MAX_RETRIES: Option<u32> = Some(#max_retries);)*
+            #(const BACKOFF: Option<swirl::Backoff> = Some(#backoff);)*
+            #(const RATE_LIMIT: Option<swirl::RateLimit> = Some(#rate_limit);)*
+            #(const MAX_CONCURRENCY: Option<usize> = Some(#max_concurrency);)*
+            #(const MAX_PAYLOAD_SIZE: Option<usize> = Some(#max_payload_size);)*
+            #(const COMPRESS_OVER: Option<usize> = Some(#compress_over);)*
+            #(#codec)*
+            #unique
+
+            #perform
         }
 
+        #perform_with
+
         mod #name {
             use super::*;
 
@@ -57,6 +156,205 @@ pub fn expand(item: syn::ItemFn) -> Result<TokenStream, Diagnostic> {
     Ok(res)
 }
 
+#[derive(Default)]
+struct JobOptions {
+    queue: Option<QueueOption>,
+    environment: Option<syn::Path>,
+    statement_timeout_ms: Option<syn::LitInt>,
+    max_retries: Option<syn::LitInt>,
+    priority: Option<syn::LitInt>,
+    backoff: Option<syn::Path>,
+    rate_limit: Option<syn::Path>,
+    max_concurrency: Option<syn::LitInt>,
+    max_payload_size: Option<syn::LitInt>,
+    compress_over: Option<syn::LitInt>,
+    codec: Option<syn::Path>,
+    /// The arguments which make up the job's unique key, or every argument
+    /// if this is empty
+    unique: Option<Vec<syn::Ident>>,
+}
+
+/// The queue a job is placed on, given by name or as a type implementing
+/// `swirl::Queue`
+enum QueueOption {
+    Name(syn::LitStr),
+    Type(syn::Path),
+}
+
+impl JobOptions {
+    fn try_from(args: syn::AttributeArgs) -> Result<Self, Diagnostic> {
+        let mut options = Self::default();
+
+        for arg in args {
+            match arg {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("queue") => match lit {
+                    syn::Lit::Str(queue) => options.queue = Some(QueueOption::Name(queue.clone())),
+                    _ => return Err(lit.span().error("Expected a string literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("queue") => match nested.iter().collect::<Vec<_>>()[..] {
+                    [syn::NestedMeta::Meta(syn::Meta::Path(queue))] => {
+                        options.queue = Some(QueueOption::Type(queue.clone()))
+                    }
+                    _ => return Err(nested.span().error("Expected the path of a queue type")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("environment") => {
+                    match nested.iter().collect::<Vec<_>>()[..] {
+                        [syn::NestedMeta::Meta(syn::Meta::Path(environment))] => {
+                            options.environment = Some(environment.clone())
+                        }
+                        _ => {
+                            return Err(nested
+                                .span()
+                                .error("Expected the path of the runner's environment type"))
+                        }
+                    }
+                }
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("statement_timeout_ms") => match lit {
+                    syn::Lit::Int(ms) => options.statement_timeout_ms = Some(ms.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("max_retries") => match lit {
+                    syn::Lit::Int(retries) => options.max_retries = Some(retries.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("priority") => match lit {
+                    syn::Lit::Int(priority) => options.priority = Some(priority.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("max_concurrency") => match lit {
+                    syn::Lit::Int(max) => options.max_concurrency = Some(max.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("max_payload_size") => match lit {
+                    syn::Lit::Int(size) => options.max_payload_size = Some(size.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                    ref path,
+                    ref lit,
+                    ..
+                })) if path.is_ident("compress_over") => match lit {
+                    syn::Lit::Int(size) => options.compress_over = Some(size.clone()),
+                    _ => return Err(lit.span().error("Expected an integer literal")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("backoff") => match nested.iter().collect::<Vec<_>>()[..] {
+                    [syn::NestedMeta::Meta(syn::Meta::Path(backoff))] => {
+                        options.backoff = Some(backoff.clone())
+                    }
+                    _ => {
+                        return Err(nested
+                            .span()
+                            .error("Expected the path of a Backoff constant"))
+                    }
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("rate_limit") => match nested.iter().collect::<Vec<_>>()[..] {
+                    [syn::NestedMeta::Meta(syn::Meta::Path(rate_limit))] => {
+                        options.rate_limit = Some(rate_limit.clone())
+                    }
+                    _ => {
+                        return Err(nested
+                            .span()
+                            .error("Expected the path of a RateLimit constant"))
+                    }
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("codec") => match nested.iter().collect::<Vec<_>>()[..] {
+                    [syn::NestedMeta::Meta(syn::Meta::Path(codec))] => {
+                        options.codec = Some(codec.clone())
+                    }
+                    _ => return Err(nested.span().error("Expected the path of a codec type")),
+                },
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("unique") => {
+                    options.unique = Some(Vec::new())
+                }
+                syn::NestedMeta::Meta(syn::Meta::List(syn::MetaList {
+                    ref path,
+                    ref nested,
+                    ..
+                })) if path.is_ident("unique") => {
+                    let fields = nested
+                        .iter()
+                        .map(|field| match field {
+                            syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                                path.get_ident().cloned()
+                            }
+                            _ => None,
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|fields| !fields.is_empty());
+                    match fields {
+                        Some(fields) => options.unique = Some(fields),
+                        None => {
+                            return Err(nested
+                                .span()
+                                .error("Expected the names of the job's arguments"))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(arg
+                        .span()
+                        .error("Unrecognized argument to #[swirl::background_job]")
+                        .help(
+                            "The supported arguments are: `queue = \"name\"`, \
+                             `queue(QueueType)`, `environment(EnvType)`, \
+                             `statement_timeout_ms = 5000`, `max_retries = 5`, \
+                             `priority = 10`, `backoff(BACKOFF_CONST)`, \
+                             `rate_limit(RATE_LIMIT_CONST)`, `max_concurrency = 1`, \
+                             `max_payload_size = 65536`, `compress_over = 4096`, \
+                             `codec(CodecType)`, `unique`, `unique(arg, ...)`",
+                        ));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
 struct BackgroundJob {
     attrs: Vec<syn::Attribute>,
     visibility: syn::Visibility,
@@ -133,7 +431,11 @@ impl BackgroundJob {
 struct JobArgs {
     env_arg: EnvArg,
     connection_arg: ConnectionArg,
+    /// The `&JobContext` argument, if there is one
+    context_arg: Option<(Box<syn::Pat>, Box<syn::Type>)>,
     args: Punctuated<syn::PatType, syn::Token![,]>,
+    /// Arguments marked with `#[redact]`
+    redacted: Vec<syn::Ident>,
 }
 
 impl JobArgs {
@@ -144,10 +446,12 @@ impl JobArgs {
     fn try_from(decl: syn::Signature) -> Result<Self, Diagnostic> {
         let mut env_arg = None;
         let mut connection_arg = ConnectionArg::None;
+        let mut context_arg = None;
         let mut args = Punctuated::new();
+        let mut redacted = Vec::new();
 
         for fn_arg in decl.inputs {
-            let pat_type = match fn_arg {
+            let mut pat_type = match fn_arg {
                 syn::FnArg::Receiver(..) => {
                     return Err(fn_arg.span().error("Background jobs cannot take self"));
                 }
@@ -168,8 +472,18 @@ impl JobArgs {
                     .error("#[swirl::background_job] cannot yet handle patterns"));
             }
 
+            let redact = take_redact_attr(&mut pat_type)?;
             let span = pat_type.span();
-            match (&env_arg, &connection_arg, Arg::try_from(pat_type)?) {
+            let arg = Arg::try_from(pat_type)?;
+            if let (Some(attr), Arg::Env(_))
+            | (Some(attr), Arg::Connection(_))
+            | (Some(attr), Arg::Context(..)) = (&redact, &arg)
+            {
+                return Err(attr
+                    .span()
+                    .error("Only arguments which are serialized can be redacted"));
+            }
+            match (&env_arg, &connection_arg, arg) {
                 (None, _, Arg::Env(arg)) => env_arg = Some(arg),
                 (Some(_), _, Arg::Env(_)) => {
                     return Err(
@@ -184,14 +498,29 @@ impl JobArgs {
                             .help("To take a connection pool as an argument instead of a single connection, use the type `&dyn swirl::db::DieselPoolObj`")
                     );
                 }
-                (_, _, Arg::Normal(pat_type)) => args.push(pat_type),
+                (_, _, Arg::Context(pat, ty)) => {
+                    if context_arg.is_some() {
+                        return Err(span.error("Multiple job context arguments"));
+                    }
+                    context_arg = Some((pat, ty));
+                }
+                (_, _, Arg::Normal(pat_type)) => {
+                    if redact.is_some() {
+                        if let syn::Pat::Ident(pat_ident) = &*pat_type.pat {
+                            redacted.push(pat_ident.ident.clone());
+                        }
+                    }
+                    args.push(pat_type)
+                }
             }
         }
 
         Ok(Self {
             env_arg: env_arg.unwrap_or_default(),
             connection_arg,
+            context_arg,
             args,
+            redacted,
         })
     }
 
@@ -223,6 +552,7 @@ impl<'a> IntoIterator for &'a JobArgs {
 enum Arg {
     Env(EnvArg),
     Connection(ConnectionArg),
+    Context(Box<syn::Pat>, Box<syn::Type>),
     Normal(syn::PatType),
 }
 
@@ -236,6 +566,8 @@ impl Arg {
             let ty = type_ref.elem;
             if ConnectionArg::is_connection_arg(&ty) {
                 Ok(Arg::Connection(ConnectionArg::from_arg(pat, ty)))
+            } else if is_context(&ty) {
+                Ok(Arg::Context(pat, ty))
             } else {
                 Ok(Arg::Env(EnvArg { pat, ty }))
             }
@@ -334,6 +666,34 @@ impl ConnectionArg {
     }
 }
 
+fn is_context(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(syn::TypePath { path, .. }) = ty {
+        path_ends_with(path, "JobContext")
+    } else {
+        false
+    }
+}
+
+/// Removes `#[redact]` from an argument, returning it if it was present
+fn take_redact_attr(pat_type: &mut syn::PatType) -> Result<Option<syn::Attribute>, Diagnostic> {
+    let index = match pat_type
+        .attrs
+        .iter()
+        .position(|attr| attr.path.is_ident("redact"))
+    {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let attr = pat_type.attrs.remove(index);
+    if !attr.tokens.is_empty() {
+        return Err(attr
+            .tokens
+            .span()
+            .error("#[redact] does not take any arguments"));
+    }
+    Ok(Some(attr))
+}
+
 fn path_ends_with(path: &syn::Path, needle: &str) -> bool {
     path.segments
         .last()